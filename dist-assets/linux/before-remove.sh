@@ -24,3 +24,6 @@ elif /sbin/init --version | grep upstart &> /dev/null; then
     stop mullvad-daemon
     rm -f /etc/init/mullvad-daemon.conf
 fi
+
+# Undo DNS changes left behind if the daemon was killed while connected
+/opt/Mullvad\ VPN/resources/mullvad-setup reset-dns || true
@@ -287,6 +287,12 @@ const tunnelStateSchema = oneOf(
       ),
     }),
   }),
+  object({
+    state: enumeration('disconnected'),
+    details: partialObject({
+      paused_until: maybe(string),
+    }),
+  }),
   object({
     state: enumeration('connected', 'connecting', 'disconnected'),
   }),
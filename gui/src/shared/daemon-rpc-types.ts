@@ -95,12 +95,16 @@ export interface ITunnelStateRelayInfo {
 }
 
 export type TunnelState =
-  | { state: 'disconnected' }
+  | { state: 'disconnected'; details?: IDisconnectedState }
   | { state: 'connecting'; details?: ITunnelStateRelayInfo }
   | { state: 'connected'; details: ITunnelStateRelayInfo }
   | { state: 'disconnecting'; details: AfterDisconnect }
   | { state: 'error'; details: IErrorState };
 
+export interface IDisconnectedState {
+  pausedUntil?: string;
+}
+
 export interface IErrorState {
   isBlocking: boolean;
   cause: ErrorStateCause;
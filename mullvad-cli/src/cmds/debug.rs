@@ -0,0 +1,63 @@
+use crate::{new_rpc_client, Command, Error, Result};
+use talpid_types::net::firewall::FirewallPolicyKind;
+
+pub struct Debug;
+
+impl Command for Debug {
+    fn name(&self) -> &'static str {
+        "debug"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Inspect the internals of the daemon")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("firewall")
+                    .about(
+                        "Print the nftables script that applies a firewall policy, without \
+                         applying it. The policy that is currently applied is printed if it is of \
+                         the given kind, otherwise one is built from the current settings, with \
+                         a made up relay and tunnel",
+                    )
+                    .arg(
+                        clap::Arg::with_name("policy")
+                            .help("The firewall policy to print")
+                            .long("policy")
+                            .takes_value(true)
+                            .required(true)
+                            .possible_values(&["connecting", "connected", "blocked"]),
+                    ),
+            )
+    }
+
+    fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("firewall", Some(firewall_matches)) => {
+                let kind = match firewall_matches.value_of("policy").unwrap() {
+                    "connecting" => FirewallPolicyKind::Connecting,
+                    "connected" => FirewallPolicyKind::Connected,
+                    "blocked" => FirewallPolicyKind::Blocked,
+                    _ => unreachable!("unhandled policy"),
+                };
+                self.render_firewall_policy(kind)
+            }
+            _ => unreachable!("unhandled command"),
+        }
+    }
+}
+
+impl Debug {
+    fn render_firewall_policy(&self, kind: FirewallPolicyKind) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        match rpc.render_firewall_policy(kind)? {
+            Some(script) => {
+                print!("{}", script);
+                Ok(())
+            }
+            None => Err(Error::InvalidCommand(
+                "Firewall policies can only be rendered on Linux",
+            )),
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use crate::{new_rpc_client, Command, Error, Result};
+use talpid_types::dns::DnsCheckResult;
+
+pub struct Diagnose;
+
+impl Command for Diagnose {
+    fn name(&self) -> &'static str {
+        "diagnose"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Check that the app works as intended")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(clap::SubCommand::with_name("dns").about(
+                "Check that the system uses the DNS servers set by the app and that DNS queries \
+                 go through the tunnel",
+            ))
+    }
+
+    fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("dns", Some(_)) => self.diagnose_dns(),
+            _ => unreachable!("unhandled command"),
+        }
+    }
+}
+
+impl Diagnose {
+    fn diagnose_dns(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let diagnostics = rpc.diagnose_dns()?;
+
+        println!(
+            "DNS backend: {}",
+            diagnostics
+                .backend
+                .as_ref()
+                .map(String::as_str)
+                .unwrap_or("none")
+        );
+        for check in &diagnostics.checks {
+            println!("[{}] {}: {}", check.result, check.name, check.details);
+        }
+
+        if diagnostics.passed() {
+            if diagnostics
+                .checks
+                .iter()
+                .all(|check| check.result == DnsCheckResult::Skipped)
+            {
+                println!("No checks were made");
+            } else {
+                println!("All checks passed");
+            }
+            Ok(())
+        } else {
+            Err(Error::DiagnosticsFailed("DNS"))
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use crate::{new_rpc_client, Command, Result};
+use std::time::Duration;
 
 pub struct Disconnect;
 
@@ -10,11 +11,45 @@ impl Command for Disconnect {
     fn clap_subcommand(&self) -> clap::App<'static, 'static> {
         clap::SubCommand::with_name(self.name())
             .about("Command the client to disconnect the VPN tunnel")
+            .arg(
+                clap::Arg::with_name("for")
+                    .help(
+                        "Connect again after the given duration, such as 30s, 10m or 1h. \
+                         A number without a unit is interpreted as seconds",
+                    )
+                    .long("for")
+                    .takes_value(true)
+                    .value_name("DURATION")
+                    .validator(|value| parse_duration(&value).map(|_| ())),
+            )
     }
 
-    fn run(&self, _matches: &clap::ArgMatches<'_>) -> Result<()> {
+    fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
         let mut rpc = new_rpc_client()?;
-        rpc.disconnect()?;
+        match matches.value_of("for") {
+            Some(value) => rpc.pause(parse_duration(value).unwrap())?,
+            None => rpc.disconnect()?,
+        }
         Ok(())
     }
 }
+
+fn parse_duration(value: &str) -> std::result::Result<Duration, String> {
+    let unit_start = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(unit_start);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("Invalid duration: {}", value))?;
+    let multiplier = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => return Err(format!("Invalid duration unit: {}", unit)),
+    };
+    match amount.checked_mul(multiplier) {
+        Some(0) | None => Err(format!("Invalid duration: {}", value)),
+        Some(seconds) => Ok(Duration::from_secs(seconds)),
+    }
+}
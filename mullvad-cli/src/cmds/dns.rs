@@ -0,0 +1,526 @@
+use crate::{new_rpc_client, Command, Result};
+use clap::values_t;
+#[cfg(target_os = "linux")]
+use clap::{value_t, value_t_or_exit};
+use std::net::IpAddr;
+#[cfg(target_os = "linux")]
+use talpid_types::dns::{
+    BlockingCategory, DnsBackend, EncryptedDnsProtocol, EncryptedDnsServer, SplitDnsRule,
+};
+
+pub struct Dns;
+
+impl Command for Dns {
+    fn name(&self) -> &'static str {
+        "dns"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        let subcommand = clap::SubCommand::with_name(self.name())
+            .about("Configure DNS while connected")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("set")
+                    .about("Set the DNS servers to use")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::SubCommand::with_name("default")
+                            .about("Use the DNS server of the relay"),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("custom")
+                            .about(
+                                "Use the given DNS servers. Servers on the local network are \
+                                 only reachable outside the tunnel if local network sharing is \
+                                 allowed",
+                            )
+                            .arg(
+                                clap::Arg::with_name("servers")
+                                    .multiple(true)
+                                    .required(true),
+                            ),
+                    ),
+            )
+            .subcommand(clap::SubCommand::with_name("get").about("Display the DNS servers in use"));
+        #[cfg(target_os = "linux")]
+        let subcommand = subcommand
+            .subcommand(create_blocking_subcommand())
+            .subcommand(create_encrypted_subcommand())
+            .subcommand(create_split_subcommand())
+            .subcommand(create_backend_subcommand());
+        subcommand
+    }
+
+    fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("set", Some(set_matches)) => match set_matches.subcommand() {
+                ("default", Some(_)) => self.set(vec![]),
+                ("custom", Some(custom_matches)) => {
+                    let servers = values_t!(custom_matches.values_of("servers"), IpAddr)
+                        .unwrap_or_else(|e| e.exit());
+                    self.set(servers)
+                }
+                _ => unreachable!("unhandled subcommand"),
+            },
+            ("get", Some(_)) => self.get(),
+            #[cfg(target_os = "linux")]
+            ("blocking", Some(blocking_matches)) => self.handle_blocking(blocking_matches),
+            #[cfg(target_os = "linux")]
+            ("encrypted", Some(encrypted_matches)) => self.handle_encrypted(encrypted_matches),
+            #[cfg(target_os = "linux")]
+            ("split", Some(split_matches)) => self.handle_split(split_matches),
+            #[cfg(target_os = "linux")]
+            ("backend", Some(backend_matches)) => self.handle_backend(backend_matches),
+            _ => unreachable!("unhandled command"),
+        }
+    }
+}
+
+impl Dns {
+    fn set(&self, servers: Vec<IpAddr>) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_custom_dns_servers(servers)?;
+        println!("Updated DNS settings");
+        Ok(())
+    }
+
+    fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let servers = rpc
+            .get_settings()?
+            .tunnel_options
+            .generic
+            .custom_dns_servers;
+        if servers.is_empty() {
+            println!("DNS servers: default");
+        } else {
+            println!(
+                "DNS servers: {}",
+                servers
+                    .iter()
+                    .map(|server| server.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn create_blocking_subcommand() -> clap::App<'static, 'static> {
+    let categories: Vec<&'static str> = BlockingCategory::ALL
+        .iter()
+        .map(|category| category.name())
+        .collect();
+
+    clap::SubCommand::with_name("blocking")
+        .about(
+            "Block ads, trackers or malware through a local DNS resolver. Domains are read from \
+             <category>.txt in the dns-blocklists directory in the settings directory",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .about("Enable or disable blocking of a category of domains")
+                .arg(
+                    clap::Arg::with_name("category")
+                        .required(true)
+                        .possible_values(&categories),
+                )
+                .arg(
+                    clap::Arg::with_name("policy")
+                        .required(true)
+                        .possible_values(&["on", "off"]),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("get")
+                .about("Display the blocked categories and the number of blocked queries"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("reload").about("Read the blocklists from disk again"),
+        )
+}
+
+#[cfg(target_os = "linux")]
+fn create_encrypted_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("encrypted")
+        .about("Send DNS queries over encrypted connections instead of as plain DNS")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("add")
+                .about(
+                    "Add an encrypted DNS server. Servers are tried in the order they were \
+                     added",
+                )
+                .arg(
+                    clap::Arg::with_name("protocol")
+                        .required(true)
+                        .possible_values(&["https", "tls"]),
+                )
+                .arg(
+                    clap::Arg::with_name("address")
+                        .help("The IP address of the server")
+                        .required(true),
+                )
+                .arg(
+                    clap::Arg::with_name("hostname")
+                        .help("The name that the certificate of the server is valid for")
+                        .required(true),
+                )
+                .arg(
+                    clap::Arg::with_name("port")
+                        .help("The port of the server. Defaults to 443 for https and 853 for tls")
+                        .long("port")
+                        .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("path")
+                        .help("The path to send queries to with https. Defaults to /dns-query")
+                        .long("path")
+                        .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("pin")
+                        .help(
+                            "Only trust the server certificate with this hex encoded SHA-256 \
+                             fingerprint. Can be given multiple times",
+                        )
+                        .long("pin")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("clear")
+                .about("Remove all encrypted DNS servers and use plain DNS again"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("fallback")
+                .about("Set whether plain DNS may be used if no encrypted DNS server responds")
+                .arg(
+                    clap::Arg::with_name("policy")
+                        .required(true)
+                        .possible_values(&["on", "off"]),
+                ),
+        )
+        .subcommand(clap::SubCommand::with_name("get").about("Display the encrypted DNS settings"))
+}
+
+#[cfg(target_os = "linux")]
+fn create_split_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("split")
+        .about(
+            "Send DNS queries for some domains to other DNS servers than the ones used in the \
+             tunnel. Queries are sent outside the tunnel if the servers are not reached through it",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("add")
+                .about("Send queries for a domain and its subdomains to the given servers")
+                .arg(clap::Arg::with_name("domain").required(true))
+                .arg(
+                    clap::Arg::with_name("servers")
+                        .multiple(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("remove")
+                .about("Remove the rule for a domain")
+                .arg(clap::Arg::with_name("domain").required(true)),
+        )
+        .subcommand(clap::SubCommand::with_name("clear").about("Remove all split DNS rules"))
+        .subcommand(clap::SubCommand::with_name("list").about("Display the split DNS rules"))
+}
+
+#[cfg(target_os = "linux")]
+fn create_backend_subcommand() -> clap::App<'static, 'static> {
+    let mut backends: Vec<&'static str> = DnsBackend::ALL
+        .iter()
+        .map(|backend| backend.name())
+        .collect();
+    backends.push("auto");
+
+    clap::SubCommand::with_name("backend")
+        .about("Control how the system DNS settings are changed")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .about(
+                    "Always change the DNS settings through the given backend, or detect which \
+                     one to use with 'auto'",
+                )
+                .arg(
+                    clap::Arg::with_name("backend")
+                        .required(true)
+                        .possible_values(&backends),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("get")
+                .about("Display which backends can be used, and which one is used"),
+        )
+}
+
+#[cfg(target_os = "linux")]
+impl Dns {
+    fn handle_blocking(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("set", Some(set_matches)) => {
+                let category = value_t_or_exit!(set_matches.value_of("category"), String);
+                let category = *BlockingCategory::ALL
+                    .iter()
+                    .find(|candidate| candidate.name() == category)
+                    .unwrap();
+                let policy = value_t_or_exit!(set_matches.value_of("policy"), String);
+                self.set_blocking(category, policy == "on")
+            }
+            ("get", Some(_)) => self.get_blocking(),
+            ("reload", Some(_)) => self.reload_blocklists(),
+            _ => unreachable!("unhandled subcommand"),
+        }
+    }
+
+    fn set_blocking(&self, category: BlockingCategory, enabled: bool) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut categories = rpc.get_settings()?.tunnel_options.generic.dns_blocking;
+        categories.set(category, enabled);
+        rpc.set_dns_blocking(categories)?;
+        println!("Updated DNS blocking settings");
+        Ok(())
+    }
+
+    fn get_blocking(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let categories = rpc.get_settings()?.tunnel_options.generic.dns_blocking;
+        let stats = rpc.get_dns_blocking_stats()?;
+        println!("Blocking: {}", categories);
+        println!("Queries: {}", stats.queries);
+        println!("Blocked queries: {}", stats.blocked);
+        Ok(())
+    }
+
+    fn reload_blocklists(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.reload_dns_blocklists()?;
+        println!("Reloaded DNS blocklists");
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Dns {
+    fn handle_encrypted(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("add", Some(add_matches)) => {
+                let protocol =
+                    match value_t_or_exit!(add_matches.value_of("protocol"), String).as_str() {
+                        "https" => EncryptedDnsProtocol::Https,
+                        "tls" => EncryptedDnsProtocol::Tls,
+                        _ => unreachable!("invalid protocol"),
+                    };
+                let port = match value_t!(add_matches.value_of("port"), u16) {
+                    Ok(port) => Some(port),
+                    Err(e) => match e.kind {
+                        clap::ErrorKind::ArgumentNotFound => None,
+                        _ => e.exit(),
+                    },
+                };
+                let server = EncryptedDnsServer {
+                    protocol,
+                    address: value_t_or_exit!(add_matches.value_of("address"), IpAddr),
+                    port,
+                    hostname: value_t_or_exit!(add_matches.value_of("hostname"), String),
+                    path: add_matches.value_of("path").map(str::to_owned),
+                    pinned_certificates: add_matches
+                        .values_of("pin")
+                        .map(|pins| pins.map(str::to_owned).collect())
+                        .unwrap_or_default(),
+                };
+                self.add_encrypted_server(server)
+            }
+            ("clear", Some(_)) => self.clear_encrypted_servers(),
+            ("fallback", Some(fallback_matches)) => {
+                let policy = value_t_or_exit!(fallback_matches.value_of("policy"), String);
+                self.set_encrypted_fallback(policy == "on")
+            }
+            ("get", Some(_)) => self.get_encrypted(),
+            _ => unreachable!("unhandled subcommand"),
+        }
+    }
+
+    fn add_encrypted_server(&self, server: EncryptedDnsServer) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut encrypted_dns = rpc.get_settings()?.tunnel_options.generic.encrypted_dns;
+        encrypted_dns.servers.push(server);
+        rpc.set_encrypted_dns(encrypted_dns)?;
+        println!("Added encrypted DNS server");
+        Ok(())
+    }
+
+    fn clear_encrypted_servers(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut encrypted_dns = rpc.get_settings()?.tunnel_options.generic.encrypted_dns;
+        encrypted_dns.servers.clear();
+        rpc.set_encrypted_dns(encrypted_dns)?;
+        println!("Removed all encrypted DNS servers");
+        Ok(())
+    }
+
+    fn set_encrypted_fallback(&self, allow_plaintext_fallback: bool) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut encrypted_dns = rpc.get_settings()?.tunnel_options.generic.encrypted_dns;
+        encrypted_dns.allow_plaintext_fallback = allow_plaintext_fallback;
+        rpc.set_encrypted_dns(encrypted_dns)?;
+        println!("Updated encrypted DNS settings");
+        Ok(())
+    }
+
+    fn get_encrypted(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let encrypted_dns = rpc.get_settings()?.tunnel_options.generic.encrypted_dns;
+        if encrypted_dns.servers.is_empty() {
+            println!("Encrypted DNS servers: none");
+        } else {
+            println!("Encrypted DNS servers:");
+            for server in &encrypted_dns.servers {
+                println!("\t{}", server);
+                for pin in &server.pinned_certificates {
+                    println!("\t\tPinned certificate: {}", pin);
+                }
+            }
+        }
+        println!(
+            "Plain DNS fallback: {}",
+            if encrypted_dns.allow_plaintext_fallback {
+                "on"
+            } else {
+                "off"
+            }
+        );
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Dns {
+    fn handle_split(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("add", Some(add_matches)) => {
+                let domain = value_t_or_exit!(add_matches.value_of("domain"), String);
+                let servers = values_t!(add_matches.values_of("servers"), IpAddr)
+                    .unwrap_or_else(|e| e.exit());
+                self.add_split_rule(SplitDnsRule {
+                    domain: normalize_domain(&domain),
+                    servers,
+                })
+            }
+            ("remove", Some(remove_matches)) => {
+                let domain = value_t_or_exit!(remove_matches.value_of("domain"), String);
+                self.remove_split_rule(&normalize_domain(&domain))
+            }
+            ("clear", Some(_)) => self.clear_split_rules(),
+            ("list", Some(_)) => self.list_split_rules(),
+            _ => unreachable!("unhandled subcommand"),
+        }
+    }
+
+    fn add_split_rule(&self, rule: SplitDnsRule) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut rules = rpc.get_settings()?.tunnel_options.generic.split_dns;
+        rules.retain(|existing_rule| existing_rule.domain != rule.domain);
+        rules.push(rule);
+        rpc.set_split_dns(rules)?;
+        println!("Added split DNS rule");
+        Ok(())
+    }
+
+    fn remove_split_rule(&self, domain: &str) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut rules = rpc.get_settings()?.tunnel_options.generic.split_dns;
+        let rule_count = rules.len();
+        rules.retain(|rule| rule.domain != domain);
+        if rules.len() == rule_count {
+            println!("No split DNS rule for {}", domain);
+        } else {
+            rpc.set_split_dns(rules)?;
+            println!("Removed split DNS rule");
+        }
+        Ok(())
+    }
+
+    fn clear_split_rules(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_split_dns(vec![])?;
+        println!("Removed all split DNS rules");
+        Ok(())
+    }
+
+    fn list_split_rules(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let rules = rpc.get_settings()?.tunnel_options.generic.split_dns;
+        if rules.is_empty() {
+            println!("Split DNS rules: none");
+        } else {
+            println!("Split DNS rules:");
+            for rule in &rules {
+                println!("\t{}", rule);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn normalize_domain(domain: &str) -> String {
+    domain.trim_matches('.').to_lowercase()
+}
+
+#[cfg(target_os = "linux")]
+impl Dns {
+    fn handle_backend(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("set", Some(set_matches)) => {
+                let backend = value_t_or_exit!(set_matches.value_of("backend"), String);
+                let backend = DnsBackend::ALL
+                    .iter()
+                    .find(|candidate| candidate.name() == backend)
+                    .cloned();
+                self.set_backend(backend)
+            }
+            ("get", Some(_)) => self.get_backends(),
+            _ => unreachable!("unhandled subcommand"),
+        }
+    }
+
+    fn set_backend(&self, backend: Option<DnsBackend>) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_dns_backend(backend)?;
+        println!("Updated DNS backend setting");
+        Ok(())
+    }
+
+    fn get_backends(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let backends = rpc.get_dns_backends()?;
+        match backends.forced {
+            Some(backend) => println!("Backend setting: {}", backend),
+            None => println!("Backend setting: auto"),
+        }
+        match backends.selected {
+            Some(backend) => println!("Selected backend: {}", backend),
+            None => println!("Selected backend: none"),
+        }
+        println!("Backends:");
+        for candidate in &backends.candidates {
+            match candidate.rejection_reason {
+                Some(ref reason) => println!("\t{}: unusable: {}", candidate.backend, reason),
+                None => println!("\t{}: usable", candidate.backend),
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use crate::{new_rpc_client, Command, Result};
+use clap::{value_t, value_t_or_exit};
+use talpid_types::net::{
+    firewall::{AllowRule, Direction, PortRange},
+    TransportProtocol,
+};
+
+pub struct Firewall;
+
+impl Command for Firewall {
+    fn name(&self) -> &'static str {
+        "firewall"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Control the firewall")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(create_allow_subcommand())
+    }
+
+    fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("allow", Some(allow_matches)) => self.handle_allow(allow_matches),
+            _ => unreachable!("unhandled subcommand"),
+        }
+    }
+}
+
+fn create_allow_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("allow")
+        .about(
+            "Manage traffic that the firewall always allows, even when connecting or blocking. \
+             Replies to the allowed connections are allowed too. DNS is still blocked outside \
+             the tunnel",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("add")
+                .about("Add an allow rule")
+                .arg(
+                    clap::Arg::with_name("direction")
+                        .help("Allow connections to the network (out) or from it (in)")
+                        .required(true)
+                        .possible_values(&["in", "out"]),
+                )
+                .arg(
+                    clap::Arg::with_name("network")
+                        .help("The remote IP address or network, in CIDR notation")
+                        .required(true),
+                )
+                .arg(
+                    clap::Arg::with_name("protocol")
+                        .help("Only allow this transport protocol")
+                        .long("protocol")
+                        .takes_value(true)
+                        .possible_values(&["tcp", "udp"]),
+                )
+                .arg(
+                    clap::Arg::with_name("port")
+                        .help(
+                            "Only allow connections to this port or port range, such as 8000-8100. \
+                             Allows both TCP and UDP unless --protocol is given",
+                        )
+                        .long("port")
+                        .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("interface")
+                        .help("Only allow the traffic on this network interface")
+                        .long("interface")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("remove")
+                .about("Remove an allow rule")
+                .arg(
+                    clap::Arg::with_name("index")
+                        .help("The number of the rule, as shown by 'list'")
+                        .required(true),
+                ),
+        )
+        .subcommand(clap::SubCommand::with_name("clear").about("Remove all allow rules"))
+        .subcommand(clap::SubCommand::with_name("list").about("Display the allow rules"))
+}
+
+impl Firewall {
+    fn handle_allow(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("add", Some(add_matches)) => {
+                let direction =
+                    match value_t_or_exit!(add_matches.value_of("direction"), String).as_str() {
+                        "in" => Direction::In,
+                        "out" => Direction::Out,
+                        _ => unreachable!("invalid direction"),
+                    };
+                let network =
+                    value_t_or_exit!(add_matches.value_of("network"), ipnetwork::IpNetwork);
+                let protocol = match value_t!(add_matches.value_of("protocol"), TransportProtocol) {
+                    Ok(protocol) => Some(protocol),
+                    Err(e) => match e.kind {
+                        clap::ErrorKind::ArgumentNotFound => None,
+                        _ => e.exit(),
+                    },
+                };
+                let ports = match value_t!(add_matches.value_of("port"), PortRange) {
+                    Ok(ports) => Some(ports),
+                    Err(e) => match e.kind {
+                        clap::ErrorKind::ArgumentNotFound => None,
+                        _ => e.exit(),
+                    },
+                };
+                let interface = add_matches.value_of("interface").map(str::to_owned);
+                self.add_allow_rule(AllowRule {
+                    direction,
+                    network,
+                    protocol,
+                    ports,
+                    interface,
+                })
+            }
+            ("remove", Some(remove_matches)) => {
+                let index = value_t_or_exit!(remove_matches.value_of("index"), usize);
+                self.remove_allow_rule(index)
+            }
+            ("clear", Some(_)) => self.clear_allow_rules(),
+            ("list", Some(_)) => self.list_allow_rules(),
+            _ => unreachable!("unhandled subcommand"),
+        }
+    }
+
+    fn add_allow_rule(&self, rule: AllowRule) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut rules = rpc.get_settings()?.firewall_allow_rules;
+        if rules.contains(&rule) {
+            println!("The rule already exists");
+            return Ok(());
+        }
+        rules.push(rule);
+        rpc.set_firewall_allow_rules(rules)?;
+        println!("Added allow rule");
+        Ok(())
+    }
+
+    fn remove_allow_rule(&self, index: usize) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut rules = rpc.get_settings()?.firewall_allow_rules;
+        if index == 0 || index > rules.len() {
+            println!("No allow rule number {}", index);
+        } else {
+            let rule = rules.remove(index - 1);
+            rpc.set_firewall_allow_rules(rules)?;
+            println!("Removed allow rule: {}", rule);
+        }
+        Ok(())
+    }
+
+    fn clear_allow_rules(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_firewall_allow_rules(vec![])?;
+        println!("Removed all allow rules");
+        Ok(())
+    }
+
+    fn list_allow_rules(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let rules = rpc.get_settings()?.firewall_allow_rules;
+        if rules.is_empty() {
+            println!("Allow rules: none");
+        } else {
+            println!("Allow rules:");
+            for (index, rule) in rules.iter().enumerate() {
+                println!("\t{}: {}", index + 1, rule);
+            }
+        }
+        Ok(())
+    }
+}
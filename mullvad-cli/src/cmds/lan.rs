@@ -1,5 +1,7 @@
 use crate::{new_rpc_client, Command, Result};
 use clap::value_t_or_exit;
+use ipnetwork::IpNetwork;
+use talpid_types::net::firewall::LanNetworks;
 
 pub struct Lan;
 
@@ -25,6 +27,7 @@ impl Command for Lan {
                 clap::SubCommand::with_name("get")
                     .about("Display the current local network sharing setting"),
             )
+            .subcommand(create_networks_subcommand())
     }
 
     fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
@@ -33,12 +36,48 @@ impl Command for Lan {
             self.set(allow_lan == "allow")
         } else if let Some(_matches) = matches.subcommand_matches("get") {
             self.get()
+        } else if let Some(networks_matches) = matches.subcommand_matches("networks") {
+            self.handle_networks(networks_matches)
         } else {
             unreachable!("No lan command given");
         }
     }
 }
 
+fn create_networks_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("networks")
+        .about(
+            "Manage the networks that local network sharing applies to. Added networks are \
+             routed outside the tunnel",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("add")
+                .about("Treat a network as local")
+                .arg(
+                    clap::Arg::with_name("network")
+                        .help("The network, in CIDR notation")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("remove")
+                .about("Stop treating a network as local. Can also remove the default networks")
+                .arg(
+                    clap::Arg::with_name("network")
+                        .help("The network, in CIDR notation")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("reset").about("Only treat the default networks as local"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("list")
+                .about("Display the changes to the default local networks"),
+        )
+}
+
 impl Lan {
     fn set(&self, allow_lan: bool) -> Result<()> {
         let mut rpc = new_rpc_client()?;
@@ -47,6 +86,47 @@ impl Lan {
         Ok(())
     }
 
+    fn handle_networks(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("add", Some(add_matches)) => {
+                let network = value_t_or_exit!(add_matches.value_of("network"), IpNetwork);
+                self.update_networks(|lan_networks| lan_networks.add(network))?;
+                println!("Added local network {}", network);
+                Ok(())
+            }
+            ("remove", Some(remove_matches)) => {
+                let network = value_t_or_exit!(remove_matches.value_of("network"), IpNetwork);
+                self.update_networks(|lan_networks| lan_networks.remove(network))?;
+                println!("Removed local network {}", network);
+                Ok(())
+            }
+            ("reset", Some(_)) => {
+                let mut rpc = new_rpc_client()?;
+                rpc.set_lan_networks(LanNetworks::default())?;
+                println!("Reset the local networks to the defaults");
+                Ok(())
+            }
+            ("list", Some(_)) => self.list_networks(),
+            _ => unreachable!("unhandled subcommand"),
+        }
+    }
+
+    fn update_networks(&self, update: impl FnOnce(&mut LanNetworks)) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut lan_networks = rpc.get_settings()?.lan_networks;
+        update(&mut lan_networks);
+        rpc.set_lan_networks(lan_networks)?;
+        Ok(())
+    }
+
+    fn list_networks(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let lan_networks = rpc.get_settings()?.lan_networks;
+        print_networks("Added local networks", &lan_networks.added);
+        print_networks("Removed default local networks", &lan_networks.removed);
+        Ok(())
+    }
+
     fn get(&self) -> Result<()> {
         let mut rpc = new_rpc_client()?;
         let allow_lan = rpc.get_settings()?.allow_lan;
@@ -57,3 +137,14 @@ impl Lan {
         Ok(())
     }
 }
+
+fn print_networks(title: &str, networks: &[IpNetwork]) {
+    if networks.is_empty() {
+        println!("{}: none", title);
+    } else {
+        println!("{}:", title);
+        for network in networks {
+            println!("\t{}", network);
+        }
+    }
+}
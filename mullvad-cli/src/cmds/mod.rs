@@ -19,9 +19,21 @@ pub use self::bridge::Bridge;
 mod connect;
 pub use self::connect::Connect;
 
+mod debug;
+pub use self::debug::Debug;
+
+mod diagnose;
+pub use self::diagnose::Diagnose;
+
 mod disconnect;
 pub use self::disconnect::Disconnect;
 
+mod dns;
+pub use self::dns::Dns;
+
+mod firewall;
+pub use self::firewall::Firewall;
+
 mod lan;
 pub use self::lan::Lan;
 
@@ -57,7 +69,11 @@ pub fn get_commands() -> HashMap<&'static str, Box<dyn Command>> {
         Box::new(BlockWhenDisconnected),
         Box::new(Bridge),
         Box::new(Connect),
+        Box::new(Debug),
+        Box::new(Diagnose),
         Box::new(Disconnect),
+        Box::new(Dns),
+        Box::new(Firewall),
         Box::new(Reconnect),
         Box::new(Lan),
         Box::new(Relay),
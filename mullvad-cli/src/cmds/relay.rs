@@ -1,20 +1,25 @@
 use crate::{location, new_rpc_client, Command, Error, Result};
 use clap::{value_t, values_t};
 use std::{
+    fs,
     io::{self, BufRead},
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
     str::FromStr,
 };
 
 use mullvad_types::{
+    openvpn_profile::OpenVpnProfile,
     relay_constraints::{
-        Constraint, OpenVpnConstraints, RelayConstraintsUpdate, RelaySettingsUpdate,
+        Constraint, OpenVpnConstraints, RelayConstraintsUpdate, RelaySettings, RelaySettingsUpdate,
         WireguardConstraints,
     },
-    ConnectionConfig, CustomTunnelEndpoint,
+    wg_quick::WgQuickConfig,
+    ConnectionConfig, CustomTunnelEndpoint, CustomWireguardPeer,
 };
-use talpid_types::net::{
-    all_of_the_internet, openvpn, wireguard, Endpoint, TransportProtocol, TunnelType,
+use talpid_types::{
+    net::{all_of_the_internet, openvpn, wireguard, Endpoint, TransportProtocol, TunnelType},
+    ErrorExt,
 };
 
 pub struct Relay;
@@ -42,15 +47,29 @@ impl Command for Relay {
                                 .arg(
                                     clap::Arg::with_name("host")
                                         .help("Hostname or IP")
-                                        .required(true)
+                                        .required_unless("config")
                                         .index(1),
                                 )
                                 .arg(
                                     clap::Arg::with_name("port")
                                         .help("Remote network port")
-                                        .required(true)
+                                        .required_unless("config")
                                         .index(2),
                                 )
+                                .arg(
+                                    clap::Arg::with_name("config")
+                                        .help("Read the relay from a wg-quick configuration file")
+                                        .long("config")
+                                        .takes_value(true)
+                                        .conflicts_with_all(&[
+                                            "host",
+                                            "port",
+                                            "peer-key",
+                                            "v4-gateway",
+                                            "v6-gateway",
+                                            "addr",
+                                        ]),
+                                )
                                 .arg(
                                     clap::Arg::with_name("peer-key")
                                         .help("Base64 encoded peer public key")
@@ -83,15 +102,29 @@ impl Command for Relay {
                                 .arg(
                                     clap::Arg::with_name("host")
                                         .help("Hostname or IP")
-                                        .required(true)
+                                        .required_unless("profile")
                                         .index(1),
                                 )
                                 .arg(
                                     clap::Arg::with_name("port")
                                         .help("Remote network port")
-                                        .required(true)
+                                        .required_unless("profile")
                                         .index(2),
                                 )
+                                .arg(
+                                    clap::Arg::with_name("profile")
+                                        .help("Read the relay from an OpenVPN profile. The \
+                                               username and password are read from standard \
+                                               input if the profile uses auth-user-pass")
+                                        .long("profile")
+                                        .takes_value(true)
+                                        .conflicts_with_all(&[
+                                            "host",
+                                            "port",
+                                            "username",
+                                            "password",
+                                        ]),
+                                )
                                 .arg(
                                     clap::Arg::with_name("protocol")
                                         .help("Transport protocol. For Wireguard this is ignored.")
@@ -135,6 +168,37 @@ impl Command for Relay {
                             ),
 
                     )
+                    .subcommand(
+                        clap::SubCommand::with_name("tunnel-options")
+                            .about("Override the global tunnel options for the current relay \
+                                   settings. \"unset\" uses the global option again")
+                            .arg(
+                                clap::Arg::with_name("mtu")
+                                    .long("mtu")
+                                    .help("WireGuard MTU, or \"unset\"")
+                                    .takes_value(true),
+                            )
+                            .arg(
+                                clap::Arg::with_name("mssfix")
+                                    .long("mssfix")
+                                    .help("OpenVPN mssfix, or \"unset\"")
+                                    .takes_value(true),
+                            )
+                            .arg(
+                                clap::Arg::with_name("ipv6")
+                                    .long("ipv6")
+                                    .takes_value(true)
+                                    .possible_values(&["on", "off", "unset"]),
+                            )
+                            .arg(
+                                clap::Arg::with_name("dns")
+                                    .long("dns")
+                                    .help("Custom DNS servers, \"default\" for the DNS of the \
+                                          relay, or \"unset\"")
+                                    .takes_value(true)
+                                    .multiple(true),
+                            ),
+                    )
                     .subcommand(clap::SubCommand::with_name("tunnel-protocol")
                                 .about("Set tunnel protocol")
                                 .arg(
@@ -146,6 +210,40 @@ impl Command for Relay {
                                 ),
             )
             .subcommand(clap::SubCommand::with_name("get"))
+            .subcommand(
+                clap::SubCommand::with_name("custom-peers")
+                    .about("Manage the peers that a custom WireGuard relay falls back on")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(clap::SubCommand::with_name("list"))
+                    .subcommand(
+                        clap::SubCommand::with_name("add")
+                            .about("Add a peer to try after the current ones")
+                            .arg(
+                                clap::Arg::with_name("host")
+                                    .help("Hostname or IP")
+                                    .required(true)
+                                    .index(1),
+                            )
+                            .arg(
+                                clap::Arg::with_name("port")
+                                    .help("Remote network port")
+                                    .required(true)
+                                    .index(2),
+                            )
+                            .arg(
+                                clap::Arg::with_name("peer-key")
+                                    .help("Base64 encoded peer public key")
+                                    .required(true)
+                                    .index(3),
+                            ),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("remove")
+                            .about("Remove a peer by its position in the list")
+                            .arg(clap::Arg::with_name("position").required(true)),
+                    )
+                    .subcommand(clap::SubCommand::with_name("clear")),
+            )
             .subcommand(
                 clap::SubCommand::with_name("list").about("List available countries and cities"),
             )
@@ -160,6 +258,8 @@ impl Command for Relay {
             self.set(set_matches)
         } else if matches.subcommand_matches("get").is_some() {
             self.get()
+        } else if let Some(peers_matches) = matches.subcommand_matches("custom-peers") {
+            self.custom_peers(peers_matches)
         } else if matches.subcommand_matches("list").is_some() {
             self.list()
         } else if matches.subcommand_matches("update").is_some() {
@@ -187,6 +287,8 @@ impl Relay {
             self.set_tunnel(tunnel_matches)
         } else if let Some(tunnel_matches) = matches.subcommand_matches("tunnel-protocol") {
             self.set_tunnel_protocol(tunnel_matches)
+        } else if let Some(options_matches) = matches.subcommand_matches("tunnel-options") {
+            self.set_tunnel_options(options_matches)
         } else {
             unreachable!("No set relay command given");
         }
@@ -194,13 +296,74 @@ impl Relay {
 
     fn set_custom(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
         let custom_endpoint = match matches.subcommand() {
-            ("openvpn", Some(openvpn_matches)) => Self::read_custom_openvpn_relay(openvpn_matches),
-            ("wireguard", Some(wg_matches)) => Self::read_custom_wireguard_relay(wg_matches),
+            ("openvpn", Some(openvpn_matches)) => {
+                if let Some(path) = openvpn_matches.value_of("profile") {
+                    return self.set_custom_openvpn_profile(Path::new(path));
+                }
+                Self::read_custom_openvpn_relay(openvpn_matches)
+            }
+            ("wireguard", Some(wg_matches)) => {
+                if let Some(path) = wg_matches.value_of("config") {
+                    return self.set_custom_wireguard_config(path);
+                }
+                Self::read_custom_wireguard_relay(wg_matches)
+            }
             (_unknown_tunnel, _) => unreachable!("No set relay command given"),
         };
         self.update_constraints(RelaySettingsUpdate::CustomTunnelEndpoint(custom_endpoint))
     }
 
+    fn set_custom_wireguard_config(&self, path: &str) -> Result<()> {
+        let config = fs::read_to_string(path).unwrap_or_else(|e| {
+            eprintln!("Failed to read {}: {}", path, e);
+            std::process::exit(1);
+        });
+        let config = WgQuickConfig::parse(&config).unwrap_or_else(|e| {
+            eprintln!("Invalid wg-quick configuration: {}", e);
+            std::process::exit(1);
+        });
+
+        self.update_constraints(RelaySettingsUpdate::CustomTunnelEndpoint(
+            config.to_custom_tunnel_endpoint(),
+        ))
+    }
+
+    fn set_custom_openvpn_profile(&self, path: &Path) -> Result<()> {
+        let profile = fs::read_to_string(path).unwrap_or_else(|e| {
+            eprintln!("Failed to read {}: {}", path.display(), e);
+            std::process::exit(1);
+        });
+        // Files referenced by the profile are relative to the directory of the profile.
+        let profile_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let profile =
+            OpenVpnProfile::parse(&profile, |file| fs::read_to_string(profile_dir.join(file)))
+                .unwrap_or_else(|e| {
+                    eprintln!("Invalid OpenVPN profile: {}", e.display_chain());
+                    std::process::exit(1);
+                });
+
+        let (username, password) = if profile.requires_credentials {
+            (
+                Self::read_line("Reading username from standard input"),
+                Self::read_line("Reading password from standard input"),
+            )
+        } else {
+            (String::new(), String::new())
+        };
+
+        let mut rpc = new_rpc_client()?;
+        rpc.set_openvpn_profile(profile, username, password)?;
+        println!("Relay constraints updated");
+        Ok(())
+    }
+
+    fn read_line(prompt: &str) -> String {
+        let mut line = String::new();
+        println!("{}", prompt);
+        let _ = io::stdin().lock().read_line(&mut line);
+        line.trim_end_matches(&['\r', '\n'][..]).to_owned()
+    }
+
     fn read_custom_openvpn_relay(matches: &clap::ArgMatches<'_>) -> CustomTunnelEndpoint {
         let host = value_t!(matches.value_of("host"), String).unwrap_or_else(|e| e.exit());
         let port = value_t!(matches.value_of("port"), u16).unwrap_or_else(|e| e.exit());
@@ -214,6 +377,7 @@ impl Relay {
                 endpoint: Endpoint::new(Ipv4Addr::UNSPECIFIED, port, protocol),
                 username,
                 password,
+                profile: None,
             }),
         )
     }
@@ -254,6 +418,8 @@ impl Relay {
                     public_key: peer_public_key,
                     allowed_ips: all_of_the_internet(),
                     endpoint: SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port),
+                    psk: None,
+                    persistent_keepalive: None,
                 },
                 ipv4_gateway,
                 ipv6_gateway,
@@ -331,6 +497,43 @@ impl Relay {
         }))
     }
 
+    fn set_tunnel_options(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let relay_settings = rpc.get_settings()?.get_relay_settings();
+        let mut tunnel_options = match &relay_settings {
+            RelaySettings::CustomTunnelEndpoint(relay) => relay.tunnel_options().clone(),
+            RelaySettings::Normal(constraints) => constraints.tunnel_options.clone(),
+        };
+
+        if let Some(mtu) = matches.value_of("mtu") {
+            tunnel_options.wireguard_mtu = parse_override(mtu, "Invalid MTU")?;
+        }
+        if let Some(mssfix) = matches.value_of("mssfix") {
+            tunnel_options.openvpn_mssfix = parse_override(mssfix, "Invalid mssfix")?;
+        }
+        if let Some(ipv6) = matches.value_of("ipv6") {
+            tunnel_options.enable_ipv6 = match ipv6 {
+                "on" => Some(true),
+                "off" => Some(false),
+                _ => None,
+            };
+        }
+        if let Some(servers) = matches.values_of("dns") {
+            tunnel_options.custom_dns_servers = parse_dns_override(servers.collect())?;
+        }
+
+        self.update_constraints(match relay_settings {
+            RelaySettings::CustomTunnelEndpoint(mut relay) => {
+                relay.set_tunnel_options(tunnel_options);
+                RelaySettingsUpdate::CustomTunnelEndpoint(relay)
+            }
+            RelaySettings::Normal(_) => RelaySettingsUpdate::Normal(RelayConstraintsUpdate {
+                tunnel_options: Some(tunnel_options),
+                ..Default::default()
+            }),
+        })
+    }
+
     fn get(&self) -> Result<()> {
         let mut rpc = new_rpc_client()?;
         let constraints = rpc.get_settings()?.get_relay_settings();
@@ -339,6 +542,64 @@ impl Relay {
         Ok(())
     }
 
+    fn custom_peers(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut relay = match rpc.get_settings()?.get_relay_settings() {
+            RelaySettings::CustomTunnelEndpoint(relay)
+                if relay.tunnel_type() == TunnelType::Wireguard =>
+            {
+                relay
+            }
+            _ => {
+                return Err(Error::InvalidCommand(
+                    "The relay is not a custom WireGuard relay",
+                ))
+            }
+        };
+        let mut peers = relay.extra_wireguard_peers().to_vec();
+
+        match matches.subcommand() {
+            ("list", _) => {
+                if peers.is_empty() {
+                    println!("No extra peers");
+                }
+                for (index, peer) in peers.iter().enumerate() {
+                    println!("{}: {}", index + 1, peer);
+                }
+                return Ok(());
+            }
+            ("add", Some(matches)) => {
+                let host = value_t!(matches.value_of("host"), String).unwrap_or_else(|e| e.exit());
+                let port = value_t!(matches.value_of("port"), u16).unwrap_or_else(|e| e.exit());
+                let peer_key_str =
+                    value_t!(matches.value_of("peer-key"), String).unwrap_or_else(|e| e.exit());
+                peers.push(CustomWireguardPeer {
+                    host,
+                    peer: wireguard::PeerConfig {
+                        public_key: Self::validate_wireguard_key(&peer_key_str).into(),
+                        allowed_ips: all_of_the_internet(),
+                        endpoint: SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port),
+                        psk: None,
+                        persistent_keepalive: None,
+                    },
+                });
+            }
+            ("remove", Some(matches)) => {
+                let position =
+                    value_t!(matches.value_of("position"), usize).unwrap_or_else(|e| e.exit());
+                if position == 0 || position > peers.len() {
+                    return Err(Error::InvalidCommand("No peer at that position"));
+                }
+                peers.remove(position - 1);
+            }
+            ("clear", _) => peers.clear(),
+            _ => unreachable!("unhandled command"),
+        }
+
+        relay.set_extra_wireguard_peers(peers);
+        self.update_constraints(RelaySettingsUpdate::CustomTunnelEndpoint(relay))
+    }
+
     fn list(&self) -> Result<()> {
         let mut rpc = new_rpc_client()?;
         let mut locations = rpc.get_relay_locations()?;
@@ -420,6 +681,34 @@ fn parse_port_constraint(raw_port: &str) -> Result<Constraint<u16>> {
     }
 }
 
+/// Parses an overridden tunnel option, where "unset" removes the override.
+fn parse_override(raw_value: &str, error: &'static str) -> Result<Option<u16>> {
+    match raw_value {
+        "unset" => Ok(None),
+        value => Ok(Some(
+            u16::from_str(value).map_err(|_| Error::InvalidCommand(error))?,
+        )),
+    }
+}
+
+/// Parses overridden DNS servers, where "unset" removes the override and "default" uses the DNS
+/// of the relay.
+fn parse_dns_override(raw_values: Vec<&str>) -> Result<Option<Vec<IpAddr>>> {
+    match raw_values.as_slice() {
+        ["unset"] => Ok(None),
+        ["default"] => Ok(Some(vec![])),
+        servers => servers
+            .iter()
+            .map(|server| {
+                server
+                    .parse()
+                    .map_err(|_| Error::InvalidCommand("Invalid DNS server"))
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(Some),
+    }
+}
+
 /// Parses a protocol constraint string. Can be infallible because the possible values are limited
 /// with clap.
 fn parse_protocol_constraint(raw_protocol: &str) -> Constraint<TransportProtocol> {
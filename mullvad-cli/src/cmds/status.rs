@@ -1,8 +1,13 @@
 use crate::{new_rpc_client, Command, Error, Result};
 use futures::{Future, Stream};
 use mullvad_ipc_client::DaemonRpcClient;
-use mullvad_types::{auth_failed::AuthFailed, states::TunnelState, DaemonEvent};
-use talpid_types::tunnel::{ErrorState, ErrorStateCause};
+use mullvad_types::{
+    auth_failed::AuthFailed, settings::TunnelOptions, states::TunnelState, DaemonEvent,
+};
+use talpid_types::{
+    net::TunnelType,
+    tunnel::{ErrorState, ErrorStateCause},
+};
 
 pub struct Status;
 
@@ -20,6 +25,12 @@ impl Command for Status {
                     .short("l")
                     .help("Prints the current location and IP. Based on GeoIP lookups"),
             )
+            .arg(
+                clap::Arg::with_name("stats")
+                    .long("stats")
+                    .short("s")
+                    .help("Prints the traffic counters and round-trip time of the tunnel"),
+            )
             .subcommand(
                 clap::SubCommand::with_name("listen")
                     .about("Listen for VPN tunnel state changes")
@@ -36,9 +47,13 @@ impl Command for Status {
         let state = rpc.get_state()?;
 
         print_state(&state);
+        print_tunnel_options(&mut rpc, &state)?;
         if matches.is_present("location") {
             print_location(&mut rpc)?;
         }
+        if matches.is_present("stats") {
+            print_tunnel_stats(&mut rpc)?;
+        }
 
         if let Some(listen_matches) = matches.subcommand_matches("listen") {
             let verbose = listen_matches.is_present("verbose");
@@ -50,9 +65,10 @@ impl Command for Status {
                 match event? {
                     DaemonEvent::TunnelState(new_state) => {
                         print_state(&new_state);
+                        print_tunnel_options(&mut rpc, &new_state)?;
                         use self::TunnelState::*;
                         match new_state {
-                            Connected { .. } | Disconnected => {
+                            Connected { .. } | Disconnected { .. } => {
                                 if matches.is_present("location") {
                                     print_location(&mut rpc)?;
                                 }
@@ -92,15 +108,81 @@ fn print_state(state: &TunnelState) {
     print!("Tunnel status: ");
     match state {
         Error(reason) => print_error_state(reason),
-        Connected { endpoint, .. } => {
-            println!("Connected to {}", endpoint);
+        Connected {
+            endpoint, roamed, ..
+        } => {
+            if *roamed {
+                println!("Connected to {}, roamed to a new default route", endpoint);
+            } else {
+                println!("Connected to {}", endpoint);
+            }
+            if let Some(mtu) = endpoint.mtu {
+                println!("Tunnel MTU: {}", mtu);
+            }
         }
         Connecting { endpoint, .. } => println!("Connecting to {}...", endpoint),
-        Disconnected => println!("Disconnected"),
+        Disconnected {
+            paused_until: Some(paused_until),
+        } => println!(
+            "Disconnected, connecting again at {}",
+            paused_until.with_timezone(&chrono::offset::Local)
+        ),
+        Disconnected { paused_until: None } => println!("Disconnected"),
         Disconnecting(_) => println!("Disconnecting..."),
     }
 }
 
+/// Prints the tunnel options in effect for the relay settings, with the overrides of the relay
+/// applied.
+fn print_tunnel_options(rpc: &mut DaemonRpcClient, state: &TunnelState) -> Result<()> {
+    let tunnel_type = match state {
+        TunnelState::Connected { endpoint, .. } | TunnelState::Connecting { endpoint, .. } => {
+            endpoint.tunnel_type
+        }
+        _ => return Ok(()),
+    };
+    let tunnel_options = rpc.get_settings()?.effective_tunnel_options();
+    println!(
+        "Tunnel options: {}",
+        format_tunnel_options(&tunnel_options, tunnel_type)
+    );
+    Ok(())
+}
+
+fn format_tunnel_options(tunnel_options: &TunnelOptions, tunnel_type: TunnelType) -> String {
+    let size_option = match tunnel_type {
+        TunnelType::Wireguard => match tunnel_options.wireguard.mtu {
+            Some(mtu) => format!("MTU {}", mtu),
+            None if tunnel_options.wireguard.automatic_mtu => "automatic MTU".to_owned(),
+            None => "default MTU".to_owned(),
+        },
+        TunnelType::OpenVpn => match tunnel_options.openvpn.mssfix {
+            Some(mssfix) => format!("mssfix {}", mssfix),
+            None => "default mssfix".to_owned(),
+        },
+    };
+    let ipv6 = if tunnel_options.generic.enable_ipv6 {
+        "on"
+    } else {
+        "off"
+    };
+    let dns = if tunnel_options.generic.custom_dns_servers.is_empty() {
+        "default DNS".to_owned()
+    } else {
+        format!(
+            "DNS {}",
+            tunnel_options
+                .generic
+                .custom_dns_servers
+                .iter()
+                .map(|server| server.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        )
+    };
+    format!("{}, IPv6 {}, {}", size_option, ipv6, dns)
+}
+
 fn print_error_state(error_state: &ErrorState) {
     if !error_state.is_blocking() {
         eprintln!("Mullvad daemon failed to setup firewall rules!");
@@ -128,6 +210,22 @@ fn print_blocked_reason(reason: &ErrorStateCause) {
     }
 }
 
+fn print_tunnel_stats(rpc: &mut DaemonRpcClient) -> Result<()> {
+    let stats = match rpc.get_tunnel_stats()? {
+        Some(stats) => stats,
+        None => {
+            println!("Tunnel stats unavailable");
+            return Ok(());
+        }
+    };
+    println!("Bytes sent: {}", stats.tx_bytes);
+    println!("Bytes received: {}", stats.rx_bytes);
+    if let Some(round_trip_time) = stats.round_trip_time {
+        println!("Round-trip time: {} ms", round_trip_time.as_millis());
+    }
+    Ok(())
+}
+
 fn print_location(rpc: &mut DaemonRpcClient) -> Result<()> {
     let location = match rpc.get_current_location()? {
         Some(loc) => loc,
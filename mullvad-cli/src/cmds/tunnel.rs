@@ -1,7 +1,11 @@
 use crate::{new_rpc_client, Command, Result};
-use clap::value_t;
+use clap::{value_t, values_t};
 
 use mullvad_types::settings::TunnelOptions;
+use talpid_types::net::{
+    openvpn::{self, DataCipher, ExtraDirective},
+    wireguard::ConnectivityProbe,
+};
 
 pub struct Tunnel;
 
@@ -37,6 +41,8 @@ fn create_wireguard_subcommand() -> clap::App<'static, 'static> {
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(create_wireguard_mtu_subcommand())
         .subcommand(create_wireguard_keys_subcommand())
+        .subcommand(create_wireguard_connectivity_check_subcommand())
+        .subcommand(create_wireguard_export_subcommand())
 }
 
 fn create_wireguard_mtu_subcommand() -> clap::App<'static, 'static> {
@@ -46,7 +52,14 @@ fn create_wireguard_mtu_subcommand() -> clap::App<'static, 'static> {
         .subcommand(clap::SubCommand::with_name("get"))
         .subcommand(clap::SubCommand::with_name("unset"))
         .subcommand(
-            clap::SubCommand::with_name("set").arg(clap::Arg::with_name("mtu").required(true)),
+            clap::SubCommand::with_name("set").arg(
+                clap::Arg::with_name("mtu")
+                    .help(
+                        "The MTU to use, or \"auto\" to discover it from the path to the \
+                         relay. Discovery is only supported on Linux",
+                    )
+                    .required(true),
+            ),
         )
 }
 
@@ -70,12 +83,98 @@ fn create_wireguard_keys_rotation_interval_subcommand() -> clap::App<'static, 's
         )
 }
 
+fn create_wireguard_connectivity_check_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("connectivity-check")
+        .about("Configure how connectivity is verified inside the wireguard tunnel")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(
+            clap::SubCommand::with_name("reset").about("Only use ICMP to check connectivity"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .about("Set the probes to use. A reply to any of them counts as connectivity")
+                .arg(
+                    clap::Arg::with_name("probe")
+                        .help("icmp, tcp:<address>:<port> or http:<address>:<port>")
+                        .required(true)
+                        .multiple(true),
+                ),
+        )
+}
+
+fn create_wireguard_export_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("export")
+        .about("Print a wg-quick config for the current wireguard key and a relay")
+        .arg(
+            clap::Arg::with_name("hostname")
+                .help("Hostname of the relay. Defaults to the last selected relay"),
+        )
+        .arg(
+            clap::Arg::with_name("include-private-key")
+                .help("Include the private key in the config instead of a placeholder")
+                .long("include-private-key"),
+        )
+}
+
 
 fn create_openvpn_subcommand() -> clap::App<'static, 'static> {
     clap::SubCommand::with_name("openvpn")
         .about("Manage options for OpenVPN tunnels")
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(create_openvpn_mssfix_subcommand())
+        .subcommand(create_openvpn_option_subcommand(
+            "ciphers",
+            "Configure the data channel ciphers, in order of preference",
+            "AES-256-GCM, AES-128-GCM or CHACHA20-POLY1305",
+            true,
+        ))
+        .subcommand(create_openvpn_option_subcommand(
+            "tun-mtu",
+            "Configure the MTU of the tunnel device",
+            "The MTU",
+            false,
+        ))
+        .subcommand(create_openvpn_option_subcommand(
+            "fragment",
+            "Configure the maximum size of UDP datagrams",
+            "The size in bytes",
+            false,
+        ))
+        .subcommand(create_openvpn_option_subcommand(
+            "reneg-sec",
+            "Configure how often the data channel key is renegotiated",
+            "The interval in seconds",
+            false,
+        ))
+        .subcommand(create_openvpn_option_subcommand(
+            "directives",
+            "Configure extra directives to pass to OpenVPN",
+            "tcp-nodelay, mute-replay-warnings, \"sndbuf <bytes>\", \"rcvbuf <bytes>\", \
+             \"txqueuelen <length>\", \"replay-window <size>\" or \"hand-window <seconds>\"",
+            true,
+        ))
+}
+
+fn create_openvpn_option_subcommand(
+    name: &'static str,
+    about: &'static str,
+    value_help: &'static str,
+    multiple: bool,
+) -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name(name)
+        .about(about)
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(clap::SubCommand::with_name("unset"))
+        .subcommand(
+            clap::SubCommand::with_name("set").arg(
+                clap::Arg::with_name("value")
+                    .help(value_help)
+                    .required(true)
+                    .multiple(multiple),
+            ),
+        )
 }
 
 fn create_openvpn_mssfix_subcommand() -> clap::App<'static, 'static> {
@@ -107,6 +206,9 @@ impl Tunnel {
     fn handle_openvpn_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
         match matches.subcommand() {
             ("mssfix", Some(mssfix_matches)) => Self::handle_openvpn_mssfix_cmd(mssfix_matches),
+            (option, Some(option_matches)) => {
+                Self::handle_openvpn_option_cmd(option, option_matches)
+            }
             _ => unreachable!("unhandled command"),
         }
     }
@@ -120,6 +222,53 @@ impl Tunnel {
         }
     }
 
+    fn handle_openvpn_option_cmd(option: &str, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut options = rpc.get_settings()?.tunnel_options.openvpn;
+        match matches.subcommand() {
+            ("get", _) => {
+                println!("{}: {}", option, format_openvpn_option(&options, option));
+                return Ok(());
+            }
+            ("unset", _) => match option {
+                "ciphers" => options.data_ciphers.clear(),
+                "tun-mtu" => options.tun_mtu = None,
+                "fragment" => options.fragment = None,
+                "reneg-sec" => options.reneg_sec = None,
+                "directives" => options.extra_directives.clear(),
+                _ => unreachable!("unhandled option"),
+            },
+            ("set", Some(matches)) => match option {
+                "ciphers" => {
+                    options.data_ciphers = values_t!(matches.values_of("value"), DataCipher)
+                        .unwrap_or_else(|e| e.exit())
+                }
+                "tun-mtu" => {
+                    options.tun_mtu =
+                        Some(value_t!(matches.value_of("value"), u16).unwrap_or_else(|e| e.exit()))
+                }
+                "fragment" => {
+                    options.fragment =
+                        Some(value_t!(matches.value_of("value"), u16).unwrap_or_else(|e| e.exit()))
+                }
+                "reneg-sec" => {
+                    options.reneg_sec =
+                        Some(value_t!(matches.value_of("value"), u32).unwrap_or_else(|e| e.exit()))
+                }
+                "directives" => {
+                    options.extra_directives = values_t!(matches.values_of("value"), ExtraDirective)
+                        .unwrap_or_else(|e| e.exit())
+                }
+                _ => unreachable!("unhandled option"),
+            },
+            _ => unreachable!("unhandled command"),
+        }
+
+        rpc.set_openvpn_options(options)?;
+        println!("{} has been updated", option);
+        Ok(())
+    }
+
     fn handle_wireguard_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
         match matches.subcommand() {
             ("mtu", Some(matches)) => match matches.subcommand() {
@@ -143,27 +292,39 @@ impl Tunnel {
                 _ => unreachable!("unhandled command"),
             },
 
+            ("connectivity-check", Some(matches)) => match matches.subcommand() {
+                ("get", _) => Self::process_wireguard_connectivity_probes_get(),
+                ("set", Some(matches)) => Self::process_wireguard_connectivity_probes_set(matches),
+                ("reset", _) => Self::process_wireguard_connectivity_probes_reset(),
+                _ => unreachable!("unhandled command"),
+            },
+
+            ("export", Some(matches)) => Self::process_wireguard_export(matches),
+
             _ => unreachable!("unhandled command"),
         }
     }
 
     fn process_wireguard_mtu_get() -> Result<()> {
         let tunnel_options = Self::get_tunnel_options()?;
-        println!(
-            "mtu: {}",
-            tunnel_options
-                .wireguard
-                .mtu
-                .map(|mtu| mtu.to_string())
-                .unwrap_or_else(|| "unset".to_owned())
-        );
+        let mtu = match tunnel_options.wireguard.mtu {
+            Some(mtu) => mtu.to_string(),
+            None if tunnel_options.wireguard.automatic_mtu => "auto".to_owned(),
+            None => "unset".to_owned(),
+        };
+        println!("mtu: {}", mtu);
         Ok(())
     }
 
     fn process_wireguard_mtu_set(matches: &clap::ArgMatches<'_>) -> Result<()> {
-        let mtu = value_t!(matches.value_of("mtu"), u16).unwrap_or_else(|e| e.exit());
         let mut rpc = new_rpc_client()?;
-        rpc.set_wireguard_mtu(Some(mtu))?;
+        if matches.value_of("mtu") == Some("auto") {
+            rpc.set_wireguard_mtu(None)?;
+            rpc.set_wireguard_automatic_mtu(true)?;
+        } else {
+            let mtu = value_t!(matches.value_of("mtu"), u16).unwrap_or_else(|e| e.exit());
+            rpc.set_wireguard_mtu(Some(mtu))?;
+        }
         println!("Wireguard MTU has been updated");
         Ok(())
     }
@@ -171,10 +332,25 @@ impl Tunnel {
     fn process_wireguard_mtu_unset() -> Result<()> {
         let mut rpc = new_rpc_client()?;
         rpc.set_wireguard_mtu(None)?;
+        rpc.set_wireguard_automatic_mtu(false)?;
         println!("Wireguard MTU has been unset");
         Ok(())
     }
 
+    fn process_wireguard_export(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let include_private_key = matches.is_present("include-private-key");
+        let config = rpc.export_wireguard_config(
+            matches.value_of("hostname").map(str::to_owned),
+            include_private_key,
+        )?;
+        print!("{}", config);
+        if !include_private_key {
+            eprintln!("Replace the PrivateKey placeholder with the private key of the account");
+        }
+        Ok(())
+    }
+
     fn process_wireguard_key_check() -> Result<()> {
         let mut rpc = new_rpc_client()?;
         match rpc.get_wireguard_key()? {
@@ -234,6 +410,37 @@ impl Tunnel {
         Ok(())
     }
 
+    fn process_wireguard_connectivity_probes_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options()?;
+        let probes = &tunnel_options.wireguard.connectivity_probes;
+        if probes.is_empty() {
+            println!("Connectivity probes: {} (default)", ConnectivityProbe::Icmp);
+        } else {
+            let probes = probes
+                .iter()
+                .map(|probe| probe.to_string())
+                .collect::<Vec<_>>();
+            println!("Connectivity probes: {}", probes.join(", "));
+        }
+        Ok(())
+    }
+
+    fn process_wireguard_connectivity_probes_set(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let probes =
+            values_t!(matches.values_of("probe"), ConnectivityProbe).unwrap_or_else(|e| e.exit());
+        let mut rpc = new_rpc_client()?;
+        rpc.set_wireguard_connectivity_probes(probes)?;
+        println!("Connectivity probes have been updated");
+        Ok(())
+    }
+
+    fn process_wireguard_connectivity_probes_reset() -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_wireguard_connectivity_probes(vec![])?;
+        println!("Connectivity probes have been reset");
+        Ok(())
+    }
+
     fn handle_ipv6_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
         if matches.subcommand_matches("get").is_some() {
             Self::process_ipv6_get()
@@ -298,3 +505,28 @@ impl Tunnel {
         Ok(())
     }
 }
+
+fn format_openvpn_option(options: &openvpn::TunnelOptions, option: &str) -> String {
+    fn format_value(value: Option<impl ToString>) -> String {
+        value.map_or_else(|| "unset".to_owned(), |value| value.to_string())
+    }
+    fn format_list<T: ToString>(values: &[T]) -> String {
+        if values.is_empty() {
+            return "unset".to_owned();
+        }
+        values
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    match option {
+        "ciphers" => format_list(&options.data_ciphers),
+        "tun-mtu" => format_value(options.tun_mtu),
+        "fragment" => format_value(options.fragment),
+        "reneg-sec" => format_value(options.reneg_sec),
+        "directives" => format_list(&options.extra_directives),
+        _ => unreachable!("unhandled option"),
+    }
+}
@@ -27,6 +27,10 @@ pub enum Error {
     /// The given command is not correct in some way
     #[error(display = "Invalid command: {}", _0)]
     InvalidCommand(&'static str),
+
+    /// A diagnostics check failed
+    #[error(display = "{} diagnostics failed", _0)]
+    DiagnosticsFailed(&'static str),
 }
 
 pub fn new_rpc_client() -> Result<DaemonRpcClient> {
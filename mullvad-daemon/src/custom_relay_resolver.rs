@@ -0,0 +1,147 @@
+//! Resolves the hosts of custom relays.
+//!
+//! On Linux, hosts are first looked up through the DNS servers of the network, outside the tunnel,
+//! so that lookups also work while connecting or blocking. The servers are recorded while DNS is
+//! not set for a tunnel. Lookups otherwise go through the system resolver, which only works when
+//! the firewall lets them out. The last addresses of every host are remembered, so that a relay
+//! can still be reached when a new lookup fails.
+
+use mullvad_types::{resolve_host, Error as CustomTunnelError};
+use parking_lot::Mutex;
+use std::{collections::HashMap, net::IpAddr, sync::Arc, time::Duration};
+
+/// How often the host of a connected custom relay is looked up again.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Clone, Default)]
+pub struct CustomRelayResolver {
+    addresses: Arc<Mutex<HashMap<String, Vec<IpAddr>>>>,
+    #[cfg(target_os = "linux")]
+    network_servers: Arc<Mutex<Vec<IpAddr>>>,
+}
+
+impl CustomRelayResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the DNS servers of the network, used for lookups outside the tunnel. This must
+    /// be called while DNS is not set for a tunnel.
+    #[cfg(target_os = "linux")]
+    pub fn update_network_servers(&self) {
+        let servers = talpid_core::dns::lookup::network_servers();
+        log::debug!("DNS servers for custom relay lookups: {:?}", servers);
+        *self.network_servers.lock() = servers;
+    }
+
+    /// Returns every address of `host`. The last known addresses are returned if the lookup
+    /// fails.
+    pub fn resolve(&self, host: &str) -> Result<Vec<IpAddr>, CustomTunnelError> {
+        self.resolve_with(host, |host| self.lookup(host))
+    }
+
+    /// Looks up `host` every `REFRESH_INTERVAL`, and returns once it no longer resolves to
+    /// `address`. This is meant for hosts with dynamic DNS.
+    pub async fn wait_for_address_change(self, host: String, address: IpAddr) {
+        loop {
+            tokio02::time::delay_for(REFRESH_INTERVAL).await;
+            match tokio02::task::block_in_place(|| self.lookup(&host)) {
+                Ok(addresses) => {
+                    self.addresses
+                        .lock()
+                        .insert(host.clone(), addresses.clone());
+                    if !addresses.contains(&address) {
+                        log::info!(
+                            "{} no longer resolves to {}, new addresses: {:?}",
+                            host,
+                            address,
+                            addresses
+                        );
+                        return;
+                    }
+                }
+                Err(error) => log::debug!("Failed to refresh the addresses of {}: {}", host, error),
+            }
+        }
+    }
+
+    fn resolve_with(
+        &self,
+        host: &str,
+        lookup: impl FnOnce(&str) -> Result<Vec<IpAddr>, CustomTunnelError>,
+    ) -> Result<Vec<IpAddr>, CustomTunnelError> {
+        match lookup(host) {
+            Ok(addresses) => {
+                self.addresses
+                    .lock()
+                    .insert(host.to_owned(), addresses.clone());
+                Ok(addresses)
+            }
+            Err(error) => match self.addresses.lock().get(host) {
+                Some(addresses) => {
+                    log::warn!(
+                        "Failed to resolve {}, using the last known addresses: {}",
+                        host,
+                        error
+                    );
+                    Ok(addresses.clone())
+                }
+                None => Err(error),
+            },
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn lookup(&self, host: &str) -> Result<Vec<IpAddr>, CustomTunnelError> {
+        let servers = self.network_servers.lock().clone();
+        match talpid_core::dns::lookup::resolve(host, &servers) {
+            Ok(addresses) => Ok(addresses),
+            Err(error) => {
+                log::debug!(
+                    "Failed to resolve {} outside the tunnel, using the system resolver: {}",
+                    host,
+                    error
+                );
+                resolve_host(host)
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn lookup(&self, host: &str) -> Result<Vec<IpAddr>, CustomTunnelError> {
+        resolve_host(host)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn keeps_addresses_when_lookup_fails() {
+        let resolver = CustomRelayResolver::new();
+        let addresses = vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))];
+        let failed_lookup =
+            |host: &str| Err(CustomTunnelError::HostHasNoAddresses(host.to_owned()));
+
+        assert!(resolver
+            .resolve_with("relay.example.com", failed_lookup)
+            .is_err());
+        assert_eq!(
+            resolver
+                .resolve_with("relay.example.com", |_| Ok(addresses.clone()))
+                .unwrap(),
+            addresses
+        );
+        assert_eq!(
+            resolver
+                .resolve_with("relay.example.com", failed_lookup)
+                .unwrap(),
+            addresses
+        );
+        assert!(resolver
+            .resolve_with("other.example.com", failed_lookup)
+            .is_err());
+    }
+}
@@ -6,6 +6,7 @@ extern crate serde;
 
 
 mod account_history;
+mod custom_relay_resolver;
 pub mod exception_logging;
 mod geoip;
 pub mod logging;
@@ -18,6 +19,7 @@ mod settings;
 pub mod version;
 mod version_check;
 
+use chrono::{offset::Utc, DateTime};
 use futures::future::{abortable, AbortHandle};
 use futures01::{
     future::{self, Executor},
@@ -28,21 +30,25 @@ use futures01::{
     },
     Future, Stream,
 };
+use ipnetwork::IpNetwork;
 use log::{debug, error, info, warn};
 use mullvad_rpc::AccountsProxy;
 use mullvad_types::{
     account::{AccountData, AccountToken, VoucherSubmission},
     endpoint::MullvadEndpoint,
-    location::GeoIpLocation,
+    location::{GeoIpLocation, Location},
+    openvpn_profile::{self, OpenVpnProfile},
     relay_constraints::{
         BridgeSettings, BridgeState, Constraint, InternalBridgeConstraints, RelaySettings,
-        RelaySettingsUpdate,
+        RelaySettingsUpdate, WireguardConstraints,
     },
     relay_list::{Relay, RelayList},
     settings::Settings,
     states::{TargetState, TunnelState},
     version::{AppVersion, AppVersionInfo},
+    wg_quick::WgQuickConfig,
     wireguard::KeygenEvent,
+    ConnectionConfig, CustomTunnelEndpoint,
 };
 use settings::SettingsPersister;
 #[cfg(not(target_os = "android"))]
@@ -52,21 +58,32 @@ use std::{
     io,
     marker::PhantomData,
     mem,
+    net::{IpAddr, Ipv4Addr},
     path::PathBuf,
     sync::{mpsc, Arc, Weak},
     time::Duration,
 };
 #[cfg(target_os = "linux")]
-use talpid_core::split_tunnel;
+use talpid_core::{dns::stub_resolver::Blocklists, split_tunnel};
 use talpid_core::{
     mpsc::Sender,
     tunnel_state_machine::{self, TunnelCommand, TunnelParametersGenerator},
 };
 #[cfg(target_os = "android")]
 use talpid_types::android::AndroidContext;
+#[cfg(target_os = "linux")]
+use talpid_types::dns::{DnsBackends, DnsDiagnostics, ResolverStats};
+#[cfg(target_os = "linux")]
+use talpid_types::net::firewall::FirewallPolicyKind;
 use talpid_types::{
-    net::{openvpn, TransportProtocol, TunnelParameters, TunnelType},
-    tunnel::{ErrorStateCause, ParameterGenerationError, TunnelStateTransition},
+    dns::{BlockingCategories, DnsBackend, EncryptedDnsSettings, SplitDnsRule},
+    net::{
+        firewall::{AllowRule, LanNetworks},
+        openvpn,
+        wireguard::ConnectivityProbe,
+        Endpoint, TransportProtocol, TunnelParameters, TunnelType,
+    },
+    tunnel::{ErrorStateCause, ParameterGenerationError, TunnelStateTransition, TunnelStats},
     ErrorExt,
 };
 
@@ -74,8 +91,20 @@ use talpid_types::{
 mod wireguard;
 
 const TARGET_START_STATE_FILE: &str = "target-start-state.json";
+/// Directory in the settings directory that DNS blocklists are read from.
+#[cfg(target_os = "linux")]
+const DNS_BLOCKLISTS_DIR: &str = "dns-blocklists";
 mod event_loop;
 
+/// The target state that is cached across a restart of the daemon. Older versions of the daemon
+/// only cached a bare `TargetState`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum CachedTargetState {
+    Paused { paused_until: DateTime<Utc> },
+    TargetState(TargetState),
+}
+
 /// FIXME(linus): This is here just because the futures crate has deprecated it and jsonrpc_core
 /// did not introduce their own yet (https://github.com/paritytech/jsonrpc/pull/196).
 /// Remove this and use the one in jsonrpc_core when that is released.
@@ -86,8 +115,8 @@ const TUNNEL_STATE_MACHINE_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 /// Timeout for first WireGuard key pushing
 const FIRST_KEY_PUSH_TIMEOUT: Duration = Duration::from_secs(5);
 
-/// Delay between generating a new WireGuard key and reconnecting
-const WG_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+/// Delay between generating a new WireGuard key and applying it to the tunnel
+const WG_KEY_UPDATE_DELAY: Duration = Duration::from_secs(30);
 
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
@@ -114,6 +143,50 @@ pub enum Error {
     #[error(display = "No bridge available")]
     NoBridgeAvailable,
 
+    #[error(display = "No account token is set")]
+    NoAccountToken,
+
+    #[error(display = "No relay has been selected")]
+    NoRelaySelected,
+
+    #[error(display = "Relay {} has no matching WireGuard tunnel", _0)]
+    NoWireguardTunnel(String),
+
+    #[error(display = "Invalid OpenVPN profile")]
+    InvalidOpenVpnProfile(#[error(source)] openvpn_profile::Error),
+
+    #[error(display = "Custom relays can only use the OpenVPN profile that was set by the daemon")]
+    UnknownOpenVpnProfile,
+
+    #[error(
+        display = "Custom DNS server {} is on the local network, which can't be reached while \
+                   LAN access is blocked",
+        _0
+    )]
+    LanDnsServer(IpAddr),
+
+    #[error(
+        display = "LAN network {} would route all traffic outside the tunnel",
+        _0
+    )]
+    DefaultRouteLanNetwork(IpNetwork),
+
+    #[error(
+        display = "LAN network {} contains {}, which must be reached through the tunnel",
+        _0,
+        _1
+    )]
+    TunnelAddressLanNetwork(IpNetwork, IpAddr),
+
+    #[error(display = "Split DNS rule has no domain")]
+    SplitDnsRuleWithoutDomain,
+
+    #[error(display = "Split DNS rule for {} has no servers", _0)]
+    SplitDnsRuleWithoutServers(String),
+
+    #[error(display = "Unable to save settings")]
+    SaveSettings(#[error(source)] settings::Error),
+
     #[error(display = "Account history problems")]
     AccountHistory(#[error(source)] account_history::Error),
 
@@ -152,8 +225,14 @@ pub enum Error {
 pub enum DaemonCommand {
     /// Set target state. Does nothing if the daemon already has the state that is being set.
     SetTargetState(oneshot::Sender<std::result::Result<(), ()>>, TargetState),
+    /// Disconnect the tunnel, and secure it again after the given duration.
+    Pause(oneshot::Sender<()>, Duration),
+    /// Secure the tunnel again, since the pause has expired.
+    EndPause,
     /// Reconnect the tunnel, if one is connecting/connected.
     Reconnect,
+    /// Replace the key of the connected wireguard tunnel with the current key of the account.
+    ApplyWireguardKey,
     /// Request the current state.
     GetState(oneshot::Sender<TunnelState>),
     /// Get the current geographical location.
@@ -185,9 +264,21 @@ pub enum DaemonCommand {
     /// Set which account token to use for subsequent connection attempts.
     SetAccount(oneshot::Sender<()>, Option<AccountToken>),
     /// Place constraints on the type of tunnel and relay
-    UpdateRelaySettings(oneshot::Sender<()>, RelaySettingsUpdate),
+    UpdateRelaySettings(oneshot::Sender<Result<(), Error>>, RelaySettingsUpdate),
+    /// Store an OpenVPN profile and use it as a custom relay, with the given username and
+    /// password
+    SetOpenVpnProfile(
+        oneshot::Sender<std::result::Result<(), Error>>,
+        OpenVpnProfile,
+        String,
+        String,
+    ),
     /// Set the allow LAN setting.
     SetAllowLan(oneshot::Sender<()>, bool),
+    /// Set the changes to the networks that are considered to be LAN networks.
+    SetLanNetworks(oneshot::Sender<Result<(), Error>>, LanNetworks),
+    /// Set the traffic that the firewall always allows.
+    SetFirewallAllowRules(oneshot::Sender<()>, Vec<AllowRule>),
     /// Set the beta program setting.
     SetShowBetaReleases(oneshot::Sender<()>, bool),
     /// Set the block_when_disconnected setting.
@@ -196,6 +287,8 @@ pub enum DaemonCommand {
     SetAutoConnect(oneshot::Sender<()>, bool),
     /// Set the mssfix argument for OpenVPN
     SetOpenVpnMssfix(oneshot::Sender<()>, Option<u16>),
+    /// Set the OpenVPN tunnel options
+    SetOpenVpnOptions(oneshot::Sender<()>, openvpn::TunnelOptions),
     /// Set proxy details for OpenVPN
     SetBridgeSettings(
         oneshot::Sender<std::result::Result<(), settings::Error>>,
@@ -208,10 +301,24 @@ pub enum DaemonCommand {
     ),
     /// Set if IPv6 should be enabled in the tunnel
     SetEnableIpv6(oneshot::Sender<()>, bool),
+    /// Set the DNS servers to use instead of the tunnel gateway(s)
+    SetCustomDnsServers(oneshot::Sender<Result<(), Error>>, Vec<IpAddr>),
+    /// Set the categories of domains to block through the local DNS resolver
+    SetDnsBlocking(oneshot::Sender<()>, BlockingCategories),
+    /// Set the encrypted DNS servers to forward queries to through the local DNS resolver
+    SetEncryptedDns(oneshot::Sender<()>, EncryptedDnsSettings),
+    /// Set the rules for which domains to send DNS queries for to other servers
+    SetSplitDns(oneshot::Sender<Result<(), Error>>, Vec<SplitDnsRule>),
+    /// Set the backend to manage the system DNS settings through, or detect one if `None`
+    SetDnsBackend(oneshot::Sender<()>, Option<DnsBackend>),
     /// Set MTU for wireguard tunnels
     SetWireguardMtu(oneshot::Sender<()>, Option<u16>),
+    /// Set if the MTU of wireguard tunnels should be discovered automatically
+    SetWireguardAutomaticMtu(oneshot::Sender<()>, bool),
     /// Set automatic key rotation interval for wireguard tunnels
     SetWireguardRotationInterval(oneshot::Sender<()>, Option<u32>),
+    /// Set the probes used to check connectivity in wireguard tunnels
+    SetWireguardConnectivityProbes(oneshot::Sender<()>, Vec<ConnectivityProbe>),
     /// Get the daemon settings
     GetSettings(oneshot::Sender<Settings>),
     /// Generate new wireguard key
@@ -220,6 +327,13 @@ pub enum DaemonCommand {
     GetWireguardKey(oneshot::Sender<Option<wireguard::PublicKey>>),
     /// Verify if the currently set wireguard key is valid.
     VerifyWireguardKey(oneshot::Sender<bool>),
+    /// Export a wg-quick config for the current wireguard key and the given relay, or the last
+    /// selected relay. The private key is only included if the boolean is set.
+    ExportWireguardConfig(
+        oneshot::Sender<std::result::Result<String, Error>>,
+        Option<String>,
+        bool,
+    ),
     /// Get information about the currently running and latest app versions
     GetVersionInfo(oneshot::Sender<AppVersionInfo>),
     /// Get current version of the app
@@ -239,6 +353,24 @@ pub enum DaemonCommand {
     /// Clear list of processes excluded from the tunnel
     #[cfg(target_os = "linux")]
     ClearSplitTunnelProcesses(oneshot::Sender<()>),
+    /// Read the DNS blocklists from disk again
+    #[cfg(target_os = "linux")]
+    ReloadDnsBlocklists(oneshot::Sender<()>),
+    /// Get the number of queries handled and blocked by the local DNS resolver
+    #[cfg(target_os = "linux")]
+    GetDnsBlockingStats(oneshot::Sender<ResolverStats>),
+    /// Check that the system DNS settings are correct and that DNS queries go through the tunnel
+    #[cfg(target_os = "linux")]
+    DiagnoseDns(oneshot::Sender<DnsDiagnostics>),
+    /// Get which backends can manage the system DNS settings, and which one is used
+    #[cfg(target_os = "linux")]
+    GetDnsBackends(oneshot::Sender<DnsBackends>),
+    /// Render a firewall policy as an nftables script. The applied policy is rendered if it is of
+    /// the requested kind, otherwise an example is built from the current settings
+    #[cfg(target_os = "linux")]
+    RenderFirewallPolicy(oneshot::Sender<String>, FirewallPolicyKind),
+    /// Get the traffic counters and latency of the tunnel, if connected
+    GetTunnelStats(oneshot::Sender<Option<TunnelStats>>),
     /// Makes the daemon exit the main loop and quit.
     Shutdown,
     /// Saves the target tunnel state and enters a blocking state. The state is restored
@@ -307,7 +439,7 @@ impl DaemonExecutionState {
         match self {
             Running => {
                 match tunnel_state {
-                    TunnelState::Disconnected => mem::replace(self, Finished),
+                    TunnelState::Disconnected { .. } => mem::replace(self, Finished),
                     _ => mem::replace(self, Exiting),
                 };
             }
@@ -460,9 +592,16 @@ pub struct Daemon<L: EventListener> {
     state: DaemonExecutionState,
     #[cfg(target_os = "linux")]
     exclude_pids: split_tunnel::PidManager,
+    #[cfg(target_os = "linux")]
+    dns_blocklists: Blocklists,
     rx: Wait<UnboundedReceiver<InternalDaemonEvent>>,
     tx: DaemonEventSender,
     reconnection_job: Option<AbortHandle>,
+    wireguard_key_update_job: Option<AbortHandle>,
+    custom_relay_refresh_job: Option<AbortHandle>,
+    /// When the tunnel will be secured again, if it has been paused.
+    paused_until: Option<DateTime<Utc>>,
+    pause_job: Option<AbortHandle>,
     event_listener: L,
     settings: SettingsPersister,
     account_history: account_history::AccountHistory,
@@ -475,6 +614,9 @@ pub struct Daemon<L: EventListener> {
     relay_selector: relays::RelaySelector,
     last_generated_relay: Option<Relay>,
     last_generated_bridge_relay: Option<Relay>,
+    custom_relay_resolver: custom_relay_resolver::CustomRelayResolver,
+    /// The host that the last parameters for a custom relay were generated for.
+    last_custom_relay_host: Option<String>,
     app_version_info: AppVersionInfo,
     shutdown_callbacks: Vec<Box<dyn FnOnce()>>,
     /// oneshot channel that completes once the tunnel state machine has been shut down
@@ -539,7 +681,7 @@ where
 
         // Restore the tunnel to a previous state
         let target_cache = cache_dir.join(TARGET_START_STATE_FILE);
-        let cached_target_state: Option<TargetState> = match File::open(&target_cache) {
+        let cached_target_state: Option<CachedTargetState> = match File::open(&target_cache) {
             Ok(handle) => serde_json::from_reader(io::BufReader::new(handle))
                 .map(Some)
                 .map_err(Error::ReadCachedTargetState),
@@ -560,8 +702,22 @@ where
         let tunnel_parameters_generator = MullvadTunnelParametersGenerator {
             tx: internal_event_tx.clone(),
         };
+        #[cfg(target_os = "linux")]
+        let dns_blocklists = Blocklists::new(settings_dir.join(DNS_BLOCKLISTS_DIR));
+        #[cfg(target_os = "linux")]
+        {
+            if let Err(error) = dns_blocklists.reload() {
+                error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to load DNS blocklists")
+                );
+            }
+        }
+
         let tunnel_command_tx = tunnel_state_machine::spawn(
             settings.allow_lan,
+            settings.lan_networks.clone(),
+            settings.firewall_allow_rules.clone(),
             settings.block_when_disconnected,
             tunnel_parameters_generator,
             log_dir,
@@ -569,6 +725,8 @@ where
             cache_dir.clone(),
             internal_event_tx.to_specialized_sender(),
             tunnel_state_machine_shutdown_tx,
+            #[cfg(target_os = "linux")]
+            dns_blocklists.clone(),
             #[cfg(target_os = "android")]
             android_context,
         )
@@ -580,14 +738,29 @@ where
         // Attempt to download a fresh relay list
         relay_selector.update();
 
+        let mut initial_paused_until = None;
         let initial_target_state = if settings.get_account_token().is_some() {
-            if settings.auto_connect {
-                // Note: Auto-connect overrides the cached target state
-                info!("Automatically connecting since auto-connect is turned on");
-                TargetState::Secured
-            } else {
-                info!("Restoring cached target state");
-                cached_target_state.unwrap_or(TargetState::Unsecured)
+            match cached_target_state {
+                // Note: A pause overrides auto-connect, since it ends by itself
+                Some(CachedTargetState::Paused { paused_until }) if paused_until > Utc::now() => {
+                    info!("Restoring cached pause, until {}", paused_until);
+                    initial_paused_until = Some(paused_until);
+                    TargetState::Unsecured
+                }
+                Some(CachedTargetState::Paused { .. }) => {
+                    info!("Connecting since the cached pause has expired");
+                    TargetState::Secured
+                }
+                _ if settings.auto_connect => {
+                    // Note: Auto-connect overrides the cached target state
+                    info!("Automatically connecting since auto-connect is turned on");
+                    TargetState::Secured
+                }
+                Some(CachedTargetState::TargetState(target_state)) => {
+                    info!("Restoring cached target state");
+                    target_state
+                }
+                None => TargetState::Unsecured,
             }
         } else {
             TargetState::Unsecured
@@ -595,14 +768,22 @@ where
 
         let mut daemon = Daemon {
             tunnel_command_tx,
-            tunnel_state: TunnelState::Disconnected,
+            tunnel_state: TunnelState::Disconnected {
+                paused_until: initial_paused_until,
+            },
             target_state: initial_target_state,
             state: DaemonExecutionState::Running,
             #[cfg(target_os = "linux")]
             exclude_pids: split_tunnel::PidManager::new().map_err(Error::InitSplitTunneling)?,
+            #[cfg(target_os = "linux")]
+            dns_blocklists,
             rx: internal_event_rx.wait(),
             tx: internal_event_tx,
             reconnection_job: None,
+            wireguard_key_update_job: None,
+            custom_relay_refresh_job: None,
+            paused_until: initial_paused_until,
+            pause_job: None,
             event_listener,
             settings,
             account_history,
@@ -615,12 +796,17 @@ where
             relay_selector,
             last_generated_relay: None,
             last_generated_bridge_relay: None,
+            custom_relay_resolver: custom_relay_resolver::CustomRelayResolver::new(),
+            last_custom_relay_host: None,
             app_version_info,
             shutdown_callbacks: vec![],
             tunnel_state_machine_shutdown_signal,
             cache_dir,
         };
 
+        #[cfg(target_os = "linux")]
+        daemon.custom_relay_resolver.update_network_servers();
+
         daemon.ensure_wireguard_keys_for_current_account();
 
         if let Some(token) = daemon.settings.get_account_token() {
@@ -645,6 +831,9 @@ where
         if self.target_state == TargetState::Secured {
             self.connect_tunnel();
         }
+        if let Some(paused_until) = self.paused_until {
+            self.schedule_pause_expiry(paused_until);
+        }
         while let Some(Ok(event)) = self.rx.next() {
             self.handle_event(event);
             if self.state == DaemonExecutionState::Finished {
@@ -721,16 +910,25 @@ where
         match &tunnel_state_transition {
             TunnelStateTransition::Disconnected
             | TunnelStateTransition::Connected(_)
+            | TunnelStateTransition::Roamed(_)
             | TunnelStateTransition::Error(_) => {
                 // Reset the RPCs so that they fail immediately after the underlying socket gets
-                // invalidated due to the tunnel either coming up or breaking.
+                // invalidated due to the tunnel either coming up, moving to a new default route
+                // or breaking.
                 self.rpc_handle.service().reset();
             }
             _ => (),
         };
 
         let tunnel_state = match tunnel_state_transition {
-            TunnelStateTransition::Disconnected => TunnelState::Disconnected,
+            TunnelStateTransition::Disconnected => {
+                // DNS is not set for a tunnel, so the servers of the network can be read
+                #[cfg(target_os = "linux")]
+                self.custom_relay_resolver.update_network_servers();
+                TunnelState::Disconnected {
+                    paused_until: self.paused_until,
+                }
+            }
             TunnelStateTransition::Connecting(endpoint) => TunnelState::Connecting {
                 endpoint,
                 location: self.build_location_from_relay(),
@@ -738,7 +936,17 @@ where
             TunnelStateTransition::Connected(endpoint) => TunnelState::Connected {
                 endpoint,
                 location: self.build_location_from_relay(),
+                roamed: false,
             },
+            TunnelStateTransition::Roamed(endpoint) => {
+                // The tunnel stayed up, so clients see it as connected through the new endpoint
+                info!("Tunnel to {} roamed to a new default route", endpoint);
+                TunnelState::Connected {
+                    endpoint,
+                    location: self.build_location_from_relay(),
+                    roamed: true,
+                }
+            }
             TunnelStateTransition::Disconnecting(after_disconnect) => {
                 TunnelState::Disconnecting(after_disconnect)
             }
@@ -747,10 +955,19 @@ where
 
 
         self.unschedule_reconnect();
+        self.unschedule_custom_relay_refresh();
+        if let TunnelState::Connected { ref endpoint, .. } = tunnel_state {
+            if let Some(host) = self.last_custom_relay_host.clone() {
+                self.schedule_custom_relay_refresh(host, endpoint.endpoint.address.ip());
+            }
+        } else {
+            // A new tunnel is set up with the current key
+            self.unschedule_wireguard_key_update();
+        }
 
         debug!("New tunnel state: {:?}", tunnel_state);
         match tunnel_state {
-            TunnelState::Disconnected => self.state.disconnected(),
+            TunnelState::Disconnected { .. } => self.state.disconnected(),
             TunnelState::Error(ref error_state) => {
                 if error_state.is_blocking() {
                     info!(
@@ -784,13 +1001,44 @@ where
             let result = match self.settings.get_relay_settings() {
                 RelaySettings::CustomTunnelEndpoint(custom_relay) => {
                     self.last_generated_relay = None;
-                    custom_relay
-                        // TODO(emilsp): generate proxy settings for custom tunnels
-                        .to_tunnel_parameters(self.settings.tunnel_options.clone(), None)
-                        .map_err(|e| {
-                            log::error!("Failed to resolve hostname for custom tunnel config: {}", e);
-                            ParameterGenerationError::CustomTunnelHostResultionError
-                        })
+                    self.last_generated_bridge_relay = None;
+                    // Bridges only carry TCP, so they can't be used with UDP relays. Connecting
+                    // without a bridge when bridges are forced on would ignore the setting.
+                    let bridges_forced = *self.settings.get_bridge_state() == BridgeState::On;
+                    let proxy_settings =
+                        match (custom_relay.tunnel_type(), custom_relay.endpoint().protocol) {
+                            (TunnelType::OpenVpn, TransportProtocol::Tcp) => {
+                                self.generate_proxy_settings(None, retry_attempt)
+                            }
+                            (TunnelType::OpenVpn, TransportProtocol::Udp) if bridges_forced => {
+                                Err(Error::NoBridgeAvailable)
+                            }
+                            _ => Ok(None),
+                        };
+                    let resolver = &self.custom_relay_resolver;
+                    let mut resolved_host = None;
+                    let result = match proxy_settings {
+                        Ok(proxy_settings) => custom_relay
+                            .to_tunnel_parameters(
+                                self.settings.effective_tunnel_options(),
+                                proxy_settings,
+                                retry_attempt,
+                                |host| {
+                                    resolved_host = Some(host.to_owned());
+                                    resolver.resolve(host)
+                                },
+                            )
+                            .map_err(|e| {
+                                log::error!(
+                                    "Failed to resolve hostname for custom tunnel config: {}",
+                                    e
+                                );
+                                ParameterGenerationError::CustomTunnelHostResultionError
+                            }),
+                        Err(_) => Err(ParameterGenerationError::NoMatchingBridgeRelay),
+                    };
+                    self.last_custom_relay_host = resolved_host;
+                    result
                 }
                 RelaySettings::Normal(constraints) => self
                     .relay_selector
@@ -806,6 +1054,7 @@ where
                     )
                     .map_err(|_| ParameterGenerationError::NoMatchingRelay)
                     .and_then(|(relay, endpoint)| {
+                        self.last_custom_relay_host = None;
                         let result = self.create_tunnel_parameters(
                             &relay,
                             endpoint,
@@ -848,58 +1097,12 @@ where
         account_token: String,
         retry_attempt: u32,
     ) -> Result<TunnelParameters, Error> {
-        let tunnel_options = self.settings.tunnel_options.clone();
+        let tunnel_options = self.settings.effective_tunnel_options();
         let location = relay.location.as_ref().expect("Relay has no location set");
         self.last_generated_bridge_relay = None;
         match endpoint {
             MullvadEndpoint::OpenVpn(endpoint) => {
-                let proxy_settings = match &self.settings.bridge_settings {
-                    BridgeSettings::Normal(settings) => {
-                        let bridge_constraints = InternalBridgeConstraints {
-                            location: settings.location.clone(),
-                            // FIXME: This is temporary while talpid-core only supports TCP proxies
-                            transport_protocol: Constraint::Only(TransportProtocol::Tcp),
-                        };
-                        match self.settings.get_bridge_state() {
-                            BridgeState::On => {
-                                let (bridge_settings, bridge_relay) = self
-                                    .relay_selector
-                                    .get_proxy_settings(&bridge_constraints, location)
-                                    .ok_or(Error::NoBridgeAvailable)?;
-                                self.last_generated_bridge_relay = Some(bridge_relay);
-                                Some(bridge_settings)
-                            }
-                            BridgeState::Auto => {
-                                if let Some((bridge_settings, bridge_relay)) =
-                                    self.relay_selector.get_auto_proxy_settings(
-                                        &bridge_constraints,
-                                        location,
-                                        retry_attempt,
-                                    )
-                                {
-                                    self.last_generated_bridge_relay = Some(bridge_relay);
-                                    Some(bridge_settings)
-                                } else {
-                                    None
-                                }
-                            }
-                            BridgeState::Off => None,
-                        }
-                    }
-                    BridgeSettings::Custom(proxy_settings) => {
-                        match self.settings.get_bridge_state() {
-                            BridgeState::On => Some(proxy_settings.clone()),
-                            BridgeState::Auto => {
-                                if self.relay_selector.should_use_bridge(retry_attempt) {
-                                    Some(proxy_settings.clone())
-                                } else {
-                                    None
-                                }
-                            }
-                            BridgeState::Off => None,
-                        }
-                    }
-                };
+                let proxy_settings = self.generate_proxy_settings(Some(location), retry_attempt)?;
 
                 Ok(openvpn::TunnelParameters {
                     config: openvpn::ConnectionConfig::new(
@@ -918,19 +1121,7 @@ where
                 ipv4_gateway,
                 ipv6_gateway,
             } => {
-                let wg_data = self
-                    .account_history
-                    .get(&account_token)
-                    .map_err(Error::AccountHistory)?
-                    .and_then(|entry| entry.wireguard)
-                    .ok_or(Error::NoKeyAvailable)?;
-                let tunnel = wireguard::TunnelConfig {
-                    private_key: wg_data.private_key,
-                    addresses: vec![
-                        wg_data.addresses.ipv4_address.ip().into(),
-                        wg_data.addresses.ipv6_address.ip().into(),
-                    ],
-                };
+                let tunnel = self.get_wireguard_tunnel_config(&account_token)?;
                 Ok(wireguard::TunnelParameters {
                     connection: wireguard::ConnectionConfig {
                         tunnel,
@@ -946,6 +1137,78 @@ where
         }
     }
 
+    /// Selects the bridge to use for an OpenVPN connection attempt, if any. Bridges are picked
+    /// close to `location` when it is known.
+    fn generate_proxy_settings(
+        &mut self,
+        location: Option<&Location>,
+        retry_attempt: u32,
+    ) -> Result<Option<openvpn::ProxySettings>, Error> {
+        self.last_generated_bridge_relay = None;
+        let proxy_settings = match &self.settings.bridge_settings {
+            BridgeSettings::Normal(settings) => {
+                let bridge_constraints = InternalBridgeConstraints {
+                    location: settings.location.clone(),
+                    // FIXME: This is temporary while talpid-core only supports TCP proxies
+                    transport_protocol: Constraint::Only(TransportProtocol::Tcp),
+                };
+                match self.settings.get_bridge_state() {
+                    BridgeState::On => {
+                        let (bridge_settings, bridge_relay) = self
+                            .relay_selector
+                            .get_proxy_settings(&bridge_constraints, location)
+                            .ok_or(Error::NoBridgeAvailable)?;
+                        self.last_generated_bridge_relay = Some(bridge_relay);
+                        Some(bridge_settings)
+                    }
+                    BridgeState::Auto => {
+                        if let Some((bridge_settings, bridge_relay)) = self
+                            .relay_selector
+                            .get_auto_proxy_settings(&bridge_constraints, location, retry_attempt)
+                        {
+                            self.last_generated_bridge_relay = Some(bridge_relay);
+                            Some(bridge_settings)
+                        } else {
+                            None
+                        }
+                    }
+                    BridgeState::Off => None,
+                }
+            }
+            BridgeSettings::Custom(proxy_settings) => match self.settings.get_bridge_state() {
+                BridgeState::On => Some(proxy_settings.clone()),
+                BridgeState::Auto => {
+                    if self.relay_selector.should_use_bridge(retry_attempt) {
+                        Some(proxy_settings.clone())
+                    } else {
+                        None
+                    }
+                }
+                BridgeState::Off => None,
+            },
+        };
+        Ok(proxy_settings)
+    }
+
+    fn get_wireguard_tunnel_config(
+        &self,
+        account_token: &AccountToken,
+    ) -> Result<wireguard::TunnelConfig, Error> {
+        let wg_data = self
+            .account_history
+            .get(account_token)
+            .map_err(Error::AccountHistory)?
+            .and_then(|entry| entry.wireguard)
+            .ok_or(Error::NoKeyAvailable)?;
+        Ok(wireguard::TunnelConfig {
+            private_key: wg_data.private_key,
+            addresses: vec![
+                wg_data.addresses.ipv4_address.ip().into(),
+                wg_data.addresses.ipv6_address.ip().into(),
+            ],
+        })
+    }
+
     fn schedule_reconnect(&mut self, delay: Duration) {
         let tunnel_command_tx = self.tx.to_specialized_sender();
         let (future, abort_handle) = abortable(Box::pin(async move {
@@ -958,12 +1221,92 @@ where
         self.reconnection_job = Some(abort_handle);
     }
 
+    /// Applies the current wireguard key to the connected tunnel after `delay`, which gives the
+    /// relays time to learn about the new key.
+    fn schedule_wireguard_key_update(&mut self, delay: Duration) {
+        let command_tx = self.tx.to_specialized_sender();
+        let (future, abort_handle) = abortable(Box::pin(async move {
+            tokio02::time::delay_for(delay).await;
+            log::debug!("Applying new wireguard key to the tunnel");
+            let _ = command_tx.send(DaemonCommand::ApplyWireguardKey);
+        }));
+
+        self.spawn_future(future);
+        if let Some(job) = self.wireguard_key_update_job.replace(abort_handle) {
+            job.abort();
+        }
+    }
+
+    /// Reconnects once the host of the connected custom relay no longer resolves to `address`.
+    fn schedule_custom_relay_refresh(&mut self, host: String, address: IpAddr) {
+        let command_tx = self.tx.to_specialized_sender();
+        let resolver = self.custom_relay_resolver.clone();
+        let (future, abort_handle) = abortable(Box::pin(async move {
+            resolver.wait_for_address_change(host, address).await;
+            log::debug!("Reconnecting to the new address of the custom relay");
+            let _ = command_tx.send(DaemonCommand::Reconnect);
+        }));
+
+        self.spawn_future(future);
+        self.custom_relay_refresh_job = Some(abort_handle);
+    }
+
+    /// Secures the tunnel again at `paused_until`.
+    fn schedule_pause_expiry(&mut self, paused_until: DateTime<Utc>) {
+        let delay = (paused_until - Utc::now())
+            .to_std()
+            .unwrap_or(Duration::from_secs(0));
+        let command_tx = self.tx.to_specialized_sender();
+        let (future, abort_handle) = abortable(Box::pin(async move {
+            tokio02::time::delay_for(delay).await;
+            log::debug!("The pause has expired");
+            let _ = command_tx.send(DaemonCommand::EndPause);
+        }));
+
+        self.spawn_future(future);
+        if let Some(job) = self.pause_job.replace(abort_handle) {
+            job.abort();
+        }
+    }
+
+    fn unschedule_pause(&mut self) {
+        if let Some(job) = self.pause_job.take() {
+            job.abort();
+        }
+        if self.paused_until.is_some() {
+            self.set_paused_until(None);
+        }
+    }
+
+    /// Updates when the tunnel will be secured again, and shows it to clients if the tunnel is
+    /// already disconnected. Otherwise, it is shown once the tunnel has disconnected.
+    fn set_paused_until(&mut self, paused_until: Option<DateTime<Utc>>) {
+        self.paused_until = paused_until;
+        if let TunnelState::Disconnected { .. } = self.tunnel_state {
+            self.tunnel_state = TunnelState::Disconnected { paused_until };
+            self.event_listener
+                .notify_new_state(self.tunnel_state.clone());
+        }
+    }
+
+    fn unschedule_custom_relay_refresh(&mut self) {
+        if let Some(job) = self.custom_relay_refresh_job.take() {
+            job.abort();
+        }
+    }
+
     fn unschedule_reconnect(&mut self) {
         if let Some(job) = self.reconnection_job.take() {
             job.abort();
         }
     }
 
+    fn unschedule_wireguard_key_update(&mut self) {
+        if let Some(job) = self.wireguard_key_update_job.take() {
+            job.abort();
+        }
+    }
+
     fn spawn_future<F>(&mut self, fut: F)
     where
         F: std::future::Future + Send + 'static,
@@ -981,7 +1324,10 @@ where
         }
         match command {
             SetTargetState(tx, state) => self.on_set_target_state(tx, state),
+            Pause(tx, duration) => self.on_pause(tx, duration),
+            EndPause => self.on_end_pause(),
             Reconnect => self.on_reconnect(),
+            ApplyWireguardKey => self.on_apply_wireguard_key(),
             GetState(tx) => self.on_get_state(tx),
             GetCurrentLocation(tx) => self.on_get_current_location(tx),
             CreateNewAccount(tx) => self.on_create_new_account(tx),
@@ -997,26 +1343,46 @@ where
             }
             ClearAccountHistory(tx) => self.on_clear_account_history(tx),
             UpdateRelaySettings(tx, update) => self.on_update_relay_settings(tx, update),
+            SetOpenVpnProfile(tx, profile, username, password) => {
+                self.on_set_openvpn_profile(tx, profile, username, password)
+            }
             SetAllowLan(tx, allow_lan) => self.on_set_allow_lan(tx, allow_lan),
+            SetLanNetworks(tx, lan_networks) => self.on_set_lan_networks(tx, lan_networks),
+            SetFirewallAllowRules(tx, rules) => self.on_set_firewall_allow_rules(tx, rules),
             SetShowBetaReleases(tx, enabled) => self.on_set_show_beta_releases(tx, enabled),
             SetBlockWhenDisconnected(tx, block_when_disconnected) => {
                 self.on_set_block_when_disconnected(tx, block_when_disconnected)
             }
             SetAutoConnect(tx, auto_connect) => self.on_set_auto_connect(tx, auto_connect),
             SetOpenVpnMssfix(tx, mssfix_arg) => self.on_set_openvpn_mssfix(tx, mssfix_arg),
+            SetOpenVpnOptions(tx, options) => self.on_set_openvpn_options(tx, options),
             SetBridgeSettings(tx, bridge_settings) => {
                 self.on_set_bridge_settings(tx, bridge_settings)
             }
             SetBridgeState(tx, bridge_state) => self.on_set_bridge_state(tx, bridge_state),
             SetEnableIpv6(tx, enable_ipv6) => self.on_set_enable_ipv6(tx, enable_ipv6),
+            SetCustomDnsServers(tx, servers) => self.on_set_custom_dns_servers(tx, servers),
+            SetDnsBlocking(tx, categories) => self.on_set_dns_blocking(tx, categories),
+            SetEncryptedDns(tx, encrypted_dns) => self.on_set_encrypted_dns(tx, encrypted_dns),
+            SetSplitDns(tx, rules) => self.on_set_split_dns(tx, rules),
+            SetDnsBackend(tx, backend) => self.on_set_dns_backend(tx, backend),
             SetWireguardMtu(tx, mtu) => self.on_set_wireguard_mtu(tx, mtu),
+            SetWireguardAutomaticMtu(tx, automatic_mtu) => {
+                self.on_set_wireguard_automatic_mtu(tx, automatic_mtu)
+            }
             SetWireguardRotationInterval(tx, interval) => {
                 self.on_set_wireguard_rotation_interval(tx, interval)
             }
+            SetWireguardConnectivityProbes(tx, probes) => {
+                self.on_set_wireguard_connectivity_probes(tx, probes)
+            }
             GetSettings(tx) => self.on_get_settings(tx),
             GenerateWireguardKey(tx) => self.on_generate_wireguard_key(tx),
             GetWireguardKey(tx) => self.on_get_wireguard_key(tx),
             VerifyWireguardKey(tx) => self.on_verify_wireguard_key(tx),
+            ExportWireguardConfig(tx, hostname, include_private_key) => {
+                self.on_export_wireguard_config(tx, hostname, include_private_key)
+            }
             GetVersionInfo(tx) => self.on_get_version_info(tx),
             GetCurrentVersion(tx) => self.on_get_current_version(tx),
             #[cfg(not(target_os = "android"))]
@@ -1029,6 +1395,17 @@ where
             RemoveSplitTunnelProcess(tx, pid) => self.on_remove_split_tunnel_process(tx, pid),
             #[cfg(target_os = "linux")]
             ClearSplitTunnelProcesses(tx) => self.on_clear_split_tunnel_processes(tx),
+            #[cfg(target_os = "linux")]
+            ReloadDnsBlocklists(tx) => self.on_reload_dns_blocklists(tx),
+            #[cfg(target_os = "linux")]
+            GetDnsBlockingStats(tx) => self.on_get_dns_blocking_stats(tx),
+            #[cfg(target_os = "linux")]
+            DiagnoseDns(tx) => self.send_tunnel_command(TunnelCommand::DiagnoseDns(tx)),
+            #[cfg(target_os = "linux")]
+            GetDnsBackends(tx) => self.on_get_dns_backends(tx),
+            #[cfg(target_os = "linux")]
+            RenderFirewallPolicy(tx, kind) => self.on_render_firewall_policy(tx, kind),
+            GetTunnelStats(tx) => self.send_tunnel_command(TunnelCommand::GetTunnelStats(tx)),
             Shutdown => self.trigger_shutdown_event(),
             PrepareRestart => self.on_prepare_restart(),
         }
@@ -1070,7 +1447,7 @@ where
                 match self.account_history.insert(account_entry) {
                     Ok(_) => {
                         if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
-                            self.schedule_reconnect(WG_RECONNECT_DELAY);
+                            self.schedule_wireguard_key_update(WG_KEY_UPDATE_DELAY);
                         }
                         self.event_listener
                             .notify_key_event(KeygenEvent::NewKey(public_key))
@@ -1136,6 +1513,38 @@ where
         Self::oneshot_send(tx, Ok(()), "target state");
     }
 
+    fn on_pause(&mut self, tx: oneshot::Sender<()>, duration: Duration) {
+        if self.state.is_running() {
+            let paused_until = chrono::Duration::from_std(duration)
+                .ok()
+                .and_then(|duration| Utc::now().checked_add_signed(duration));
+            match paused_until {
+                Some(paused_until) => {
+                    self.set_target_state(TargetState::Unsecured);
+                    info!("Pausing the tunnel until {}", paused_until);
+                    self.set_paused_until(Some(paused_until));
+                    self.schedule_pause_expiry(paused_until);
+                }
+                None => warn!("Ignoring pause request with an invalid duration"),
+            }
+        } else {
+            warn!("Ignoring pause request due to shutdown");
+        }
+        Self::oneshot_send(tx, (), "pause response");
+    }
+
+    fn on_end_pause(&mut self) {
+        if self.paused_until.is_none() {
+            return;
+        }
+        if self.settings.get_account_token().is_some() {
+            info!("Securing the tunnel since the pause has expired");
+            self.set_target_state(TargetState::Secured);
+        } else {
+            self.unschedule_pause();
+        }
+    }
+
     fn on_reconnect(&mut self) {
         if self.target_state == TargetState::Secured || self.tunnel_state.is_in_error_state() {
             self.connect_tunnel();
@@ -1144,6 +1553,34 @@ where
         }
     }
 
+    fn on_apply_wireguard_key(&mut self) {
+        if self.get_connected_tunnel_type() != Some(TunnelType::Wireguard) {
+            debug!("Ignoring wireguard key update. Not connected to a wireguard relay");
+            return;
+        }
+        // Custom tunnel endpoints have their own keys
+        if let RelaySettings::CustomTunnelEndpoint(_) = self.settings.get_relay_settings() {
+            return;
+        }
+
+        let account_token = match self.settings.get_account_token() {
+            Some(account_token) => account_token,
+            None => return,
+        };
+        match self.get_wireguard_tunnel_config(&account_token) {
+            Ok(tunnel_config) => {
+                self.send_tunnel_command(TunnelCommand::SetWireguardKey(tunnel_config))
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to read wireguard key, reconnecting")
+                );
+                self.reconnect_tunnel();
+            }
+        }
+    }
+
     fn on_get_state(&self, tx: oneshot::Sender<TunnelState>) {
         Self::oneshot_send(tx, self.tunnel_state.clone(), "current state");
     }
@@ -1152,7 +1589,7 @@ where
         use self::TunnelState::*;
         let get_location: Box<dyn Future<Item = Option<GeoIpLocation>, Error = ()> + Send> =
             match &self.tunnel_state {
-                Disconnected => Box::new(self.get_geo_location().map(Some)),
+                Disconnected { .. } => Box::new(self.get_geo_location().map(Some)),
                 Connecting { location, .. } => Box::new(future::result(Ok(location.clone()))),
                 Disconnecting(..) => Box::new(future::result(Ok(self.build_location_from_relay()))),
                 Connected { location, .. } => {
@@ -1441,11 +1878,86 @@ where
         }
     }
 
-    fn on_update_relay_settings(&mut self, tx: oneshot::Sender<()>, update: RelaySettingsUpdate) {
+    #[cfg(target_os = "linux")]
+    fn on_reload_dns_blocklists(&mut self, tx: oneshot::Sender<()>) {
+        match self.dns_blocklists.reload() {
+            Ok(()) => Self::oneshot_send(tx, (), "reload_dns_blocklists response"),
+            Err(e) => error!(
+                "{}",
+                e.display_chain_with_msg("Unable to reload DNS blocklists")
+            ),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_get_dns_blocking_stats(&mut self, tx: oneshot::Sender<ResolverStats>) {
+        Self::oneshot_send(
+            tx,
+            self.dns_blocklists.stats(),
+            "get_dns_blocking_stats response",
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_get_dns_backends(&mut self, tx: oneshot::Sender<DnsBackends>) {
+        let forced_backend = self.settings.tunnel_options.generic.dns_backend;
+        // Probing talks to other services over D-Bus, so don't block the daemon while doing it
+        std::thread::spawn(move || {
+            let backends = talpid_core::dns::probe_backends(forced_backend);
+            Self::oneshot_send(tx, backends, "get_dns_backends response");
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_render_firewall_policy(&mut self, tx: oneshot::Sender<String>, kind: FirewallPolicyKind) {
+        let (policy_tx, policy_rx) = oneshot::channel();
+        self.send_tunnel_command(TunnelCommand::GetFirewallPolicy(policy_tx));
+
+        // Policies that aren't currently applied are rendered for a made up relay and tunnel
+        let sample_policy = tunnel_state_machine::sample_firewall_policy(
+            kind,
+            self.settings.allow_lan,
+            self.settings.lan_networks.clone(),
+            self.settings.firewall_allow_rules.clone(),
+            &self.settings.tunnel_options.generic,
+        );
+        std::thread::spawn(move || {
+            let policy = match policy_rx.wait() {
+                Ok(Some(policy)) if policy.kind() == kind => policy,
+                _ => sample_policy,
+            };
+            Self::oneshot_send(
+                tx,
+                talpid_core::firewall::render_policy(&policy),
+                "render_firewall_policy response",
+            );
+        });
+    }
+
+    fn on_update_relay_settings(
+        &mut self,
+        tx: oneshot::Sender<Result<(), Error>>,
+        update: RelaySettingsUpdate,
+    ) {
+        // OpenVPN runs as root, so clients may only refer to the profile that was validated and
+        // stored by `on_set_openvpn_profile`.
+        if let RelaySettingsUpdate::CustomTunnelEndpoint(relay) = &update {
+            if let Some(profile) = relay.openvpn_profile() {
+                if profile != self.settings.openvpn_profile_path() {
+                    let error = Error::UnknownOpenVpnProfile;
+                    error!(
+                        "{}",
+                        error.display_chain_with_msg("Rejected relay settings")
+                    );
+                    Self::oneshot_send(tx, Err(error), "update_relay_settings response");
+                    return;
+                }
+            }
+        }
         let save_result = self.settings.update_relay_settings(update);
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, (), "update_relay_settings response");
+                Self::oneshot_send(tx, Ok(()), "update_relay_settings response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
@@ -1453,7 +1965,82 @@ where
                     self.reconnect_tunnel();
                 }
             }
-            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::SaveSettings(e)),
+                    "update_relay_settings response",
+                );
+            }
+        }
+    }
+
+    fn on_set_openvpn_profile(
+        &mut self,
+        tx: oneshot::Sender<Result<(), Error>>,
+        profile: OpenVpnProfile,
+        username: String,
+        password: String,
+    ) {
+        // OpenVPN runs as root, so the options can't be trusted just because the client checked
+        // them.
+        if let Err(e) = profile.validate() {
+            error!("{}", e.display_chain_with_msg("Rejected OpenVPN profile"));
+            Self::oneshot_send(
+                tx,
+                Err(Error::InvalidOpenVpnProfile(e)),
+                "set_openvpn_profile response",
+            );
+            return;
+        }
+        let profile_path = match self.settings.save_openvpn_profile(&profile.config) {
+            Ok(path) => path,
+            Err(e) => {
+                error!(
+                    "{}",
+                    e.display_chain_with_msg("Unable to save OpenVPN profile")
+                );
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::SaveSettings(e)),
+                    "set_openvpn_profile response",
+                );
+                return;
+            }
+        };
+        let custom_relay = CustomTunnelEndpoint::new(
+            profile.host,
+            ConnectionConfig::OpenVpn(openvpn::ConnectionConfig {
+                endpoint: Endpoint::new(Ipv4Addr::UNSPECIFIED, profile.port, profile.protocol),
+                username,
+                password,
+                profile: Some(profile_path),
+            }),
+        );
+
+        let save_result = self
+            .settings
+            .update_relay_settings(RelaySettingsUpdate::CustomTunnelEndpoint(custom_relay));
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_openvpn_profile response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+                // The profile may have changed even if the settings didn't.
+                info!("Initiating tunnel restart because the OpenVPN profile changed");
+                self.reconnect_tunnel();
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::SaveSettings(e)),
+                    "set_openvpn_profile response",
+                );
+            }
         }
     }
 
@@ -1465,7 +2052,94 @@ where
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    self.send_tunnel_command(TunnelCommand::AllowLan(allow_lan));
+                    self.send_tunnel_command(TunnelCommand::AllowLan(
+                        allow_lan,
+                        self.settings.lan_networks.clone(),
+                    ));
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
+    fn on_set_lan_networks(
+        &mut self,
+        tx: oneshot::Sender<Result<(), Error>>,
+        lan_networks: LanNetworks,
+    ) {
+        if let Err(error) = self.validate_lan_networks(&lan_networks) {
+            error!("{}", error.display_chain_with_msg("Rejected LAN networks"));
+            Self::oneshot_send(tx, Err(error), "set_lan_networks response");
+            return;
+        }
+        let save_result = self.settings.set_lan_networks(lan_networks.clone());
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_lan_networks response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::AllowLan(
+                        self.settings.allow_lan,
+                        lan_networks,
+                    ));
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(Error::SaveSettings(e)), "set_lan_networks response");
+            }
+        }
+    }
+
+    /// Rejects added LAN networks that would route the whole internet, the relay or the tunnel
+    /// itself outside the tunnel.
+    fn validate_lan_networks(&self, lan_networks: &LanNetworks) -> Result<(), Error> {
+        let tunnel_addresses = self.tunnel_addresses();
+        for network in &lan_networks.added {
+            if network.prefix() == 0 {
+                return Err(Error::DefaultRouteLanNetwork(*network));
+            }
+            if let Some(address) = tunnel_addresses
+                .iter()
+                .find(|address| network.contains(**address))
+            {
+                return Err(Error::TunnelAddressLanNetwork(*network, *address));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the addresses of the relay and proxy currently in use, and the tunnel addresses
+    /// assigned to the WireGuard key of the account.
+    fn tunnel_addresses(&self) -> Vec<IpAddr> {
+        let mut addresses = vec![];
+        match &self.tunnel_state {
+            TunnelState::Connecting { endpoint, .. } | TunnelState::Connected { endpoint, .. } => {
+                addresses.push(endpoint.endpoint.address.ip());
+                if let Some(proxy) = &endpoint.proxy {
+                    addresses.push(proxy.endpoint.address.ip());
+                }
+            }
+            _ => (),
+        }
+        if let Some(account_token) = self.settings.get_account_token() {
+            if let Ok(tunnel_config) = self.get_wireguard_tunnel_config(&account_token) {
+                addresses.extend(tunnel_config.addresses);
+            }
+        }
+        addresses
+    }
+
+    fn on_set_firewall_allow_rules(&mut self, tx: oneshot::Sender<()>, rules: Vec<AllowRule>) {
+        let save_result = self.settings.set_firewall_allow_rules(rules.clone());
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_firewall_allow_rules response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::AllowRules(rules));
                 }
             }
             Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
@@ -1546,6 +2220,24 @@ where
         }
     }
 
+    fn on_set_openvpn_options(&mut self, tx: oneshot::Sender<()>, options: openvpn::TunnelOptions) {
+        let save_result = self.settings.set_openvpn_options(options);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_openvpn_options response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::OpenVpn) = self.get_connected_tunnel_type() {
+                        info!("Initiating tunnel restart because the OpenVPN options changed");
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
     fn on_set_bridge_settings(
         &mut self,
         tx: oneshot::Sender<Result<(), settings::Error>>,
@@ -1614,6 +2306,142 @@ where
         }
     }
 
+    fn on_set_custom_dns_servers(
+        &mut self,
+        tx: oneshot::Sender<Result<(), Error>>,
+        servers: Vec<IpAddr>,
+    ) {
+        if !self.settings.allow_lan {
+            if let Some(server) = servers.iter().find(|server| {
+                talpid_core::firewall::is_local_address(server, &self.settings.lan_networks)
+            }) {
+                let error = Error::LanDnsServer(*server);
+                error!(
+                    "{}",
+                    error.display_chain_with_msg("Rejected custom DNS servers")
+                );
+                Self::oneshot_send(tx, Err(error), "set_custom_dns_servers response");
+                return;
+            }
+        }
+        let save_result = self.settings.set_custom_dns_servers(servers);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_custom_dns_servers response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    info!("Initiating tunnel restart because the custom DNS servers changed");
+                    self.reconnect_tunnel();
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::SaveSettings(e)),
+                    "set_custom_dns_servers response",
+                );
+            }
+        }
+    }
+
+    fn on_set_dns_blocking(&mut self, tx: oneshot::Sender<()>, categories: BlockingCategories) {
+        let save_result = self.settings.set_dns_blocking(categories);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_dns_blocking response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    info!("Initiating tunnel restart because the DNS blocking settings changed");
+                    self.reconnect_tunnel();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
+    fn on_set_encrypted_dns(
+        &mut self,
+        tx: oneshot::Sender<()>,
+        encrypted_dns: EncryptedDnsSettings,
+    ) {
+        let save_result = self.settings.set_encrypted_dns(encrypted_dns);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_encrypted_dns response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    info!("Initiating tunnel restart because the encrypted DNS settings changed");
+                    self.reconnect_tunnel();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
+    fn on_set_split_dns(
+        &mut self,
+        tx: oneshot::Sender<Result<(), Error>>,
+        rules: Vec<SplitDnsRule>,
+    ) {
+        if let Err(error) = Self::validate_split_dns(&rules) {
+            error!(
+                "{}",
+                error.display_chain_with_msg("Rejected split DNS rules")
+            );
+            Self::oneshot_send(tx, Err(error), "set_split_dns response");
+            return;
+        }
+        let save_result = self.settings.set_split_dns(rules);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_split_dns response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    info!("Initiating tunnel restart because the split DNS rules changed");
+                    self.reconnect_tunnel();
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(Error::SaveSettings(e)), "set_split_dns response");
+            }
+        }
+    }
+
+    /// Rejects rules that would match every domain, or that have no servers to send queries to.
+    fn validate_split_dns(rules: &[SplitDnsRule]) -> Result<(), Error> {
+        for rule in rules {
+            if rule.domain.trim_matches('.').is_empty() {
+                return Err(Error::SplitDnsRuleWithoutDomain);
+            }
+            if rule.servers.is_empty() {
+                return Err(Error::SplitDnsRuleWithoutServers(rule.domain.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    fn on_set_dns_backend(&mut self, tx: oneshot::Sender<()>, backend: Option<DnsBackend>) {
+        let save_result = self.settings.set_dns_backend(backend);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_dns_backend response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    info!("Initiating tunnel restart because the DNS backend setting changed");
+                    self.reconnect_tunnel();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
     fn on_set_wireguard_mtu(&mut self, tx: oneshot::Sender<()>, mtu: Option<u16>) {
         let save_result = self.settings.set_wireguard_mtu(mtu);
         match save_result {
@@ -1634,6 +2462,52 @@ where
         }
     }
 
+    fn on_set_wireguard_automatic_mtu(&mut self, tx: oneshot::Sender<()>, automatic_mtu: bool) {
+        let save_result = self.settings.set_wireguard_automatic_mtu(automatic_mtu);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_wireguard_automatic_mtu response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        info!(
+                            "Initiating tunnel restart because the WireGuard automatic MTU \
+                             setting changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
+    fn on_set_wireguard_connectivity_probes(
+        &mut self,
+        tx: oneshot::Sender<()>,
+        probes: Vec<ConnectivityProbe>,
+    ) {
+        let save_result = self.settings.set_wireguard_connectivity_probes(probes);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_wireguard_connectivity_probes response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        info!(
+                            "Initiating tunnel restart because the WireGuard connectivity probes \
+                             changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
     fn on_set_wireguard_rotation_interval(
         &mut self,
         tx: oneshot::Sender<()>,
@@ -1763,6 +2637,67 @@ where
         Self::oneshot_send(tx, key, "get_wireguard_key response");
     }
 
+    fn on_export_wireguard_config(
+        &mut self,
+        tx: oneshot::Sender<Result<String, Error>>,
+        hostname: Option<String>,
+        include_private_key: bool,
+    ) {
+        let result = self.export_wireguard_config(hostname, include_private_key);
+        Self::oneshot_send(tx, result, "export_wireguard_config response");
+    }
+
+    fn export_wireguard_config(
+        &mut self,
+        hostname: Option<String>,
+        include_private_key: bool,
+    ) -> Result<String, Error> {
+        let account_token = self
+            .settings
+            .get_account_token()
+            .ok_or(Error::NoAccountToken)?;
+        let tunnel = self.get_wireguard_tunnel_config(&account_token)?;
+
+        let hostname = match hostname {
+            Some(hostname) => hostname,
+            None => self
+                .last_generated_relay
+                .as_ref()
+                .map(|relay| relay.hostname.clone())
+                .ok_or(Error::NoRelaySelected)?,
+        };
+        let constraints = match self.settings.get_relay_settings() {
+            RelaySettings::Normal(constraints) => constraints.wireguard_constraints,
+            RelaySettings::CustomTunnelEndpoint(_) => WireguardConstraints::default(),
+        };
+        let (relay, endpoint) = self
+            .relay_selector
+            .get_wireguard_endpoint(&hostname, constraints)
+            .ok_or_else(|| Error::NoWireguardTunnel(hostname.clone()))?;
+
+        let connection = match endpoint {
+            MullvadEndpoint::Wireguard {
+                peer,
+                ipv4_gateway,
+                ipv6_gateway,
+            } => wireguard::ConnectionConfig {
+                tunnel,
+                peer,
+                ipv4_gateway,
+                ipv6_gateway: Some(ipv6_gateway),
+            },
+            MullvadEndpoint::OpenVpn(_) => return Err(Error::NoWireguardTunnel(hostname)),
+        };
+        let tunnel_options = self.settings.effective_tunnel_options();
+        let config = WgQuickConfig {
+            host: relay.ipv4_addr_in.to_string(),
+            connection,
+            dns: tunnel_options.generic.custom_dns_servers,
+            mtu: tunnel_options.wireguard.mtu,
+        };
+        Ok(config.to_config_string(include_private_key))
+    }
+
     fn on_verify_wireguard_key(&mut self, tx: oneshot::Sender<bool>) {
         let account = match self.settings.get_account_token() {
             Some(account) => account,
@@ -1827,10 +2762,14 @@ where
         // Cache the current target state
         let cache_file = self.cache_dir.join(TARGET_START_STATE_FILE);
         log::debug!("Saving tunnel target state to {}", cache_file.display());
+        let cached_target_state = match self.paused_until {
+            Some(paused_until) => CachedTargetState::Paused { paused_until },
+            None => CachedTargetState::TargetState(self.target_state),
+        };
         match File::create(&cache_file) {
             Ok(handle) => {
                 if let Err(e) =
-                    serde_json::to_writer(io::BufWriter::new(handle), &self.target_state)
+                    serde_json::to_writer(io::BufWriter::new(handle), &cached_target_state)
                 {
                     log::error!("Failed to serialize target start state: {}", e);
                 }
@@ -1849,6 +2788,8 @@ where
     /// progress towards that state.
     /// Returns an error if trying to set secured state, but no account token is present.
     fn set_target_state(&mut self, new_state: TargetState) {
+        // Changing the target state ends any pause
+        self.unschedule_pause();
         if new_state != self.target_state || self.tunnel_state.is_in_error_state() {
             debug!("Target state {:?} => {:?}", self.target_state, new_state);
             self.target_state = new_state;
@@ -11,6 +11,7 @@ use mullvad_rpc::{rest::Error as RestError, StatusCode};
 use mullvad_types::{
     account::{AccountData, AccountToken, VoucherSubmission},
     location::GeoIpLocation,
+    openvpn_profile::OpenVpnProfile,
     relay_constraints::{BridgeSettings, BridgeState, RelaySettingsUpdate},
     relay_list::RelayList,
     settings::Settings,
@@ -20,16 +21,33 @@ use mullvad_types::{
 use parking_lot::RwLock;
 use std::{
     collections::{hash_map::Entry, HashMap},
+    net::IpAddr,
     sync::Arc,
+    time::Duration,
 };
 use talpid_ipc;
-use talpid_types::ErrorExt;
+use talpid_types::{
+    dns::{
+        BlockingCategories, DnsBackend, DnsBackends, DnsDiagnostics, EncryptedDnsSettings,
+        ResolverStats, SplitDnsRule,
+    },
+    net::{
+        firewall::{AllowRule, FirewallPolicyKind, LanNetworks},
+        openvpn,
+        wireguard::ConnectivityProbe,
+    },
+    tunnel::TunnelStats,
+    ErrorExt,
+};
 use uuid;
 
 pub const INVALID_VOUCHER_CODE: i64 = -400;
 pub const VOUCHER_USED_ALREADY_CODE: i64 = -401;
 pub const INVALID_ACCOUNT_CODE: i64 = -200;
 
+/// The longest time the tunnel can be paused for.
+const MAX_PAUSE_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+
 
 build_rpc_trait! {
     pub trait ManagementInterfaceApi {
@@ -70,10 +88,23 @@ build_rpc_trait! {
             Self::Metadata, RelaySettingsUpdate
             ) -> BoxFuture<(), Error>;
 
+        /// Store an OpenVPN profile and use it as a custom relay, with the given username and
+        /// password
+        #[rpc(meta, name = "set_openvpn_profile")]
+        fn set_openvpn_profile(&self, Self::Metadata, OpenVpnProfile, String, String) -> BoxFuture<(), Error>;
+
         /// Set if the client should allow communication with the LAN while in secured state.
         #[rpc(meta, name = "set_allow_lan")]
         fn set_allow_lan(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
 
+        /// Set the changes to the networks that are considered to be LAN networks.
+        #[rpc(meta, name = "set_lan_networks")]
+        fn set_lan_networks(&self, Self::Metadata, LanNetworks) -> BoxFuture<(), Error>;
+
+        /// Set the traffic that the firewall allows regardless of the tunnel state.
+        #[rpc(meta, name = "set_firewall_allow_rules")]
+        fn set_firewall_allow_rules(&self, Self::Metadata, Vec<AllowRule>) -> BoxFuture<(), Error>;
+
         /// Set whether to enable the beta program.
         #[rpc(meta, name = "set_show_beta_releases")]
         fn set_show_beta_releases(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
@@ -95,6 +126,11 @@ build_rpc_trait! {
         #[rpc(meta, name = "disconnect")]
         fn disconnect(&self, Self::Metadata) -> BoxFuture<(), Error>;
 
+        /// Disconnect the VPN tunnel, and connect again after the given number of seconds.
+        /// Connecting or disconnecting before then cancels the pause.
+        #[rpc(meta, name = "pause")]
+        fn pause(&self, Self::Metadata, u64) -> BoxFuture<(), Error>;
+
         /// Reconnect if connecting/connected, or do nothing if disconnected.
         #[rpc(meta, name = "reconnect")]
         fn reconnect(&self, Self::Metadata) -> BoxFuture<(), Error>;
@@ -109,6 +145,11 @@ build_rpc_trait! {
         #[rpc(meta, name = "get_current_location")]
         fn get_current_location(&self, Self::Metadata) -> BoxFuture<Option<GeoIpLocation>, Error>;
 
+        /// Returns the traffic counters and latency of the tunnel, or `null` if there is no
+        /// connected tunnel that reports them.
+        #[rpc(meta, name = "get_tunnel_stats")]
+        fn get_tunnel_stats(&self, Self::Metadata) -> BoxFuture<Option<TunnelStats>, Error>;
+
         /// Makes the daemon exit its main loop and quit.
         #[rpc(meta, name = "shutdown")]
         fn shutdown(&self, Self::Metadata) -> BoxFuture<(), Error>;
@@ -134,6 +175,10 @@ build_rpc_trait! {
         #[rpc(meta, name = "set_openvpn_mssfix")]
         fn set_openvpn_mssfix(&self, Self::Metadata, Option<u16>) -> BoxFuture<(), Error>;
 
+        /// Sets the ciphers and advanced options of OpenVPN tunnels
+        #[rpc(meta, name = "set_openvpn_options")]
+        fn set_openvpn_options(&self, Self::Metadata, openvpn::TunnelOptions) -> BoxFuture<(), Error>;
+
         /// Sets proxy details for OpenVPN
         #[rpc(meta, name = "set_bridge_settings")]
         fn set_bridge_settings(&self, Self::Metadata, BridgeSettings) -> BoxFuture<(), Error>;
@@ -146,14 +191,76 @@ build_rpc_trait! {
         #[rpc(meta, name = "set_enable_ipv6")]
         fn set_enable_ipv6(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
 
+        /// Set the DNS servers to use instead of the tunnel gateway(s). An empty list means the
+        /// tunnel gateway(s) are used
+        #[rpc(meta, name = "set_custom_dns_servers")]
+        fn set_custom_dns_servers(&self, Self::Metadata, Vec<IpAddr>) -> BoxFuture<(), Error>;
+
+        /// Set the categories of domains to block through the local DNS resolver. Blocking is
+        /// only supported on Linux
+        #[rpc(meta, name = "set_dns_blocking")]
+        fn set_dns_blocking(&self, Self::Metadata, BlockingCategories) -> BoxFuture<(), Error>;
+
+        /// Set the encrypted DNS servers to use instead of plain DNS. Encrypted DNS is only
+        /// supported on Linux
+        #[rpc(meta, name = "set_encrypted_dns")]
+        fn set_encrypted_dns(&self, Self::Metadata, EncryptedDnsSettings) -> BoxFuture<(), Error>;
+
+        /// Set the rules for which domains to send DNS queries for to other servers than the
+        /// ones used in the tunnel. Split DNS is only supported on Linux
+        #[rpc(meta, name = "set_split_dns")]
+        fn set_split_dns(&self, Self::Metadata, Vec<SplitDnsRule>) -> BoxFuture<(), Error>;
+
+        /// Read the DNS blocklists from disk again
+        #[rpc(meta, name = "reload_dns_blocklists")]
+        fn reload_dns_blocklists(&self, Self::Metadata) -> BoxFuture<(), Error>;
+
+        /// Get the number of queries handled and blocked by the local DNS resolver
+        #[rpc(meta, name = "get_dns_blocking_stats")]
+        fn get_dns_blocking_stats(&self, Self::Metadata) -> BoxFuture<ResolverStats, Error>;
+
+        /// Check that the system uses the DNS servers that were set, and that DNS queries go
+        /// through the tunnel. DNS diagnostics are only supported on Linux
+        #[rpc(meta, name = "diagnose_dns")]
+        fn diagnose_dns(&self, Self::Metadata) -> BoxFuture<DnsDiagnostics, Error>;
+
+        /// Set the backend to manage the system DNS settings through, or detect one if `null`.
+        /// The backend can only be chosen on Linux
+        #[rpc(meta, name = "set_dns_backend")]
+        fn set_dns_backend(&self, Self::Metadata, Option<DnsBackend>) -> BoxFuture<(), Error>;
+
+        /// Get which backends can manage the system DNS settings, why the others can't, and which
+        /// one is used. DNS backends only exist on Linux
+        #[rpc(meta, name = "get_dns_backends")]
+        fn get_dns_backends(&self, Self::Metadata) -> BoxFuture<DnsBackends, Error>;
+
+        /// Render a firewall policy as the nftables script that applies it. The applied policy is
+        /// rendered if it is of the requested kind, otherwise an example is built from the current
+        /// settings. Returns `null` on platforms other than Linux
+        #[rpc(meta, name = "render_firewall_policy")]
+        fn render_firewall_policy(
+            &self,
+            Self::Metadata,
+            FirewallPolicyKind,
+        ) -> BoxFuture<Option<String>, Error>;
+
         /// Set MTU for wireguard tunnels
         #[rpc(meta, name = "set_wireguard_mtu")]
         fn set_wireguard_mtu(&self, Self::Metadata, Option<u16>) -> BoxFuture<(), Error>;
 
+        /// Set if the MTU of wireguard tunnels should be discovered automatically. Only supported
+        /// on Linux.
+        #[rpc(meta, name = "set_wireguard_automatic_mtu")]
+        fn set_wireguard_automatic_mtu(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
+
         /// Set automatic key rotation interval for wireguard tunnels
         #[rpc(meta, name = "set_wireguard_rotation_interval")]
         fn set_wireguard_rotation_interval(&self, Self::Metadata, Option<u32>) -> BoxFuture<(), Error>;
 
+        /// Set the probes used to check connectivity in wireguard tunnels
+        #[rpc(meta, name = "set_wireguard_connectivity_probes")]
+        fn set_wireguard_connectivity_probes(&self, Self::Metadata, Vec<ConnectivityProbe>) -> BoxFuture<(), Error>;
+
         /// Returns the current daemon settings
         #[rpc(meta, name = "get_settings")]
         fn get_settings(&self, Self::Metadata) -> BoxFuture<Settings, Error>;
@@ -170,6 +277,12 @@ build_rpc_trait! {
         #[rpc(meta, name = "verify_wireguard_key")]
         fn verify_wireguard_key(&self, Self::Metadata) -> BoxFuture<bool, Error>;
 
+        /// Export a wg-quick config for the current wireguard key and a relay. The last selected
+        /// relay is used if no hostname is given. The private key is only included if the
+        /// boolean is set.
+        #[rpc(meta, name = "export_wireguard_config")]
+        fn export_wireguard_config(&self, Self::Metadata, Option<String>, bool) -> BoxFuture<String, Error>;
+
         /// Retreive version of the app
         #[rpc(meta, name = "get_current_version")]
         fn get_current_version(&self, Self::Metadata) -> BoxFuture<String, Error>;
@@ -477,7 +590,39 @@ impl ManagementInterfaceApi for ManagementInterface {
         let message = DaemonCommand::UpdateRelaySettings(tx, constraints_update);
         let future = self
             .send_command_to_daemon(message)
-            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| {
+                result.map_err(|error| Error {
+                    code: ErrorCode::ServerError(-900),
+                    message: error.display_chain(),
+                    data: None,
+                })
+            });
+        Box::new(future)
+    }
+
+    fn set_openvpn_profile(
+        &self,
+        _: Self::Metadata,
+        profile: OpenVpnProfile,
+        username: String,
+        password: String,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_openvpn_profile({})", profile.host);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(DaemonCommand::SetOpenVpnProfile(
+                tx, profile, username, password,
+            ))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| {
+                result.map_err(|error| Error {
+                    code: ErrorCode::ServerError(-900),
+                    message: error.display_chain(),
+                    data: None,
+                })
+            });
+
         Box::new(future)
     }
 
@@ -490,6 +635,39 @@ impl ManagementInterfaceApi for ManagementInterface {
         Box::new(future)
     }
 
+    fn set_lan_networks(
+        &self,
+        _: Self::Metadata,
+        lan_networks: LanNetworks,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_lan_networks({:?})", lan_networks);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(DaemonCommand::SetLanNetworks(tx, lan_networks))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| {
+                result.map_err(|error| Error {
+                    code: ErrorCode::ServerError(-900),
+                    message: error.display_chain(),
+                    data: None,
+                })
+            });
+        Box::new(future)
+    }
+
+    fn set_firewall_allow_rules(
+        &self,
+        _: Self::Metadata,
+        rules: Vec<AllowRule>,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_firewall_allow_rules({:?})", rules);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(DaemonCommand::SetFirewallAllowRules(tx, rules))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
     fn set_show_beta_releases(&self, _: Self::Metadata, enabled: bool) -> BoxFuture<(), Error> {
         log::debug!("set_show_beta_releases({})", enabled);
         let (tx, rx) = sync::oneshot::channel();
@@ -550,6 +728,22 @@ impl ManagementInterfaceApi for ManagementInterface {
         Box::new(future)
     }
 
+    fn pause(&self, _: Self::Metadata, seconds: u64) -> BoxFuture<(), Error> {
+        log::debug!("pause({})", seconds);
+        let duration = Duration::from_secs(seconds);
+        if duration.as_secs() == 0 || duration > MAX_PAUSE_DURATION {
+            return Box::new(future::err(Error::invalid_params(format!(
+                "The pause duration must be between 1 and {} seconds",
+                MAX_PAUSE_DURATION.as_secs()
+            ))));
+        }
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(DaemonCommand::Pause(tx, duration))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
     fn reconnect(&self, _: Self::Metadata) -> BoxFuture<(), Error> {
         log::debug!("reconnect");
         let future = self.send_command_to_daemon(DaemonCommand::Reconnect);
@@ -574,6 +768,15 @@ impl ManagementInterfaceApi for ManagementInterface {
         Box::new(future)
     }
 
+    fn get_tunnel_stats(&self, _: Self::Metadata) -> BoxFuture<Option<TunnelStats>, Error> {
+        log::debug!("get_tunnel_stats");
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(DaemonCommand::GetTunnelStats(tx))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
     fn shutdown(&self, _: Self::Metadata) -> BoxFuture<(), Error> {
         log::debug!("shutdown");
         Box::new(self.send_command_to_daemon(DaemonCommand::Shutdown))
@@ -625,6 +828,20 @@ impl ManagementInterfaceApi for ManagementInterface {
         Box::new(future)
     }
 
+    fn set_openvpn_options(
+        &self,
+        _: Self::Metadata,
+        options: openvpn::TunnelOptions,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_openvpn_options({:?})", options);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(DaemonCommand::SetOpenVpnOptions(tx, options))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+
+        Box::new(future)
+    }
+
     fn set_bridge_settings(
         &self,
         _: Self::Metadata,
@@ -665,6 +882,184 @@ impl ManagementInterfaceApi for ManagementInterface {
         Box::new(future)
     }
 
+    fn set_custom_dns_servers(
+        &self,
+        _: Self::Metadata,
+        servers: Vec<IpAddr>,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_custom_dns_servers({:?})", servers);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(DaemonCommand::SetCustomDnsServers(tx, servers))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| {
+                result.map_err(|error| Error {
+                    code: ErrorCode::ServerError(-900),
+                    message: error.display_chain(),
+                    data: None,
+                })
+            });
+
+        Box::new(future)
+    }
+
+    fn set_dns_blocking(
+        &self,
+        _: Self::Metadata,
+        categories: BlockingCategories,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_dns_blocking({:?})", categories);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(DaemonCommand::SetDnsBlocking(tx, categories))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+
+        Box::new(future)
+    }
+
+    fn set_encrypted_dns(
+        &self,
+        _: Self::Metadata,
+        encrypted_dns: EncryptedDnsSettings,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_encrypted_dns({:?})", encrypted_dns);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(DaemonCommand::SetEncryptedDns(tx, encrypted_dns))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+
+        Box::new(future)
+    }
+
+    fn set_split_dns(&self, _: Self::Metadata, rules: Vec<SplitDnsRule>) -> BoxFuture<(), Error> {
+        log::debug!("set_split_dns({:?})", rules);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(DaemonCommand::SetSplitDns(tx, rules))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| {
+                result.map_err(|error| Error {
+                    code: ErrorCode::ServerError(-900),
+                    message: error.display_chain(),
+                    data: None,
+                })
+            });
+
+        Box::new(future)
+    }
+
+    fn reload_dns_blocklists(&self, _: Self::Metadata) -> BoxFuture<(), Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("reload_dns_blocklists");
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(DaemonCommand::ReloadDnsBlocklists(tx))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()));
+            Box::new(future)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            Box::new(future::ok(()))
+        }
+    }
+
+    fn get_dns_blocking_stats(&self, _: Self::Metadata) -> BoxFuture<ResolverStats, Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("get_dns_blocking_stats");
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(DaemonCommand::GetDnsBlockingStats(tx))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()));
+            Box::new(future)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            Box::new(future::ok(ResolverStats::default()))
+        }
+    }
+
+    fn diagnose_dns(&self, _: Self::Metadata) -> BoxFuture<DnsDiagnostics, Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("diagnose_dns");
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(DaemonCommand::DiagnoseDns(tx))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()));
+            Box::new(future)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            Box::new(future::ok(DnsDiagnostics {
+                backend: None,
+                expected_servers: vec![],
+                effective_servers: vec![],
+                checks: vec![talpid_types::dns::DnsCheck {
+                    name: "Platform".to_owned(),
+                    result: talpid_types::dns::DnsCheckResult::Skipped,
+                    details: "DNS diagnostics are only supported on Linux".to_owned(),
+                }],
+            }))
+        }
+    }
+
+    fn set_dns_backend(
+        &self,
+        _: Self::Metadata,
+        backend: Option<DnsBackend>,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_dns_backend({:?})", backend);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(DaemonCommand::SetDnsBackend(tx, backend))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn get_dns_backends(&self, _: Self::Metadata) -> BoxFuture<DnsBackends, Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("get_dns_backends");
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(DaemonCommand::GetDnsBackends(tx))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()));
+            Box::new(future)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            Box::new(future::ok(DnsBackends {
+                forced: None,
+                selected: None,
+                candidates: vec![],
+            }))
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn render_firewall_policy(
+        &self,
+        _: Self::Metadata,
+        kind: FirewallPolicyKind,
+    ) -> BoxFuture<Option<String>, Error> {
+        log::debug!("render_firewall_policy({:?})", kind);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(DaemonCommand::RenderFirewallPolicy(tx, kind))
+            .and_then(|_| rx.map(Some).map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+    #[cfg(not(target_os = "linux"))]
+    fn render_firewall_policy(
+        &self,
+        _: Self::Metadata,
+        _: FirewallPolicyKind,
+    ) -> BoxFuture<Option<String>, Error> {
+        Box::new(future::ok(None))
+    }
+
     /// Set MTU for wireguard tunnels
     fn set_wireguard_mtu(&self, _: Self::Metadata, mtu: Option<u16>) -> BoxFuture<(), Error> {
         log::debug!("set_wireguard_mtu({:?})", mtu);
@@ -675,6 +1070,20 @@ impl ManagementInterfaceApi for ManagementInterface {
         Box::new(future)
     }
 
+    /// Set if the MTU of wireguard tunnels should be discovered automatically
+    fn set_wireguard_automatic_mtu(
+        &self,
+        _: Self::Metadata,
+        automatic_mtu: bool,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_wireguard_automatic_mtu({})", automatic_mtu);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(DaemonCommand::SetWireguardAutomaticMtu(tx, automatic_mtu))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
     /// Set automatic key rotation interval for wireguard tunnels
     fn set_wireguard_rotation_interval(
         &self,
@@ -689,6 +1098,20 @@ impl ManagementInterfaceApi for ManagementInterface {
         Box::new(future)
     }
 
+    /// Set the probes used to check connectivity in wireguard tunnels
+    fn set_wireguard_connectivity_probes(
+        &self,
+        _: Self::Metadata,
+        probes: Vec<ConnectivityProbe>,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_wireguard_connectivity_probes({:?})", probes);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(DaemonCommand::SetWireguardConnectivityProbes(tx, probes))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
     fn get_settings(&self, _: Self::Metadata) -> BoxFuture<Settings, Error> {
         log::debug!("get_settings");
         let (tx, rx) = sync::oneshot::channel();
@@ -731,6 +1154,35 @@ impl ManagementInterfaceApi for ManagementInterface {
         Box::new(future)
     }
 
+    fn export_wireguard_config(
+        &self,
+        _: Self::Metadata,
+        hostname: Option<String>,
+        include_private_key: bool,
+    ) -> BoxFuture<String, Error> {
+        log::debug!(
+            "export_wireguard_config({:?}, {})",
+            hostname,
+            include_private_key
+        );
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(DaemonCommand::ExportWireguardConfig(
+                tx,
+                hostname,
+                include_private_key,
+            ))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| {
+                result.map_err(|error| Error {
+                    code: ErrorCode::ServerError(-900),
+                    message: error.display_chain(),
+                    data: None,
+                })
+            });
+        Box::new(future)
+    }
+
     fn get_current_version(&self, _: Self::Metadata) -> BoxFuture<String, Error> {
         log::debug!("get_current_version");
         let (tx, rx) = sync::oneshot::channel();
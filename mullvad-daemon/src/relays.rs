@@ -237,6 +237,26 @@ impl RelaySelector {
         }
     }
 
+    /// Returns a WireGuard endpoint of the relay with the given hostname, or `None` if there is no
+    /// such relay or if it has no WireGuard tunnels matching the constraints.
+    pub fn get_wireguard_endpoint(
+        &mut self,
+        hostname: &str,
+        constraints: WireguardConstraints,
+    ) -> Option<(Relay, MullvadEndpoint)> {
+        let relay = self
+            .parsed_relays
+            .lock()
+            .relays()
+            .iter()
+            .find(|relay| relay.hostname == hostname)
+            .cloned()?;
+        let wg_tunnel = relay.tunnels.wireguard.choose(&mut self.rng).cloned()?;
+        let endpoint =
+            self.wg_data_to_endpoint(relay.ipv4_addr_in.into(), wg_tunnel, constraints)?;
+        Some((relay, endpoint))
+    }
+
     fn preferred_constraints(
         &self,
         original_constraints: &RelayConstraints,
@@ -318,7 +338,7 @@ impl RelaySelector {
     pub fn get_auto_proxy_settings(
         &mut self,
         bridge_constraints: &InternalBridgeConstraints,
-        location: &Location,
+        location: Option<&Location>,
         retry_attempt: u32,
     ) -> Option<(ProxySettings, Relay)> {
         if !self.should_use_bridge(retry_attempt) {
@@ -343,10 +363,12 @@ impl RelaySelector {
             (retry_attempt % 4) < 2
     }
 
+    /// Picks a bridge matching `constraints`. The bridge closest to `location` is used if a
+    /// location is given, otherwise any matching bridge.
     pub fn get_proxy_settings(
         &mut self,
         constraints: &InternalBridgeConstraints,
-        location: &Location,
+        location: Option<&Location>,
     ) -> Option<(ProxySettings, Relay)> {
         let mut matching_relays: Vec<Relay> = self
             .parsed_relays
@@ -361,12 +383,18 @@ impl RelaySelector {
             return None;
         }
 
-        matching_relays.sort_by_cached_key(|relay| {
-            (relay.location.as_ref().unwrap().distance_from(&location) * 1000.0) as i64
-        });
-        matching_relays.get(0).and_then(|relay| {
+        let relay = match location {
+            Some(location) => {
+                matching_relays.sort_by_cached_key(|relay| {
+                    (relay.location.as_ref().unwrap().distance_from(&location) * 1000.0) as i64
+                });
+                matching_relays.get(0)
+            }
+            None => matching_relays.choose(&mut self.rng),
+        };
+        relay.cloned().and_then(|relay| {
             self.pick_random_bridge(&relay)
-                .map(|bridge| (bridge, relay.clone()))
+                .map(|bridge| (bridge, relay))
         })
     }
 
@@ -700,6 +728,8 @@ impl RelaySelector {
             public_key: data.public_key,
             endpoint: SocketAddr::new(host, port),
             allowed_ips: all_of_the_internet(),
+            psk: None,
+            persistent_keepalive: None,
         };
         Some(MullvadEndpoint::Wireguard {
             peer: peer_config,
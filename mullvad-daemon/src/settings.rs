@@ -5,17 +5,27 @@ use mullvad_types::{
 };
 use std::{
     fs::{self, File},
-    io,
+    io::{self, Write},
+    net::IpAddr,
     ops::Deref,
     path::{Path, PathBuf},
 };
-use talpid_types::ErrorExt;
+use talpid_types::{
+    dns::{BlockingCategories, DnsBackend, EncryptedDnsSettings, SplitDnsRule},
+    net::{
+        firewall::{AllowRule, LanNetworks},
+        openvpn,
+        wireguard::ConnectivityProbe,
+    },
+    ErrorExt,
+};
 
 #[cfg(windows)]
 use talpid_core::logging::windows::log_sink;
 
 
 static SETTINGS_FILE: &str = "settings.json";
+static OPENVPN_PROFILE_FILE: &str = "openvpn-profile.conf";
 
 
 #[derive(err_derive::Error, Debug)]
@@ -146,6 +156,47 @@ impl SettingsPersister {
             .map_err(|e| Error::WriteError(self.path.display().to_string(), e))
     }
 
+    /// Returns the path that user-supplied OpenVPN profiles are stored at.
+    pub fn openvpn_profile_path(&self) -> PathBuf {
+        self.path.with_file_name(OPENVPN_PROFILE_FILE)
+    }
+
+    /// Stores a user-supplied OpenVPN profile next to the settings file and returns its path.
+    /// The profile may contain private keys, so only the owner can read it.
+    pub fn save_openvpn_profile(&self, profile: &str) -> Result<PathBuf, Error> {
+        let path = self.openvpn_profile_path();
+        debug!("Writing OpenVPN profile to {}", path.display());
+        Self::write_private_file(&path, profile)
+            .map_err(|e| Error::WriteError(path.display().to_string(), e))?;
+        Ok(path)
+    }
+
+    fn write_private_file(path: &Path, contents: &str) -> io::Result<()> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(path)?;
+        Self::set_private_file_permissions(&file)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()
+    }
+
+    #[cfg(unix)]
+    fn set_private_file_permissions(file: &File) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(PermissionsExt::from_mode(0o600))
+    }
+
+    #[cfg(windows)]
+    fn set_private_file_permissions(_file: &File) -> io::Result<()> {
+        // The settings directory is only accessible by administrators.
+        Ok(())
+    }
+
     /// Resets default settings
     #[cfg(not(target_os = "android"))]
     pub fn reset(&mut self) -> Result<(), Error> {
@@ -182,6 +233,11 @@ impl SettingsPersister {
         self.update(should_save)
     }
 
+    pub fn set_lan_networks(&mut self, lan_networks: LanNetworks) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.lan_networks, lan_networks);
+        self.update(should_save)
+    }
+
     pub fn set_block_when_disconnected(
         &mut self,
         block_when_disconnected: bool,
@@ -193,6 +249,11 @@ impl SettingsPersister {
         self.update(should_save)
     }
 
+    pub fn set_firewall_allow_rules(&mut self, rules: Vec<AllowRule>) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.firewall_allow_rules, rules);
+        self.update(should_save)
+    }
+
     pub fn set_auto_connect(&mut self, auto_connect: bool) -> Result<bool, Error> {
         let should_save = Self::update_field(&mut self.settings.auto_connect, auto_connect);
         self.update(should_save)
@@ -206,6 +267,15 @@ impl SettingsPersister {
         self.update(should_save)
     }
 
+    pub fn set_openvpn_options(
+        &mut self,
+        openvpn_options: openvpn::TunnelOptions,
+    ) -> Result<bool, Error> {
+        let should_save =
+            Self::update_field(&mut self.settings.tunnel_options.openvpn, openvpn_options);
+        self.update(should_save)
+    }
+
     pub fn set_enable_ipv6(&mut self, enable_ipv6: bool) -> Result<bool, Error> {
         let should_save = Self::update_field(
             &mut self.settings.tunnel_options.generic.enable_ipv6,
@@ -214,11 +284,60 @@ impl SettingsPersister {
         self.update(should_save)
     }
 
+    pub fn set_custom_dns_servers(&mut self, servers: Vec<IpAddr>) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_options.generic.custom_dns_servers,
+            servers,
+        );
+        self.update(should_save)
+    }
+
+    pub fn set_dns_blocking(&mut self, categories: BlockingCategories) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_options.generic.dns_blocking,
+            categories,
+        );
+        self.update(should_save)
+    }
+
+    pub fn set_encrypted_dns(
+        &mut self,
+        encrypted_dns: EncryptedDnsSettings,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_options.generic.encrypted_dns,
+            encrypted_dns,
+        );
+        self.update(should_save)
+    }
+
+    pub fn set_split_dns(&mut self, rules: Vec<SplitDnsRule>) -> Result<bool, Error> {
+        let should_save =
+            Self::update_field(&mut self.settings.tunnel_options.generic.split_dns, rules);
+        self.update(should_save)
+    }
+
+    pub fn set_dns_backend(&mut self, backend: Option<DnsBackend>) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_options.generic.dns_backend,
+            backend,
+        );
+        self.update(should_save)
+    }
+
     pub fn set_wireguard_mtu(&mut self, mtu: Option<u16>) -> Result<bool, Error> {
         let should_save = Self::update_field(&mut self.settings.tunnel_options.wireguard.mtu, mtu);
         self.update(should_save)
     }
 
+    pub fn set_wireguard_automatic_mtu(&mut self, automatic_mtu: bool) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_options.wireguard.automatic_mtu,
+            automatic_mtu,
+        );
+        self.update(should_save)
+    }
+
     pub fn set_wireguard_rotation_interval(
         &mut self,
         automatic_rotation: Option<u32>,
@@ -230,6 +349,17 @@ impl SettingsPersister {
         self.update(should_save)
     }
 
+    pub fn set_wireguard_connectivity_probes(
+        &mut self,
+        probes: Vec<ConnectivityProbe>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_options.wireguard.connectivity_probes,
+            probes,
+        );
+        self.update(should_save)
+    }
+
     pub fn set_show_beta_releases(&mut self, show_beta_releases: bool) -> Result<bool, Error> {
         let should_save =
             Self::update_field(&mut self.settings.show_beta_releases, show_beta_releases);
@@ -6,6 +6,7 @@ use jsonrpc_client_ipc::IpcTransport;
 use mullvad_types::{
     account::{AccountData, AccountToken, VoucherSubmission},
     location::GeoIpLocation,
+    openvpn_profile::OpenVpnProfile,
     relay_constraints::{BridgeSettings, BridgeState, RelaySettings, RelaySettingsUpdate},
     relay_list::RelayList,
     settings::{Settings, TunnelOptions},
@@ -14,7 +15,19 @@ use mullvad_types::{
     wireguard, DaemonEvent,
 };
 use serde::{Deserialize, Serialize};
-use std::{io, path::Path, thread};
+use std::{io, net::IpAddr, path::Path, thread, time::Duration};
+use talpid_types::{
+    dns::{
+        BlockingCategories, DnsBackend, DnsBackends, DnsDiagnostics, EncryptedDnsSettings,
+        ResolverStats, SplitDnsRule,
+    },
+    net::{
+        firewall::{AllowRule, FirewallPolicyKind, LanNetworks},
+        openvpn,
+        wireguard::ConnectivityProbe,
+    },
+    tunnel::TunnelStats,
+};
 
 static NO_ARGS: [u8; 0] = [];
 
@@ -99,6 +112,10 @@ impl DaemonRpcClient {
         self.call("disconnect", &NO_ARGS)
     }
 
+    pub fn pause(&mut self, duration: Duration) -> Result<()> {
+        self.call("pause", &[duration.as_secs()])
+    }
+
     pub fn reconnect(&mut self) -> Result<()> {
         self.call("reconnect", &NO_ARGS)
     }
@@ -123,6 +140,14 @@ impl DaemonRpcClient {
         self.call("set_allow_lan", &[allow_lan])
     }
 
+    pub fn set_lan_networks(&mut self, lan_networks: LanNetworks) -> Result<()> {
+        self.call("set_lan_networks", &[lan_networks])
+    }
+
+    pub fn set_firewall_allow_rules(&mut self, rules: Vec<AllowRule>) -> Result<()> {
+        self.call("set_firewall_allow_rules", &[rules])
+    }
+
     pub fn set_show_beta_releases(&mut self, enabled: bool) -> Result<()> {
         self.call("set_show_beta_releases", &[enabled])
     }
@@ -147,6 +172,10 @@ impl DaemonRpcClient {
         self.call("get_current_location", &NO_ARGS)
     }
 
+    pub fn get_tunnel_stats(&mut self) -> Result<Option<TunnelStats>> {
+        self.call("get_tunnel_stats", &NO_ARGS)
+    }
+
     pub fn get_current_version(&mut self) -> Result<String> {
         self.call("get_current_version", &NO_ARGS)
     }
@@ -187,6 +216,14 @@ impl DaemonRpcClient {
         self.call("verify_wireguard_key", &NO_ARGS)
     }
 
+    pub fn export_wireguard_config(
+        &mut self,
+        hostname: Option<String>,
+        include_private_key: bool,
+    ) -> Result<String> {
+        self.call("export_wireguard_config", &(hostname, include_private_key))
+    }
+
     pub fn get_version_info(&mut self) -> Result<AppVersionInfo> {
         self.call("get_version_info", &NO_ARGS)
     }
@@ -203,18 +240,73 @@ impl DaemonRpcClient {
         self.call("set_enable_ipv6", &[enabled])
     }
 
+    pub fn set_custom_dns_servers(&mut self, servers: Vec<IpAddr>) -> Result<()> {
+        self.call("set_custom_dns_servers", &[servers])
+    }
+
+    pub fn set_dns_blocking(&mut self, categories: BlockingCategories) -> Result<()> {
+        self.call("set_dns_blocking", &[categories])
+    }
+
+    pub fn set_encrypted_dns(&mut self, encrypted_dns: EncryptedDnsSettings) -> Result<()> {
+        self.call("set_encrypted_dns", &[encrypted_dns])
+    }
+
+    pub fn set_split_dns(&mut self, rules: Vec<SplitDnsRule>) -> Result<()> {
+        self.call("set_split_dns", &[rules])
+    }
+
+    pub fn reload_dns_blocklists(&mut self) -> Result<()> {
+        self.call("reload_dns_blocklists", &NO_ARGS)
+    }
+
+    pub fn get_dns_blocking_stats(&mut self) -> Result<ResolverStats> {
+        self.call("get_dns_blocking_stats", &NO_ARGS)
+    }
+
+    pub fn diagnose_dns(&mut self) -> Result<DnsDiagnostics> {
+        self.call("diagnose_dns", &NO_ARGS)
+    }
+
+    pub fn set_dns_backend(&mut self, backend: Option<DnsBackend>) -> Result<()> {
+        self.call("set_dns_backend", &[backend])
+    }
+
+    pub fn get_dns_backends(&mut self) -> Result<DnsBackends> {
+        self.call("get_dns_backends", &NO_ARGS)
+    }
+
+    pub fn render_firewall_policy(&mut self, kind: FirewallPolicyKind) -> Result<Option<String>> {
+        self.call("render_firewall_policy", &[kind])
+    }
+
     pub fn set_wireguard_mtu(&mut self, mtu: Option<u16>) -> Result<()> {
         self.call("set_wireguard_mtu", &[mtu])
     }
 
+    pub fn set_wireguard_automatic_mtu(&mut self, automatic_mtu: bool) -> Result<()> {
+        self.call("set_wireguard_automatic_mtu", &[automatic_mtu])
+    }
+
     pub fn set_wireguard_rotation_interval(&mut self, interval: Option<u32>) -> Result<()> {
         self.call("set_wireguard_rotation_interval", &[interval])
     }
 
+    pub fn set_wireguard_connectivity_probes(
+        &mut self,
+        probes: Vec<ConnectivityProbe>,
+    ) -> Result<()> {
+        self.call("set_wireguard_connectivity_probes", &[probes])
+    }
+
     pub fn set_openvpn_mssfix(&mut self, mssfix: Option<u16>) -> Result<()> {
         self.call("set_openvpn_mssfix", &[mssfix])
     }
 
+    pub fn set_openvpn_options(&mut self, options: openvpn::TunnelOptions) -> Result<()> {
+        self.call("set_openvpn_options", &[options])
+    }
+
     pub fn set_bridge_settings(&mut self, settings: BridgeSettings) -> Result<()> {
         self.call("set_bridge_settings", &[settings])
     }
@@ -239,6 +331,15 @@ impl DaemonRpcClient {
         self.call("update_relay_settings", &[update])
     }
 
+    pub fn set_openvpn_profile(
+        &mut self,
+        profile: OpenVpnProfile,
+        username: String,
+        password: String,
+    ) -> Result<()> {
+        self.call("set_openvpn_profile", &(profile, username, password))
+    }
+
     pub fn get_split_tunnel_processes(&mut self) -> Result<Vec<i32>> {
         self.call("get_split_tunnel_processes", &NO_ARGS)
     }
@@ -24,6 +24,9 @@ pub enum Error {
 
     #[error(display = "Error performing RPC with the remote API")]
     RpcError(#[error(source)] mullvad_rpc::rest::Error),
+
+    #[error(display = "Failed to update relay settings")]
+    UpdateRelaySettings(#[error(source, no_from)] mullvad_daemon::Error),
 }
 
 type Result<T> = std::result::Result<T, Error>;
@@ -226,7 +229,9 @@ impl DaemonInterface {
 
         self.send_command(DaemonCommand::UpdateRelaySettings(tx, update))?;
 
-        rx.wait().map_err(|_| Error::NoResponse)
+        rx.wait()
+            .map_err(|_| Error::NoResponse)?
+            .map_err(Error::UpdateRelaySettings)
     }
 
     fn send_command(&self, command: DaemonCommand) -> Result<()> {
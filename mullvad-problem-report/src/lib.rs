@@ -94,6 +94,14 @@ pub enum LogError {
     #[cfg(target_os = "windows")]
     #[error(display = "Missing %LOCALAPPDATA% environment variable")]
     NoLocalAppDataDir,
+
+    #[cfg(target_os = "linux")]
+    #[error(display = "Failed to connect to the daemon")]
+    DaemonConnect(#[error(source)] io::Error),
+
+    #[cfg(target_os = "linux")]
+    #[error(display = "Failed to get the DNS backends from the daemon")]
+    GetDnsBackends(#[error(source)] mullvad_ipc_client::Error),
 }
 
 pub fn collect_report(
@@ -159,6 +167,12 @@ pub fn collect_report(
         Err(error) => problem_report.add_error("Failed to collect logcat", &error),
     }
 
+    #[cfg(target_os = "linux")]
+    match get_dns_backends() {
+        Ok(backends) => problem_report.add_text("DNS backends", backends.to_string()),
+        Err(error) => problem_report.add_error("Failed to collect DNS backends", &error),
+    }
+
     problem_report.add_logs(extra_logs);
 
     write_problem_report(&output_path, &problem_report).map_err(|source| Error::WriteReportError {
@@ -167,6 +181,15 @@ pub fn collect_report(
     })
 }
 
+/// Asks the daemon which DNS backends can be used right now, and which one it would use.
+#[cfg(target_os = "linux")]
+fn get_dns_backends() -> Result<talpid_types::dns::DnsBackends, LogError> {
+    let mut rpc =
+        mullvad_ipc_client::new_standalone_ipc_client(&mullvad_paths::get_rpc_socket_path())
+            .map_err(LogError::DaemonConnect)?;
+    rpc.get_dns_backends().map_err(LogError::GetDnsBackends)
+}
+
 /// Returns an iterator over all files in the given directory that has the `.log` extension.
 fn list_logs(
     log_dir: PathBuf,
@@ -335,6 +358,13 @@ impl ProblemReport {
         }
     }
 
+    /// Attach some text that isn't read from a file to the report.
+    #[cfg(target_os = "linux")]
+    pub fn add_text(&mut self, label: &'static str, content: String) {
+        let redacted_content = self.redact(&content);
+        self.logs.push((label.to_string(), redacted_content));
+    }
+
     /// Attach an error to the report.
     pub fn add_error(&mut self, message: &'static str, error: &impl ErrorExt) {
         let redacted_error = self.redact(&error.display_chain());
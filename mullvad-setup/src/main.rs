@@ -19,17 +19,31 @@ pub enum Error {
 
     #[error(display = "Firewall error")]
     FirewallError(#[error(source)] firewall::Error),
+
+    #[cfg(target_os = "linux")]
+    #[error(display = "Failed to get cache directory")]
+    CacheDirError(#[error(source)] mullvad_paths::Error),
+
+    #[cfg(target_os = "linux")]
+    #[error(display = "Failed to restore DNS settings")]
+    DnsError(#[error(source)] talpid_core::dns::Error),
 }
 
 fn main() {
     env_logger::init();
 
-    let subcommands = vec![
+    #[allow(unused_mut)]
+    let mut subcommands = vec![
         SubCommand::with_name("prepare-restart")
             .about("Move a running daemon into a blocking state and save its target state"),
         SubCommand::with_name("reset-firewall")
             .about("Remove any firewall rules introduced by the daemon"),
     ];
+    #[cfg(target_os = "linux")]
+    subcommands.push(
+        SubCommand::with_name("reset-dns")
+            .about("Restore DNS settings left behind by a daemon that was not shut down cleanly"),
+    );
 
     let app = clap::App::new(crate_name!())
         .version(PRODUCT_VERSION)
@@ -46,6 +60,8 @@ fn main() {
     let result = match matches.subcommand_name().expect("Subcommand has no name") {
         "prepare-restart" => prepare_restart(),
         "reset-firewall" => reset_firewall(),
+        #[cfg(target_os = "linux")]
+        "reset-dns" => reset_dns(),
         _ => unreachable!("No command matched"),
     };
 
@@ -75,6 +91,22 @@ fn reset_firewall() -> Result<(), Error> {
     firewall.reset_policy().map_err(Error::FirewallError)
 }
 
+#[cfg(target_os = "linux")]
+fn reset_dns() -> Result<(), Error> {
+    // Ensure that the daemon isn't running
+    if let Ok(_) = new_rpc_client() {
+        return Err(Error::DaemonIsRunning);
+    }
+
+    let cache_dir = mullvad_paths::cache_dir().map_err(Error::CacheDirError)?;
+    if talpid_core::dns::restore_from_journal(&cache_dir).map_err(Error::DnsError)? {
+        println!("Restored DNS settings");
+    } else {
+        println!("No DNS settings to restore");
+    }
+    Ok(())
+}
+
 fn new_rpc_client() -> Result<DaemonRpcClient, Error> {
     new_standalone_ipc_client(&mullvad_paths::get_rpc_socket_path()).map_err(Error::DaemonConnect)
 }
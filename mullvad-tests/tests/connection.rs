@@ -5,10 +5,16 @@ use mullvad_tests::{
     mock_openvpn::search_openvpn_args, watch_event, DaemonRunner, MockOpenVpnPluginRpcClient,
     PathWatcher,
 };
-use mullvad_types::DaemonEvent;
-use std::{fs, path::Path, time::Duration};
+use mullvad_types::{
+    relay_constraints::{BridgeSettings, BridgeState, RelaySettingsUpdate},
+    ConnectionConfig, CustomTunnelEndpoint, DaemonEvent,
+};
+use std::{fs, io, path::Path, time::Duration};
 use talpid_types::{
-    net::{Endpoint, TransportProtocol, TunnelEndpoint, TunnelType},
+    net::{
+        openvpn::{self, ProxySettings, RemoteProxySettings},
+        Endpoint, TransportProtocol, TunnelEndpoint, TunnelType,
+    },
     tunnel::{ActionAfterDisconnect, TunnelStateTransition},
 };
 
@@ -179,6 +185,51 @@ fn disconnects() {
     let _ = assert_state_event(state_events, TunnelStateTransition::Disconnected);
 }
 
+#[test]
+fn uses_bridge_for_custom_relay() {
+    let mut daemon = DaemonRunner::spawn();
+    let mut rpc_client = daemon.rpc_client().unwrap();
+    let openvpn_args_file = daemon.mock_openvpn_args_file();
+    let mut openvpn_args_file_events = PathWatcher::watch(&openvpn_args_file).unwrap();
+
+    let relay_endpoint = Endpoint {
+        address: "192.168.0.101:443".parse().unwrap(),
+        protocol: TransportProtocol::Tcp,
+    };
+    rpc_client
+        .update_relay_settings(RelaySettingsUpdate::CustomTunnelEndpoint(
+            CustomTunnelEndpoint::new(
+                "192.168.0.101".to_owned(),
+                ConnectionConfig::OpenVpn(openvpn::ConnectionConfig::new(
+                    relay_endpoint,
+                    "user".to_owned(),
+                    "password".to_owned(),
+                )),
+            ),
+        ))
+        .unwrap();
+    rpc_client
+        .set_bridge_settings(BridgeSettings::Custom(ProxySettings::Remote(
+            RemoteProxySettings {
+                address: "192.168.0.200:1080".parse().unwrap(),
+                auth: None,
+            },
+        )))
+        .unwrap();
+    rpc_client.set_bridge_state(BridgeState::On).unwrap();
+
+    rpc_client.set_account(Some("123456".to_owned())).unwrap();
+    rpc_client.connect().unwrap();
+
+    openvpn_args_file_events.assert_create_write_close_sequence();
+
+    let proxy_arguments = search_openvpn_args(&openvpn_args_file, "--socks-proxy")
+        .take(3)
+        .collect::<io::Result<Vec<_>>>()
+        .expect("Failed to read from mock OpenVPN arguments file");
+    assert_eq!(proxy_arguments, ["--socks-proxy", "192.168.0.200", "1080"]);
+}
+
 fn get_default_endpoint() -> TunnelEndpoint {
     TunnelEndpoint {
         endpoint: Endpoint {
@@ -186,6 +237,8 @@ fn get_default_endpoint() -> TunnelEndpoint {
             protocol: TransportProtocol::Udp,
         },
         tunnel_type: TunnelType::OpenVpn,
+        proxy: None,
+        mtu: None,
     }
 }
 
@@ -1,12 +1,13 @@
-use crate::settings::TunnelOptions;
+use crate::settings::{TunnelOptions, TunnelOptionsOverride};
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
 use std::{
     fmt, io,
     net::{IpAddr, SocketAddr, ToSocketAddrs},
+    path::Path,
 };
-use talpid_types::net::{openvpn, wireguard, Endpoint, TunnelParameters};
+use talpid_types::net::{openvpn, wireguard, Endpoint, TunnelParameters, TunnelType};
 
 
 #[derive(err_derive::Error, Debug)]
@@ -14,8 +15,8 @@ pub enum Error {
     #[error(display = "Invalid host/domain: {}", _0)]
     InvalidHost(String, #[error(source)] io::Error),
 
-    #[error(display = "Host has no IPv4 address: {}", _0)]
-    HostHasNoIpv4(String),
+    #[error(display = "Host has no addresses: {}", _0)]
+    HostHasNoAddresses(String),
 }
 
 
@@ -27,11 +28,57 @@ pub enum Error {
 pub struct CustomTunnelEndpoint {
     host: String,
     config: ConnectionConfig,
+    /// WireGuard peers that share the tunnel config of `config`. Connection attempts rotate
+    /// through the peer in `config` and these peers, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    extra_wireguard_peers: Vec<CustomWireguardPeer>,
+    /// Tunnel options that replace the global ones for this relay.
+    #[serde(default, skip_serializing_if = "TunnelOptionsOverride::is_empty")]
+    tunnel_options: TunnelOptionsOverride,
 }
 
 impl CustomTunnelEndpoint {
     pub fn new(host: String, config: ConnectionConfig) -> Self {
-        Self { host, config }
+        Self {
+            host,
+            config,
+            extra_wireguard_peers: vec![],
+            tunnel_options: TunnelOptionsOverride::default(),
+        }
+    }
+
+    pub fn tunnel_type(&self) -> TunnelType {
+        match &self.config {
+            ConnectionConfig::OpenVpn(_) => TunnelType::OpenVpn,
+            ConnectionConfig::Wireguard(_) => TunnelType::Wireguard,
+        }
+    }
+
+    /// Returns the WireGuard peers that are tried after the first one.
+    pub fn extra_wireguard_peers(&self) -> &[CustomWireguardPeer] {
+        &self.extra_wireguard_peers
+    }
+
+    /// Sets the WireGuard peers that are tried after the first one. They are ignored for OpenVPN
+    /// relays.
+    pub fn set_extra_wireguard_peers(&mut self, peers: Vec<CustomWireguardPeer>) {
+        self.extra_wireguard_peers = peers;
+    }
+
+    pub fn tunnel_options(&self) -> &TunnelOptionsOverride {
+        &self.tunnel_options
+    }
+
+    pub fn set_tunnel_options(&mut self, tunnel_options: TunnelOptionsOverride) {
+        self.tunnel_options = tunnel_options;
+    }
+
+    /// Returns the user-supplied OpenVPN profile of the relay, if any.
+    pub fn openvpn_profile(&self) -> Option<&Path> {
+        match &self.config {
+            ConnectionConfig::OpenVpn(config) => config.profile.as_deref(),
+            ConnectionConfig::Wireguard(_) => None,
+        }
     }
 
     pub fn endpoint(&self) -> Endpoint {
@@ -41,14 +88,26 @@ impl CustomTunnelEndpoint {
         }
     }
 
+    /// Returns the tunnel parameters for a connection attempt. WireGuard relays with extra peers
+    /// use the next peer for every attempt. Once every peer has been tried, the next address of
+    /// each host is used. The addresses of a host are looked up with `resolve`. `tunnel_options`
+    /// must already have the overrides of the relay applied, as returned by
+    /// `Settings::effective_tunnel_options`.
     pub fn to_tunnel_parameters(
         &self,
         tunnel_options: TunnelOptions,
         proxy: Option<openvpn::ProxySettings>,
+        retry_attempt: u32,
+        resolve: impl FnOnce(&str) -> Result<Vec<IpAddr>, Error>,
     ) -> Result<TunnelParameters, Error> {
-        let ip = resolve_to_ip(&self.host)?;
-        let mut config = self.config.clone();
-        config.set_ip(ip);
+        let (host, mut config) = self.select_peer(retry_attempt);
+        let addresses = resolve(host)?;
+        let peer_count = self.extra_wireguard_peers.len() as u32 + 1;
+        let address_index = (retry_attempt / peer_count) as usize % addresses.len().max(1);
+        let ip = addresses
+            .get(address_index)
+            .ok_or_else(|| Error::HostHasNoAddresses(host.to_owned()))?;
+        config.set_ip(*ip);
 
         let parameters = match config {
             ConnectionConfig::OpenVpn(config) => openvpn::TunnelParameters {
@@ -67,6 +126,21 @@ impl CustomTunnelEndpoint {
         };
         Ok(parameters)
     }
+
+    fn select_peer(&self, retry_attempt: u32) -> (&str, ConnectionConfig) {
+        let mut config = self.config.clone();
+        if let ConnectionConfig::Wireguard(ref mut connection) = config {
+            let index = retry_attempt as usize % (self.extra_wireguard_peers.len() + 1);
+            if let Some(extra_peer) = index
+                .checked_sub(1)
+                .and_then(|index| self.extra_wireguard_peers.get(index))
+            {
+                connection.peer = extra_peer.peer.clone();
+                return (&extra_peer.host, config);
+            }
+        }
+        (&self.host, config)
+    }
 }
 
 impl fmt::Display for CustomTunnelEndpoint {
@@ -78,34 +152,67 @@ impl fmt::Display for CustomTunnelEndpoint {
                 self.host,
                 config.endpoint.address.port(),
                 config.endpoint.protocol
-            ),
-            ConnectionConfig::Wireguard(connection) => write!(
-                f,
-                "WireGuard relay - {} with public key {}",
-                connection.peer.endpoint, connection.peer.public_key
-            ),
+            )?,
+            ConnectionConfig::Wireguard(connection) => {
+                write!(
+                    f,
+                    "WireGuard relay - {} with public key {}",
+                    connection.peer.endpoint, connection.peer.public_key
+                )?;
+                for extra_peer in &self.extra_wireguard_peers {
+                    write!(f, ", then {}", extra_peer)?;
+                }
+            }
         }
+        if !self.tunnel_options.is_empty() {
+            write!(f, " ({})", self.tunnel_options)?;
+        }
+        Ok(())
+    }
+}
+
+/// A WireGuard peer of a custom relay, in addition to the peer in its connection config.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CustomWireguardPeer {
+    /// Hostname or IP of the peer. It replaces the IP of `peer.endpoint` when connecting.
+    pub host: String,
+    pub peer: wireguard::PeerConfig,
+}
+
+impl fmt::Display for CustomWireguardPeer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{} with public key {}",
+            self.host,
+            self.peer.endpoint.port(),
+            self.peer.public_key
+        )
     }
 }
 
 
 /// Does a DNS lookup if the host isn't an IP.
-/// Returns the first IPv4 address if one exists, otherwise the first IPv6 address.
+/// Returns every address of the host, with the IPv4 addresses first.
 /// Rust only provides means to resolve a socket addr, not just a host, for some reason. So
 /// because of this we do the resolving with port zero and then pick out the IPs.
-fn resolve_to_ip(host: &str) -> Result<IpAddr, Error> {
-    let (mut ipv4, mut ipv6): (Vec<IpAddr>, Vec<IpAddr>) = (host, 0)
+pub fn resolve_host(host: &str) -> Result<Vec<IpAddr>, Error> {
+    let mut addresses: Vec<IpAddr> = Vec::new();
+    for address in (host, 0)
         .to_socket_addrs()
         .map_err(|e| Error::InvalidHost(host.to_owned(), e))?
-        .map(|addr| addr.ip())
-        .partition(|addr| addr.is_ipv4());
-
-    ipv4.pop()
-        .or_else(|| {
-            log::info!("No IPv4 for host {}", host);
-            ipv6.pop()
-        })
-        .ok_or_else(|| Error::HostHasNoIpv4(host.to_owned()))
+    {
+        if !addresses.contains(&address.ip()) {
+            addresses.push(address.ip());
+        }
+    }
+    // The sort is stable, so the resolver's order is kept within each family.
+    addresses.sort_by_key(|address| address.is_ipv6());
+
+    if addresses.is_empty() {
+        return Err(Error::HostHasNoAddresses(host.to_owned()));
+    }
+    Ok(addresses)
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -129,3 +236,147 @@ impl ConnectionConfig {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{relay_constraints::RelaySettingsUpdate, settings::Settings};
+    use std::net::Ipv4Addr;
+    use talpid_types::net::all_of_the_internet;
+
+    fn peer(key: u8, port: u16) -> wireguard::PeerConfig {
+        wireguard::PeerConfig {
+            public_key: [key; 32].into(),
+            allowed_ips: all_of_the_internet(),
+            endpoint: SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port),
+            psk: None,
+            persistent_keepalive: None,
+        }
+    }
+
+    #[test]
+    fn test_rotate_wireguard_peers() {
+        let mut relay = CustomTunnelEndpoint::new(
+            "first.example.com".to_owned(),
+            ConnectionConfig::Wireguard(wireguard::ConnectionConfig {
+                tunnel: wireguard::TunnelConfig {
+                    private_key: [0; 32].into(),
+                    addresses: vec![Ipv4Addr::new(10, 99, 0, 2).into()],
+                },
+                peer: peer(1, 51820),
+                ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+                ipv6_gateway: None,
+            }),
+        );
+        relay.set_extra_wireguard_peers(vec![CustomWireguardPeer {
+            host: "second.example.com".to_owned(),
+            peer: peer(2, 53),
+        }]);
+
+        let selected_peers: Vec<_> = (0..4)
+            .map(|attempt| match relay.select_peer(attempt) {
+                (host, ConnectionConfig::Wireguard(connection)) => {
+                    (host.to_owned(), connection.peer.endpoint.port())
+                }
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            selected_peers,
+            vec![
+                ("first.example.com".to_owned(), 51820),
+                ("second.example.com".to_owned(), 53),
+                ("first.example.com".to_owned(), 51820),
+                ("second.example.com".to_owned(), 53),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rotate_addresses() {
+        let relay = CustomTunnelEndpoint::new(
+            "relay.example.com".to_owned(),
+            ConnectionConfig::OpenVpn(openvpn::ConnectionConfig::new(
+                Endpoint {
+                    address: SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 1194),
+                    protocol: talpid_types::net::TransportProtocol::Udp,
+                },
+                "user".to_owned(),
+                "password".to_owned(),
+            )),
+        );
+        let addresses: Vec<IpAddr> = vec![
+            Ipv4Addr::new(10, 0, 0, 1).into(),
+            Ipv4Addr::new(10, 0, 0, 2).into(),
+            "fd00::1".parse().unwrap(),
+        ];
+
+        let selected_addresses: Vec<_> = (0..4)
+            .map(|attempt| {
+                let parameters = relay
+                    .to_tunnel_parameters(TunnelOptions::default(), None, attempt, |host| {
+                        assert_eq!(host, "relay.example.com");
+                        Ok(addresses.clone())
+                    })
+                    .unwrap();
+                parameters.get_tunnel_endpoint().endpoint.address.ip()
+            })
+            .collect();
+        assert_eq!(
+            selected_addresses,
+            vec![addresses[0], addresses[1], addresses[2], addresses[0]]
+        );
+    }
+
+    #[test]
+    fn test_tunnel_options_override() {
+        let mut relay = CustomTunnelEndpoint::new(
+            "10.0.0.1".to_owned(),
+            ConnectionConfig::Wireguard(wireguard::ConnectionConfig {
+                tunnel: wireguard::TunnelConfig {
+                    private_key: [0; 32].into(),
+                    addresses: vec![Ipv4Addr::new(10, 99, 0, 2).into()],
+                },
+                peer: peer(1, 51820),
+                ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+                ipv6_gateway: None,
+            }),
+        );
+        relay.set_tunnel_options(TunnelOptionsOverride {
+            wireguard_mtu: Some(1280),
+            custom_dns_servers: Some(vec![Ipv4Addr::new(192, 0, 2, 53).into()]),
+            ..Default::default()
+        });
+        let mut settings = Settings::default();
+        settings.tunnel_options.wireguard.mtu = Some(1420);
+        settings.tunnel_options.generic.enable_ipv6 = true;
+        settings.tunnel_options.generic.custom_dns_servers =
+            vec![Ipv4Addr::new(10, 64, 0, 1).into()];
+        settings.update_relay_settings(RelaySettingsUpdate::CustomTunnelEndpoint(relay.clone()));
+
+        match relay
+            .to_tunnel_parameters(settings.effective_tunnel_options(), None, 0, |_| {
+                Ok(vec![Ipv4Addr::new(10, 0, 0, 1).into()])
+            })
+            .unwrap()
+        {
+            TunnelParameters::Wireguard(parameters) => {
+                assert_eq!(parameters.options.mtu, Some(1280));
+                assert!(parameters.generic_options.enable_ipv6);
+                assert_eq!(
+                    parameters.generic_options.custom_dns_servers,
+                    vec![IpAddr::from(Ipv4Addr::new(192, 0, 2, 53))]
+                );
+            }
+            parameters => panic!("Unexpected parameters: {:?}", parameters),
+        }
+    }
+
+    #[test]
+    fn test_resolve_ip_host() {
+        assert_eq!(
+            resolve_host("192.168.1.1").unwrap(),
+            vec![IpAddr::from(Ipv4Addr::new(192, 168, 1, 1))]
+        );
+    }
+}
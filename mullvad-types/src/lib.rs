@@ -4,12 +4,14 @@ pub mod account;
 pub mod auth_failed;
 pub mod endpoint;
 pub mod location;
+pub mod openvpn_profile;
 pub mod relay_constraints;
 pub mod relay_list;
 pub mod settings;
 pub mod states;
 pub mod version;
 pub mod wireguard;
+pub mod wg_quick;
 
 mod custom_tunnel;
 pub use crate::custom_tunnel::*;
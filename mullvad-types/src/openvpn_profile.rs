@@ -0,0 +1,409 @@
+//! Conversion of OpenVPN profiles into custom OpenVPN relays.
+use serde::{Deserialize, Serialize};
+use std::io;
+use talpid_types::net::TransportProtocol;
+
+/// Port used when the profile doesn't specify one.
+const DEFAULT_PORT: u16 = 1194;
+
+/// Options that are set by the app and therefore removed from profiles.
+static REPLACED_OPTIONS: &[&str] = &[
+    "remote",
+    "proto",
+    "port",
+    "rport",
+    "auth-user-pass",
+    "client",
+    "nobind",
+    "dev",
+    "dev-type",
+    "dev-node",
+    "verb",
+    "log",
+    "log-append",
+];
+
+/// Options that are passed on to OpenVPN. Anything else is rejected, since OpenVPN runs as root
+/// and many options run commands, load plugins or write files.
+static ALLOWED_OPTIONS: &[&str] = &[
+    "allow-compression",
+    "auth",
+    "auth-nocache",
+    "cipher",
+    "comp-lzo",
+    "compress",
+    "connect-retry",
+    "connect-retry-max",
+    "data-ciphers",
+    "data-ciphers-fallback",
+    "explicit-exit-notify",
+    "float",
+    "fragment",
+    "hand-window",
+    "keepalive",
+    "key-direction",
+    "mssfix",
+    "mute",
+    "mute-replay-warnings",
+    "ncp-ciphers",
+    "ncp-disable",
+    "ns-cert-type",
+    "persist-key",
+    "persist-tun",
+    "ping",
+    "ping-restart",
+    "pull",
+    "rcvbuf",
+    "remote-cert-eku",
+    "remote-cert-ku",
+    "remote-cert-tls",
+    "reneg-sec",
+    "resolv-retry",
+    "server-poll-timeout",
+    "sndbuf",
+    "tls-client",
+    "tls-timeout",
+    "tls-version-max",
+    "tls-version-min",
+    "tun-mtu",
+    "verify-x509-name",
+];
+
+/// Options that take a file, which is inlined into the profile. These are the only inline blocks
+/// that are allowed.
+static FILE_OPTIONS: &[&str] = &[
+    "ca",
+    "cert",
+    "key",
+    "extra-certs",
+    "pkcs12",
+    "tls-auth",
+    "tls-crypt",
+    "tls-crypt-v2",
+    "crl-verify",
+];
+
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "Line {}: invalid value for {}", _0, _1)]
+    InvalidValue(usize, String),
+
+    #[error(display = "Line {}: <{}> is never closed", _0, _1)]
+    UnclosedBlock(usize, String),
+
+    #[error(display = "Failed to read {}", _0)]
+    ReadFile(String, #[error(source)] io::Error),
+
+    #[error(display = "Unsupported options: {}", _0)]
+    UnsupportedOptions(String),
+
+    #[error(display = "The profile has no remote")]
+    MissingRemote,
+
+    #[error(display = "Expected exactly one remote, found {}", _0)]
+    InvalidRemoteCount(usize),
+}
+
+/// A custom OpenVPN relay read from an `.ovpn` profile.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpenVpnProfile {
+    /// Hostname or IP of the relay, from the `remote` option.
+    pub host: String,
+    pub port: u16,
+    pub protocol: TransportProtocol,
+    /// Whether the profile uses `auth-user-pass`.
+    pub requires_credentials: bool,
+    /// The remaining profile, with referenced files inlined. It can be given to OpenVPN as is.
+    pub config: String,
+}
+
+impl OpenVpnProfile {
+    /// Parses an OpenVPN profile. Files referenced by the profile are read with `read_file`, and
+    /// inlined so that the profile can be stored on its own.
+    pub fn parse(
+        profile: &str,
+        read_file: impl FnMut(&str) -> io::Result<String>,
+    ) -> Result<Self, Error> {
+        let mut options = ParsedOptions::parse(profile, read_file)?;
+
+        if !options.unsupported_options.is_empty() {
+            return Err(Error::UnsupportedOptions(
+                options.unsupported_options.join(", "),
+            ));
+        }
+        let (host, remote_port, remote_protocol) = match options.remotes.len() {
+            0 => return Err(Error::MissingRemote),
+            1 => options.remotes.remove(0),
+            count => return Err(Error::InvalidRemoteCount(count)),
+        };
+
+        options.config.push(String::new());
+        Ok(OpenVpnProfile {
+            host,
+            port: remote_port.or(options.port).unwrap_or(DEFAULT_PORT),
+            protocol: remote_protocol
+                .or(options.protocol)
+                .unwrap_or(TransportProtocol::Udp),
+            requires_credentials: options.requires_credentials,
+            config: options.config.join("\n"),
+        })
+    }
+
+    /// Checks that `config` only contains options that are allowed in profiles, with every file
+    /// inlined. Profiles are sent by clients, so this is checked again before the profile is
+    /// given to OpenVPN.
+    pub fn validate(&self) -> Result<(), Error> {
+        let options = ParsedOptions::parse(&self.config, |_| {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Files have to be inlined",
+            ))
+        })?;
+
+        let mut rejected_options = options.replaced_options;
+        rejected_options.extend(options.unsupported_options);
+        if !rejected_options.is_empty() {
+            return Err(Error::UnsupportedOptions(rejected_options.join(", ")));
+        }
+        Ok(())
+    }
+}
+
+/// The options of a profile, sorted by how they are handled.
+#[derive(Default)]
+struct ParsedOptions {
+    /// Lines that are passed on to OpenVPN.
+    config: Vec<String>,
+    remotes: Vec<(String, Option<u16>, Option<TransportProtocol>)>,
+    port: Option<u16>,
+    protocol: Option<TransportProtocol>,
+    requires_credentials: bool,
+    /// Options that are set by the app instead.
+    replaced_options: Vec<String>,
+    unsupported_options: Vec<String>,
+}
+
+impl ParsedOptions {
+    fn parse(
+        profile: &str,
+        mut read_file: impl FnMut(&str) -> io::Result<String>,
+    ) -> Result<Self, Error> {
+        let mut options = ParsedOptions::default();
+
+        let mut lines = profile.lines().enumerate();
+        while let Some((index, line)) = lines.next() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if line.starts_with('<') && line.ends_with('>') && !line.starts_with("</") {
+                let name = &line[1..line.len() - 1];
+                if !FILE_OPTIONS.contains(&name) {
+                    options.unsupported_options.push(name.to_owned());
+                }
+                // OpenVPN ends the block at the first line that starts with the end tag.
+                let end_tag = format!("</{}>", name);
+                options.config.push(line.to_owned());
+                loop {
+                    match lines.next() {
+                        Some((_, block_line)) => {
+                            options.config.push(block_line.trim_end().to_owned());
+                            if block_line.trim_start().starts_with(&end_tag) {
+                                break;
+                            }
+                        }
+                        None => return Err(Error::UnclosedBlock(line_number, name.to_owned())),
+                    }
+                }
+                continue;
+            }
+
+            let mut args = line.split_whitespace();
+            let option = args.next().unwrap_or("").trim_start_matches("--");
+            let args: Vec<&str> = args.collect();
+            let invalid_value = || Error::InvalidValue(line_number, option.to_owned());
+
+            if REPLACED_OPTIONS.contains(&option) {
+                options.replaced_options.push(option.to_owned());
+            }
+            match option {
+                "remote" => {
+                    let host = args.get(0).ok_or_else(invalid_value)?;
+                    let remote_port = args
+                        .get(1)
+                        .map(|port| port.parse::<u16>().map_err(|_| invalid_value()))
+                        .transpose()?;
+                    let remote_protocol = args
+                        .get(2)
+                        .map(|protocol| parse_protocol(protocol).ok_or_else(invalid_value))
+                        .transpose()?;
+                    options
+                        .remotes
+                        .push((host.to_string(), remote_port, remote_protocol));
+                }
+                "port" | "rport" => {
+                    let value = args.get(0).ok_or_else(invalid_value)?;
+                    options.port = Some(value.parse::<u16>().map_err(|_| invalid_value())?);
+                }
+                "proto" => {
+                    let value = args.get(0).ok_or_else(invalid_value)?;
+                    options.protocol = Some(parse_protocol(value).ok_or_else(invalid_value)?);
+                }
+                "auth-user-pass" => options.requires_credentials = true,
+                _ if REPLACED_OPTIONS.contains(&option) => (),
+                _ if FILE_OPTIONS.contains(&option) => match args.get(0) {
+                    Some(&"[inline]") => options.config.push(line.to_owned()),
+                    Some(path) => {
+                        let contents =
+                            read_file(path).map_err(|e| Error::ReadFile(path.to_string(), e))?;
+                        options.config.push(format!("<{}>", option));
+                        options.config.push(contents.trim_end().to_owned());
+                        options.config.push(format!("</{}>", option));
+                        // The key direction can't be given together with an inline key.
+                        if let Some(direction) = args.get(1) {
+                            options.config.push(format!("key-direction {}", direction));
+                        }
+                    }
+                    None => return Err(invalid_value()),
+                },
+                _ if ALLOWED_OPTIONS.contains(&option) => options.config.push(line.to_owned()),
+                _ => options.unsupported_options.push(option.to_owned()),
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn parse_protocol(protocol: &str) -> Option<TransportProtocol> {
+    match protocol {
+        "udp" | "udp4" | "udp6" => Some(TransportProtocol::Udp),
+        "tcp" | "tcp4" | "tcp6" | "tcp-client" | "tcp4-client" | "tcp6-client" => {
+            Some(TransportProtocol::Tcp)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn no_files(path: &str) -> io::Result<String> {
+        panic!("Unexpected read of {}", path);
+    }
+
+    #[test]
+    fn test_parse_profile() {
+        let profile = r#"
+client
+dev tun
+proto tcp
+remote vpn.example.com 443
+auth-user-pass
+cipher AES-256-GCM
+# Certificates
+ca ca.crt
+tls-auth ta.key 1
+<cert>
+-----BEGIN CERTIFICATE-----
+MIIB
+-----END CERTIFICATE-----
+</cert>
+"#;
+        let parsed = OpenVpnProfile::parse(profile, |path| match path {
+            "ca.crt" => Ok("CA\n".to_owned()),
+            "ta.key" => Ok("TLS AUTH".to_owned()),
+            _ => Err(io::Error::new(io::ErrorKind::NotFound, "no such file")),
+        })
+        .unwrap();
+
+        assert_eq!(
+            parsed,
+            OpenVpnProfile {
+                host: "vpn.example.com".to_owned(),
+                port: 443,
+                protocol: TransportProtocol::Tcp,
+                requires_credentials: true,
+                config: "cipher AES-256-GCM\n<ca>\nCA\n</ca>\n<tls-auth>\nTLS AUTH\n</tls-auth>\n\
+                         key-direction 1\n<cert>\n-----BEGIN CERTIFICATE-----\nMIIB\n\
+                         -----END CERTIFICATE-----\n</cert>\n"
+                    .to_owned(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_default_remote_options() {
+        let parsed = OpenVpnProfile::parse("remote 10.0.0.1\n", no_files).unwrap();
+        assert_eq!(parsed.port, DEFAULT_PORT);
+        assert_eq!(parsed.protocol, TransportProtocol::Udp);
+        assert!(!parsed.requires_credentials);
+
+        let parsed =
+            OpenVpnProfile::parse("port 1300\nremote 10.0.0.1 1301 tcp\n", no_files).unwrap();
+        assert_eq!(parsed.port, 1301);
+        assert_eq!(parsed.protocol, TransportProtocol::Tcp);
+    }
+
+    #[test]
+    fn test_invalid_profiles() {
+        match OpenVpnProfile::parse("remote a\nup /bin/up.sh\nscript-security 2\n", no_files) {
+            Err(Error::UnsupportedOptions(options)) => assert_eq!(options, "up, script-security"),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        let profile = "remote a\ntls-verify /bin/check\nlearn-address /bin/learn\n\
+                       status /etc/motd\n<connection>\nremote b\n</connection>\n";
+        match OpenVpnProfile::parse(profile, no_files) {
+            Err(Error::UnsupportedOptions(options)) => {
+                assert_eq!(options, "tls-verify, learn-address, status, connection")
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        match OpenVpnProfile::parse("cipher AES-256-GCM\n", no_files) {
+            Err(Error::MissingRemote) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        match OpenVpnProfile::parse("remote a\nremote b\n", no_files) {
+            Err(Error::InvalidRemoteCount(2)) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        match OpenVpnProfile::parse("remote a\n<ca>\nCA\n", no_files) {
+            Err(Error::UnclosedBlock(2, name)) => assert_eq!(name, "ca"),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        match OpenVpnProfile::parse("remote a\n<ca>\n</ca> x\nup /bin/up.sh\n", no_files) {
+            Err(Error::UnsupportedOptions(options)) => assert_eq!(options, "up"),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_validate_profile() {
+        let profile = OpenVpnProfile::parse(
+            "remote a\nlog /tmp/openvpn.log\ncipher AES-256-GCM\nca ca.crt\n",
+            |_| Ok("CA".to_owned()),
+        )
+        .unwrap();
+        assert!(profile.validate().is_ok());
+
+        let with_config = |config: &str| OpenVpnProfile {
+            config: config.to_owned(),
+            ..profile.clone()
+        };
+        match with_config("log /etc/motd\nremote b\n").validate() {
+            Err(Error::UnsupportedOptions(options)) => assert_eq!(options, "log, remote"),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        match with_config("plugin /tmp/plugin.so\n").validate() {
+            Err(Error::UnsupportedOptions(options)) => assert_eq!(options, "plugin"),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        match with_config("ca /etc/shadow\n").validate() {
+            Err(Error::ReadFile(path, _)) => assert_eq!(path, "/etc/shadow"),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+}
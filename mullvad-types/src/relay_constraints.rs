@@ -4,6 +4,7 @@
 use crate::{
     location::{CityCode, CountryCode, Hostname},
     relay_list::{OpenVpnEndpointData, WireguardEndpointData},
+    settings::TunnelOptionsOverride,
     CustomTunnelEndpoint,
 };
 #[cfg(target_os = "android")]
@@ -143,6 +144,10 @@ pub struct RelayConstraints {
     pub wireguard_constraints: WireguardConstraints,
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub openvpn_constraints: OpenVpnConstraints,
+    /// Tunnel options that replace the global ones for the selected relays.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[serde(default, skip_serializing_if = "TunnelOptionsOverride::is_empty")]
+    pub tunnel_options: TunnelOptionsOverride,
 }
 
 #[cfg(target_os = "android")]
@@ -153,6 +158,7 @@ impl Default for RelayConstraints {
             tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
             wireguard_constraints: WireguardConstraints::default(),
             openvpn_constraints: OpenVpnConstraints::default(),
+            tunnel_options: TunnelOptionsOverride::default(),
         }
     }
 }
@@ -170,6 +176,9 @@ impl RelayConstraints {
             openvpn_constraints: update
                 .openvpn_constraints
                 .unwrap_or_else(|| self.openvpn_constraints.clone()),
+            tunnel_options: update
+                .tunnel_options
+                .unwrap_or_else(|| self.tunnel_options.clone()),
         }
     }
 }
@@ -196,9 +205,13 @@ impl fmt::Display for RelayConstraints {
         }
         write!(f, " in ")?;
         match self.location {
-            Constraint::Any => write!(f, "any location"),
-            Constraint::Only(ref location_constraint) => location_constraint.fmt(f),
+            Constraint::Any => write!(f, "any location")?,
+            Constraint::Only(ref location_constraint) => location_constraint.fmt(f)?,
         }
+        if !self.tunnel_options.is_empty() {
+            write!(f, " with {}", self.tunnel_options)?;
+        }
+        Ok(())
     }
 }
 
@@ -432,4 +445,6 @@ pub struct RelayConstraintsUpdate {
     pub wireguard_constraints: Option<WireguardConstraints>,
     #[cfg_attr(target_os = "android", jnix(default))]
     pub openvpn_constraints: Option<OpenVpnConstraints>,
+    #[cfg_attr(target_os = "android", jnix(default))]
+    pub tunnel_options: Option<TunnelOptionsOverride>,
 }
@@ -67,7 +67,9 @@ impl super::SettingsMigration for Migration {
                 bridge_settings: old.bridge_settings,
                 bridge_state: old.bridge_state,
                 allow_lan: old.allow_lan,
+                lan_networks: Default::default(),
                 block_when_disconnected: old.block_when_disconnected,
+                firewall_allow_rules: vec![],
                 auto_connect: old.auto_connect,
                 tunnel_options: old.tunnel_options,
                 show_beta_releases: false,
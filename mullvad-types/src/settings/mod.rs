@@ -7,7 +7,11 @@ use jnix::IntoJava;
 use log::{debug, info};
 use serde::{Deserialize, Serialize};
 use serde_json;
-use talpid_types::net::{openvpn, wireguard, GenericTunnelOptions};
+use std::{fmt, net::IpAddr};
+use talpid_types::net::{
+    firewall::{AllowRule, LanNetworks},
+    openvpn, wireguard, GenericTunnelOptions,
+};
 
 mod migrations;
 
@@ -38,10 +42,16 @@ pub struct Settings {
     bridge_state: BridgeState,
     /// If the daemon should allow communication with private (LAN) networks.
     pub allow_lan: bool,
+    /// Changes to the networks that are considered to be private (LAN) networks.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub lan_networks: LanNetworks,
     /// Extra level of kill switch. When this setting is on, the disconnected state will block
     /// the firewall to not allow any traffic in or out.
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub block_when_disconnected: bool,
+    /// Traffic that the firewall always allows, regardless of the tunnel state.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub firewall_allow_rules: Vec<AllowRule>,
     /// If the daemon should connect the VPN tunnel directly on start or not.
     pub auto_connect: bool,
     /// Options that should be applied to tunnels of a specific type regardless of where the relays
@@ -68,7 +78,9 @@ impl Default for Settings {
             }),
             bridge_state: BridgeState::Auto,
             allow_lan: false,
+            lan_networks: LanNetworks::default(),
             block_when_disconnected: false,
+            firewall_allow_rules: vec![],
             auto_connect: false,
             tunnel_options: TunnelOptions::default(),
             show_beta_releases: false,
@@ -135,6 +147,18 @@ impl Settings {
         }
     }
 
+    /// Returns the global tunnel options with the overrides of the current relay settings
+    /// applied. This is the only place where the overrides are applied.
+    pub fn effective_tunnel_options(&self) -> TunnelOptions {
+        let tunnel_options = self.tunnel_options.clone();
+        match &self.relay_settings {
+            RelaySettings::CustomTunnelEndpoint(endpoint) => {
+                endpoint.tunnel_options().apply(tunnel_options)
+            }
+            RelaySettings::Normal(constraints) => constraints.tunnel_options.apply(tunnel_options),
+        }
+    }
+
     pub fn get_bridge_state(&self) -> &BridgeState {
         &self.bridge_state
     }
@@ -174,16 +198,90 @@ impl Default for TunnelOptions {
             openvpn: openvpn::TunnelOptions::default(),
             wireguard: wireguard::TunnelOptions {
                 mtu: None,
+                automatic_mtu: false,
                 automatic_rotation: None,
+                connectivity_probes: vec![],
             },
             generic: GenericTunnelOptions {
                 // Enable IPv6 be default on Android
                 enable_ipv6: cfg!(target_os = "android"),
+                custom_dns_servers: vec![],
+                dns_blocking: Default::default(),
+                encrypted_dns: Default::default(),
+                split_dns: vec![],
+                dns_backend: None,
             },
         }
     }
 }
 
+/// Tunnel options that replace the global [`TunnelOptions`] for a single relay. Options that are
+/// not set fall back on the global ones.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TunnelOptionsOverride {
+    pub wireguard_mtu: Option<u16>,
+    pub openvpn_mssfix: Option<u16>,
+    pub enable_ipv6: Option<bool>,
+    /// Replaces the global custom DNS servers. An empty list uses the DNS of the relay.
+    pub custom_dns_servers: Option<Vec<IpAddr>>,
+}
+
+impl TunnelOptionsOverride {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Returns `tunnel_options` with the overridden options replaced.
+    pub fn apply(&self, mut tunnel_options: TunnelOptions) -> TunnelOptions {
+        if let Some(mtu) = self.wireguard_mtu {
+            tunnel_options.wireguard.mtu = Some(mtu);
+        }
+        if let Some(mssfix) = self.openvpn_mssfix {
+            tunnel_options.openvpn.mssfix = Some(mssfix);
+        }
+        if let Some(enable_ipv6) = self.enable_ipv6 {
+            tunnel_options.generic.enable_ipv6 = enable_ipv6;
+        }
+        if let Some(servers) = &self.custom_dns_servers {
+            tunnel_options.generic.custom_dns_servers = servers.clone();
+        }
+        tunnel_options
+    }
+}
+
+impl fmt::Display for TunnelOptionsOverride {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut options = vec![];
+        if let Some(mtu) = self.wireguard_mtu {
+            options.push(format!("MTU {}", mtu));
+        }
+        if let Some(mssfix) = self.openvpn_mssfix {
+            options.push(format!("mssfix {}", mssfix));
+        }
+        if let Some(enable_ipv6) = self.enable_ipv6 {
+            options.push(format!("IPv6 {}", if enable_ipv6 { "on" } else { "off" }));
+        }
+        match &self.custom_dns_servers {
+            Some(servers) if servers.is_empty() => options.push("default DNS".to_owned()),
+            Some(servers) => options.push(format!(
+                "DNS {}",
+                servers
+                    .iter()
+                    .map(|server| server.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            )),
+            None => (),
+        }
+        if options.is_empty() {
+            write!(f, "no overrides")
+        } else {
+            write!(f, "{}", options.join(", "))
+        }
+    }
+}
+
 /// Used to deserialize the `show_beta_releases` field in the settings struct, as it used to be
 /// a nullable field, but it is no longer.
 fn deserialize_show_beta_releases<'de, D: serde::de::Deserializer<'de>>(
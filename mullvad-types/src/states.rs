@@ -1,4 +1,5 @@
 use crate::location::GeoIpLocation;
+use chrono::{offset::Utc, DateTime};
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
@@ -24,7 +25,12 @@ pub enum TargetState {
 #[cfg_attr(target_os = "android", derive(IntoJava))]
 #[cfg_attr(target_os = "android", jnix(package = "net.mullvad.mullvadvpn.model"))]
 pub enum TunnelState {
-    Disconnected,
+    Disconnected {
+        /// When the tunnel will be secured again, if it has been paused.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[cfg_attr(target_os = "android", jnix(skip))]
+        paused_until: Option<DateTime<Utc>>,
+    },
     Connecting {
         endpoint: TunnelEndpoint,
         location: Option<GeoIpLocation>,
@@ -32,6 +38,11 @@ pub enum TunnelState {
     Connected {
         endpoint: TunnelEndpoint,
         location: Option<GeoIpLocation>,
+        /// Set when the tunnel stayed up and moved to a new default route, instead of having
+        /// just been established.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        #[cfg_attr(target_os = "android", jnix(skip))]
+        roamed: bool,
     },
     Disconnecting(ActionAfterDisconnect),
     Error(ErrorState),
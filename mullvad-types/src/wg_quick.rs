@@ -0,0 +1,610 @@
+//! Conversion of wg-quick configuration files into custom WireGuard relays.
+use crate::{settings::TunnelOptionsOverride, ConnectionConfig, CustomTunnelEndpoint};
+use ipnetwork::IpNetwork;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use talpid_types::net::wireguard;
+
+#[derive(err_derive::Error, Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    #[error(display = "Line {}: expected a section header or a key-value pair", _0)]
+    InvalidLine(usize),
+
+    #[error(display = "Line {}: unknown section [{}]", _0, _1)]
+    UnknownSection(usize, String),
+
+    #[error(display = "Line {}: {} is not inside a section", _0, _1)]
+    KeyOutsideSection(usize, String),
+
+    #[error(display = "Line {}: invalid value for {}", _0, _1)]
+    InvalidValue(usize, String),
+
+    #[error(display = "Unsupported keys: {}", _0)]
+    UnsupportedKeys(String),
+
+    #[error(display = "Missing {}", _0)]
+    MissingKey(&'static str),
+
+    #[error(display = "Missing [Peer] section")]
+    MissingPeer,
+
+    #[error(
+        display = "Found {} [Peer] sections, but only configurations with a single peer are \
+                   supported",
+        _0
+    )]
+    MultiplePeers(usize),
+
+    #[error(
+        display = "Unable to find the IPv4 tunnel address of the peer in Address, AllowedIPs \
+                   or DNS"
+    )]
+    NoIpv4Gateway,
+}
+
+/// A custom WireGuard relay in the form of a wg-quick configuration file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WgQuickConfig {
+    /// Hostname or IP of the relay, from the `Endpoint` of the `[Peer]` section.
+    pub host: String,
+    /// The tunnel and peer. The IP of the peer endpoint is unspecified until `host` is resolved.
+    pub connection: wireguard::ConnectionConfig,
+    /// The `DNS` servers of the `[Interface]` section. The tunnel gateways are used if empty.
+    pub dns: Vec<IpAddr>,
+    /// The `MTU` of the `[Interface]` section.
+    pub mtu: Option<u16>,
+}
+
+impl WgQuickConfig {
+    /// Parses a wg-quick configuration file. The tunnel gateways are the tunnel addresses of the
+    /// peer. They are taken from `AllowedIPs` or `Address`, or from `DNS` as a last resort.
+    pub fn parse(config: &str) -> Result<Self, Error> {
+        let mut parser = Parser::default();
+        for (index, line) in config.lines().enumerate() {
+            parser.parse_line(index + 1, line)?;
+        }
+        parser.finish()
+    }
+
+    /// Returns the relay as a custom tunnel endpoint. The `DNS` servers and the `MTU` only apply
+    /// to this relay.
+    pub fn to_custom_tunnel_endpoint(&self) -> CustomTunnelEndpoint {
+        let mut endpoint = CustomTunnelEndpoint::new(
+            self.host.clone(),
+            ConnectionConfig::Wireguard(self.connection.clone()),
+        );
+        endpoint.set_tunnel_options(TunnelOptionsOverride {
+            wireguard_mtu: self.mtu,
+            custom_dns_servers: if self.dns.is_empty() {
+                None
+            } else {
+                Some(self.dns.clone())
+            },
+            ..TunnelOptionsOverride::default()
+        });
+        endpoint
+    }
+
+    /// Formats the relay as a wg-quick configuration file. The private key is commented out
+    /// unless `include_private_key` is set.
+    pub fn to_config_string(&self, include_private_key: bool) -> String {
+        let tunnel = &self.connection.tunnel;
+        let peer = &self.connection.peer;
+        let mut config = String::from("[Interface]\n");
+
+        if include_private_key {
+            config += &format!("PrivateKey = {}\n", tunnel.private_key);
+        } else {
+            config += "# PrivateKey = <private key of the account>\n";
+        }
+        let addresses = tunnel.addresses.iter().map(|address| match address {
+            IpAddr::V4(_) => format!("{}/32", address),
+            IpAddr::V6(_) => format!("{}/128", address),
+        });
+        config += &format!("Address = {}\n", join(addresses));
+        if self.dns.is_empty() {
+            let gateways = Some(IpAddr::from(self.connection.ipv4_gateway))
+                .into_iter()
+                .chain(self.connection.ipv6_gateway.map(IpAddr::from));
+            config += &format!("DNS = {}\n", join(gateways));
+        } else {
+            config += &format!("DNS = {}\n", join(self.dns.iter()));
+        }
+        if let Some(mtu) = self.mtu {
+            config += &format!("MTU = {}\n", mtu);
+        }
+
+        config += "\n[Peer]\n";
+        config += &format!("PublicKey = {}\n", peer.public_key);
+        if let Some(psk) = &peer.psk {
+            config += &format!("PresharedKey = {}\n", psk.to_base64());
+        }
+        config += &format!("AllowedIPs = {}\n", join(peer.allowed_ips.iter()));
+        let port = peer.endpoint.port();
+        if self.host.contains(':') {
+            config += &format!("Endpoint = [{}]:{}\n", self.host, port);
+        } else {
+            config += &format!("Endpoint = {}:{}\n", self.host, port);
+        }
+        if let Some(interval) = peer.persistent_keepalive {
+            config += &format!("PersistentKeepalive = {}\n", interval);
+        }
+        config
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Section {
+    Interface,
+    Peer,
+}
+
+#[derive(Default)]
+struct Parser {
+    section: Option<Section>,
+    unsupported_keys: Vec<String>,
+
+    private_key: Option<wireguard::PrivateKey>,
+    addresses: Vec<IpNetwork>,
+    dns: Vec<IpAddr>,
+    mtu: Option<u16>,
+
+    peers: Vec<PeerSection>,
+}
+
+#[derive(Default)]
+struct PeerSection {
+    public_key: Option<wireguard::PublicKey>,
+    psk: Option<wireguard::PresharedKey>,
+    endpoint: Option<(String, u16)>,
+    allowed_ips: Vec<IpNetwork>,
+    persistent_keepalive: Option<u16>,
+}
+
+impl Parser {
+    fn parse_line(&mut self, line_number: usize, line: &str) -> Result<(), Error> {
+        let line = line.splitn(2, '#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        if line.starts_with('[') && line.ends_with(']') {
+            let section = line[1..line.len() - 1].trim();
+            self.section = match section {
+                "Interface" => Some(Section::Interface),
+                "Peer" => {
+                    self.peers.push(PeerSection::default());
+                    Some(Section::Peer)
+                }
+                _ => return Err(Error::UnknownSection(line_number, section.to_owned())),
+            };
+            return Ok(());
+        }
+
+        let mut parts = line.splitn(2, '=');
+        let (key, value) = match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) => (key.trim(), value.trim()),
+            _ => return Err(Error::InvalidLine(line_number)),
+        };
+        let invalid_value = || Error::InvalidValue(line_number, key.to_owned());
+
+        match self.section {
+            None => return Err(Error::KeyOutsideSection(line_number, key.to_owned())),
+            Some(Section::Interface) => match key.to_lowercase().as_str() {
+                "privatekey" => {
+                    self.private_key = Some(value.parse().map_err(|_| invalid_value())?);
+                }
+                "address" => {
+                    for address in split_list(value) {
+                        self.addresses
+                            .push(address.parse().map_err(|_| invalid_value())?);
+                    }
+                }
+                "dns" => {
+                    for server in split_list(value) {
+                        self.dns.push(server.parse().map_err(|_| invalid_value())?);
+                    }
+                }
+                "mtu" => self.mtu = Some(value.parse().map_err(|_| invalid_value())?),
+                _ => self.unsupported_keys.push(format!("Interface.{}", key)),
+            },
+            Some(Section::Peer) => {
+                let peer = self
+                    .peers
+                    .last_mut()
+                    .expect("A peer is added when the section starts");
+                match key.to_lowercase().as_str() {
+                    "publickey" => {
+                        peer.public_key = Some(value.parse().map_err(|_| invalid_value())?);
+                    }
+                    "presharedkey" => {
+                        peer.psk = Some(value.parse().map_err(|_| invalid_value())?);
+                    }
+                    "endpoint" => {
+                        peer.endpoint = Some(parse_endpoint(value).ok_or_else(invalid_value)?);
+                    }
+                    "allowedips" => {
+                        for network in split_list(value) {
+                            peer.allowed_ips
+                                .push(network.parse().map_err(|_| invalid_value())?);
+                        }
+                    }
+                    "persistentkeepalive" => {
+                        peer.persistent_keepalive = match value {
+                            "off" => None,
+                            _ => match value.parse().map_err(|_| invalid_value())? {
+                                0 => None,
+                                interval => Some(interval),
+                            },
+                        };
+                    }
+                    _ => self.unsupported_keys.push(format!("Peer.{}", key)),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<WgQuickConfig, Error> {
+        if !self.unsupported_keys.is_empty() {
+            return Err(Error::UnsupportedKeys(self.unsupported_keys.join(", ")));
+        }
+        let peer = match self.peers.len() {
+            0 => return Err(Error::MissingPeer),
+            1 => self.peers.remove(0),
+            peer_count => return Err(Error::MultiplePeers(peer_count)),
+        };
+
+        let private_key = self
+            .private_key
+            .ok_or(Error::MissingKey("Interface.PrivateKey"))?;
+        if self.addresses.is_empty() {
+            return Err(Error::MissingKey("Interface.Address"));
+        }
+        let public_key = peer.public_key.ok_or(Error::MissingKey("Peer.PublicKey"))?;
+        let (host, port) = peer.endpoint.ok_or(Error::MissingKey("Peer.Endpoint"))?;
+        if peer.allowed_ips.is_empty() {
+            return Err(Error::MissingKey("Peer.AllowedIPs"));
+        }
+
+        let gateways = find_gateways(&self.addresses, &peer.allowed_ips, &self.dns);
+        let ipv4_gateway = gateways
+            .iter()
+            .find_map(|gateway| match gateway {
+                IpAddr::V4(gateway) => Some(*gateway),
+                IpAddr::V6(_) => None,
+            })
+            .ok_or(Error::NoIpv4Gateway)?;
+        let ipv6_gateway = gateways.iter().find_map(|gateway| match gateway {
+            IpAddr::V4(_) => None,
+            IpAddr::V6(gateway) => Some(*gateway),
+        });
+
+        let connection = wireguard::ConnectionConfig {
+            tunnel: wireguard::TunnelConfig {
+                private_key,
+                addresses: self.addresses.iter().map(IpNetwork::ip).collect(),
+            },
+            peer: wireguard::PeerConfig {
+                public_key,
+                allowed_ips: peer.allowed_ips,
+                endpoint: SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port),
+                psk: peer.psk,
+                persistent_keepalive: peer.persistent_keepalive,
+            },
+            ipv4_gateway,
+            ipv6_gateway,
+        };
+
+        Ok(WgQuickConfig {
+            host,
+            connection,
+            dns: self.dns,
+            mtu: self.mtu,
+        })
+    }
+}
+
+/// Returns the candidates for the tunnel addresses of the peer, best first:
+/// - host routes in `AllowedIPs`,
+/// - the first address of each subnet in `Address`, unless that is the local address,
+/// - the `DNS` servers, since the peer is usually the DNS server of the tunnel.
+///
+/// Only addresses that are routed through the tunnel are returned.
+fn find_gateways(
+    addresses: &[IpNetwork],
+    allowed_ips: &[IpNetwork],
+    dns_servers: &[IpAddr],
+) -> Vec<IpAddr> {
+    let host_routes = allowed_ips
+        .iter()
+        .filter(|network| network.prefix() == max_prefix(network))
+        .map(IpNetwork::ip);
+    let subnet_gateways = addresses
+        .iter()
+        .filter_map(|address| first_host(address).filter(|gateway| *gateway != address.ip()));
+    host_routes
+        .chain(subnet_gateways)
+        .chain(dns_servers.iter().cloned())
+        .filter(|gateway| allowed_ips.iter().any(|network| network.contains(*gateway)))
+        .collect()
+}
+
+fn max_prefix(network: &IpNetwork) -> u8 {
+    match network {
+        IpNetwork::V4(_) => 32,
+        IpNetwork::V6(_) => 128,
+    }
+}
+
+/// Returns the first address after the network address, if the network has room for more than
+/// one host.
+fn first_host(network: &IpNetwork) -> Option<IpAddr> {
+    if network.prefix() + 1 >= max_prefix(network) {
+        return None;
+    }
+    match network {
+        IpNetwork::V4(network) => Some(Ipv4Addr::from(u32::from(network.network()) + 1).into()),
+        IpNetwork::V6(network) => Some(Ipv6Addr::from(u128::from(network.network()) + 1).into()),
+    }
+}
+
+fn join<T: ToString>(items: impl Iterator<Item = T>) -> String {
+    items
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+/// Splits `host:port`, where an IPv6 host is enclosed in brackets.
+fn parse_endpoint(endpoint: &str) -> Option<(String, u16)> {
+    let separator = endpoint.rfind(':')?;
+    let port = endpoint[separator + 1..].parse().ok()?;
+    let host = &endpoint[..separator];
+    let host = if host.starts_with('[') && host.ends_with(']') {
+        &host[1..host.len() - 1]
+    } else {
+        host
+    };
+    if host.is_empty() {
+        return None;
+    }
+    Some((host.to_owned(), port))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use talpid_types::net::all_of_the_internet;
+
+    const PRIVATE_KEY: &str = "mPyjqHHVibBJZ0WzTRf3jy7Be9tD4LHIuhgy7lHnTXs=";
+    const PUBLIC_KEY: &str = "kZD2z9ZNNcHqgjMFfUYC8W7oqdGDhwH8xxSk1hN4g1U=";
+    const PRESHARED_KEY: &str = "4T6M1wuwOHuJuODR2c8jq8bsA9XzbJz1ZsWVb4tLxUg=";
+
+    #[test]
+    fn test_parse_config() {
+        let config = format!(
+            r#"
+            [Interface]
+            # Device: Happy Otter
+            PrivateKey = {}
+            Address = 10.99.0.2/32,fc00:bbbb:bbbb:bb01::2/128
+            DNS = 10.64.0.1
+            MTU = 1280
+
+            [Peer]
+            PublicKey = {}
+            AllowedIPs = 0.0.0.0/0,::0/0
+            Endpoint = se-got-001.example.com:51820
+            "#,
+            PRIVATE_KEY, PUBLIC_KEY
+        );
+
+        let parsed = WgQuickConfig::parse(&config).unwrap();
+        let expected_connection = wireguard::ConnectionConfig {
+            tunnel: wireguard::TunnelConfig {
+                private_key: PRIVATE_KEY.parse().unwrap(),
+                addresses: vec![
+                    "10.99.0.2".parse().unwrap(),
+                    "fc00:bbbb:bbbb:bb01::2".parse().unwrap(),
+                ],
+            },
+            peer: wireguard::PeerConfig {
+                public_key: PUBLIC_KEY.parse().unwrap(),
+                allowed_ips: all_of_the_internet(),
+                endpoint: "0.0.0.0:51820".parse().unwrap(),
+                psk: None,
+                persistent_keepalive: None,
+            },
+            ipv4_gateway: "10.64.0.1".parse().unwrap(),
+            ipv6_gateway: None,
+        };
+        assert_eq!(
+            parsed,
+            WgQuickConfig {
+                host: "se-got-001.example.com".to_owned(),
+                connection: expected_connection,
+                dns: vec!["10.64.0.1".parse().unwrap()],
+                mtu: Some(1280),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_peer_options() {
+        let config = format!(
+            "[Interface]\nPrivateKey={}\nAddress=10.99.0.2\nDNS=10.64.0.1, fc00:bbbb:bbbb:bb01::1\n\
+             [Peer]\nPublicKey={}\nPresharedKey={}\nAllowedIPs=10.0.0.0/8, fc00::/7\n\
+             Endpoint=[2001:db8::1]:51820\nPersistentKeepalive=25\n",
+            PRIVATE_KEY, PUBLIC_KEY, PRESHARED_KEY
+        );
+
+        let parsed = WgQuickConfig::parse(&config).unwrap();
+        let connection = &parsed.connection;
+        assert_eq!(parsed.host, "2001:db8::1");
+        assert_eq!(connection.peer.psk, Some(PRESHARED_KEY.parse().unwrap()));
+        assert_eq!(connection.peer.persistent_keepalive, Some(25));
+        assert_eq!(
+            connection.ipv6_gateway,
+            Some("fc00:bbbb:bbbb:bb01::1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_gateway_without_dns() {
+        let parse_gateways = |address: &str, allowed_ips: &str| {
+            let config = format!(
+                "[Interface]\nPrivateKey={}\nAddress={}\n\
+                 [Peer]\nPublicKey={}\nAllowedIPs={}\nEndpoint=10.0.0.1:51820\n",
+                PRIVATE_KEY, address, PUBLIC_KEY, allowed_ips
+            );
+            WgQuickConfig::parse(&config).map(|config| {
+                (
+                    config.connection.ipv4_gateway,
+                    config.connection.ipv6_gateway,
+                )
+            })
+        };
+
+        assert_eq!(
+            parse_gateways("10.99.0.2/24, fc00:bbbb::2/64", "0.0.0.0/0, ::/0"),
+            Ok((
+                "10.99.0.1".parse().unwrap(),
+                Some("fc00:bbbb::1".parse().unwrap())
+            ))
+        );
+        assert_eq!(
+            parse_gateways("10.99.0.2/32", "10.64.0.1/32, 192.168.5.0/24"),
+            Ok(("10.64.0.1".parse().unwrap(), None))
+        );
+        assert_eq!(
+            parse_gateways("10.99.0.1/24", "0.0.0.0/0"),
+            Err(Error::NoIpv4Gateway)
+        );
+        assert_eq!(
+            parse_gateways("10.99.0.2/24", "192.168.5.0/24"),
+            Err(Error::NoIpv4Gateway)
+        );
+    }
+
+    #[test]
+    fn test_dns_and_mtu_only_apply_to_relay() {
+        let config = format!(
+            "[Interface]\nPrivateKey={}\nAddress=10.99.0.2/24\nDNS=10.64.0.1\nMTU=1380\n\
+             [Peer]\nPublicKey={}\nAllowedIPs=0.0.0.0/0\nEndpoint=10.0.0.1:51820\n",
+            PRIVATE_KEY, PUBLIC_KEY
+        );
+
+        let endpoint = WgQuickConfig::parse(&config)
+            .unwrap()
+            .to_custom_tunnel_endpoint();
+        assert_eq!(endpoint.tunnel_options().wireguard_mtu, Some(1380));
+        assert_eq!(
+            endpoint.tunnel_options().custom_dns_servers,
+            Some(vec!["10.64.0.1".parse().unwrap()])
+        );
+
+        let config = config.replace("DNS=10.64.0.1\n", "");
+        let endpoint = WgQuickConfig::parse(&config)
+            .unwrap()
+            .to_custom_tunnel_endpoint();
+        assert_eq!(endpoint.tunnel_options().custom_dns_servers, None);
+    }
+
+    #[test]
+    fn test_config_string_round_trip() {
+        let config = WgQuickConfig {
+            host: "2001:db8::1".to_owned(),
+            connection: wireguard::ConnectionConfig {
+                tunnel: wireguard::TunnelConfig {
+                    private_key: PRIVATE_KEY.parse().unwrap(),
+                    addresses: vec![
+                        "10.99.0.2".parse().unwrap(),
+                        "fc00:bbbb:bbbb:bb01::2".parse().unwrap(),
+                    ],
+                },
+                peer: wireguard::PeerConfig {
+                    public_key: PUBLIC_KEY.parse().unwrap(),
+                    allowed_ips: all_of_the_internet(),
+                    endpoint: "0.0.0.0:53".parse().unwrap(),
+                    psk: Some(PRESHARED_KEY.parse().unwrap()),
+                    persistent_keepalive: Some(25),
+                },
+                ipv4_gateway: "10.64.0.1".parse().unwrap(),
+                ipv6_gateway: Some("fc00:bbbb:bbbb:bb01::1".parse().unwrap()),
+            },
+            dns: vec![
+                "10.64.0.1".parse().unwrap(),
+                "fc00:bbbb:bbbb:bb01::1".parse().unwrap(),
+            ],
+            mtu: Some(1380),
+        };
+
+        let config_string = config.to_config_string(true);
+        assert!(config_string.contains(&format!("PrivateKey = {}\n", PRIVATE_KEY)));
+        assert_eq!(WgQuickConfig::parse(&config_string), Ok(config.clone()));
+
+        let config_string = config.to_config_string(false);
+        assert!(!config_string.contains(PRIVATE_KEY));
+        assert_eq!(
+            WgQuickConfig::parse(&config_string),
+            Err(Error::MissingKey("Interface.PrivateKey"))
+        );
+    }
+
+    #[test]
+    fn test_unsupported_keys() {
+        let config = format!(
+            "[Interface]\nPrivateKey={}\nAddress=10.99.0.2\nDNS=10.64.0.1\nListenPort=51820\n\
+             PostUp=iptables -A FORWARD -i wg0 -j ACCEPT\n\
+             [Peer]\nPublicKey={}\nAllowedIPs=0.0.0.0/0\nEndpoint=10.0.0.1:51820\n",
+            PRIVATE_KEY, PUBLIC_KEY
+        );
+
+        assert_eq!(
+            WgQuickConfig::parse(&config),
+            Err(Error::UnsupportedKeys(
+                "Interface.ListenPort, Interface.PostUp".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_invalid_configs() {
+        assert_eq!(
+            WgQuickConfig::parse("PrivateKey = abc"),
+            Err(Error::KeyOutsideSection(1, "PrivateKey".to_owned()))
+        );
+        assert_eq!(
+            WgQuickConfig::parse("[Interface]\nPrivateKey = abc"),
+            Err(Error::InvalidValue(2, "PrivateKey".to_owned()))
+        );
+        assert_eq!(
+            WgQuickConfig::parse("[Interface]\nDNS = 10.64.0.1"),
+            Err(Error::MissingPeer)
+        );
+        assert_eq!(
+            WgQuickConfig::parse(&format!(
+                "[Interface]\nPrivateKey={}\nAddress=10.99.0.2\n\
+                 [Peer]\nPublicKey={}\nAllowedIPs=10.0.0.0/8\nEndpoint=10.0.0.1:51820\n\
+                 [Peer]\nPublicKey={}\nAllowedIPs=0.0.0.0/0\nEndpoint=10.0.0.2:51820\n",
+                PRIVATE_KEY, PUBLIC_KEY, PUBLIC_KEY
+            )),
+            Err(Error::MultiplePeers(2))
+        );
+        assert_eq!(
+            WgQuickConfig::parse(&format!(
+                "[Interface]\nPrivateKey={}\nAddress=10.99.0.2\n\
+                 [Peer]\nPublicKey={}\nAllowedIPs=0.0.0.0/0\nEndpoint=10.0.0.1:51820\n",
+                PRIVATE_KEY, PUBLIC_KEY
+            )),
+            Err(Error::NoIpv4Gateway)
+        );
+    }
+}
@@ -0,0 +1,225 @@
+//! Checks that the system DNS settings are the ones that were set, and that DNS queries reach the
+//! DNS servers through the tunnel.
+
+use super::{stub_resolver::message, DnsMonitor};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    time::Duration,
+};
+use talpid_types::{
+    dns::{DnsCheck, DnsCheckResult, DnsDiagnostics},
+    ErrorExt,
+};
+
+const TEST_QUERY_NAME: &str = "mullvad.net";
+const DNS_PORT: u16 = 53;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_RESPONSE_SIZE: usize = 4096;
+
+/// DNS checks to make while connected. The system DNS settings are read when this is created,
+/// and the test queries are sent when it is run, so that the queries can be sent from another
+/// thread.
+pub struct ConnectedDiagnostics {
+    backend: Option<String>,
+    expected_servers: Vec<IpAddr>,
+    effective_servers: Result<Vec<IpAddr>, String>,
+    tunnel_ips: Vec<IpAddr>,
+    upstream_servers: Vec<IpAddr>,
+}
+
+impl ConnectedDiagnostics {
+    /// Reads the current DNS settings from `dns_monitor`. `expected_servers` are the servers that
+    /// the system was set to use, and `upstream_servers` the servers that the local resolver
+    /// forwards plain queries to, if it is used. Queries to servers that are not on a loopback
+    /// address must be sent from one of the `tunnel_ips`.
+    pub fn new(
+        dns_monitor: &DnsMonitor,
+        expected_servers: Vec<IpAddr>,
+        tunnel_ips: Vec<IpAddr>,
+        upstream_servers: Vec<IpAddr>,
+    ) -> Self {
+        let effective_servers = dns_monitor.get_dns().map_err(|error| {
+            error.display_chain_with_msg("Failed to read the DNS servers used by the system")
+        });
+        ConnectedDiagnostics {
+            backend: dns_monitor.backend().map(|backend| backend.to_string()),
+            expected_servers,
+            effective_servers,
+            tunnel_ips,
+            upstream_servers,
+        }
+    }
+
+    /// Compares the DNS settings and sends a test query to each DNS server. This blocks until
+    /// every server has answered or timed out.
+    pub fn run(self) -> DnsDiagnostics {
+        let mut checks = vec![self.check_backend(), self.check_effective_servers()];
+
+        // Test the servers that the system actually uses, if they could be read
+        let system_servers = self
+            .effective_servers
+            .as_ref()
+            .unwrap_or(&self.expected_servers);
+        for server in system_servers {
+            checks.push(check_query(
+                format!("Query to system resolver {}", server),
+                *server,
+                &self.tunnel_ips,
+            ));
+        }
+        for server in &self.upstream_servers {
+            checks.push(check_query(
+                format!("Query to local resolver upstream {}", server),
+                *server,
+                &self.tunnel_ips,
+            ));
+        }
+
+        DnsDiagnostics {
+            backend: self.backend,
+            expected_servers: self.expected_servers,
+            effective_servers: self.effective_servers.unwrap_or_default(),
+            checks,
+        }
+    }
+
+    fn check_backend(&self) -> DnsCheck {
+        match self.backend {
+            Some(ref backend) => check("DNS backend", DnsCheckResult::Pass, backend.clone()),
+            None => check(
+                "DNS backend",
+                DnsCheckResult::Fail,
+                "DNS has not been set".to_owned(),
+            ),
+        }
+    }
+
+    fn check_effective_servers(&self) -> DnsCheck {
+        const NAME: &str = "System resolvers";
+        match self.effective_servers {
+            Ok(ref effective_servers) => {
+                if same_servers(effective_servers, &self.expected_servers) {
+                    check(
+                        NAME,
+                        DnsCheckResult::Pass,
+                        format_servers(effective_servers),
+                    )
+                } else {
+                    check(
+                        NAME,
+                        DnsCheckResult::Fail,
+                        format!(
+                            "Expected {}, but the system uses {}",
+                            format_servers(&self.expected_servers),
+                            format_servers(effective_servers)
+                        ),
+                    )
+                }
+            }
+            Err(ref error) => check(NAME, DnsCheckResult::Fail, error.clone()),
+        }
+    }
+}
+
+/// Returns the diagnostics to report when there is no tunnel that DNS is set for.
+pub fn not_connected() -> DnsDiagnostics {
+    DnsDiagnostics {
+        backend: None,
+        expected_servers: vec![],
+        effective_servers: vec![],
+        checks: vec![check(
+            "Tunnel",
+            DnsCheckResult::Skipped,
+            "Not connected, so DNS is not managed by the app".to_owned(),
+        )],
+    }
+}
+
+fn check(name: &str, result: DnsCheckResult, details: String) -> DnsCheck {
+    DnsCheck {
+        name: name.to_owned(),
+        result,
+        details,
+    }
+}
+
+fn check_query(name: String, server: IpAddr, tunnel_ips: &[IpAddr]) -> DnsCheck {
+    let (result, details) = match send_test_query(server, tunnel_ips) {
+        Ok(QueryOutcome::Answered(source)) if server.is_loopback() => (
+            DnsCheckResult::Pass,
+            format!("Answered locally, query sent from {}", source),
+        ),
+        Ok(QueryOutcome::Answered(source)) => (
+            DnsCheckResult::Pass,
+            format!("Answered through the tunnel, query sent from {}", source),
+        ),
+        Ok(QueryOutcome::OutsideTunnel(source)) => (
+            DnsCheckResult::Fail,
+            format!(
+                "Queries would be sent from {}, outside the tunnel. No query was sent",
+                source
+            ),
+        ),
+        Err(error) => (
+            DnsCheckResult::Fail,
+            error.display_chain_with_msg("No answer"),
+        ),
+    };
+    DnsCheck {
+        name,
+        result,
+        details,
+    }
+}
+
+enum QueryOutcome {
+    /// The server answered a query sent from the given address.
+    Answered(IpAddr),
+    /// The query would have been sent from the given address outside the tunnel.
+    OutsideTunnel(IpAddr),
+}
+
+/// Sends a query for `TEST_QUERY_NAME` to `server` and waits for the answer. The query is only
+/// sent if the route to the server goes through the tunnel, or if the server is on loopback.
+fn send_test_query(server: IpAddr, tunnel_ips: &[IpAddr]) -> io::Result<QueryOutcome> {
+    let bind_address = match server {
+        IpAddr::V4(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+        IpAddr::V6(_) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+    };
+    let socket = UdpSocket::bind(bind_address)?;
+    socket.connect(SocketAddr::new(server, DNS_PORT))?;
+
+    // The source address of a connected socket is picked from the route to the server
+    let source = socket.local_addr()?.ip();
+    if !server.is_loopback() && !tunnel_ips.contains(&source) {
+        return Ok(QueryOutcome::OutsideTunnel(source));
+    }
+
+    let query = message::build_query(rand::random(), TEST_QUERY_NAME, message::TYPE_A);
+    socket.set_read_timeout(Some(QUERY_TIMEOUT))?;
+    socket.send(&query)?;
+
+    let mut response = [0u8; MAX_RESPONSE_SIZE];
+    loop {
+        let len = socket.recv(&mut response)?;
+        if message::is_response_to(&query, &response[..len]) {
+            return Ok(QueryOutcome::Answered(source));
+        }
+    }
+}
+
+fn same_servers(a: &[IpAddr], b: &[IpAddr]) -> bool {
+    a.iter().all(|server| b.contains(server)) && b.iter().all(|server| a.contains(server))
+}
+
+fn format_servers(servers: &[IpAddr]) -> String {
+    if servers.is_empty() {
+        return "no servers".to_owned();
+    }
+    servers
+        .iter()
+        .map(|server| server.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,182 @@
+//! A record of the DNS changes made to the system, kept on disk so that the changes can be undone
+//! if the daemon stops without resetting DNS. Changes that failed to be undone are kept in the
+//! record as well, so that undoing them can be retried.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+const JOURNAL_FILENAME: &str = "dns-journal.json";
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to read the DNS journal")]
+    Read(#[error(source)] io::Error),
+
+    #[error(display = "Failed to parse the DNS journal")]
+    Parse(#[error(source)] serde_json::Error),
+
+    #[error(display = "Failed to serialize the DNS journal")]
+    Serialize(#[error(source)] serde_json::Error),
+
+    #[error(display = "Failed to write the DNS journal")]
+    Write(#[error(source)] io::Error),
+
+    #[error(display = "Failed to remove the DNS journal")]
+    Remove(#[error(source)] io::Error),
+}
+
+/// What has to be undone to restore the DNS settings of the system.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "backend")]
+pub enum JournalEntry {
+    /// /etc/resolv.conf was overwritten. `resolv_conf` holds its original contents.
+    StaticFile { resolv_conf: String },
+    /// A record was added with `resolvconf -a`.
+    Resolvconf { record_name: String },
+    /// DNS was set on the link of `interface_name` or globally, and routing domains were added
+    /// to the links in `split_dns_links`, which hold the original settings of those links.
+    SystemdResolved {
+        interface_name: String,
+        split_dns_links: Vec<LinkSettings>,
+    },
+    /// The global DNS configuration of NetworkManager was set.
+    NetworkManager,
+}
+
+/// The DNS settings of a network interface in systemd-resolved.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkSettings {
+    pub interface_name: String,
+    pub servers: Vec<IpAddr>,
+    /// Domains of the link, and whether each is only used for routing queries.
+    pub domains: Vec<(String, bool)>,
+}
+
+/// The DNS journal file in the cache directory.
+pub struct Journal {
+    path: PathBuf,
+}
+
+impl Journal {
+    pub fn new(cache_dir: &Path) -> Self {
+        Journal {
+            path: cache_dir.join(JOURNAL_FILENAME),
+        }
+    }
+
+    /// Returns the recorded changes, oldest first. The list is empty if nothing has to be
+    /// restored.
+    pub fn read(&self) -> Result<Vec<JournalEntry>> {
+        match fs::read(&self.path) {
+            Ok(contents) => serde_json::from_slice(&contents).map_err(Error::Parse),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+            Err(error) => Err(Error::Read(error)),
+        }
+    }
+
+    /// Replaces the recorded changes, or removes the journal if `entries` is empty. The journal
+    /// is written to a temporary file that is then moved into place, so that a crash never
+    /// leaves a partially written journal behind.
+    pub fn write(&self, entries: &[JournalEntry]) -> Result<()> {
+        if entries.is_empty() {
+            return self.remove();
+        }
+        let contents = serde_json::to_vec(entries).map_err(Error::Serialize)?;
+        let temp_path = self.path.with_extension("json.tmp");
+        fs::File::create(&temp_path)
+            .and_then(|mut file| {
+                io::Write::write_all(&mut file, &contents)?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&temp_path, &self.path))
+            .map_err(Error::Write)
+    }
+
+    /// Removes the journal, after all changes in it have been undone.
+    pub fn remove(&self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result.map_err(Error::Remove),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_written_entry() {
+        let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+        let journal = Journal::new(temp_dir.path());
+        let entry = JournalEntry::SystemdResolved {
+            interface_name: "wg-mullvad".to_owned(),
+            split_dns_links: vec![LinkSettings {
+                interface_name: "eth0".to_owned(),
+                servers: vec!["192.168.1.1".parse().unwrap()],
+                domains: vec![("lan".to_owned(), false)],
+            }],
+        };
+
+        journal
+            .write(std::slice::from_ref(&entry))
+            .expect("failed to write journal");
+        assert_eq!(
+            journal.read().expect("failed to read journal"),
+            vec![entry.clone()]
+        );
+
+        let entries = vec![entry, JournalEntry::NetworkManager];
+        journal.write(&entries).expect("failed to replace journal");
+        assert_eq!(journal.read().expect("failed to read journal"), entries);
+    }
+
+    #[test]
+    fn missing_journal_is_empty() {
+        let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+        let journal = Journal::new(temp_dir.path());
+
+        assert!(journal.read().expect("failed to read journal").is_empty());
+        journal.remove().expect("failed to remove missing journal");
+    }
+
+    #[test]
+    fn removes_journal() {
+        let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+        let journal = Journal::new(temp_dir.path());
+        let entry = JournalEntry::Resolvconf {
+            record_name: "wg-mullvad.mullvad".to_owned(),
+        };
+
+        journal.write(&[entry]).expect("failed to write journal");
+        journal.remove().expect("failed to remove journal");
+        assert!(journal.read().expect("failed to read journal").is_empty());
+    }
+
+    #[test]
+    fn writing_no_entries_removes_journal() {
+        let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+        let journal = Journal::new(temp_dir.path());
+
+        journal
+            .write(&[JournalEntry::NetworkManager])
+            .expect("failed to write journal");
+        journal.write(&[]).expect("failed to clear journal");
+        assert!(!temp_dir.path().join(JOURNAL_FILENAME).exists());
+    }
+
+    #[test]
+    fn corrupt_journal_is_an_error() {
+        let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+        fs::write(temp_dir.path().join(JOURNAL_FILENAME), b"[{\"backend\":").unwrap();
+
+        assert!(Journal::new(temp_dir.path()).read().is_err());
+    }
+}
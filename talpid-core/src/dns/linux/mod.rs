@@ -1,14 +1,21 @@
+mod journal;
 mod network_manager;
 mod resolvconf;
 mod static_resolv_conf;
 mod systemd_resolved;
 
 use self::{
-    network_manager::NetworkManager, resolvconf::Resolvconf, static_resolv_conf::StaticResolvConf,
+    journal::{Journal, JournalEntry},
+    network_manager::NetworkManager,
+    resolvconf::Resolvconf,
+    static_resolv_conf::StaticResolvConf,
     systemd_resolved::SystemdResolved,
 };
-use std::{env, fmt, net::IpAddr, path::Path};
-
+use std::{env, fmt, fs, io, net::IpAddr, path::Path};
+use talpid_types::{
+    dns::{DnsBackend, DnsBackendCandidate, DnsBackends, SplitDnsRule},
+    ErrorExt,
+};
 
 const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
 
@@ -33,6 +40,18 @@ pub enum Error {
     #[error(display = "Error in static /etc/resolv.conf DNS monitor")]
     StaticResolvConf(#[error(source)] static_resolv_conf::Error),
 
+    /// Failed to read /etc/resolv.conf
+    #[error(display = "Failed to read /etc/resolv.conf")]
+    ReadResolvConf(#[error(source)] io::Error),
+
+    /// Failed to parse /etc/resolv.conf
+    #[error(display = "Failed to parse /etc/resolv.conf")]
+    ParseResolvConf(#[error(source)] resolv_conf::ParseError),
+
+    /// Failed to read or write the record of DNS changes
+    #[error(display = "Failed to access the DNS journal")]
+    Journal(#[error(source)] journal::Error),
+
     /// No suitable DNS monitor implementation detected
     #[error(display = "No suitable DNS monitor implementation detected")]
     NoDnsMonitor,
@@ -40,20 +59,114 @@ pub enum Error {
 
 pub struct DnsMonitor {
     inner: Option<DnsMonitorHolder>,
+    forced_backend: Option<DnsBackend>,
+    journal: Journal,
+    /// Changes from before an unclean shutdown that couldn't be undone. They are kept in the
+    /// journal, so that undoing them is retried on the next start or with `reset-dns`.
+    unrestored: Vec<JournalEntry>,
+}
+
+impl DnsMonitor {
+    /// Sets the backend to use the next time DNS is set, instead of detecting one.
+    pub fn force_backend(&mut self, backend: Option<DnsBackend>) {
+        self.forced_backend = backend;
+    }
+
+    /// Routes DNS queries for the domains of `rules` to the servers of the rules. This is only
+    /// supported when DNS is managed through systemd-resolved, and must be done after DNS has
+    /// been set. Returns whether the rules were applied.
+    pub fn set_split_dns(&mut self, rules: &[SplitDnsRule]) -> Result<bool> {
+        let systemd_resolved = match self.inner {
+            Some(DnsMonitorHolder::SystemdResolved(ref mut systemd_resolved)) => systemd_resolved,
+            _ => return Ok(false),
+        };
+        let changes = match systemd_resolved.prepare_split_dns(rules)? {
+            Some(changes) => changes,
+            None => return Ok(false),
+        };
+        // Record how to undo the changes before making them, in case the daemon is killed
+        if let Some(entry) = systemd_resolved.journal_entry(&changes) {
+            self.journal.write(&with_entry(&self.unrestored, entry))?;
+        }
+        let result = systemd_resolved.apply_split_dns(changes);
+        if result.is_err() {
+            // The changed links have been restored, so they are no longer recorded
+            if let Some(entry) = systemd_resolved.journal_entry(&[]) {
+                self.journal.write(&with_entry(&self.unrestored, entry))?;
+            }
+        }
+        result?;
+        Ok(true)
+    }
+
+    /// Returns the backend that DNS was set through, if DNS is set.
+    pub fn backend(&self) -> Option<DnsBackend> {
+        self.inner.as_ref().map(DnsMonitorHolder::backend)
+    }
+
+    /// Returns the DNS servers that the system is using, as reported by the backend that DNS
+    /// was set through. Returns an empty list if DNS is not set.
+    pub fn get_dns(&self) -> Result<Vec<IpAddr>> {
+        match self.inner {
+            Some(ref inner) => inner.get_dns(),
+            None => Ok(vec![]),
+        }
+    }
 }
 
 impl super::DnsMonitorT for DnsMonitor {
     type Error = Error;
 
-    fn new(_cache_dir: impl AsRef<Path>) -> Result<Self> {
-        Ok(DnsMonitor { inner: None })
+    fn new(cache_dir: impl AsRef<Path>) -> Result<Self> {
+        let journal = Journal::new(cache_dir.as_ref());
+        // A journal is only left behind if the daemon stopped without resetting DNS
+        if let Err(error) = restore_journal(&journal, restore_entry) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg(
+                    "Failed to restore DNS settings after unclean shutdown"
+                )
+            );
+        }
+        let unrestored = journal.read().unwrap_or_else(|error| {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Discarding unreadable DNS journal")
+            );
+            vec![]
+        });
+
+        Ok(DnsMonitor {
+            inner: None,
+            forced_backend: None,
+            journal,
+            unrestored,
+        })
     }
 
     fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<()> {
         self.reset()?;
         // Creating a new DNS monitor for each set, in case the system changed how it manages DNS.
-        let mut inner = DnsMonitorHolder::new()?;
-        inner.set(interface, servers)?;
+        let mut inner = DnsMonitorHolder::new(self.forced_backend)?;
+        // Record how to undo the change before making it, in case the daemon is killed
+        let entry = inner.journal_entry(interface)?;
+        self.journal
+            .write(&with_entry(&self.unrestored, entry.clone()))?;
+        if let Err(error) = inner.set(interface, servers) {
+            // Undo what was changed before the error. The change is only left in the journal if
+            // that fails too.
+            match inner.reset() {
+                Ok(()) => self.journal.write(&self.unrestored)?,
+                Err(reset_error) => {
+                    log::error!(
+                        "{}",
+                        reset_error.display_chain_with_msg("Failed to undo partially set DNS")
+                    );
+                    self.unrestored.push(entry);
+                }
+            }
+            return Err(error);
+        }
         self.inner = Some(inner);
         Ok(())
     }
@@ -61,6 +174,7 @@ impl super::DnsMonitorT for DnsMonitor {
     fn reset(&mut self) -> Result<()> {
         if let Some(mut inner) = self.inner.take() {
             inner.reset()?;
+            self.journal.write(&self.unrestored)?;
         }
         Ok(())
     }
@@ -87,27 +201,50 @@ impl fmt::Display for DnsMonitorHolder {
 }
 
 impl DnsMonitorHolder {
-    fn new() -> Result<Self> {
-        let dns_module = env::var_os("TALPID_DNS_MODULE");
-
-        let manager = match dns_module.as_ref().and_then(|value| value.to_str()) {
-            Some("static-file") => DnsMonitorHolder::StaticResolvConf(StaticResolvConf::new()?),
-            Some("resolvconf") => DnsMonitorHolder::Resolvconf(Resolvconf::new()?),
-            Some("systemd") => DnsMonitorHolder::SystemdResolved(SystemdResolved::new()?),
-            Some("network-manager") => DnsMonitorHolder::NetworkManager(NetworkManager::new()?),
-            Some(_) | None => Self::with_detected_dns_manager()?,
+    /// Creates the given backend, or the one set with the `TALPID_DNS_MODULE` environment
+    /// variable, or otherwise the first backend that can be used.
+    fn new(forced_backend: Option<DnsBackend>) -> Result<Self> {
+        let manager = match forced_backend.or_else(backend_from_env) {
+            Some(backend) => Self::with_backend(backend)?,
+            None => Self::with_detected_dns_manager()?,
         };
         log::debug!("Managing DNS via {}", manager);
         Ok(manager)
     }
 
+    fn with_backend(backend: DnsBackend) -> Result<Self> {
+        let manager = match backend {
+            DnsBackend::SystemdResolved => {
+                DnsMonitorHolder::SystemdResolved(SystemdResolved::new()?)
+            }
+            DnsBackend::NetworkManager => DnsMonitorHolder::NetworkManager(NetworkManager::new()?),
+            DnsBackend::Resolvconf => DnsMonitorHolder::Resolvconf(Resolvconf::new()?),
+            DnsBackend::StaticFile => DnsMonitorHolder::StaticResolvConf(StaticResolvConf::new()?),
+        };
+        Ok(manager)
+    }
+
     fn with_detected_dns_manager() -> Result<Self> {
-        SystemdResolved::new()
-            .map(DnsMonitorHolder::SystemdResolved)
-            .or_else(|_| NetworkManager::new().map(DnsMonitorHolder::NetworkManager))
-            .or_else(|_| Resolvconf::new().map(DnsMonitorHolder::Resolvconf))
-            .or_else(|_| StaticResolvConf::new().map(DnsMonitorHolder::StaticResolvConf))
-            .map_err(|_| Error::NoDnsMonitor)
+        for backend in DnsBackend::ALL.iter() {
+            match Self::with_backend(*backend) {
+                Ok(manager) => return Ok(manager),
+                Err(error) => log::debug!(
+                    "{}",
+                    error.display_chain_with_msg(&format!("Not managing DNS via {}", backend))
+                ),
+            }
+        }
+        Err(Error::NoDnsMonitor)
+    }
+
+    fn backend(&self) -> DnsBackend {
+        use self::DnsMonitorHolder::*;
+        match self {
+            Resolvconf(..) => DnsBackend::Resolvconf,
+            StaticResolvConf(..) => DnsBackend::StaticFile,
+            SystemdResolved(..) => DnsBackend::SystemdResolved,
+            NetworkManager(..) => DnsBackend::NetworkManager,
+        }
     }
 
     fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<()> {
@@ -125,6 +262,33 @@ impl DnsMonitorHolder {
         Ok(())
     }
 
+    fn journal_entry(&self, interface: &str) -> Result<JournalEntry> {
+        use self::DnsMonitorHolder::*;
+        let entry = match self {
+            Resolvconf(..) => JournalEntry::Resolvconf {
+                record_name: self::Resolvconf::record_name(interface),
+            },
+            StaticResolvConf(..) => JournalEntry::StaticFile {
+                resolv_conf: fs::read_to_string(RESOLV_CONF_PATH).map_err(Error::ReadResolvConf)?,
+            },
+            SystemdResolved(..) => JournalEntry::SystemdResolved {
+                interface_name: interface.to_owned(),
+                split_dns_links: vec![],
+            },
+            NetworkManager(..) => JournalEntry::NetworkManager,
+        };
+        Ok(entry)
+    }
+
+    fn get_dns(&self) -> Result<Vec<IpAddr>> {
+        use self::DnsMonitorHolder::*;
+        match self {
+            Resolvconf(..) | StaticResolvConf(..) => read_resolv_conf_servers(RESOLV_CONF_PATH),
+            SystemdResolved(ref systemd_resolved) => Ok(systemd_resolved.get_dns()?),
+            NetworkManager(ref network_manager) => Ok(network_manager.get_dns()?),
+        }
+    }
+
     fn reset(&mut self) -> Result<()> {
         use self::DnsMonitorHolder::*;
         match self {
@@ -136,3 +300,233 @@ impl DnsMonitorHolder {
         Ok(())
     }
 }
+
+/// Checks which backends can be used to manage DNS, without changing any DNS settings, and which
+/// one would be used the next time DNS is set.
+pub fn probe_backends(forced_backend: Option<DnsBackend>) -> DnsBackends {
+    let candidates: Vec<_> = DnsBackend::ALL
+        .iter()
+        .map(|backend| DnsBackendCandidate {
+            backend: *backend,
+            rejection_reason: probe_backend(*backend)
+                .err()
+                .map(|error| error.display_chain()),
+        })
+        .collect();
+    let selected = select_backend(forced_backend.or_else(backend_from_env), &candidates);
+
+    DnsBackends {
+        forced: forced_backend,
+        selected,
+        candidates,
+    }
+}
+
+/// Returns the backend that would be used among `candidates`. A `forced_backend` is used if it's
+/// usable, and otherwise no backend is used, since setting DNS then fails. Without a forced
+/// backend, the first usable backend in order of preference is used.
+fn select_backend(
+    forced_backend: Option<DnsBackend>,
+    candidates: &[DnsBackendCandidate],
+) -> Option<DnsBackend> {
+    let is_usable = |backend: DnsBackend| {
+        candidates
+            .iter()
+            .any(|candidate| candidate.backend == backend && candidate.rejection_reason.is_none())
+    };
+    match forced_backend {
+        Some(backend) if is_usable(backend) => Some(backend),
+        Some(_) => None,
+        None => DnsBackend::ALL
+            .iter()
+            .cloned()
+            .find(|backend| is_usable(*backend)),
+    }
+}
+
+fn probe_backend(backend: DnsBackend) -> Result<()> {
+    match backend {
+        // Creating this backend restores /etc/resolv.conf from any backup, which must not be done
+        // while the file is managed by another instance. It only needs the file to be readable.
+        DnsBackend::StaticFile => fs::read_to_string(RESOLV_CONF_PATH)
+            .map(|_| ())
+            .map_err(Error::ReadResolvConf),
+        _ => DnsMonitorHolder::with_backend(backend).map(|_| ()),
+    }
+}
+
+/// Undoes the DNS changes recorded in the journal in `cache_dir`, which are left behind if the
+/// daemon stopped without resetting DNS. Returns whether there was anything to restore.
+pub fn restore_from_journal(cache_dir: &Path) -> Result<bool> {
+    restore_journal(&Journal::new(cache_dir), restore_entry)
+}
+
+/// Undoes every change in the journal with `restore`, oldest first. Changes that fail to be
+/// undone are kept in the journal, and the last error is returned.
+fn restore_journal(
+    journal: &Journal,
+    restore: impl Fn(&JournalEntry) -> Result<()>,
+) -> Result<bool> {
+    let entries = journal.read()?;
+    if entries.is_empty() {
+        return Ok(false);
+    }
+    log::info!("Restoring DNS settings that were not reset before the last shutdown");
+    let mut result = Ok(true);
+    let mut unrestored = vec![];
+    for entry in entries {
+        if let Err(error) = restore(&entry) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to restore DNS settings")
+            );
+            unrestored.push(entry);
+            result = Err(error);
+        }
+    }
+    journal.write(&unrestored)?;
+    result
+}
+
+fn restore_entry(entry: &JournalEntry) -> Result<()> {
+    match entry {
+        JournalEntry::StaticFile { resolv_conf } => static_resolv_conf::restore(resolv_conf)?,
+        JournalEntry::Resolvconf { record_name } => Resolvconf::restore(record_name)?,
+        JournalEntry::SystemdResolved {
+            interface_name,
+            split_dns_links,
+        } => SystemdResolved::restore(interface_name, split_dns_links)?,
+        JournalEntry::NetworkManager => NetworkManager::restore()?,
+    }
+    Ok(())
+}
+
+/// Returns `entries` followed by `entry`.
+fn with_entry(entries: &[JournalEntry], entry: JournalEntry) -> Vec<JournalEntry> {
+    let mut entries = entries.to_vec();
+    entries.push(entry);
+    entries
+}
+
+/// Returns the backend set with the `TALPID_DNS_MODULE` environment variable, if any.
+fn backend_from_env() -> Option<DnsBackend> {
+    parse_backend(&env::var("TALPID_DNS_MODULE").ok()?)
+}
+
+fn parse_backend(name: &str) -> Option<DnsBackend> {
+    match name {
+        "static-file" => Some(DnsBackend::StaticFile),
+        "resolvconf" => Some(DnsBackend::Resolvconf),
+        "systemd" => Some(DnsBackend::SystemdResolved),
+        "network-manager" => Some(DnsBackend::NetworkManager),
+        _ => None,
+    }
+}
+
+/// Returns the name servers in the resolv.conf file at `path`.
+pub(super) fn read_resolv_conf_servers(path: &str) -> Result<Vec<IpAddr>> {
+    let contents = fs::read_to_string(path).map_err(Error::ReadResolvConf)?;
+    let config = resolv_conf::Config::parse(contents).map_err(Error::ParseResolvConf)?;
+    Ok(config
+        .nameservers
+        .into_iter()
+        .map(|nameserver| match nameserver {
+            resolv_conf::ScopedIp::V4(address) => IpAddr::V4(address),
+            resolv_conf::ScopedIp::V6(address, _) => IpAddr::V6(address),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn candidates(usable: &[DnsBackend]) -> Vec<DnsBackendCandidate> {
+        DnsBackend::ALL
+            .iter()
+            .map(|backend| DnsBackendCandidate {
+                backend: *backend,
+                rejection_reason: if usable.contains(backend) {
+                    None
+                } else {
+                    Some("Not detected".to_owned())
+                },
+            })
+            .collect()
+    }
+
+    #[test]
+    fn selects_first_usable_backend() {
+        let candidates = candidates(&[DnsBackend::Resolvconf, DnsBackend::StaticFile]);
+        assert_eq!(
+            select_backend(None, &candidates),
+            Some(DnsBackend::Resolvconf)
+        );
+        assert_eq!(select_backend(None, &self::candidates(&[])), None);
+    }
+
+    #[test]
+    fn selects_forced_backend_only_if_usable() {
+        let candidates = candidates(&[DnsBackend::SystemdResolved, DnsBackend::StaticFile]);
+        assert_eq!(
+            select_backend(Some(DnsBackend::StaticFile), &candidates),
+            Some(DnsBackend::StaticFile)
+        );
+        assert_eq!(
+            select_backend(Some(DnsBackend::NetworkManager), &candidates),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_backend_names() {
+        assert_eq!(parse_backend("static-file"), Some(DnsBackend::StaticFile));
+        assert_eq!(parse_backend("resolvconf"), Some(DnsBackend::Resolvconf));
+        assert_eq!(parse_backend("systemd"), Some(DnsBackend::SystemdResolved));
+        assert_eq!(
+            parse_backend("network-manager"),
+            Some(DnsBackend::NetworkManager)
+        );
+        assert_eq!(parse_backend("systemd-resolved"), None);
+        assert_eq!(parse_backend(""), None);
+    }
+
+    #[test]
+    fn keeps_unrestored_journal_entries() {
+        let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+        let journal = Journal::new(temp_dir.path());
+        let failing_entry = JournalEntry::Resolvconf {
+            record_name: "wg-mullvad.mullvad".to_owned(),
+        };
+        journal
+            .write(&[JournalEntry::NetworkManager, failing_entry.clone()])
+            .unwrap();
+
+        let restored = std::cell::RefCell::new(vec![]);
+        let result = restore_journal(&journal, |entry| {
+            restored.borrow_mut().push(entry.clone());
+            match entry {
+                JournalEntry::NetworkManager => Ok(()),
+                _ => Err(Error::NoDnsMonitor),
+            }
+        });
+
+        assert!(result.is_err());
+        assert_eq!(restored.borrow().len(), 2);
+        assert_eq!(journal.read().unwrap(), vec![failing_entry]);
+
+        assert!(restore_journal(&journal, |_| Ok(())).unwrap());
+        assert!(journal.read().unwrap().is_empty());
+        assert!(!restore_journal(&journal, |_| Ok(())).unwrap());
+    }
+
+    #[test]
+    fn reads_backend_from_env() {
+        env::set_var("TALPID_DNS_MODULE", "network-manager");
+        assert_eq!(backend_from_env(), Some(DnsBackend::NetworkManager));
+        env::set_var("TALPID_DNS_MODULE", "unknown");
+        assert_eq!(backend_from_env(), None);
+        env::remove_var("TALPID_DNS_MODULE");
+        assert_eq!(backend_from_env(), None);
+    }
+}
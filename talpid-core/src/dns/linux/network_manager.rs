@@ -41,6 +41,7 @@ const RPC_TIMEOUT_MS: i32 = 3000;
 const GLOBAL_DNS_CONF_KEY: &str = "GlobalDnsConfiguration";
 const RC_MANAGEMENT_MODE_KEY: &str = "RcManager";
 const DNS_MODE_KEY: &str = "Mode";
+const DNS_CONFIGURATION_KEY: &str = "Configuration";
 
 pub struct NetworkManager {
     dbus_connection: dbus::Connection,
@@ -49,14 +50,27 @@ pub struct NetworkManager {
 
 impl NetworkManager {
     pub fn new() -> Result<Self> {
-        let dbus_connection =
-            dbus::Connection::get_private(BusType::System).map_err(Error::Dbus)?;
-        let manager = NetworkManager { dbus_connection };
+        let manager = Self::connect()?;
         manager.ensure_resolv_conf_is_managed()?;
         manager.ensure_network_manager_exists()?;
         Ok(manager)
     }
 
+    fn connect() -> Result<Self> {
+        let dbus_connection =
+            dbus::Connection::get_private(BusType::System).map_err(Error::Dbus)?;
+        Ok(NetworkManager { dbus_connection })
+    }
+
+    /// Clears the global DNS configuration set before an unclean shutdown. NetworkManager keeps
+    /// that configuration across restarts, so it has to be cleared even if NetworkManager no
+    /// longer manages /etc/resolv.conf.
+    pub fn restore() -> Result<()> {
+        let mut manager = Self::connect()?;
+        manager.ensure_network_manager_exists()?;
+        clear_global_dns(&mut manager)
+    }
+
     fn ensure_network_manager_exists(&self) -> Result<()> {
         let _: Box<dyn RefArg> = self
             .as_manager()
@@ -107,22 +121,53 @@ impl NetworkManager {
             .with_path(NM_BUS, NM_OBJECT_PATH, RPC_TIMEOUT_MS)
     }
 
+    /// Sets the global DNS servers, which are used instead of the servers of every connection.
+    /// They aren't tied to any interface, so they may be on the loopback interface.
     pub fn set_dns(&mut self, servers: &[IpAddr]) -> Result<()> {
         self.set_global_dns(create_global_settings(servers))
     }
 
+    pub fn reset(&mut self) -> Result<()> {
+        clear_global_dns(self)
+    }
+
+    /// Returns the DNS servers in the configuration of NetworkManager's DNS manager.
+    pub fn get_dns(&self) -> Result<Vec<IpAddr>> {
+        let configuration: Vec<HashMap<String, Variant<Box<dyn RefArg>>>> = self
+            .dbus_connection
+            .with_path(NM_BUS, NM_DNS_MANAGER_PATH, RPC_TIMEOUT_MS)
+            .get(NM_DNS_MANAGER, DNS_CONFIGURATION_KEY)
+            .map_err(Error::Dbus)?;
+        Ok(configuration
+            .iter()
+            .filter_map(|entry| entry.get("nameservers"))
+            .filter_map(|nameservers| nameservers.0.as_iter())
+            .flatten()
+            .filter_map(|nameserver| nameserver.as_str()?.parse().ok())
+            .collect())
+    }
+}
+
+type GlobalDnsConfig = HashMap<&'static str, Variant<Box<dyn RefArg>>>;
+
+/// Sets the global DNS configuration, so that restoring it can be tested without D-Bus.
+trait GlobalDns {
+    fn set_global_dns(&mut self, config: GlobalDnsConfig) -> Result<()>;
+}
+
+impl GlobalDns for NetworkManager {
     fn set_global_dns(&mut self, config: GlobalDnsConfig) -> Result<()> {
         self.as_manager()
             .set(NM_TOP_OBJECT, GLOBAL_DNS_CONF_KEY, config)
             .map_err(Error::Dbus)
     }
-
-    pub fn reset(&mut self) -> Result<()> {
-        self.set_global_dns(create_empty_global_settings())
-    }
 }
 
-type GlobalDnsConfig = HashMap<&'static str, Variant<Box<dyn RefArg>>>;
+/// Clears the global DNS configuration, which makes NetworkManager use the DNS servers of each
+/// connection again.
+fn clear_global_dns(global_dns: &mut impl GlobalDns) -> Result<()> {
+    global_dns.set_global_dns(create_empty_global_settings())
+}
 
 // The NetworkManager GlobalDnsConfiguration schema looks something like this
 // {
@@ -189,3 +234,33 @@ fn eq_file_content<P: AsRef<Path>>(a: &P, b: &P) -> bool {
             _ => false,
         })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Keeps the global DNS configuration that would have been set over D-Bus.
+    #[derive(Default)]
+    struct MockGlobalDns {
+        config: Option<GlobalDnsConfig>,
+    }
+
+    impl GlobalDns for MockGlobalDns {
+        fn set_global_dns(&mut self, config: GlobalDnsConfig) -> Result<()> {
+            self.config = Some(config);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn restore_clears_global_dns() {
+        let mut global_dns = MockGlobalDns::default();
+        global_dns
+            .set_global_dns(create_global_settings(&["10.64.0.1".parse().unwrap()]))
+            .unwrap();
+        assert!(!global_dns.config.as_ref().unwrap().is_empty());
+
+        clear_global_dns(&mut global_dns).expect("failed to clear global DNS");
+        assert!(global_dns.config.unwrap().is_empty());
+    }
+}
@@ -75,8 +75,13 @@ impl Resolvconf {
             .unwrap_or_else(|_| false)
     }
 
+    /// Returns the name of the record that DNS for `interface` is added as.
+    pub fn record_name(interface: &str) -> String {
+        format!("{}.mullvad", interface)
+    }
+
     pub fn set_dns(&mut self, interface: &str, servers: &[IpAddr]) -> Result<()> {
-        let record_name = format!("{}.mullvad", interface);
+        let record_name = Self::record_name(interface);
         let mut record_contents = String::new();
 
         for address in servers {
@@ -106,25 +111,24 @@ impl Resolvconf {
         let mut result = Ok(());
 
         for record_name in self.record_names.drain() {
-            let output = duct::cmd!(&self.resolvconf, "-d", &record_name)
-                .stderr_capture()
-                .unchecked()
-                .run()
-                .map_err(Error::RunResolvconf)?;
-
-            if !output.status.success() {
-                log::error!(
-                    "Failed to delete 'resolvconf' record '{}':\n{}",
-                    record_name,
-                    String::from_utf8_lossy(&output.stderr)
-                );
-                result = Err(Error::DeleteRecordError);
+            if let Err(error) = delete_record(&self.resolvconf, &record_name) {
+                match error {
+                    Error::RunResolvconf(..) => return Err(error),
+                    _ => result = Err(error),
+                }
             }
         }
 
         result
     }
 
+    /// Deletes a record added before an unclean shutdown. This works as long as the `resolvconf`
+    /// program exists, even if it no longer manages /etc/resolv.conf.
+    pub fn restore(record_name: &str) -> Result<()> {
+        let resolvconf_path = which("resolvconf").map_err(|_| Error::NoResolvconf)?;
+        delete_record(&resolvconf_path, record_name)
+    }
+
     fn is_dnsmasq_running() -> bool {
         let pid = match fs::read_to_string("/var/run/dnsmasq/dnsmasq.pid") {
             Ok(pid) => pid,
@@ -186,3 +190,70 @@ impl Resolvconf {
         }
     }
 }
+
+fn delete_record(resolvconf: &Path, record_name: &str) -> Result<()> {
+    let output = duct::cmd!(resolvconf, "-d", record_name)
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .map_err(Error::RunResolvconf)?;
+
+    if !output.status.success() {
+        log::error!(
+            "Failed to delete 'resolvconf' record '{}':\n{}",
+            record_name,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return Err(Error::DeleteRecordError);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// Creates a fake `resolvconf` that appends its arguments to a file, and exits with
+    /// `exit_code`.
+    fn fake_resolvconf(dir: &Path, exit_code: i32) -> (PathBuf, PathBuf) {
+        let log_path = dir.join("calls");
+        let script_path = dir.join("resolvconf");
+        fs::write(
+            &script_path,
+            format!(
+                "#!/bin/sh\necho \"$@\" >> '{}'\nexit {}\n",
+                log_path.display(),
+                exit_code
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&script_path, fs::Permissions::from_mode(0o755)).unwrap();
+        (script_path, log_path)
+    }
+
+    #[test]
+    fn deletes_record() {
+        let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+        let (resolvconf, log_path) = fake_resolvconf(temp_dir.path(), 0);
+
+        delete_record(&resolvconf, &Resolvconf::record_name("wg-mullvad"))
+            .expect("failed to delete record");
+
+        assert_eq!(
+            fs::read_to_string(log_path).unwrap(),
+            "-d wg-mullvad.mullvad\n"
+        );
+    }
+
+    #[test]
+    fn failed_delete_is_an_error() {
+        let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+        let (resolvconf, _) = fake_resolvconf(temp_dir.path(), 1);
+
+        match delete_record(&resolvconf, "wg-mullvad.mullvad") {
+            Err(Error::DeleteRecordError) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+}
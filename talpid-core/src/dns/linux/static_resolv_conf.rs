@@ -5,6 +5,7 @@ use resolv_conf::{Config, ScopedIp};
 use std::{
     fs, io,
     net::IpAddr,
+    path::Path,
     sync::{mpsc, Arc},
     thread,
 };
@@ -31,6 +32,9 @@ pub enum Error {
 
     #[error(display = "Failed to remove stale resolv.conf backup at {}", _0)]
     RemoveBackup(&'static str, #[error(source)] io::Error),
+
+    #[error(display = "Failed to restore the original /etc/resolv.conf")]
+    RestoreResolvConf(#[error(source)] io::Error),
 }
 
 pub struct StaticResolvConf {
@@ -193,6 +197,29 @@ fn write_backup(backup: &Config) -> Result<()> {
         .map_err(|e| Error::WriteResolvConf(RESOLV_CONF_BACKUP_PATH, e))
 }
 
+/// Writes back the original contents of /etc/resolv.conf after an unclean shutdown, and removes
+/// the backup that would otherwise be restored the next time DNS is set.
+pub fn restore(contents: &str) -> Result<()> {
+    restore_resolv_conf(
+        Path::new(RESOLV_CONF_PATH),
+        Path::new(RESOLV_CONF_BACKUP_PATH),
+        contents,
+    )
+    .map_err(Error::RestoreResolvConf)
+}
+
+fn restore_resolv_conf(
+    resolv_conf_path: &Path,
+    backup_path: &Path,
+    contents: &str,
+) -> io::Result<()> {
+    fs::write(resolv_conf_path, contents)?;
+    match fs::remove_file(backup_path) {
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
 fn restore_from_backup() -> Result<()> {
     match fs::read_to_string(RESOLV_CONF_BACKUP_PATH) {
         Ok(backup) => {
@@ -212,3 +239,43 @@ fn restore_from_backup() -> Result<()> {
         Err(error) => Err(Error::ReadResolvConf(RESOLV_CONF_BACKUP_PATH, error)),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ORIGINAL_RESOLV_CONF: &str = "nameserver 192.168.1.1\nsearch lan\n";
+
+    #[test]
+    fn restores_resolv_conf_and_removes_backup() {
+        let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+        let resolv_conf_path = temp_dir.path().join("resolv.conf");
+        let backup_path = temp_dir.path().join("resolv.conf.mullvadbackup");
+        fs::write(&resolv_conf_path, "nameserver 10.64.0.1\n").unwrap();
+        fs::write(&backup_path, ORIGINAL_RESOLV_CONF).unwrap();
+
+        restore_resolv_conf(&resolv_conf_path, &backup_path, ORIGINAL_RESOLV_CONF)
+            .expect("failed to restore resolv.conf");
+
+        assert_eq!(
+            fs::read_to_string(&resolv_conf_path).unwrap(),
+            ORIGINAL_RESOLV_CONF
+        );
+        assert!(!backup_path.exists());
+    }
+
+    #[test]
+    fn restores_resolv_conf_without_backup() {
+        let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+        let resolv_conf_path = temp_dir.path().join("resolv.conf");
+        let backup_path = temp_dir.path().join("resolv.conf.mullvadbackup");
+
+        restore_resolv_conf(&resolv_conf_path, &backup_path, ORIGINAL_RESOLV_CONF)
+            .expect("failed to restore resolv.conf");
+
+        assert_eq!(
+            fs::read_to_string(&resolv_conf_path).unwrap(),
+            ORIGINAL_RESOLV_CONF
+        );
+    }
+}
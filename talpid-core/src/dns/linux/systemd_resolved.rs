@@ -1,17 +1,22 @@
-use super::RESOLV_CONF_PATH;
+use super::{
+    journal::{JournalEntry, LinkSettings},
+    RESOLV_CONF_PATH,
+};
 use crate::linux::iface_index;
 use dbus::{
     arg::RefArg, stdintf::*, BusType, Interface, Member, Message, MessageItem, MessageItemArray,
     Signature,
 };
+use ipnetwork::IpNetwork;
 use lazy_static::lazy_static;
 use libc::{AF_INET, AF_INET6};
+use nix::{ifaddrs::getifaddrs, sys::socket::SockAddr};
 use std::{
     fs, io,
-    net::{IpAddr, Ipv4Addr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
     path::Path,
 };
-use talpid_types::ErrorExt as _;
+use talpid_types::{dns::SplitDnsRule, ErrorExt as _};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -48,11 +53,26 @@ pub enum Error {
     #[error(display = "Failed to revert DNS settings of interface: {}", _0)]
     RevertDnsError(String, #[error(source)] dbus::Error),
 
+    #[error(display = "Failed to list network interfaces")]
+    ListInterfaces(#[error(source)] nix::Error),
+
+    #[error(display = "Failed to read DNS settings of interface: {}", _0)]
+    ReadLinkDnsError(String, #[error(source)] dbus::Error),
+
     #[error(display = "Failed to perform RPC call on D-Bus")]
     DBusRpcError(#[error(source)] dbus::Error),
 
     #[error(display = "Failed to match the returned D-Bus object with expected type")]
     MatchDBusTypeError(#[error(source)] dbus::arg::TypeMismatchError),
+
+    #[error(display = "Failed to write the global DNS configuration")]
+    WriteGlobalConfig(#[error(source)] io::Error),
+
+    #[error(display = "Failed to remove the global DNS configuration")]
+    RemoveGlobalConfig(#[error(source)] io::Error),
+
+    #[error(display = "Failed to reload systemd-resolved")]
+    ReloadResolved(#[error(source)] dbus::Error),
 }
 
 lazy_static! {
@@ -67,6 +87,15 @@ const RESOLVED_DNS_SERVER_ADDRESS: [u8; 4] = [127, 0, 0, 53];
 const RESOLVED_BUS: &str = "org.freedesktop.resolve1";
 const RPC_TIMEOUT_MS: i32 = 1000;
 
+/// Sets the global DNS servers of systemd-resolved while a local resolver is used. Global
+/// servers aren't bound to any link, unlike the servers of the tunnel link, so they can be on the
+/// loopback interface.
+const GLOBAL_DNS_CONFIG_DIR: &str = "/run/systemd/resolved.conf.d";
+const GLOBAL_DNS_CONFIG_PATH: &str = "/run/systemd/resolved.conf.d/mullvad-dns.conf";
+const RESOLVED_UNIT: &str = "systemd-resolved.service";
+const SYSTEMD_BUS: &str = "org.freedesktop.systemd1";
+const SYSTEMD_OBJECT_PATH: &str = "/org/freedesktop/systemd1";
+
 lazy_static! {
     static ref LINK_INTERFACE: Interface<'static> =
         Interface::from_slice(b"org.freedesktop.resolve1.Link").unwrap();
@@ -76,29 +105,68 @@ lazy_static! {
     static ref SET_DNS_METHOD: Member<'static> = Member::from_slice(b"SetDNS").unwrap();
     static ref SET_DOMAINS_METHOD: Member<'static> = Member::from_slice(b"SetDomains").unwrap();
     static ref REVERT_METHOD: Member<'static> = Member::from_slice(b"Revert").unwrap();
+    static ref SYSTEMD_MANAGER_INTERFACE: Interface<'static> =
+        Interface::from_slice(b"org.freedesktop.systemd1.Manager").unwrap();
+    static ref RELOAD_OR_RESTART_UNIT_METHOD: Member<'static> =
+        Member::from_slice(b"ReloadOrRestartUnit").unwrap();
 }
 
 pub struct SystemdResolved {
     dbus_connection: dbus::Connection,
     interface_link: Option<(String, dbus::Path<'static>)>,
+    split_dns_links: Vec<SplitDnsLink>,
+    /// Whether the servers were set globally instead of on the tunnel link.
+    global_dns: bool,
+}
+
+/// The original DNS settings of a link that routing domains for split DNS have been added to.
+struct SplitDnsLink {
+    interface_name: String,
+    link_object_path: dbus::Path<'static>,
+    servers: Vec<IpAddr>,
+    domains: Vec<(String, bool)>,
+}
+
+/// Servers and routing domains to add to a link for split DNS.
+pub struct SplitDnsChange {
+    link: SplitDnsLink,
+    servers: Vec<IpAddr>,
+    domains: Vec<String>,
 }
 
 impl SystemdResolved {
     pub fn new() -> Result<Self> {
+        let systemd_resolved = Self::connect()?;
+
+        Self::ensure_resolv_conf_is_resolved_symlink()?;
+        Self::ensure_resolv_conf_has_resolved_dns()?;
+
+        Ok(systemd_resolved)
+    }
+
+    /// Connects to systemd-resolved without checking that it manages /etc/resolv.conf.
+    fn connect() -> Result<Self> {
         let dbus_connection =
             dbus::Connection::get_private(BusType::System).map_err(Error::ConnectDBus)?;
         let systemd_resolved = SystemdResolved {
             dbus_connection,
             interface_link: None,
+            split_dns_links: vec![],
+            global_dns: false,
         };
-
         systemd_resolved.ensure_resolved_exists()?;
-        Self::ensure_resolv_conf_is_resolved_symlink()?;
-        Self::ensure_resolv_conf_has_resolved_dns()?;
-
         Ok(systemd_resolved)
     }
 
+    /// Undoes DNS changes made before an unclean shutdown. The settings of `interface_name` are
+    /// reverted if the interface still exists, the links in `split_dns_links` get their
+    /// original settings back, and any global DNS servers are removed.
+    pub fn restore(interface_name: &str, split_dns_links: &[LinkSettings]) -> Result<()> {
+        let systemd_resolved = Self::connect()?;
+        let result = restore_links(&systemd_resolved, interface_name, split_dns_links);
+        systemd_resolved.remove_global_dns().and(result)
+    }
+
     fn ensure_resolved_exists(&self) -> Result<()> {
         let _: Box<dyn RefArg> = self
             .as_manager_object()
@@ -181,10 +249,63 @@ impl SystemdResolved {
             );
         }
 
-        self.set_link_dns(&link_object_path, servers)?;
-        self.interface_link = Some((interface_name.to_string(), link_object_path));
+        // Servers on the link are queried from sockets bound to the link, so a local resolver
+        // can't be set there
+        if servers.iter().all(IpAddr::is_loopback) {
+            self.interface_link = Some((interface_name.to_string(), link_object_path));
+            self.global_dns = true;
+            self.set_global_dns(servers)
+        } else {
+            self.set_link_dns(&link_object_path, servers)?;
+            self.interface_link = Some((interface_name.to_string(), link_object_path));
+            Ok(())
+        }
+    }
+
+    /// Makes `servers` the global DNS servers, with a routing domain that catches all queries,
+    /// so that they are preferred over the servers of every link.
+    fn set_global_dns(&self, servers: &[IpAddr]) -> Result<()> {
+        let servers = servers
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(" ");
+        let config = format!(
+            "# Written by the Mullvad VPN daemon, removed when DNS is reset\n\
+             [Resolve]\nDNS={}\nDomains=~.\n",
+            servers
+        );
+        fs::create_dir_all(GLOBAL_DNS_CONFIG_DIR)
+            .and_then(|_| fs::write(GLOBAL_DNS_CONFIG_PATH, config))
+            .map_err(Error::WriteGlobalConfig)?;
+        self.reload_resolved()
+    }
 
-        Ok(())
+    /// Removes the global DNS servers, if they are set.
+    fn remove_global_dns(&self) -> Result<()> {
+        match fs::remove_file(GLOBAL_DNS_CONFIG_PATH) {
+            Ok(()) => self.reload_resolved(),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(Error::RemoveGlobalConfig(error)),
+        }
+    }
+
+    /// Makes systemd-resolved read its configuration files again.
+    fn reload_resolved(&self) -> Result<()> {
+        self.dbus_connection
+            .with_path(SYSTEMD_BUS, SYSTEMD_OBJECT_PATH, RPC_TIMEOUT_MS)
+            .method_call_with_args(
+                &SYSTEMD_MANAGER_INTERFACE,
+                &RELOAD_OR_RESTART_UNIT_METHOD,
+                |message| {
+                    message.append_items(&[
+                        MessageItem::Str(RESOLVED_UNIT.to_owned()),
+                        MessageItem::Str("replace".to_owned()),
+                    ]);
+                },
+            )
+            .and_then(|mut reply| reply.as_result().map(|_| ()))
+            .map_err(Error::ReloadResolved)
     }
 
     fn fetch_link(&self, interface_name: &str) -> Result<dbus::Path<'static>> {
@@ -203,9 +324,21 @@ impl SystemdResolved {
             .map_err(Error::MatchDBusTypeError)
     }
 
-    fn set_link_dns<'a, 'b: 'a>(
-        &'a self,
-        link_object_path: &'b dbus::Path<'static>,
+    fn set_link_dns(
+        &self,
+        link_object_path: &dbus::Path<'static>,
+        servers: &[IpAddr],
+    ) -> Result<()> {
+        self.set_link_servers(link_object_path, servers)?;
+
+        // set the search domain to catch all DNS requests, forces the link to be the prefered
+        // resolver, otherwise systemd-resolved will use other interfaces to do DNS lookups
+        self.set_link_domains(link_object_path, &[(".", true)])
+    }
+
+    fn set_link_servers(
+        &self,
+        link_object_path: &dbus::Path<'static>,
         servers: &[IpAddr],
     ) -> Result<()> {
         let server_addresses = build_addresses_argument(servers);
@@ -214,12 +347,14 @@ impl SystemdResolved {
                 message.append_items(&[server_addresses]);
             })
             .and_then(|mut reply| reply.as_result().map(|_| ()))
-            .map_err(Error::DBusRpcError)?;
-
-        // set the search domain to catch all DNS requests, forces the link to be the prefered
-        // resolver, otherwise systemd-resolved will use other interfaces to do DNS lookups
-        let dns_domains: &[_] = &[(&".", true)];
+            .map_err(Error::DBusRpcError)
+    }
 
+    fn set_link_domains(
+        &self,
+        link_object_path: &dbus::Path<'static>,
+        dns_domains: &[(&str, bool)],
+    ) -> Result<()> {
         let msg = Message::new_method_call(
             RESOLVED_BUS,
             link_object_path as &str,
@@ -235,7 +370,206 @@ impl SystemdResolved {
             .map_err(Error::SetDomainsError)
     }
 
+    /// Returns the changes that add a routing domain for each rule to the links that the servers
+    /// of the rule are reachable on, so that systemd-resolved sends queries for names in the
+    /// domain to those servers. Nothing is changed until the changes are passed to
+    /// `apply_split_dns`. Any previous split DNS changes are undone. Returns `None` if a server is
+    /// not on the network of any local interface, in which case the rules can't be applied
+    /// through systemd-resolved.
+    pub fn prepare_split_dns(
+        &mut self,
+        rules: &[SplitDnsRule],
+    ) -> Result<Option<Vec<SplitDnsChange>>> {
+        self.reset_split_dns();
+
+        let mut links: Vec<(String, Vec<IpAddr>, Vec<String>)> = vec![];
+        for rule in rules {
+            for server in &rule.servers {
+                let interface_name = match find_interface_for_address(*server)? {
+                    Some(interface_name) => interface_name,
+                    None => {
+                        log::debug!("No interface is on the network of DNS server {}", server);
+                        return Ok(None);
+                    }
+                };
+                let index = match links.iter().position(|(name, ..)| *name == interface_name) {
+                    Some(index) => index,
+                    None => {
+                        links.push((interface_name, vec![], vec![]));
+                        links.len() - 1
+                    }
+                };
+                let (_, servers, domains) = &mut links[index];
+                if !servers.contains(server) {
+                    servers.push(*server);
+                }
+                if !domains.contains(&rule.domain) {
+                    domains.push(rule.domain.clone());
+                }
+            }
+        }
+
+        links
+            .into_iter()
+            .map(|(interface_name, servers, domains)| {
+                Ok(SplitDnsChange {
+                    link: self.read_link_settings(interface_name)?,
+                    servers,
+                    domains,
+                })
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(Some)
+    }
+
+    /// Adds the servers and routing domains of `changes` to the existing settings of the links.
+    /// The original settings of every link are restored if any change fails.
+    pub fn apply_split_dns(&mut self, changes: Vec<SplitDnsChange>) -> Result<()> {
+        for change in changes {
+            if let Err(error) = self.add_split_dns_link(change) {
+                self.reset_split_dns();
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    fn read_link_settings(&self, interface_name: String) -> Result<SplitDnsLink> {
+        let link_object_path = self
+            .fetch_link(&interface_name)
+            .map_err(|e| Error::GetLinkError(Box::new(e)))?;
+        let servers = self.read_link_servers(&interface_name, &link_object_path)?;
+        let domains: Vec<(String, bool)> = self
+            .as_link_object(link_object_path.clone())
+            .get(&LINK_INTERFACE, "Domains")
+            .map_err(|e| Error::ReadLinkDnsError(interface_name.clone(), e))?;
+        Ok(SplitDnsLink {
+            interface_name,
+            link_object_path,
+            servers,
+            domains,
+        })
+    }
+
+    fn add_split_dns_link(&mut self, change: SplitDnsChange) -> Result<()> {
+        let SplitDnsChange {
+            link,
+            servers,
+            domains,
+        } = change;
+        let mut new_servers = link.servers.clone();
+        new_servers.extend(
+            servers
+                .into_iter()
+                .filter(|server| !link.servers.contains(server)),
+        );
+        let mut new_domains: Vec<(&str, bool)> = link
+            .domains
+            .iter()
+            .map(|(domain, routing_only)| (domain.as_str(), *routing_only))
+            .collect();
+        new_domains.extend(domains.iter().map(|domain| (domain.as_str(), true)));
+
+        log::debug!(
+            "Routing DNS queries for {} to {}",
+            domains.join(", "),
+            link.interface_name
+        );
+        let link_object_path = link.link_object_path.clone();
+        // Recorded first, so that the link is restored even if changing it fails halfway
+        self.split_dns_links.push(link);
+        self.set_link_servers(&link_object_path, &new_servers)?;
+        self.set_link_domains(&link_object_path, &new_domains)
+    }
+
+    /// Returns what has to be undone to restore the DNS settings changed so far and the links in
+    /// `pending_changes`, if DNS is set.
+    pub fn journal_entry(&self, pending_changes: &[SplitDnsChange]) -> Option<JournalEntry> {
+        let (interface_name, _) = self.interface_link.as_ref()?;
+        let split_dns_links = self
+            .split_dns_links
+            .iter()
+            .chain(pending_changes.iter().map(|change| &change.link))
+            .map(|link| LinkSettings {
+                interface_name: link.interface_name.clone(),
+                servers: link.servers.clone(),
+                domains: link.domains.clone(),
+            })
+            .collect();
+        Some(JournalEntry::SystemdResolved {
+            interface_name: interface_name.clone(),
+            split_dns_links,
+        })
+    }
+
+    /// Returns the DNS servers of the link that DNS was set on, or the global DNS servers if DNS
+    /// was set globally, as reported by systemd-resolved.
+    pub fn get_dns(&self) -> Result<Vec<IpAddr>> {
+        match &self.interface_link {
+            Some(_) if self.global_dns => self.read_global_servers(),
+            Some((interface_name, link_object_path)) => {
+                self.read_link_servers(interface_name, link_object_path)
+            }
+            None => Ok(vec![]),
+        }
+    }
+
+    fn read_global_servers(&self) -> Result<Vec<IpAddr>> {
+        // The servers of every link are listed as well, global servers have interface index 0
+        let servers: Vec<(i32, i32, Vec<u8>)> = self
+            .as_manager_object()
+            .get(&MANAGER_INTERFACE, "DNS")
+            .map_err(Error::DBusRpcError)?;
+        Ok(servers
+            .into_iter()
+            .filter(|(interface_index, ..)| *interface_index == 0)
+            .filter_map(|(_, family, octets)| parse_address(family, &octets))
+            .collect())
+    }
+
+    fn read_link_servers(
+        &self,
+        interface_name: &str,
+        link_object_path: &dbus::Path<'static>,
+    ) -> Result<Vec<IpAddr>> {
+        let servers: Vec<(i32, Vec<u8>)> = self
+            .as_link_object(link_object_path.clone())
+            .get(&LINK_INTERFACE, "DNS")
+            .map_err(|e| Error::ReadLinkDnsError(interface_name.to_owned(), e))?;
+        Ok(servers
+            .into_iter()
+            .filter_map(|(family, octets)| parse_address(family, &octets))
+            .collect())
+    }
+
+    /// Restores the original DNS settings of the links that split DNS rules were applied to.
+    fn reset_split_dns(&mut self) {
+        for link in std::mem::replace(&mut self.split_dns_links, vec![]) {
+            let domains: Vec<(&str, bool)> = link
+                .domains
+                .iter()
+                .map(|(domain, routing_only)| (domain.as_str(), *routing_only))
+                .collect();
+            let result = self
+                .set_link_servers(&link.link_object_path, &link.servers)
+                .and_then(|_| self.set_link_domains(&link.link_object_path, &domains));
+            if let Err(error) = result {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(&format!(
+                        "Failed to restore DNS settings of interface {}",
+                        link.interface_name
+                    ))
+                );
+            }
+        }
+    }
+
     pub fn reset(&mut self) -> Result<()> {
+        self.reset_split_dns();
+        if std::mem::replace(&mut self.global_dns, false) {
+            self.remove_global_dns()?;
+        }
         if let Some((interface_name, link_object_path)) = self.interface_link.take() {
             self.revert_link(link_object_path, &interface_name)
                 .map_err(|e| Error::RevertDnsError(interface_name.to_owned(), e))
@@ -269,6 +603,102 @@ impl SystemdResolved {
     }
 }
 
+/// Changes the DNS settings of links by interface name, so that restoring settings can be tested
+/// without D-Bus.
+trait LinkDns {
+    /// Reverts the settings of an interface. Succeeds if the interface doesn't exist.
+    fn revert(&self, interface_name: &str) -> Result<()>;
+    fn set(&self, settings: &LinkSettings) -> Result<()>;
+}
+
+impl LinkDns for SystemdResolved {
+    fn revert(&self, interface_name: &str) -> Result<()> {
+        let link_object_path = match self.fetch_link(interface_name) {
+            Ok(link_object_path) => link_object_path,
+            Err(Error::InvalidInterfaceName(_)) => {
+                log::debug!(
+                    "Not reverting DNS of interface {} because it no longer exists",
+                    interface_name
+                );
+                return Ok(());
+            }
+            Err(error) => return Err(Error::GetLinkError(Box::new(error))),
+        };
+        self.as_link_object(link_object_path)
+            .method_call_with_args(&LINK_INTERFACE, &REVERT_METHOD, |_| {})
+            .and_then(|mut reply| reply.as_result().map(|_| ()))
+            .map_err(|e| Error::RevertDnsError(interface_name.to_owned(), e))
+    }
+
+    fn set(&self, settings: &LinkSettings) -> Result<()> {
+        let link_object_path = self
+            .fetch_link(&settings.interface_name)
+            .map_err(|e| Error::GetLinkError(Box::new(e)))?;
+        let domains: Vec<(&str, bool)> = settings
+            .domains
+            .iter()
+            .map(|(domain, routing_only)| (domain.as_str(), *routing_only))
+            .collect();
+        self.set_link_servers(&link_object_path, &settings.servers)?;
+        self.set_link_domains(&link_object_path, &domains)
+    }
+}
+
+/// Restores every link, even if restoring one of them fails. Returns the last error.
+fn restore_links(
+    link_dns: &impl LinkDns,
+    interface_name: &str,
+    split_dns_links: &[LinkSettings],
+) -> Result<()> {
+    let mut result = Ok(());
+    for link in split_dns_links {
+        if let Err(error) = link_dns.set(link) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg(&format!(
+                    "Failed to restore DNS settings of interface {}",
+                    link.interface_name
+                ))
+            );
+            result = Err(error);
+        }
+    }
+    link_dns.revert(interface_name).and(result)
+}
+
+/// Returns the name of the interface that has an address on the same network as `address`.
+fn find_interface_for_address(address: IpAddr) -> Result<Option<String>> {
+    for interface in getifaddrs().map_err(Error::ListInterfaces)? {
+        if let (Some(SockAddr::Inet(interface_address)), Some(SockAddr::Inet(netmask))) =
+            (interface.address, interface.netmask)
+        {
+            let network =
+                IpNetwork::with_netmask(interface_address.ip().to_std(), netmask.ip().to_std());
+            if network
+                .map(|network| network.contains(address))
+                .unwrap_or(false)
+            {
+                return Ok(Some(interface.interface_name));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn parse_address(family: i32, octets: &[u8]) -> Option<IpAddr> {
+    match family {
+        AF_INET if octets.len() == 4 => {
+            Some(Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3]).into())
+        }
+        AF_INET6 if octets.len() == 16 => {
+            let mut address = [0u8; 16];
+            address.copy_from_slice(octets);
+            Some(Ipv6Addr::from(address).into())
+        }
+        _ => None,
+    }
+}
+
 fn build_addresses_argument(addresses: &[IpAddr]) -> MessageItem {
     let addresses = addresses.iter().map(ip_address_to_message_item).collect();
 
@@ -300,3 +730,64 @@ fn bytes_to_message_item_array(bytes: &[u8]) -> MessageItemArray {
     )
     .expect("Invalid construction of DBus array of bytes argument")
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Records the changes that would have been made over D-Bus.
+    #[derive(Default)]
+    struct MockLinkDns {
+        reverted: RefCell<Vec<String>>,
+        set: RefCell<Vec<LinkSettings>>,
+        failing_interface: Option<&'static str>,
+    }
+
+    impl LinkDns for MockLinkDns {
+        fn revert(&self, interface_name: &str) -> Result<()> {
+            self.reverted.borrow_mut().push(interface_name.to_owned());
+            Ok(())
+        }
+
+        fn set(&self, settings: &LinkSettings) -> Result<()> {
+            if self.failing_interface == Some(settings.interface_name.as_str()) {
+                return Err(Error::NoDnsPointsToResolved);
+            }
+            self.set.borrow_mut().push(settings.clone());
+            Ok(())
+        }
+    }
+
+    fn link_settings(interface_name: &str) -> LinkSettings {
+        LinkSettings {
+            interface_name: interface_name.to_owned(),
+            servers: vec!["192.168.1.1".parse().unwrap()],
+            domains: vec![("lan".to_owned(), false)],
+        }
+    }
+
+    #[test]
+    fn restores_tunnel_and_split_dns_links() {
+        let link_dns = MockLinkDns::default();
+        let links = vec![link_settings("eth0"), link_settings("wlan0")];
+
+        restore_links(&link_dns, "wg-mullvad", &links).expect("failed to restore links");
+
+        assert_eq!(*link_dns.reverted.borrow(), vec!["wg-mullvad".to_owned()]);
+        assert_eq!(*link_dns.set.borrow(), links);
+    }
+
+    #[test]
+    fn restores_remaining_links_after_failure() {
+        let link_dns = MockLinkDns {
+            failing_interface: Some("eth0"),
+            ..MockLinkDns::default()
+        };
+        let links = vec![link_settings("eth0"), link_settings("wlan0")];
+
+        assert!(restore_links(&link_dns, "wg-mullvad", &links).is_err());
+        assert_eq!(*link_dns.reverted.borrow(), vec!["wg-mullvad".to_owned()]);
+        assert_eq!(*link_dns.set.borrow(), vec![link_settings("wlan0")]);
+    }
+}
@@ -0,0 +1,119 @@
+//! Looks up hosts outside the tunnel, for hosts that must be reachable while the tunnel is down or
+//! being set up, like the hosts of custom relays.
+//!
+//! The queries are sent to the DNS servers of the network from sockets with the split tunneling
+//! mark. The mark routes them outside the tunnel and lets them and their responses through the
+//! firewall, so no other DNS traffic is let out. Only IPv4 servers are used, since only IPv4
+//! traffic is routed by the mark.
+
+use super::{imp, stub_resolver::message};
+use crate::split_tunnel;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    time::{Duration, Instant},
+};
+use talpid_types::ErrorExt;
+
+/// Lists the upstream servers of systemd-resolved, since /etc/resolv.conf then only lists its
+/// local stub resolver.
+const SYSTEMD_RESOLVED_SERVERS_PATH: &str = "/run/systemd/resolve/resolv.conf";
+const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+const DNS_PORT: u16 = 53;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_RESPONSE_SIZE: usize = 4096;
+
+/// Returns the DNS servers of the network that can be queried outside the tunnel. The servers
+/// are read from the system settings, so this must be called while DNS is not set for a tunnel.
+pub fn network_servers() -> Vec<IpAddr> {
+    let path = if Path::new(SYSTEMD_RESOLVED_SERVERS_PATH).exists() {
+        SYSTEMD_RESOLVED_SERVERS_PATH
+    } else {
+        RESOLV_CONF_PATH
+    };
+    match imp::read_resolv_conf_servers(path) {
+        Ok(servers) => servers
+            .into_iter()
+            .filter(|server| server.is_ipv4() && !server.is_loopback())
+            .collect(),
+        Err(error) => {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Failed to read the DNS servers of the network")
+            );
+            vec![]
+        }
+    }
+}
+
+/// Looks up the addresses of `host` through the first of `servers` that answers, with the IPv4
+/// addresses first. Blocks until a server has answered or every server has timed out.
+pub fn resolve(host: &str, servers: &[IpAddr]) -> io::Result<Vec<IpAddr>> {
+    if let Ok(address) = host.parse() {
+        return Ok(vec![address]);
+    }
+
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "No DNS servers to query");
+    for server in servers {
+        match query_server(host, *server) {
+            Ok(addresses) => return Ok(addresses),
+            Err(error) => {
+                log::debug!("Failed to look up {} through {}: {}", host, server, error);
+                last_error = error;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+fn query_server(host: &str, server: IpAddr) -> io::Result<Vec<IpAddr>> {
+    let domain = match server {
+        IpAddr::V4(_) => Domain::ipv4(),
+        IpAddr::V6(_) => Domain::ipv6(),
+    };
+    let socket = Socket::new(domain, Type::dgram(), Some(Protocol::udp()))?;
+    socket.set_mark(split_tunnel::MARK as u32)?;
+    socket.connect(&SocketAddr::new(server, DNS_PORT).into())?;
+    let socket = socket.into_udp_socket();
+
+    let queries = [
+        message::build_query(rand::random(), host, message::TYPE_A),
+        message::build_query(rand::random(), host, message::TYPE_AAAA),
+    ];
+    for query in &queries {
+        socket.send(query)?;
+    }
+
+    let mut answers = [None, None];
+    let mut response = [0u8; MAX_RESPONSE_SIZE];
+    let deadline = Instant::now() + QUERY_TIMEOUT;
+    while answers.iter().any(Option::is_none) {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if timeout == Duration::from_secs(0) {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "No answer"));
+        }
+        socket.set_read_timeout(Some(timeout))?;
+        let len = socket.recv(&mut response)?;
+        let response = &response[..len];
+
+        for (query, answer) in queries.iter().zip(answers.iter_mut()) {
+            if answer.is_none() && message::is_response_to(query, response) {
+                let addresses = message::parse_addresses(response).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "Invalid or failed response")
+                })?;
+                *answer = Some(addresses);
+            }
+        }
+    }
+
+    let addresses: Vec<IpAddr> = answers.iter().flatten().flatten().copied().collect();
+    if addresses.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} has no addresses", host),
+        ));
+    }
+    Ok(addresses)
+}
@@ -17,6 +17,20 @@ mod imp;
 mod imp;
 
 pub use self::imp::Error;
+#[cfg(target_os = "linux")]
+pub use self::imp::{probe_backends, restore_from_journal};
+
+/// Checks of the DNS configuration and of where DNS queries go.
+#[cfg(target_os = "linux")]
+pub mod diagnostics;
+
+/// DNS lookups outside the tunnel.
+#[cfg(target_os = "linux")]
+pub mod lookup;
+
+/// A local resolver that blocks unwanted domains.
+#[cfg(target_os = "linux")]
+pub mod stub_resolver;
 
 /// Sets and monitors system DNS settings. Makes sure the desired DNS servers are being used.
 pub struct DnsMonitor {
@@ -44,6 +58,36 @@ impl DnsMonitor {
         self.inner.set(interface, servers)
     }
 
+    /// Route DNS queries for the domains of the given split DNS rules to the servers of the
+    /// rules, through the system resolver. Returns `false` if the system resolver doesn't
+    /// support this, in which case nothing is changed.
+    #[cfg(target_os = "linux")]
+    pub fn set_split_dns(
+        &mut self,
+        rules: &[talpid_types::dns::SplitDnsRule],
+    ) -> Result<bool, Error> {
+        self.inner.set_split_dns(rules)
+    }
+
+    /// Returns the backend that manages the system DNS settings, if DNS is set.
+    #[cfg(target_os = "linux")]
+    pub fn backend(&self) -> Option<talpid_types::dns::DnsBackend> {
+        self.inner.backend()
+    }
+
+    /// Use the given backend the next time DNS is set, or detect which one to use if `None`.
+    #[cfg(target_os = "linux")]
+    pub fn force_backend(&mut self, backend: Option<talpid_types::dns::DnsBackend>) {
+        self.inner.force_backend(backend)
+    }
+
+    /// Read back the DNS servers that the system is using from the backend that DNS was set
+    /// through.
+    #[cfg(target_os = "linux")]
+    pub fn get_dns(&self) -> Result<Vec<IpAddr>, Error> {
+        self.inner.get_dns()
+    }
+
     /// Reset system DNS settings to what it was before being set by this instance.
     pub fn reset(&mut self) -> Result<(), Error> {
         log::info!("Resetting DNS");
@@ -0,0 +1,209 @@
+use parking_lot::RwLock;
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use talpid_types::dns::{BlockingCategories, BlockingCategory, ResolverStats};
+
+/// Errors that can happen when loading blocklists.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Failed to read a blocklist file
+    #[error(display = "Failed to read the {} blocklist", _0)]
+    ReadBlocklist(BlockingCategory, #[error(source)] io::Error),
+}
+
+/// Hostnames in hosts files that are not meant to be blocked.
+const IGNORED_HOSTS: &[&str] = &[
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+    "0.0.0.0",
+];
+
+/// Domains to block, for each category, along with counters of the queries that the blocklists
+/// have been checked against. The blocklist of a category is read from `<category>.txt` in the
+/// blocklist directory, and can be either in hosts file format or contain one domain per line.
+/// Cloning it returns a handle to the same blocklists.
+#[derive(Clone)]
+pub struct Blocklists {
+    inner: Arc<BlocklistsInner>,
+}
+
+struct BlocklistsInner {
+    dir: PathBuf,
+    domains: RwLock<HashMap<BlockingCategory, HashSet<String>>>,
+    queries: AtomicU64,
+    blocked: AtomicU64,
+}
+
+impl Blocklists {
+    /// Returns empty blocklists that are read from `dir` when reloaded.
+    pub fn new(dir: PathBuf) -> Self {
+        Blocklists {
+            inner: Arc::new(BlocklistsInner {
+                dir,
+                domains: RwLock::new(HashMap::new()),
+                queries: AtomicU64::new(0),
+                blocked: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Reads the blocklists from disk again. A missing blocklist file is treated as an empty
+    /// list. The current blocklists are kept if any file can't be read.
+    pub fn reload(&self) -> Result<(), Error> {
+        let mut domains = HashMap::new();
+        for category in BlockingCategory::ALL.iter() {
+            let path = self.inner.dir.join(format!("{}.txt", category));
+            let category_domains = match fs::read_to_string(&path) {
+                Ok(contents) => parse_blocklist(&contents),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                    log::debug!("No {} blocklist at {}", category, path.display());
+                    HashSet::new()
+                }
+                Err(error) => return Err(Error::ReadBlocklist(*category, error)),
+            };
+            log::info!(
+                "Loaded {} domains in the {} blocklist",
+                category_domains.len(),
+                category
+            );
+            domains.insert(*category, category_domains);
+        }
+        *self.inner.domains.write() = domains;
+        Ok(())
+    }
+
+    /// Returns whether `name`, or any domain that it is a subdomain of, is in the blocklist of
+    /// any of the given categories. Every call is counted in the resolver statistics.
+    pub fn check(&self, categories: BlockingCategories, name: &str) -> bool {
+        let domains = self.inner.domains.read();
+        let is_blocked = BlockingCategory::ALL
+            .iter()
+            .filter(|category| categories.contains(**category))
+            .filter_map(|category| domains.get(category))
+            .any(|category_domains| {
+                parent_domains(name).any(|domain| category_domains.contains(domain))
+            });
+
+        self.inner.queries.fetch_add(1, Ordering::Relaxed);
+        if is_blocked {
+            self.inner.blocked.fetch_add(1, Ordering::Relaxed);
+        }
+        is_blocked
+    }
+
+    /// Returns the number of checked and blocked queries.
+    pub fn stats(&self) -> ResolverStats {
+        ResolverStats {
+            queries: self.inner.queries.load(Ordering::Relaxed),
+            blocked: self.inner.blocked.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Returns `name` followed by every domain that it is a subdomain of.
+fn parent_domains(name: &str) -> impl Iterator<Item = &str> {
+    let name = name.trim_end_matches('.');
+    std::iter::once(name).chain(
+        name.match_indices('.')
+            .map(move |(index, _)| &name[index + 1..]),
+    )
+}
+
+/// Parses a blocklist in either hosts file format or with one domain per line. Comments start
+/// with `#`.
+fn parse_blocklist(contents: &str) -> HashSet<String> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let line = line.split('#').next().unwrap_or("");
+            let mut fields = line.split_whitespace();
+            let first = fields.next()?;
+            // In hosts files, the domain follows the address that it should resolve to
+            let domain = match fields.next() {
+                Some(domain) if first.parse::<std::net::IpAddr>().is_ok() => domain,
+                Some(_) => return None,
+                None => first,
+            };
+            let domain = domain.trim_end_matches('.').to_lowercase();
+            if domain.is_empty() || IGNORED_HOSTS.contains(&domain.as_str()) {
+                None
+            } else {
+                Some(domain)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_hosts_file_and_domain_list() {
+        let blocklist = parse_blocklist(
+            "# Example blocklist\n\
+             127.0.0.1 localhost\n\
+             ::1 ip6-localhost\n\
+             0.0.0.0 ads.example.com # inline comment\n\
+             0.0.0.0\tTracker.Example.net.\n\
+             malware.example.org\n\
+             \n\
+             invalid line here\n",
+        );
+
+        let mut expected = HashSet::new();
+        expected.insert("ads.example.com".to_owned());
+        expected.insert("tracker.example.net".to_owned());
+        expected.insert("malware.example.org".to_owned());
+        assert_eq!(blocklist, expected);
+    }
+
+    #[test]
+    fn lists_parent_domains() {
+        assert_eq!(
+            parent_domains("a.b.example.com.").collect::<Vec<_>>(),
+            vec!["a.b.example.com", "b.example.com", "example.com", "com"]
+        );
+    }
+
+    #[test]
+    fn blocks_subdomains_of_enabled_categories() {
+        let blocklists = Blocklists::new(PathBuf::new());
+        {
+            let mut domains = blocklists.inner.domains.write();
+            domains.insert(BlockingCategory::Ads, parse_blocklist("ads.example.com\n"));
+            domains.insert(
+                BlockingCategory::Malware,
+                parse_blocklist("0.0.0.0 malware.example.org\n"),
+            );
+        }
+        let categories = BlockingCategories {
+            ads: true,
+            ..Default::default()
+        };
+
+        assert!(blocklists.check(categories, "ads.example.com"));
+        assert!(blocklists.check(categories, "cdn.ads.example.com"));
+        assert!(!blocklists.check(categories, "example.com"));
+        assert!(!blocklists.check(categories, "malware.example.org"));
+        assert_eq!(
+            blocklists.stats(),
+            ResolverStats {
+                queries: 4,
+                blocked: 2,
+            }
+        );
+    }
+}
@@ -0,0 +1,332 @@
+//! Forwarding of DNS queries over DNS over TLS (RFC 7858) and DNS over HTTPS (RFC 8484). A new
+//! connection is made for every query.
+
+use super::Error;
+use ring::digest;
+use std::{io, net::IpAddr, sync::Arc};
+use talpid_types::dns::{EncryptedDnsProtocol, EncryptedDnsServer};
+use tokio02::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_rustls::{rustls, webpki, TlsConnector};
+
+const DEFAULT_DOH_PATH: &str = "/dns-query";
+const DNS_MESSAGE_CONTENT_TYPE: &str = "application/dns-message";
+const MAX_HTTP_RESPONSE_SIZE: usize = 128 * 1024;
+
+/// A client of a single encrypted DNS server.
+pub struct Client {
+    server: EncryptedDnsServer,
+    bind_address: IpAddr,
+    hostname: webpki::DNSName,
+    connector: TlsConnector,
+}
+
+impl Client {
+    /// Creates a client that verifies the certificate of `server` either against the pinned
+    /// certificates of the server, or against the root certificates of the system. Connections
+    /// are made from `bind_address`.
+    pub fn new(server: EncryptedDnsServer, bind_address: IpAddr) -> Result<Self, Error> {
+        let hostname = webpki::DNSNameRef::try_from_ascii_str(&server.hostname)
+            .map_err(|_| Error::InvalidHostname(server.hostname.clone()))?
+            .to_owned();
+        let pins = server
+            .pinned_certificates
+            .iter()
+            .map(|pin| parse_pin(pin))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut config = rustls::ClientConfig::new();
+        if server.protocol == EncryptedDnsProtocol::Https {
+            config.set_protocols(&[b"http/1.1".to_vec()]);
+        }
+        if pins.is_empty() {
+            config.root_store = load_root_store()?;
+        } else {
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(PinnedCertificateVerifier { pins }));
+        }
+
+        Ok(Client {
+            server,
+            bind_address,
+            hostname,
+            connector: TlsConnector::from(Arc::new(config)),
+        })
+    }
+
+    /// Returns the server that queries are sent to.
+    pub fn server(&self) -> &EncryptedDnsServer {
+        &self.server
+    }
+
+    /// Sends `query` to the server and returns the response.
+    pub async fn query(&self, query: &[u8]) -> io::Result<Vec<u8>> {
+        let stream = super::connect_tcp(
+            self.server.socket_addr(),
+            self.bind_address,
+            super::ENCRYPTED_UPSTREAM_TIMEOUT,
+        )
+        .await?;
+        let mut stream = self
+            .connector
+            .connect(self.hostname.as_ref(), stream)
+            .await?;
+        match self.server.protocol {
+            EncryptedDnsProtocol::Tls => self.query_tls(&mut stream, query).await,
+            EncryptedDnsProtocol::Https => self.query_https(&mut stream, query).await,
+        }
+    }
+
+    async fn query_tls(
+        &self,
+        stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+        query: &[u8],
+    ) -> io::Result<Vec<u8>> {
+        super::write_stream_message(stream, query).await?;
+        super::read_stream_message(stream).await
+    }
+
+    async fn query_https(
+        &self,
+        stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+        query: &[u8],
+    ) -> io::Result<Vec<u8>> {
+        let path = self
+            .server
+            .path
+            .as_ref()
+            .map(String::as_str)
+            .unwrap_or(DEFAULT_DOH_PATH);
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\n\
+             Host: {}\r\n\
+             Content-Type: {}\r\n\
+             Accept: {}\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n",
+            path,
+            self.server.hostname,
+            DNS_MESSAGE_CONTENT_TYPE,
+            DNS_MESSAGE_CONTENT_TYPE,
+            query.len()
+        )
+        .into_bytes();
+        request.extend_from_slice(query);
+        stream.write_all(&request).await?;
+        stream.flush().await?;
+
+        let mut response = vec![];
+        let mut buffer = [0u8; 4096];
+        loop {
+            let len = stream.read(&mut buffer).await?;
+            response.extend_from_slice(&buffer[..len]);
+            if let Some(body) = parse_http_response(&response, len == 0)? {
+                return Ok(body);
+            }
+            if len == 0 {
+                return Err(invalid_response("Incomplete HTTP response"));
+            }
+            if response.len() > MAX_HTTP_RESPONSE_SIZE {
+                return Err(invalid_response("HTTP response is too large"));
+            }
+        }
+    }
+}
+
+/// Accepts exactly the certificates whose SHA-256 fingerprint is pinned.
+struct PinnedCertificateVerifier {
+    pins: Vec<Vec<u8>>,
+}
+
+impl rustls::ServerCertVerifier for PinnedCertificateVerifier {
+    fn verify_server_cert(
+        &self,
+        _roots: &rustls::RootCertStore,
+        presented_certs: &[rustls::Certificate],
+        _dns_name: webpki::DNSNameRef<'_>,
+        _ocsp_response: &[u8],
+    ) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
+        let certificate = presented_certs
+            .first()
+            .ok_or(rustls::TLSError::NoCertificatesPresented)?;
+        let fingerprint = digest::digest(&digest::SHA256, &certificate.0);
+        if self
+            .pins
+            .iter()
+            .any(|pin| pin.as_slice() == fingerprint.as_ref())
+        {
+            Ok(rustls::ServerCertVerified::assertion())
+        } else {
+            Err(rustls::TLSError::General(
+                "The certificate does not match any pinned certificate".to_owned(),
+            ))
+        }
+    }
+}
+
+fn load_root_store() -> Result<rustls::RootCertStore, Error> {
+    match rustls_native_certs::load_native_certs() {
+        Ok(root_store) => Ok(root_store),
+        Err((Some(root_store), error)) => {
+            log::warn!("Failed to load some root certificates: {}", error);
+            Ok(root_store)
+        }
+        Err((None, error)) => Err(Error::LoadRootCertificates(error)),
+    }
+}
+
+/// Parses a hex encoded SHA-256 fingerprint. Bytes may be separated by colons.
+fn parse_pin(pin: &str) -> Result<Vec<u8>, Error> {
+    let digits = pin.replace(':', "");
+    match hex::decode(&digits) {
+        Ok(fingerprint) if fingerprint.len() == digest::SHA256_OUTPUT_LEN => Ok(fingerprint),
+        _ => Err(Error::InvalidCertificatePin(pin.to_owned())),
+    }
+}
+
+fn invalid_response(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Returns the body of a successful HTTP response, or `None` if more of the response must be
+/// read first. `eof` tells whether the connection has been closed, in which case the rest of the
+/// response is the body if the length of it isn't given.
+fn parse_http_response(response: &[u8], eof: bool) -> io::Result<Option<Vec<u8>>> {
+    let head_end = match find(response, b"\r\n\r\n") {
+        Some(index) => index,
+        None => return Ok(None),
+    };
+    let head = String::from_utf8_lossy(&response[..head_end]);
+    let body = &response[head_end + 4..];
+
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|status_line| status_line.split_whitespace().nth(1))
+        .ok_or_else(|| invalid_response("Invalid HTTP status line"))?;
+    if status != "200" {
+        return Err(invalid_response(&format!("HTTP status {}", status)));
+    }
+
+    let mut content_length = None;
+    let mut chunked = false;
+    for line in lines {
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim().to_lowercase();
+        let value = parts.next().unwrap_or("").trim();
+        match name.as_str() {
+            "content-length" => {
+                content_length = Some(
+                    value
+                        .parse::<usize>()
+                        .map_err(|_| invalid_response("Invalid Content-Length"))?,
+                )
+            }
+            "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+            _ => (),
+        }
+    }
+
+    if chunked {
+        decode_chunked(body)
+    } else if let Some(content_length) = content_length {
+        if body.len() >= content_length {
+            Ok(Some(body[..content_length].to_vec()))
+        } else {
+            Ok(None)
+        }
+    } else if eof {
+        Ok(Some(body.to_vec()))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Decodes a body with chunked transfer encoding, or returns `None` if the body is incomplete.
+fn decode_chunked(mut body: &[u8]) -> io::Result<Option<Vec<u8>>> {
+    let mut decoded = vec![];
+    loop {
+        let size_end = match find(body, b"\r\n") {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        let size_line = String::from_utf8_lossy(&body[..size_end]);
+        let size = size_line.split(';').next().unwrap_or("").trim();
+        let size =
+            usize::from_str_radix(size, 16).map_err(|_| invalid_response("Invalid chunk size"))?;
+        if size == 0 {
+            return Ok(Some(decoded));
+        }
+
+        let chunk_start = size_end + 2;
+        let chunk_end = chunk_start + size;
+        if body.len() < chunk_end + 2 {
+            return Ok(None);
+        }
+        decoded.extend_from_slice(&body[chunk_start..chunk_end]);
+        body = &body[chunk_end + 2..];
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_response_with_content_length() {
+        let response = b"HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\n\
+                         content-length: 3\r\n\r\nabcdef";
+        assert_eq!(
+            parse_http_response(response, false).unwrap(),
+            Some(b"abc".to_vec())
+        );
+        assert_eq!(
+            parse_http_response(&response[..response.len() - 4], false).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_chunked_response() {
+        let response = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                         3\r\nabc\r\n2;ext=1\r\nde\r\n0\r\n\r\n";
+        assert_eq!(
+            parse_http_response(response, false).unwrap(),
+            Some(b"abcde".to_vec())
+        );
+        assert_eq!(
+            parse_http_response(&response[..response.len() - 12], false).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_response_ended_by_close() {
+        let response = b"HTTP/1.1 200 OK\r\n\r\nabc";
+        assert_eq!(parse_http_response(response, false).unwrap(), None);
+        assert_eq!(
+            parse_http_response(response, true).unwrap(),
+            Some(b"abc".to_vec())
+        );
+    }
+
+    #[test]
+    fn rejects_unsuccessful_response() {
+        let response = b"HTTP/1.1 415 Unsupported Media Type\r\nContent-Length: 0\r\n\r\n";
+        assert!(parse_http_response(response, false).is_err());
+    }
+
+    #[test]
+    fn parses_pins() {
+        let pin = ["AB", "cd"].repeat(16).join(":");
+        assert_eq!(parse_pin(&pin).unwrap(), [0xab, 0xcd].repeat(16));
+        assert!(parse_pin("abcd").is_err());
+        assert!(parse_pin(&"xy".repeat(32)).is_err());
+    }
+}
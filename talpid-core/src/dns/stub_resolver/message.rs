@@ -0,0 +1,271 @@
+//! Minimal handling of DNS messages, as described in RFC 1035. Only the parts needed to find the
+//! queried name, to answer a query as blocked and to read the addresses in an answer are
+//! implemented. Everything else is forwarded as is.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+const HEADER_LEN: usize = 12;
+const MAX_NAME_LEN: usize = 255;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const OPCODE_MASK: u16 = 0x7800;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const FLAG_RECURSION_AVAILABLE: u16 = 0x0080;
+const RCODE_MASK: u16 = 0x000f;
+const RCODE_NAME_ERROR: u16 = 3;
+const CLASS_IN: u16 = 1;
+
+/// Type of IPv4 address records.
+pub const TYPE_A: u16 = 1;
+/// Type of IPv6 address records.
+pub const TYPE_AAAA: u16 = 28;
+
+/// The single question of a DNS query.
+#[derive(Debug, PartialEq)]
+pub struct Question {
+    /// The queried name in lowercase, without a trailing dot.
+    pub name: String,
+    /// Offset of the end of the question section in the query.
+    end: usize,
+}
+
+/// Parses the question of `query`. Returns `None` if the message is not a query with a single
+/// question.
+pub fn parse_question(query: &[u8]) -> Option<Question> {
+    if query.len() < HEADER_LEN {
+        return None;
+    }
+    let flags = u16::from_be_bytes([query[2], query[3]]);
+    let question_count = u16::from_be_bytes([query[4], query[5]]);
+    if flags & FLAG_RESPONSE != 0 || question_count != 1 {
+        return None;
+    }
+
+    let mut labels = vec![];
+    let mut offset = HEADER_LEN;
+    loop {
+        let len = usize::from(*query.get(offset)?);
+        offset += 1;
+        if len == 0 {
+            break;
+        }
+        // Names in questions are never compressed, so the length must be a plain label length
+        if len > 63 || offset + len - HEADER_LEN > MAX_NAME_LEN {
+            return None;
+        }
+        let label = query.get(offset..offset + len)?;
+        labels.push(String::from_utf8_lossy(label).to_lowercase());
+        offset += len;
+    }
+
+    // The name is followed by the query type and class
+    let end = offset + 4;
+    if query.len() < end {
+        return None;
+    }
+
+    Some(Question {
+        name: labels.join("."),
+        end,
+    })
+}
+
+/// Returns a response to `query` saying that the queried name does not exist.
+pub fn blocked_response(query: &[u8], question: &Question) -> Vec<u8> {
+    let query_flags = u16::from_be_bytes([query[2], query[3]]);
+    let flags = FLAG_RESPONSE
+        | (query_flags & (OPCODE_MASK | FLAG_RECURSION_DESIRED))
+        | FLAG_RECURSION_AVAILABLE
+        | RCODE_NAME_ERROR;
+
+    let mut response = Vec::with_capacity(question.end);
+    // ID
+    response.extend_from_slice(&query[0..2]);
+    response.extend_from_slice(&flags.to_be_bytes());
+    // One question, and no answer, authority or additional records
+    response.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    response.extend_from_slice(&query[HEADER_LEN..question.end]);
+    response
+}
+
+/// Returns a query with the given ID for the records of type `record_type` of `name`.
+pub fn build_query(id: u16, name: &str, record_type: u16) -> Vec<u8> {
+    let mut query = Vec::with_capacity(HEADER_LEN + name.len() + 6);
+    query.extend_from_slice(&id.to_be_bytes());
+    query.extend_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+    // One question, and no answer, authority or additional records
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&record_type.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    query
+}
+
+/// Returns the addresses in the A and AAAA records of the answer section of `response`. A
+/// response saying that the name does not exist has no addresses. Returns `None` if the response
+/// is malformed or reports any other error.
+pub fn parse_addresses(response: &[u8]) -> Option<Vec<IpAddr>> {
+    if response.len() < HEADER_LEN {
+        return None;
+    }
+    let flags = u16::from_be_bytes([response[2], response[3]]);
+    match flags & RCODE_MASK {
+        0 => (),
+        RCODE_NAME_ERROR => return Some(vec![]),
+        _ => return None,
+    }
+    let question_count = u16::from_be_bytes([response[4], response[5]]);
+    let answer_count = u16::from_be_bytes([response[6], response[7]]);
+
+    let mut offset = HEADER_LEN;
+    for _ in 0..question_count {
+        // The name is followed by the query type and class
+        offset = skip_name(response, offset)? + 4;
+    }
+
+    let mut addresses = vec![];
+    for _ in 0..answer_count {
+        offset = skip_name(response, offset)?;
+        let fields = response.get(offset..offset + 10)?;
+        let record_type = u16::from_be_bytes([fields[0], fields[1]]);
+        let class = u16::from_be_bytes([fields[2], fields[3]]);
+        let data_len = usize::from(u16::from_be_bytes([fields[8], fields[9]]));
+        offset += 10;
+        let data = response.get(offset..offset + data_len)?;
+        offset += data_len;
+
+        // Other records, like the CNAME records leading to the addresses, are skipped
+        match (record_type, class, data.len()) {
+            (TYPE_A, CLASS_IN, 4) => {
+                addresses.push(Ipv4Addr::new(data[0], data[1], data[2], data[3]).into())
+            }
+            (TYPE_AAAA, CLASS_IN, 16) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(data);
+                addresses.push(Ipv6Addr::from(octets).into());
+            }
+            _ => (),
+        }
+    }
+    Some(addresses)
+}
+
+/// Returns the offset following the possibly compressed name at `offset`.
+fn skip_name(message: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let len = usize::from(*message.get(offset)?);
+        match len {
+            0 => return Some(offset + 1),
+            // A pointer to a name elsewhere in the message ends the name
+            len if len & 0xc0 == 0xc0 => return message.get(offset + 1).map(|_| offset + 2),
+            len if len > 63 => return None,
+            len => offset += 1 + len,
+        }
+    }
+}
+
+/// Returns whether `response` is a response to `query`, regardless of whether it is successful.
+pub fn is_response_to(query: &[u8], response: &[u8]) -> bool {
+    query.len() >= HEADER_LEN
+        && response.len() >= HEADER_LEN
+        && response[0..2] == query[0..2]
+        && u16::from_be_bytes([response[2], response[3]]) & FLAG_RESPONSE != 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A query for the A record of "Ads.Example.com", with an EDNS OPT record.
+    const QUERY: &[u8] = &[
+        0x12, 0x34, 0x01, 0x20, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x03, b'A', b'd',
+        b's', 0x07, b'E', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00, 0x00,
+        0x01, 0x00, 0x01, 0x00, 0x00, 0x29, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn parses_question() {
+        let question = parse_question(QUERY).unwrap();
+        assert_eq!(question.name, "ads.example.com");
+        assert_eq!(question.end, 33);
+    }
+
+    #[test]
+    fn rejects_invalid_queries() {
+        assert_eq!(parse_question(&QUERY[..20]), None);
+        assert_eq!(parse_question(&QUERY[..31]), None);
+
+        let mut response = QUERY.to_vec();
+        response[2] |= 0x80;
+        assert_eq!(parse_question(&response), None);
+
+        let mut compressed = QUERY.to_vec();
+        compressed[12] = 0xc0;
+        assert_eq!(parse_question(&compressed), None);
+    }
+
+    #[test]
+    fn builds_blocked_response() {
+        let question = parse_question(QUERY).unwrap();
+        let response = blocked_response(QUERY, &question);
+
+        assert_eq!(&response[0..2], &[0x12, 0x34]);
+        assert_eq!(&response[2..4], &[0x81, 0x83]);
+        assert_eq!(&response[4..12], &[0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&response[12..], &QUERY[12..33]);
+    }
+
+    #[test]
+    fn builds_query() {
+        let query = build_query(0x1234, "Ads.Example.com.", TYPE_A);
+        assert_eq!(&query[..4], &[0x12, 0x34, 0x01, 0x00]);
+        let question = parse_question(&query).unwrap();
+        assert_eq!(question.name, "ads.example.com");
+        assert_eq!(question.end, query.len());
+
+        let response = blocked_response(&query, &question);
+        assert!(is_response_to(&query, &response));
+        assert!(!is_response_to(&query, &query));
+        assert!(!is_response_to(
+            &build_query(0x4321, "example.com", TYPE_A),
+            &response
+        ));
+    }
+
+    #[test]
+    fn parses_addresses() {
+        let query = build_query(0x1234, "relay.example.com", TYPE_AAAA);
+        let mut response = query.clone();
+        response[2] = 0x81;
+        response[3] = 0x80;
+        // Two answers
+        response[7] = 2;
+        // A CNAME record pointing to another name, with a compressed owner name
+        response.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 5]);
+        response.extend_from_slice(&[2, b'r', b'2', 0xc0, 18]);
+        // An AAAA record for the other name
+        response.extend_from_slice(&[0xc0, 47, 0, 28, 0, 1, 0, 0, 0, 60, 0, 16]);
+        response.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+        assert_eq!(
+            parse_addresses(&response),
+            Some(vec!["2001:db8::1".parse().unwrap()])
+        );
+        assert_eq!(parse_addresses(&response[..response.len() - 1]), None);
+
+        let question = parse_question(&query).unwrap();
+        assert_eq!(
+            parse_addresses(&blocked_response(&query, &question)),
+            Some(vec![])
+        );
+
+        let mut failure = response.clone();
+        // Server failure
+        failure[3] = 0x82;
+        assert_eq!(parse_addresses(&failure), None);
+    }
+}
@@ -0,0 +1,486 @@
+//! A local DNS resolver that answers queries for blocked domains itself and forwards all other
+//! queries to the DNS servers used in the tunnel, optionally over encrypted connections. It is
+//! set as the system resolver while connected if content blocking or encrypted DNS is enabled,
+//! or if split DNS rules can't be applied through the system resolver. Queries are accepted over
+//! both UDP and TCP, and queries received over TCP are forwarded over TCP, so that clients can
+//! retry truncated answers. Queries to the tunnel DNS servers are sent from the tunnel address.
+
+use futures::{channel::mpsc, StreamExt};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use talpid_types::{
+    dns::{BlockingCategories, EncryptedDnsServer, SplitDnsRule},
+    ErrorExt,
+};
+use tokio02::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+};
+
+mod blocklist;
+mod encrypted;
+pub(super) mod message;
+
+pub use self::blocklist::{Blocklists, Error as BlocklistError};
+
+/// The address the resolver listens on. A loopback address other than 127.0.0.1 and
+/// 127.0.0.53 is used to not collide with other local resolvers.
+pub const STUB_RESOLVER_ADDRESS: Ipv4Addr = Ipv4Addr::new(127, 0, 0, 77);
+
+const DNS_PORT: u16 = 53;
+const MAX_MESSAGE_SIZE: usize = 65535;
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(2);
+const ENCRYPTED_UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a TCP client may wait between queries before the connection is closed.
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+const RESOLVER_THREAD_NAME: &str = "mullvad-stub-resolver";
+
+/// Errors that can happen when starting the resolver.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Failed to initialize the tokio runtime.
+    #[error(display = "Failed to initialize the tokio runtime")]
+    RuntimeError(#[error(source)] io::Error),
+
+    /// Failed to bind the socket of the resolver.
+    #[error(display = "Failed to bind the resolver socket to {}", _0)]
+    BindSocket(SocketAddr, #[error(source)] io::Error),
+
+    /// The hostname of an encrypted DNS server is not a valid DNS name.
+    #[error(display = "Invalid DNS server hostname: {}", _0)]
+    InvalidHostname(String),
+
+    /// A pinned certificate fingerprint is not a hex encoded SHA-256 hash.
+    #[error(display = "Invalid certificate fingerprint: {}", _0)]
+    InvalidCertificatePin(String),
+
+    /// Failed to load the root certificates of the system.
+    #[error(display = "Failed to load root certificates")]
+    LoadRootCertificates(#[error(source)] io::Error),
+}
+
+/// A DNS server that the resolver forwards queries to.
+#[derive(Debug, Clone)]
+pub enum Upstream {
+    /// A server that is sent plain DNS over UDP.
+    Plain(IpAddr),
+    /// A server that is reached over an encrypted connection.
+    Encrypted(EncryptedDnsServer),
+}
+
+enum UpstreamClient {
+    /// A plain DNS server, and the local address that queries are sent from.
+    Plain(SocketAddr, IpAddr),
+    Encrypted(encrypted::Client),
+}
+
+impl UpstreamClient {
+    /// Creates a client that sends queries from the address in `local_ips` of the same family as
+    /// the server, or from any address if there is none.
+    fn new(upstream: Upstream, local_ips: &[IpAddr]) -> Result<Self, Error> {
+        match upstream {
+            Upstream::Plain(address) => {
+                let server = SocketAddr::new(address, DNS_PORT);
+                Ok(UpstreamClient::Plain(
+                    server,
+                    bind_address(server, local_ips),
+                ))
+            }
+            Upstream::Encrypted(server) => {
+                let local_address = bind_address(server.socket_addr(), local_ips);
+                encrypted::Client::new(server, local_address).map(UpstreamClient::Encrypted)
+            }
+        }
+    }
+
+    /// Sends `query` to the server. Plain DNS servers are sent the query over TCP if `tcp` is
+    /// set, and otherwise over UDP.
+    async fn query(&self, query: &[u8], tcp: bool) -> io::Result<Vec<u8>> {
+        match self {
+            UpstreamClient::Plain(server, bind_address) if tcp => tokio02::time::timeout(
+                UPSTREAM_TIMEOUT,
+                forward_over_tcp(query, *server, *bind_address),
+            )
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "No response"))?,
+            UpstreamClient::Plain(server, bind_address) => {
+                forward_to(query, *server, *bind_address).await
+            }
+            UpstreamClient::Encrypted(client) => {
+                tokio02::time::timeout(ENCRYPTED_UPSTREAM_TIMEOUT, client.query(query))
+                    .await
+                    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "No response"))?
+            }
+        }
+    }
+}
+
+impl fmt::Display for UpstreamClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpstreamClient::Plain(server, _) => server.fmt(f),
+            UpstreamClient::Encrypted(client) => client.server().fmt(f),
+        }
+    }
+}
+
+/// The servers that queries are forwarded to.
+struct Upstreams {
+    default: Vec<UpstreamClient>,
+    split_dns: Vec<(SplitDnsRule, Vec<UpstreamClient>)>,
+}
+
+impl Upstreams {
+    /// Returns the servers of the split DNS rule with the longest domain that matches `name`, or
+    /// the default servers if no rule matches.
+    fn for_name(&self, name: Option<&str>) -> &[UpstreamClient] {
+        name.and_then(|name| {
+            self.split_dns
+                .iter()
+                .filter(|(rule, _)| rule.matches(name))
+                .max_by_key(|(rule, _)| rule.domain.len())
+        })
+        .map(|(_, servers)| servers.as_slice())
+        .unwrap_or(&self.default[..])
+    }
+}
+
+/// Answers queries, either from the blocklists or by forwarding them.
+struct Resolver {
+    blocklists: Blocklists,
+    categories: BlockingCategories,
+    upstreams: Upstreams,
+}
+
+impl Resolver {
+    /// Returns the response to `query`, or `None` if no upstream server answered. `tcp` tells
+    /// whether the query was received over TCP.
+    async fn respond(&self, query: &[u8], tcp: bool) -> Option<Vec<u8>> {
+        match message::parse_question(query) {
+            Some(question) if self.blocklists.check(self.categories, &question.name) => {
+                log::debug!("Blocked DNS query for {}", question.name);
+                Some(message::blocked_response(query, &question))
+            }
+            question => {
+                let name = question.map(|question| question.name);
+                forward(query, self.upstreams.for_name(name.as_deref()), tcp).await
+            }
+        }
+    }
+}
+
+/// A running local resolver. The resolver is stopped when this is dropped.
+pub struct StubResolver {
+    _runtime: tokio02::runtime::Runtime,
+}
+
+impl StubResolver {
+    /// Starts a resolver on `STUB_RESOLVER_ADDRESS` that blocks the domains in the given
+    /// categories and forwards other queries to the `upstream` servers, in order. Queries to the
+    /// `upstream` servers are sent from the addresses in `tunnel_ips`. Queries for names that
+    /// match a split DNS rule are instead forwarded to the servers of the rule, which may be on
+    /// the local network, so those are sent from any address.
+    pub fn start(
+        blocklists: Blocklists,
+        categories: BlockingCategories,
+        upstream: Vec<Upstream>,
+        split_dns: Vec<SplitDnsRule>,
+        tunnel_ips: &[IpAddr],
+    ) -> Result<Self, Error> {
+        let default = upstream
+            .into_iter()
+            .map(|upstream| UpstreamClient::new(upstream, tunnel_ips))
+            .collect::<Result<Vec<_>, _>>()?;
+        let split_dns = split_dns
+            .into_iter()
+            .map(|rule| {
+                let servers = rule
+                    .servers
+                    .iter()
+                    .map(|server| UpstreamClient::new(Upstream::Plain(*server), &[]))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok((rule, servers))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let resolver = Arc::new(Resolver {
+            blocklists,
+            categories,
+            upstreams: Upstreams { default, split_dns },
+        });
+
+        let mut runtime = tokio02::runtime::Builder::new()
+            .threaded_scheduler()
+            .core_threads(1)
+            .enable_all()
+            .thread_name(RESOLVER_THREAD_NAME)
+            .build()
+            .map_err(Error::RuntimeError)?;
+
+        let address = SocketAddr::new(STUB_RESOLVER_ADDRESS.into(), DNS_PORT);
+        let (socket, listener) = runtime
+            .block_on(async {
+                Ok::<_, io::Error>((
+                    UdpSocket::bind(address).await?,
+                    TcpListener::bind(address).await?,
+                ))
+            })
+            .map_err(|error| Error::BindSocket(address, error))?;
+        runtime.spawn(serve_udp(socket, resolver.clone()));
+        runtime.spawn(serve_tcp(listener, resolver));
+
+        log::debug!("Started local DNS resolver on {}", address);
+        Ok(StubResolver { _runtime: runtime })
+    }
+}
+
+async fn serve_udp(socket: UdpSocket, resolver: Arc<Resolver>) {
+    let (mut recv_half, mut send_half) = socket.split();
+
+    let (response_tx, mut response_rx) = mpsc::unbounded::<(Vec<u8>, SocketAddr)>();
+    tokio02::spawn(async move {
+        while let Some((response, client)) = response_rx.next().await {
+            if let Err(error) = send_half.send_to(&response, &client).await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to send DNS response")
+                );
+            }
+        }
+    });
+
+    let mut buffer = vec![0u8; MAX_MESSAGE_SIZE];
+    loop {
+        let (len, client) = match recv_half.recv_from(&mut buffer).await {
+            Ok(received) => received,
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to receive DNS query")
+                );
+                continue;
+            }
+        };
+        let query = buffer[..len].to_vec();
+        let resolver = resolver.clone();
+        let response_tx = response_tx.clone();
+        tokio02::spawn(async move {
+            if let Some(response) = resolver.respond(&query, false).await {
+                let _ = response_tx.unbounded_send((response, client));
+            }
+        });
+    }
+}
+
+async fn serve_tcp(mut listener: TcpListener, resolver: Arc<Resolver>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio02::spawn(serve_tcp_client(stream, resolver.clone()));
+            }
+            Err(error) => log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to accept DNS client")
+            ),
+        }
+    }
+}
+
+/// Answers the queries of a TCP client in order, until the client closes the connection or is
+/// idle for `TCP_IDLE_TIMEOUT`.
+async fn serve_tcp_client(mut stream: TcpStream, resolver: Arc<Resolver>) {
+    loop {
+        let query = match tokio02::time::timeout(TCP_IDLE_TIMEOUT, read_stream_message(&mut stream))
+            .await
+        {
+            Ok(Ok(query)) => query,
+            Ok(Err(ref error)) if error.kind() == io::ErrorKind::UnexpectedEof => return,
+            Ok(Err(error)) => {
+                log::debug!("Failed to receive DNS query over TCP: {}", error);
+                return;
+            }
+            Err(_) => return,
+        };
+        let response = match resolver.respond(&query, true).await {
+            Some(response) => response,
+            // The client would otherwise wait for an answer until it times out
+            None => return,
+        };
+        if let Err(error) = write_stream_message(&mut stream, &response).await {
+            log::debug!("Failed to send DNS response over TCP: {}", error);
+            return;
+        }
+    }
+}
+
+/// Sends `query` to each upstream server in turn, until one of them answers.
+async fn forward(query: &[u8], upstream: &[UpstreamClient], tcp: bool) -> Option<Vec<u8>> {
+    for server in upstream {
+        match server.query(query, tcp).await {
+            Ok(response) => return Some(response),
+            Err(error) => log::debug!("Failed to forward DNS query to {}: {}", server, error),
+        }
+    }
+    None
+}
+
+async fn forward_to(query: &[u8], server: SocketAddr, bind_address: IpAddr) -> io::Result<Vec<u8>> {
+    let mut socket = UdpSocket::bind(SocketAddr::new(bind_address, 0)).await?;
+    socket.connect(server).await?;
+    socket.send(query).await?;
+
+    let mut response = vec![0u8; MAX_MESSAGE_SIZE];
+    let len = tokio02::time::timeout(UPSTREAM_TIMEOUT, socket.recv(&mut response))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "No response"))??;
+    response.truncate(len);
+    Ok(response)
+}
+
+async fn forward_over_tcp(
+    query: &[u8],
+    server: SocketAddr,
+    bind_address: IpAddr,
+) -> io::Result<Vec<u8>> {
+    let mut stream = connect_tcp(server, bind_address, UPSTREAM_TIMEOUT).await?;
+    write_stream_message(&mut stream, query).await?;
+    read_stream_message(&mut stream).await
+}
+
+/// Returns the address in `local_ips` of the same family as `server`, or the unspecified address
+/// of that family.
+fn bind_address(server: SocketAddr, local_ips: &[IpAddr]) -> IpAddr {
+    let unspecified = match server {
+        SocketAddr::V4(_) => IpAddr::from(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::from(Ipv6Addr::UNSPECIFIED),
+    };
+    local_ips
+        .iter()
+        .cloned()
+        .find(|ip| ip.is_ipv4() == server.is_ipv4())
+        .unwrap_or(unspecified)
+}
+
+/// Connects to `server` from `bind_address`. The connection is made on a blocking thread, since
+/// the sockets of the runtime can't be bound before they connect.
+async fn connect_tcp(
+    server: SocketAddr,
+    bind_address: IpAddr,
+    timeout: Duration,
+) -> io::Result<TcpStream> {
+    let stream = tokio02::task::spawn_blocking(move || -> io::Result<std::net::TcpStream> {
+        let domain = match server {
+            SocketAddr::V4(_) => Domain::ipv4(),
+            SocketAddr::V6(_) => Domain::ipv6(),
+        };
+        let socket = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))?;
+        socket.bind(&SocketAddr::new(bind_address, 0).into())?;
+        socket.connect_timeout(&server.into(), timeout)?;
+        socket.set_nonblocking(true)?;
+        Ok(socket.into_tcp_stream())
+    })
+    .await
+    .map_err(|error| io::Error::new(io::ErrorKind::Other, error))??;
+    TcpStream::from_std(stream)
+}
+
+/// Writes a DNS message prefixed by its length, as messages are sent over a stream.
+async fn write_stream_message(
+    stream: &mut (impl AsyncWrite + Unpin),
+    message: &[u8],
+) -> io::Result<()> {
+    let mut buffer = Vec::with_capacity(2 + message.len());
+    buffer.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(message);
+    stream.write_all(&buffer).await?;
+    stream.flush().await
+}
+
+/// Reads a DNS message prefixed by its length.
+async fn read_stream_message(stream: &mut (impl AsyncRead + Unpin)) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len).await?;
+    let mut message = vec![0u8; usize::from(u16::from_be_bytes(len))];
+    stream.read_exact(&mut message).await?;
+    Ok(message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn plain(address: [u8; 4]) -> UpstreamClient {
+        UpstreamClient::new(Upstream::Plain(Ipv4Addr::from(address).into()), &[]).unwrap()
+    }
+
+    fn rule(domain: &str) -> SplitDnsRule {
+        SplitDnsRule {
+            domain: domain.to_owned(),
+            servers: vec![],
+        }
+    }
+
+    #[test]
+    fn routes_names_by_longest_split_dns_domain() {
+        let upstreams = Upstreams {
+            default: vec![plain([10, 64, 0, 1])],
+            split_dns: vec![
+                (rule("corp.example"), vec![plain([192, 168, 1, 1])]),
+                (rule("lab.corp.example"), vec![plain([192, 168, 2, 1])]),
+            ],
+        };
+        let first_server = |name| upstreams.for_name(name)[0].to_string();
+
+        assert_eq!(first_server(Some("example.com")), "10.64.0.1:53");
+        assert_eq!(first_server(None), "10.64.0.1:53");
+        assert_eq!(first_server(Some("www.corp.example")), "192.168.1.1:53");
+        assert_eq!(
+            first_server(Some("host.lab.corp.example")),
+            "192.168.2.1:53"
+        );
+    }
+
+    #[test]
+    fn binds_upstream_sockets_to_matching_family() {
+        let tunnel_ips: [IpAddr; 2] = [
+            "10.64.0.2".parse().unwrap(),
+            "fc00:bbbb::2".parse().unwrap(),
+        ];
+        let v4_server = "10.64.0.1:53".parse().unwrap();
+        let v6_server = "[fc00:bbbb::1]:53".parse().unwrap();
+
+        assert_eq!(bind_address(v4_server, &tunnel_ips), tunnel_ips[0]);
+        assert_eq!(bind_address(v6_server, &tunnel_ips), tunnel_ips[1]);
+        assert_eq!(
+            bind_address(v6_server, &tunnel_ips[..1]),
+            IpAddr::from(Ipv6Addr::UNSPECIFIED)
+        );
+    }
+
+    #[test]
+    fn frames_stream_messages() {
+        let mut runtime = tokio02::runtime::Builder::new()
+            .basic_scheduler()
+            .build()
+            .unwrap();
+        let message = b"\x12\x34query".to_vec();
+
+        let framed = runtime.block_on(async {
+            let mut framed = vec![];
+            write_stream_message(&mut framed, &message).await.unwrap();
+            framed
+        });
+        assert_eq!(&framed[..2], &[0, 7]);
+
+        let read = runtime.block_on(async {
+            let mut stream = &framed[..];
+            read_stream_message(&mut stream).await.unwrap()
+        });
+        assert_eq!(read, message);
+    }
+}
@@ -9,15 +9,24 @@ use nftnl::{
     nft_expr, table, Batch, Chain, FinalizedBatch, ProtoFamily, Rule, Table,
 };
 use std::{
+    collections::HashMap,
     env,
     ffi::{CStr, CString},
-    io,
+    fmt, io,
     net::{IpAddr, Ipv4Addr},
 };
-use talpid_types::net::{Endpoint, TransportProtocol};
+use talpid_types::net::{
+    firewall::{self, AllowRule, LanNetworks, PortRange},
+    Endpoint, TransportProtocol,
+};
 
 /// Priority for rules that tag split tunneling packets. Equals NF_IP_PRI_MANGLE.
 const MANGLE_CHAIN_PRIORITY: i32 = libc::NF_IP_PRI_MANGLE;
+/// Priority for rules that masquerade split tunneling packets. Equals NF_IP_PRI_NAT_SRC.
+const NAT_CHAIN_PRIORITY: i32 = libc::NF_IP_PRI_NAT_SRC;
+/// Conntrack mark of connections to the relay. ICMP errors that are related to these connections
+/// carry the same mark, which lets path MTU discovery through without accepting other ICMP errors.
+const RELAY_CT_MARK: i32 = 0xf40;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -97,6 +106,16 @@ struct FirewallTables {
     mangle_v6: Table,
 }
 
+impl FirewallTables {
+    fn get(&self, table: TableId) -> &Table {
+        match table {
+            TableId::Main => &self.main,
+            TableId::MangleV4 => &self.mangle_v4,
+            TableId::MangleV6 => &self.mangle_v6,
+        }
+    }
+}
+
 impl FirewallT for Firewall {
     type Error = Error;
 
@@ -110,7 +129,7 @@ impl FirewallT for Firewall {
             mangle_v4: Table::new(&*MANGLE_TABLE_NAME_V4, ProtoFamily::Ipv4),
             mangle_v6: Table::new(&*MANGLE_TABLE_NAME_V6, ProtoFamily::Ipv6),
         };
-        let batch = PolicyBatch::new(&tables).finalize(&policy)?;
+        let batch = PolicyBatch::new(&policy).finalize(&tables)?;
         self.send_and_process(&batch)?;
         self.verify_tables(&[&TABLE_NAME, &MANGLE_TABLE_NAME_V4, &MANGLE_TABLE_NAME_V6])
     }
@@ -205,246 +224,386 @@ impl Firewall {
     }
 }
 
-struct PolicyBatch<'a> {
-    batch: Batch,
-    in_chain: Chain<'a>,
-    out_chain: Chain<'a>,
-    mangle_chain_v4: Chain<'a>,
-    mangle_chain_v6: Chain<'a>,
-    nat_chain_v4: Chain<'a>,
-    nat_chain_v6: Chain<'a>,
+/// Returns the nftables script that corresponds to the rules added when `policy` is applied,
+/// without applying it.
+pub fn render_policy(policy: &FirewallPolicy) -> String {
+    PolicyBatch::new(policy).to_script()
 }
 
-impl<'a> PolicyBatch<'a> {
-    /// Bootstrap a new nftnl message batch object and add the initial messages creating the
-    /// table and chains.
-    pub fn new(tables: &'a FirewallTables) -> Self {
-        let mut batch = Batch::new();
-        let mut out_chain = Chain::new(&*OUT_CHAIN_NAME, &tables.main);
-        let mut in_chain = Chain::new(&*IN_CHAIN_NAME, &tables.main);
-        out_chain.set_hook(nftnl::Hook::Out, 0);
-        in_chain.set_hook(nftnl::Hook::In, 0);
-        out_chain.set_policy(nftnl::Policy::Drop);
-        in_chain.set_policy(nftnl::Policy::Drop);
-
-        Self::flush_table(&mut batch, &tables.main);
-        batch.add(&out_chain, nftnl::MsgType::Add);
-        batch.add(&in_chain, nftnl::MsgType::Add);
-
-        Self::flush_table(&mut batch, &tables.mangle_v4);
-        Self::flush_table(&mut batch, &tables.mangle_v6);
-
-        let mut add_mangle_chain = |table| {
-            let mut chain = Chain::new(&*MANGLE_CHAIN_NAME, table);
-            chain.set_hook(nftnl::Hook::Out, MANGLE_CHAIN_PRIORITY);
-            chain.set_type(nftnl::ChainType::Route);
-            chain.set_policy(nftnl::Policy::Accept);
-            batch.add(&chain, nftnl::MsgType::Add);
-
-            chain
-        };
-        let mangle_chain_v4 = add_mangle_chain(&tables.mangle_v4);
-        let mangle_chain_v6 = add_mangle_chain(&tables.mangle_v6);
+/// The tables that the firewall chains are added to.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+enum TableId {
+    Main,
+    MangleV4,
+    MangleV6,
+}
 
-        let mut add_nat_chain = |table| {
-            let mut chain = Chain::new(&*NAT_CHAIN_NAME, table);
-            chain.set_hook(nftnl::Hook::PostRouting, libc::NF_IP_PRI_NAT_SRC);
-            chain.set_type(nftnl::ChainType::Nat);
-            chain.set_policy(nftnl::Policy::Accept);
-            batch.add(&chain, nftnl::MsgType::Add);
+impl TableId {
+    /// Every table, in the order that they are created.
+    const ALL: [TableId; 3] = [TableId::Main, TableId::MangleV4, TableId::MangleV6];
 
-            chain
-        };
-        let nat_chain_v4 = add_nat_chain(&tables.mangle_v4);
-        let nat_chain_v6 = add_nat_chain(&tables.mangle_v6);
+    fn name(self) -> &'static CString {
+        match self {
+            TableId::Main => &*TABLE_NAME,
+            TableId::MangleV4 => &*MANGLE_TABLE_NAME_V4,
+            TableId::MangleV6 => &*MANGLE_TABLE_NAME_V6,
+        }
+    }
 
-        PolicyBatch {
-            batch,
-            in_chain,
-            out_chain,
-            mangle_chain_v4,
-            mangle_chain_v6,
-            nat_chain_v4,
-            nat_chain_v6,
+    fn family(self) -> ProtoFamily {
+        match self {
+            TableId::Main => ProtoFamily::Inet,
+            TableId::MangleV4 => ProtoFamily::Ipv4,
+            TableId::MangleV6 => ProtoFamily::Ipv6,
         }
     }
 
-    /// Creates the table if it does not exist and clears it otherwise.
-    fn flush_table(batch: &mut Batch, table: &'a Table) {
-        batch.add(table, nftnl::MsgType::Add);
-        batch.add(table, nftnl::MsgType::Del);
-        batch.add(table, nftnl::MsgType::Add);
+    /// Returns the chains of the table, in the order that they are created.
+    fn chains(self) -> [ChainId; 2] {
+        match self {
+            TableId::Main => [ChainId::Out, ChainId::In],
+            TableId::MangleV4 => [ChainId::MangleV4, ChainId::NatV4],
+            TableId::MangleV6 => [ChainId::MangleV6, ChainId::NatV6],
+        }
     }
+}
 
-    /// Finalize the nftnl message batch by adding every firewall rule needed to satisfy the given
-    /// policy.
-    pub fn finalize(mut self, policy: &FirewallPolicy) -> Result<FinalizedBatch> {
-        self.add_loopback_rules()?;
-        self.add_split_tunneling_rules();
-        self.add_dhcp_client_rules();
-        self.add_policy_specific_rules(policy)?;
+/// The chains that the firewall rules are added to.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+enum ChainId {
+    In,
+    Out,
+    MangleV4,
+    MangleV6,
+    NatV4,
+    NatV6,
+}
 
-        Ok(self.batch.finalize())
+impl ChainId {
+    fn name(self) -> &'static CString {
+        match self {
+            ChainId::In => &*IN_CHAIN_NAME,
+            ChainId::Out => &*OUT_CHAIN_NAME,
+            ChainId::MangleV4 | ChainId::MangleV6 => &*MANGLE_CHAIN_NAME,
+            ChainId::NatV4 | ChainId::NatV6 => &*NAT_CHAIN_NAME,
+        }
     }
 
-    fn add_split_tunneling_rules(&mut self) {
-        let mangle_chains = [&self.mangle_chain_v4, &self.mangle_chain_v6];
-        for chain in &mangle_chains {
-            let mut rule = Rule::new(chain);
-            rule.add_expr(&nft_expr!(meta cgroup));
-            rule.add_expr(&nft_expr!(cmp == split_tunnel::NET_CLS_CLASSID));
-            rule.add_expr(&nft_expr!(immediate data split_tunnel::MARK));
-            rule.add_expr(&nft_expr!(ct mark set));
-            rule.add_expr(&nft_expr!(meta mark set));
-            self.batch.add(&rule, nftnl::MsgType::Add);
+    /// Returns where the chain hooks into the packet processing, and its default policy.
+    fn hook(self) -> ChainHook {
+        match self {
+            ChainId::In => ChainHook {
+                chain_type: nftnl::ChainType::Filter,
+                hook: nftnl::Hook::In,
+                priority: 0,
+                policy: nftnl::Policy::Drop,
+            },
+            ChainId::Out => ChainHook {
+                chain_type: nftnl::ChainType::Filter,
+                hook: nftnl::Hook::Out,
+                priority: 0,
+                policy: nftnl::Policy::Drop,
+            },
+            ChainId::MangleV4 | ChainId::MangleV6 => ChainHook {
+                chain_type: nftnl::ChainType::Route,
+                hook: nftnl::Hook::Out,
+                priority: MANGLE_CHAIN_PRIORITY,
+                policy: nftnl::Policy::Accept,
+            },
+            ChainId::NatV4 | ChainId::NatV6 => ChainHook {
+                chain_type: nftnl::ChainType::Nat,
+                hook: nftnl::Hook::PostRouting,
+                priority: NAT_CHAIN_PRIORITY,
+                policy: nftnl::Policy::Accept,
+            },
         }
+    }
+}
 
-        let mut rule = Rule::new(&self.in_chain);
-        rule.add_expr(&nft_expr!(ct mark));
-        rule.add_expr(&nft_expr!(cmp == split_tunnel::MARK));
-        add_verdict(&mut rule, &Verdict::Accept);
-        self.batch.add(&rule, nftnl::MsgType::Add);
+/// The type, hook, priority and default policy of a base chain.
+struct ChainHook {
+    chain_type: nftnl::ChainType,
+    hook: nftnl::Hook,
+    priority: i32,
+    policy: nftnl::Policy,
+}
 
-        let mut rule = Rule::new(&self.out_chain);
-        rule.add_expr(&nft_expr!(meta mark));
-        rule.add_expr(&nft_expr!(cmp == split_tunnel::MARK));
-        add_verdict(&mut rule, &Verdict::Accept);
-        self.batch.add(&rule, nftnl::MsgType::Add);
+/// A match or statement in a rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RuleExpr {
+    /// Matches the interface by index, which is looked up when the rule is added.
+    Iface(Direction, String),
+    /// Matches the interface by name, so that rules can refer to interfaces that don't exist yet.
+    IfaceName(Direction, String),
+    Net(End, IpNetwork),
+    Ip(End, IpAddr),
+    L4Proto(TransportProtocol),
+    Port(TransportProtocol, End, u16),
+    PortRange(TransportProtocol, End, PortRange),
+    /// Matches ICMP packets of the same IP version as the address.
+    Icmp(IpAddr),
+    Icmpv6 {
+        icmp_type: u8,
+        code: u8,
+    },
+    /// Only matches packets of connections that have seen traffic in both directions.
+    Established,
+    /// Only matches packets related to, but not part of, existing connections.
+    Related,
+    Skuid(u32),
+    Cgroup(u32),
+    CtMark(i32),
+    MetaMark(i32),
+    /// Sets both the conntrack mark and the packet mark.
+    SetMark(i32),
+    /// Sets only the conntrack mark.
+    SetCtMark(i32),
+    Masquerade,
+}
 
-        let nat_chains = [&self.nat_chain_v4, &self.nat_chain_v6];
-        for chain in &nat_chains {
-            let mut rule = Rule::new(chain);
-            rule.add_expr(&nft_expr!(ct mark));
-            rule.add_expr(&nft_expr!(cmp == split_tunnel::MARK));
-            rule.add_expr(&nft_expr!(masquerade));
-            add_verdict(&mut rule, &Verdict::Accept);
-            self.batch.add(&rule, nftnl::MsgType::Add);
-        }
-    }
+/// The verdict of a rule.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Action {
+    Accept,
+    Drop,
+    RejectPortUnreach,
+    RejectTcpReset,
+}
 
-    fn add_loopback_rules(&mut self) -> Result<()> {
-        const LOOPBACK_IFACE_NAME: &str = "lo";
-        self.batch.add(
-            &allow_interface_rule(&self.out_chain, Direction::Out, LOOPBACK_IFACE_NAME)?,
-            nftnl::MsgType::Add,
-        );
-        self.batch.add(
-            &allow_interface_rule(&self.in_chain, Direction::In, LOOPBACK_IFACE_NAME)?,
-            nftnl::MsgType::Add,
-        );
-        Ok(())
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PolicyRule {
+    chain: ChainId,
+    exprs: Vec<RuleExpr>,
+    action: Option<Action>,
+}
+
+/// The rules needed to satisfy a firewall policy. These are turned into nftnl messages when the
+/// policy is applied, or into an nftables script when it is rendered.
+struct PolicyBatch {
+    rules: Vec<PolicyRule>,
+}
+
+impl PolicyBatch {
+    /// Creates every firewall rule needed to satisfy the given policy.
+    pub fn new(policy: &FirewallPolicy) -> Self {
+        let mut batch = PolicyBatch { rules: vec![] };
+        batch.add_loopback_rules();
+        batch.add_split_tunneling_rules();
+        batch.add_dhcp_client_rules();
+        batch.add_policy_specific_rules(policy);
+        batch
     }
 
-    fn add_dhcp_client_rules(&mut self) {
-        use self::TransportProtocol::Udp;
-        // Outgoing DHCPv4 request
-        {
-            let mut out_v4 = Rule::new(&self.out_chain);
-            check_port(&mut out_v4, Udp, End::Src, super::DHCPV4_CLIENT_PORT);
-            check_ip(&mut out_v4, End::Dst, IpAddr::V4(Ipv4Addr::BROADCAST));
-            check_port(&mut out_v4, Udp, End::Dst, super::DHCPV4_SERVER_PORT);
-            add_verdict(&mut out_v4, &Verdict::Accept);
-            self.batch.add(&out_v4, nftnl::MsgType::Add);
-        }
-        // Incoming DHCPv4 response
-        {
-            let mut in_v4 = Rule::new(&self.in_chain);
-            check_port(&mut in_v4, Udp, End::Src, super::DHCPV4_SERVER_PORT);
-            check_port(&mut in_v4, Udp, End::Dst, super::DHCPV4_CLIENT_PORT);
-            add_verdict(&mut in_v4, &Verdict::Accept);
-            self.batch.add(&in_v4, nftnl::MsgType::Add);
+    /// Creates the nftnl message batch, which creates the tables and chains and adds the rules.
+    pub fn finalize(&self, tables: &FirewallTables) -> Result<FinalizedBatch> {
+        let mut batch = Batch::new();
+        let mut chains = HashMap::new();
+        for table_id in &TableId::ALL {
+            let table = tables.get(*table_id);
+            flush_table(&mut batch, table);
+            for chain_id in &table_id.chains() {
+                let hook = chain_id.hook();
+                let mut chain = Chain::new(chain_id.name(), table);
+                chain.set_hook(hook.hook, hook.priority);
+                chain.set_type(hook.chain_type);
+                chain.set_policy(hook.policy);
+                batch.add(&chain, nftnl::MsgType::Add);
+                chains.insert(*chain_id, chain);
+            }
         }
 
-        for dhcpv6_server in &*super::DHCPV6_SERVER_ADDRS {
-            let mut out_v6 = Rule::new(&self.out_chain);
-            check_net(&mut out_v6, End::Src, *super::IPV6_LINK_LOCAL);
-            check_port(&mut out_v6, Udp, End::Src, super::DHCPV6_CLIENT_PORT);
-            check_ip(&mut out_v6, End::Dst, *dhcpv6_server);
-            check_port(&mut out_v6, Udp, End::Dst, super::DHCPV6_SERVER_PORT);
-            add_verdict(&mut out_v6, &Verdict::Accept);
-            self.batch.add(&out_v6, nftnl::MsgType::Add);
-        }
-        {
-            let mut in_v6 = Rule::new(&self.in_chain);
-            check_net(&mut in_v6, End::Src, *super::IPV6_LINK_LOCAL);
-            check_port(&mut in_v6, Udp, End::Src, super::DHCPV6_SERVER_PORT);
-            check_net(&mut in_v6, End::Dst, *super::IPV6_LINK_LOCAL);
-            check_port(&mut in_v6, Udp, End::Dst, super::DHCPV6_CLIENT_PORT);
-            add_verdict(&mut in_v6, &Verdict::Accept);
-            self.batch.add(&in_v6, nftnl::MsgType::Add);
+        for policy_rule in &self.rules {
+            let mut rule = Rule::new(&chains[&policy_rule.chain]);
+            for expr in &policy_rule.exprs {
+                add_expr(&mut rule, expr)?;
+            }
+            if let Some(action) = policy_rule.action {
+                add_verdict(&mut rule, &verdict(action));
+            }
+            batch.add(&rule, nftnl::MsgType::Add);
         }
-        // Outgoing Router solicitation (part of NDP)
-        {
-            let mut rule = Rule::new(&self.out_chain);
-            check_ip(
-                &mut rule,
-                End::Dst,
-                *super::ROUTER_SOLICITATION_OUT_DST_ADDR,
-            );
 
-            rule.add_expr(&nft_expr!(meta l4proto));
-            rule.add_expr(&nft_expr!(cmp == libc::IPPROTO_ICMPV6 as u8));
-
-            rule.add_expr(&Payload::Transport(
-                nftnl::expr::TransportHeaderField::Icmpv6(nftnl::expr::Icmpv6HeaderField::Type),
-            ));
-            rule.add_expr(&nft_expr!(cmp == 133u8));
-            rule.add_expr(&nftnl::expr::Payload::Transport(
-                nftnl::expr::TransportHeaderField::Icmpv6(nftnl::expr::Icmpv6HeaderField::Code),
-            ));
-            rule.add_expr(&nft_expr!(cmp == 0u8));
+        Ok(batch.finalize())
+    }
 
-            add_verdict(&mut rule, &Verdict::Accept);
-            self.batch.add(&rule, nftnl::MsgType::Add);
+    /// Returns an nftables script that creates the same tables, chains and rules as `finalize`.
+    pub fn to_script(&self) -> String {
+        let mut script = String::new();
+        for table_id in &TableId::ALL {
+            let family = match table_id.family() {
+                ProtoFamily::Inet => "inet",
+                ProtoFamily::Ipv4 => "ip",
+                ProtoFamily::Ipv6 => "ip6",
+                _ => unreachable!("unhandled table family"),
+            };
+            let table_name = table_id.name().to_string_lossy();
+            // Recreates the table, like `flush_table`
+            script.push_str(&format!("table {} {}\n", family, table_name));
+            script.push_str(&format!("delete table {} {}\n", family, table_name));
+            script.push_str(&format!("table {} {} {{\n", family, table_name));
+            for chain_id in &table_id.chains() {
+                script.push_str(&format!(
+                    "\tchain {} {{\n",
+                    chain_id.name().to_string_lossy()
+                ));
+                script.push_str(&format!("\t\t{}\n", chain_id.hook()));
+                for rule in self.rules.iter().filter(|rule| rule.chain == *chain_id) {
+                    script.push_str(&format!("\t\t{}\n", rule));
+                }
+                script.push_str("\t}\n");
+            }
+            script.push_str("}\n");
         }
-        // Incoming Router advertisement (part of NDP)
-        {
-            let mut rule = Rule::new(&self.in_chain);
-            check_net(&mut rule, End::Src, *super::IPV6_LINK_LOCAL);
+        script
+    }
 
-            rule.add_expr(&nft_expr!(meta l4proto));
-            rule.add_expr(&nft_expr!(cmp == libc::IPPROTO_ICMPV6 as u8));
+    fn add_rule(&mut self, chain: ChainId, exprs: Vec<RuleExpr>, action: Action) {
+        self.rules.push(PolicyRule {
+            chain,
+            exprs,
+            action: Some(action),
+        });
+    }
 
-            rule.add_expr(&Payload::Transport(
-                nftnl::expr::TransportHeaderField::Icmpv6(nftnl::expr::Icmpv6HeaderField::Type),
-            ));
-            rule.add_expr(&nft_expr!(cmp == 134u8));
-            rule.add_expr(&nftnl::expr::Payload::Transport(
-                nftnl::expr::TransportHeaderField::Icmpv6(nftnl::expr::Icmpv6HeaderField::Code),
-            ));
-            rule.add_expr(&nft_expr!(cmp == 0u8));
+    fn add_split_tunneling_rules(&mut self) {
+        for chain in &[ChainId::MangleV4, ChainId::MangleV6] {
+            self.rules.push(PolicyRule {
+                chain: *chain,
+                exprs: vec![
+                    RuleExpr::Cgroup(split_tunnel::NET_CLS_CLASSID),
+                    RuleExpr::SetMark(split_tunnel::MARK),
+                ],
+                action: None,
+            });
+            // Sockets that set the mark themselves, like the lookups of custom relay hosts, need
+            // their connections marked as well for the responses to be let in
+            self.rules.push(PolicyRule {
+                chain: *chain,
+                exprs: vec![
+                    RuleExpr::MetaMark(split_tunnel::MARK),
+                    RuleExpr::SetCtMark(split_tunnel::MARK),
+                ],
+                action: None,
+            });
+        }
+
+        self.add_rule(
+            ChainId::In,
+            vec![RuleExpr::CtMark(split_tunnel::MARK)],
+            Action::Accept,
+        );
+        self.add_rule(
+            ChainId::Out,
+            vec![RuleExpr::MetaMark(split_tunnel::MARK)],
+            Action::Accept,
+        );
 
-            add_verdict(&mut rule, &Verdict::Accept);
-            self.batch.add(&rule, nftnl::MsgType::Add);
+        for chain in &[ChainId::NatV4, ChainId::NatV6] {
+            self.add_rule(
+                *chain,
+                vec![RuleExpr::CtMark(split_tunnel::MARK), RuleExpr::Masquerade],
+                Action::Accept,
+            );
         }
-        // Incoming Redirect (part of NDP)
-        {
-            let mut rule = Rule::new(&self.in_chain);
-            check_net(&mut rule, End::Src, *super::IPV6_LINK_LOCAL);
+    }
 
-            rule.add_expr(&nft_expr!(meta l4proto));
-            rule.add_expr(&nft_expr!(cmp == libc::IPPROTO_ICMPV6 as u8));
+    fn add_loopback_rules(&mut self) {
+        const LOOPBACK_IFACE_NAME: &str = "lo";
+        self.add_allow_interface_rules(LOOPBACK_IFACE_NAME);
+    }
 
-            rule.add_expr(&Payload::Transport(
-                nftnl::expr::TransportHeaderField::Icmpv6(nftnl::expr::Icmpv6HeaderField::Type),
-            ));
-            rule.add_expr(&nft_expr!(cmp == 137u8));
-            rule.add_expr(&nftnl::expr::Payload::Transport(
-                nftnl::expr::TransportHeaderField::Icmpv6(nftnl::expr::Icmpv6HeaderField::Code),
-            ));
-            rule.add_expr(&nft_expr!(cmp == 0u8));
+    fn add_dhcp_client_rules(&mut self) {
+        use self::TransportProtocol::Udp;
+        // Outgoing DHCPv4 request
+        self.add_rule(
+            ChainId::Out,
+            vec![
+                RuleExpr::Port(Udp, End::Src, super::DHCPV4_CLIENT_PORT),
+                RuleExpr::Ip(End::Dst, IpAddr::V4(Ipv4Addr::BROADCAST)),
+                RuleExpr::Port(Udp, End::Dst, super::DHCPV4_SERVER_PORT),
+            ],
+            Action::Accept,
+        );
+        // Incoming DHCPv4 response
+        self.add_rule(
+            ChainId::In,
+            vec![
+                RuleExpr::Port(Udp, End::Src, super::DHCPV4_SERVER_PORT),
+                RuleExpr::Port(Udp, End::Dst, super::DHCPV4_CLIENT_PORT),
+            ],
+            Action::Accept,
+        );
 
-            add_verdict(&mut rule, &Verdict::Accept);
-            self.batch.add(&rule, nftnl::MsgType::Add);
+        for dhcpv6_server in &*super::DHCPV6_SERVER_ADDRS {
+            self.add_rule(
+                ChainId::Out,
+                vec![
+                    RuleExpr::Net(End::Src, IpNetwork::V6(*super::IPV6_LINK_LOCAL)),
+                    RuleExpr::Port(Udp, End::Src, super::DHCPV6_CLIENT_PORT),
+                    RuleExpr::Ip(End::Dst, IpAddr::V6(*dhcpv6_server)),
+                    RuleExpr::Port(Udp, End::Dst, super::DHCPV6_SERVER_PORT),
+                ],
+                Action::Accept,
+            );
         }
+        self.add_rule(
+            ChainId::In,
+            vec![
+                RuleExpr::Net(End::Src, IpNetwork::V6(*super::IPV6_LINK_LOCAL)),
+                RuleExpr::Port(Udp, End::Src, super::DHCPV6_SERVER_PORT),
+                RuleExpr::Net(End::Dst, IpNetwork::V6(*super::IPV6_LINK_LOCAL)),
+                RuleExpr::Port(Udp, End::Dst, super::DHCPV6_CLIENT_PORT),
+            ],
+            Action::Accept,
+        );
+        // Outgoing Router solicitation (part of NDP)
+        self.add_rule(
+            ChainId::Out,
+            vec![
+                RuleExpr::Ip(
+                    End::Dst,
+                    IpAddr::V6(*super::ROUTER_SOLICITATION_OUT_DST_ADDR),
+                ),
+                RuleExpr::Icmpv6 {
+                    icmp_type: 133,
+                    code: 0,
+                },
+            ],
+            Action::Accept,
+        );
+        // Incoming Router advertisement (part of NDP)
+        self.add_rule(
+            ChainId::In,
+            vec![
+                RuleExpr::Net(End::Src, IpNetwork::V6(*super::IPV6_LINK_LOCAL)),
+                RuleExpr::Icmpv6 {
+                    icmp_type: 134,
+                    code: 0,
+                },
+            ],
+            Action::Accept,
+        );
+        // Incoming Redirect (part of NDP)
+        self.add_rule(
+            ChainId::In,
+            vec![
+                RuleExpr::Net(End::Src, IpNetwork::V6(*super::IPV6_LINK_LOCAL)),
+                RuleExpr::Icmpv6 {
+                    icmp_type: 137,
+                    code: 0,
+                },
+            ],
+            Action::Accept,
+        );
     }
 
-    fn add_policy_specific_rules(&mut self, policy: &FirewallPolicy) -> Result<()> {
+    fn add_policy_specific_rules(&mut self, policy: &FirewallPolicy) {
         let allow_lan = match policy {
             FirewallPolicy::Connecting {
                 peer_endpoint,
                 pingable_hosts,
                 allow_lan,
+                ..
             } => {
                 self.add_allow_icmp_pingable_hosts(&pingable_hosts);
                 self.add_allow_endpoint_rules(peer_endpoint);
@@ -457,20 +616,46 @@ impl<'a> PolicyBatch<'a> {
                 peer_endpoint,
                 tunnel,
                 allow_lan,
+                dns_servers,
+                split_dns_servers,
+                ..
             } => {
                 self.add_allow_endpoint_rules(peer_endpoint);
-                self.add_allow_dns_rules(tunnel, TransportProtocol::Udp)?;
-                self.add_allow_dns_rules(tunnel, TransportProtocol::Tcp)?;
+                self.add_allow_dns_rules(
+                    tunnel,
+                    dns_servers,
+                    *allow_lan,
+                    policy.lan_networks(),
+                    TransportProtocol::Udp,
+                );
+                self.add_allow_dns_rules(
+                    tunnel,
+                    dns_servers,
+                    *allow_lan,
+                    policy.lan_networks(),
+                    TransportProtocol::Tcp,
+                );
+                // Split DNS servers on the LAN are reached like other local DNS servers, so only
+                // if LAN access is allowed
+                for protocol in &[TransportProtocol::Udp, TransportProtocol::Tcp] {
+                    self.add_allow_dns_rules(
+                        tunnel,
+                        split_dns_servers,
+                        *allow_lan,
+                        policy.lan_networks(),
+                        *protocol,
+                    );
+                }
                 // Important to block DNS *before* we allow the tunnel and allow LAN. So DNS
                 // can't leak to the wrong IPs in the tunnel or on the LAN.
                 self.add_drop_dns_rule();
-                self.add_allow_tunnel_rules(tunnel)?;
+                self.add_allow_interface_rules(&tunnel.interface);
                 if *allow_lan {
                     self.add_block_cve_2019_14899(tunnel);
                 }
                 *allow_lan
             }
-            FirewallPolicy::Blocked { allow_lan } => {
+            FirewallPolicy::Blocked { allow_lan, .. } => {
                 // Important to drop DNS before allowing LAN (to stop DNS leaking to the LAN)
                 self.add_drop_dns_rule();
                 *allow_lan
@@ -478,128 +663,113 @@ impl<'a> PolicyBatch<'a> {
         };
 
         if allow_lan {
-            self.add_allow_lan_rules();
+            self.add_allow_lan_rules(policy.lan_networks());
         }
 
-        // Reject any remaining outgoing traffic
-        let mut reject_rule = Rule::new(&self.out_chain);
-        add_verdict(
-            &mut reject_rule,
-            &Verdict::Reject(RejectionType::Icmp(IcmpCode::PortUnreach)),
-        );
-        self.batch.add(&reject_rule, nftnl::MsgType::Add);
+        // Added after the DNS rules, so that allow rules can't be used to leak DNS
+        self.add_allow_rules(policy.allow_rules());
 
-        Ok(())
+        // Reject any remaining outgoing traffic
+        self.add_rule(ChainId::Out, vec![], Action::RejectPortUnreach);
     }
 
     fn add_allow_endpoint_rules(&mut self, endpoint: &Endpoint) {
-        let mut in_rule = Rule::new(&self.in_chain);
-        check_endpoint(&mut in_rule, End::Src, endpoint);
-
-        in_rule.add_expr(&nft_expr!(ct state));
-        let allowed_states = nftnl::expr::ct::States::ESTABLISHED.bits();
-        in_rule.add_expr(&nft_expr!(bitwise mask allowed_states, xor 0u32));
-        in_rule.add_expr(&nft_expr!(cmp != 0u32));
-        add_verdict(&mut in_rule, &Verdict::Accept);
-
-        self.batch.add(&in_rule, nftnl::MsgType::Add);
-
-
-        let mut out_rule = Rule::new(&self.out_chain);
-        check_endpoint(&mut out_rule, End::Dst, endpoint);
-        out_rule.add_expr(&nft_expr!(meta skuid));
-        out_rule.add_expr(&nft_expr!(cmp == 0u32));
-        add_verdict(&mut out_rule, &Verdict::Accept);
-
-        self.batch.add(&out_rule, nftnl::MsgType::Add);
+        let mut in_exprs = check_endpoint(End::Src, endpoint);
+        in_exprs.push(RuleExpr::Established);
+        self.add_rule(ChainId::In, in_exprs, Action::Accept);
+
+        let mut out_exprs = check_endpoint(End::Dst, endpoint);
+        out_exprs.push(RuleExpr::Skuid(0));
+        out_exprs.push(RuleExpr::SetCtMark(RELAY_CT_MARK));
+        self.add_rule(ChainId::Out, out_exprs, Action::Accept);
+
+        // Routers on the way to the endpoint report that packets are too large with ICMP errors.
+        // These are needed for path MTU discovery to work.
+        self.add_rule(
+            ChainId::In,
+            vec![
+                RuleExpr::Icmp(endpoint.address.ip()),
+                RuleExpr::Related,
+                RuleExpr::CtMark(RELAY_CT_MARK),
+            ],
+            Action::Accept,
+        );
     }
 
     fn add_allow_icmp_pingable_hosts(&mut self, pingable_hosts: &[IpAddr]) {
         for host in pingable_hosts {
-            let icmp_proto = match &host {
-                IpAddr::V4(_) => libc::IPPROTO_ICMP as u8,
-                IpAddr::V6(_) => libc::IPPROTO_ICMPV6 as u8,
-            };
-
-            let mut out_rule = Rule::new(&self.out_chain);
-            check_ip(&mut out_rule, End::Dst, *host);
-            out_rule.add_expr(&nft_expr!(meta l4proto));
-            out_rule.add_expr(&nft_expr!(cmp == icmp_proto));
-            add_verdict(&mut out_rule, &Verdict::Accept);
-            self.batch.add(&out_rule, nftnl::MsgType::Add);
-
-            let mut in_rule = Rule::new(&self.in_chain);
-            check_ip(&mut in_rule, End::Src, *host);
-            in_rule.add_expr(&nft_expr!(meta l4proto));
-            in_rule.add_expr(&nft_expr!(cmp == icmp_proto));
-            add_verdict(&mut in_rule, &Verdict::Accept);
-            self.batch.add(&in_rule, nftnl::MsgType::Add);
+            self.add_rule(
+                ChainId::Out,
+                vec![RuleExpr::Ip(End::Dst, *host), RuleExpr::Icmp(*host)],
+                Action::Accept,
+            );
+            self.add_rule(
+                ChainId::In,
+                vec![RuleExpr::Ip(End::Src, *host), RuleExpr::Icmp(*host)],
+                Action::Accept,
+            );
         }
     }
 
     fn add_allow_dns_rules(
         &mut self,
         tunnel: &tunnel::TunnelMetadata,
+        dns_servers: &[IpAddr],
+        allow_lan: bool,
+        lan_networks: &LanNetworks,
         protocol: TransportProtocol,
-    ) -> Result<()> {
-        // allow DNS traffic to the tunnel gateway(s)
-        self.add_allow_dns_rule(&tunnel.interface, protocol, tunnel.ipv4_gateway.into())?;
-        if let Some(ipv6_gateway) = tunnel.ipv6_gateway {
-            self.add_allow_dns_rule(&tunnel.interface, protocol, ipv6_gateway.into())?;
-        };
-        Ok(())
+    ) {
+        // allow DNS traffic to the servers inside the tunnel, and to local servers on any
+        // interface if LAN access is allowed
+        for server in dns_servers {
+            if super::allows_dns_outside_tunnel(server, tunnel, allow_lan, lan_networks) {
+                self.add_allow_dns_rule(None, protocol, *server);
+            } else {
+                self.add_allow_dns_rule(Some(&tunnel.interface), protocol, *server);
+            }
+        }
     }
 
     fn add_allow_dns_rule(
         &mut self,
-        interface: &str,
+        interface: Option<&str>,
         protocol: TransportProtocol,
         host: IpAddr,
-    ) -> Result<()> {
-        let mut allow_rule = Rule::new(&self.out_chain);
-        let daddr = match host {
-            IpAddr::V4(_) => nft_expr!(payload ipv4 daddr),
-            IpAddr::V6(_) => nft_expr!(payload ipv6 daddr),
-        };
-
-        check_iface(&mut allow_rule, Direction::Out, interface)?;
-        check_port(&mut allow_rule, protocol, End::Dst, 53);
-        check_l3proto(&mut allow_rule, host);
-
-        allow_rule.add_expr(&daddr);
-        allow_rule.add_expr(&nft_expr!(cmp == host));
-        add_verdict(&mut allow_rule, &Verdict::Accept);
-
-        self.batch.add(&allow_rule, nftnl::MsgType::Add);
-        Ok(())
+    ) {
+        let mut exprs = vec![];
+        if let Some(interface) = interface {
+            exprs.push(RuleExpr::Iface(Direction::Out, interface.to_owned()));
+        }
+        exprs.push(RuleExpr::Port(protocol, End::Dst, 53));
+        exprs.push(RuleExpr::Ip(End::Dst, host));
+        self.add_rule(ChainId::Out, exprs, Action::Accept);
     }
 
     /// Blocks all outgoing DNS (port 53) on both TCP and UDP
     fn add_drop_dns_rule(&mut self) {
-        let mut block_udp_rule = Rule::new(&self.out_chain);
-        check_port(&mut block_udp_rule, TransportProtocol::Udp, End::Dst, 53);
-        add_verdict(
-            &mut block_udp_rule,
-            &Verdict::Reject(RejectionType::Icmp(IcmpCode::PortUnreach)),
+        self.add_rule(
+            ChainId::Out,
+            vec![RuleExpr::Port(TransportProtocol::Udp, End::Dst, 53)],
+            Action::RejectPortUnreach,
+        );
+        self.add_rule(
+            ChainId::Out,
+            vec![RuleExpr::Port(TransportProtocol::Tcp, End::Dst, 53)],
+            Action::RejectTcpReset,
         );
-        self.batch.add(&block_udp_rule, nftnl::MsgType::Add);
-
-        let mut block_tcp_rule = Rule::new(&self.out_chain);
-        check_port(&mut block_tcp_rule, TransportProtocol::Tcp, End::Dst, 53);
-        add_verdict(&mut block_tcp_rule, &Verdict::Reject(RejectionType::TcpRst));
-        self.batch.add(&block_tcp_rule, nftnl::MsgType::Add);
     }
 
-    fn add_allow_tunnel_rules(&mut self, tunnel: &tunnel::TunnelMetadata) -> Result<()> {
-        self.batch.add(
-            &allow_interface_rule(&self.out_chain, Direction::Out, &tunnel.interface[..])?,
-            nftnl::MsgType::Add,
+    fn add_allow_interface_rules(&mut self, iface: &str) {
+        self.add_rule(
+            ChainId::Out,
+            vec![RuleExpr::Iface(Direction::Out, iface.to_owned())],
+            Action::Accept,
         );
-        self.batch.add(
-            &allow_interface_rule(&self.in_chain, Direction::In, &tunnel.interface[..])?,
-            nftnl::MsgType::Add,
+        self.add_rule(
+            ChainId::In,
+            vec![RuleExpr::Iface(Direction::In, iface.to_owned())],
+            Action::Accept,
         );
-        Ok(())
     }
 
     /// Adds rules for stopping [CVE-2019-14899](https://seclists.org/oss-sec/2019/q4/122).
@@ -609,71 +779,297 @@ impl<'a> PolicyBatch<'a> {
     /// after the rule allowing the tunnel, otherwise even the tunnel can't talk to that IP.
     fn add_block_cve_2019_14899(&mut self, tunnel: &tunnel::TunnelMetadata) {
         for tunnel_ip in &tunnel.ips {
-            let mut rule = Rule::new(&self.in_chain);
-            check_ip(&mut rule, End::Dst, *tunnel_ip);
-            add_verdict(&mut rule, &Verdict::Drop);
-            self.batch.add(&rule, nftnl::MsgType::Add);
+            self.add_rule(
+                ChainId::In,
+                vec![RuleExpr::Ip(End::Dst, *tunnel_ip)],
+                Action::Drop,
+            );
         }
     }
 
-    fn add_allow_lan_rules(&mut self) {
+    fn add_allow_lan_rules(&mut self, lan_networks: &LanNetworks) {
         // LAN -> LAN
-        for net in &*super::ALLOWED_LAN_NETS {
-            let mut out_rule = Rule::new(&self.out_chain);
-            check_net(&mut out_rule, End::Dst, *net);
-            add_verdict(&mut out_rule, &Verdict::Accept);
-            self.batch.add(&out_rule, nftnl::MsgType::Add);
-
-            let mut in_rule = Rule::new(&self.in_chain);
-            check_net(&mut in_rule, End::Src, *net);
-            add_verdict(&mut in_rule, &Verdict::Accept);
-            self.batch.add(&in_rule, nftnl::MsgType::Add);
+        for net in super::allowed_lan_nets(lan_networks) {
+            self.add_rule(
+                ChainId::Out,
+                vec![RuleExpr::Net(End::Dst, net)],
+                Action::Accept,
+            );
+            self.add_rule(
+                ChainId::In,
+                vec![RuleExpr::Net(End::Src, net)],
+                Action::Accept,
+            );
         }
         // LAN -> Multicast
-        for net in &*super::ALLOWED_LAN_MULTICAST_NETS {
-            let mut rule = Rule::new(&self.out_chain);
-            check_net(&mut rule, End::Dst, *net);
-            add_verdict(&mut rule, &Verdict::Accept);
-            self.batch.add(&rule, nftnl::MsgType::Add);
+        for net in super::allowed_lan_multicast_nets(lan_networks) {
+            self.add_rule(
+                ChainId::Out,
+                vec![RuleExpr::Net(End::Dst, net)],
+                Action::Accept,
+            );
         }
         self.add_dhcp_server_rules();
     }
 
+    fn add_allow_rules(&mut self, allow_rules: &[AllowRule]) {
+        for allow_rule in allow_rules {
+            if let Some(interface) = &allow_rule.interface {
+                if iface_name_bytes(interface).is_none() {
+                    log::warn!("Ignoring allow rule with invalid interface: {}", allow_rule);
+                    continue;
+                }
+            }
+            for spec in allow_rule_specs(allow_rule) {
+                let (chain, remote_end) = match spec.chain {
+                    Direction::In => (ChainId::In, End::Src),
+                    Direction::Out => (ChainId::Out, End::Dst),
+                };
+                let mut exprs = vec![];
+                if let Some(interface) = spec.interface {
+                    exprs.push(RuleExpr::IfaceName(spec.chain, interface));
+                }
+                exprs.push(RuleExpr::Net(remote_end, spec.network));
+                match (spec.protocol, spec.ports) {
+                    (Some(protocol), Some((end, ports))) => {
+                        exprs.push(RuleExpr::PortRange(protocol, end, ports))
+                    }
+                    (Some(protocol), None) => exprs.push(RuleExpr::L4Proto(protocol)),
+                    (None, _) => (),
+                }
+                if spec.established_only {
+                    exprs.push(RuleExpr::Established);
+                }
+                self.add_rule(chain, exprs, Action::Accept);
+            }
+        }
+    }
+
     fn add_dhcp_server_rules(&mut self) {
         use TransportProtocol::Udp;
         // Outgoing DHCPv4 response
-        {
-            let mut out_v4 = Rule::new(&self.out_chain);
-            check_port(&mut out_v4, Udp, End::Src, super::DHCPV4_SERVER_PORT);
-            check_port(&mut out_v4, Udp, End::Dst, super::DHCPV4_CLIENT_PORT);
-            add_verdict(&mut out_v4, &Verdict::Accept);
-            self.batch.add(&out_v4, nftnl::MsgType::Add);
-        }
+        self.add_rule(
+            ChainId::Out,
+            vec![
+                RuleExpr::Port(Udp, End::Src, super::DHCPV4_SERVER_PORT),
+                RuleExpr::Port(Udp, End::Dst, super::DHCPV4_CLIENT_PORT),
+            ],
+            Action::Accept,
+        );
         // Incoming DHCPv4 request
-        {
-            let mut in_v4 = Rule::new(&self.in_chain);
-            check_port(&mut in_v4, Udp, End::Src, super::DHCPV4_CLIENT_PORT);
-            check_endpoint(
-                &mut in_v4,
-                End::Dst,
-                &Endpoint::new(Ipv4Addr::BROADCAST, super::DHCPV4_SERVER_PORT, Udp),
-            );
-            add_verdict(&mut in_v4, &Verdict::Accept);
-            self.batch.add(&in_v4, nftnl::MsgType::Add);
+        let mut in_exprs = vec![RuleExpr::Port(Udp, End::Src, super::DHCPV4_CLIENT_PORT)];
+        in_exprs.extend(check_endpoint(
+            End::Dst,
+            &Endpoint::new(Ipv4Addr::BROADCAST, super::DHCPV4_SERVER_PORT, Udp),
+        ));
+        self.add_rule(ChainId::In, in_exprs, Action::Accept);
+    }
+}
+
+/// Creates the table if it does not exist and clears it otherwise.
+fn flush_table(batch: &mut Batch, table: &Table) {
+    batch.add(table, nftnl::MsgType::Add);
+    batch.add(table, nftnl::MsgType::Del);
+    batch.add(table, nftnl::MsgType::Add);
+}
+
+fn check_endpoint(end: End, endpoint: &Endpoint) -> Vec<RuleExpr> {
+    vec![
+        RuleExpr::Ip(end, endpoint.address.ip()),
+        RuleExpr::Port(endpoint.protocol, end, endpoint.address.port()),
+    ]
+}
+
+impl fmt::Display for PolicyRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts: Vec<String> = self.exprs.iter().map(ToString::to_string).collect();
+        if let Some(action) = self.action {
+            if *ADD_COUNTERS {
+                parts.push("counter".to_owned());
+            }
+            parts.push(action.to_string());
+        }
+        f.write_str(&parts.join(" "))
+    }
+}
+
+impl fmt::Display for RuleExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = |direction: &Direction| match direction {
+            Direction::In => "i",
+            Direction::Out => "o",
+        };
+        let ip_family = |ip: &IpAddr| match ip {
+            IpAddr::V4(_) => "ip",
+            IpAddr::V6(_) => "ip6",
+        };
+        let addr_end = |end: &End| match end {
+            End::Src => "saddr",
+            End::Dst => "daddr",
+        };
+        let port_end = |end: &End| match end {
+            End::Src => "sport",
+            End::Dst => "dport",
+        };
+        let protocol = |protocol: &TransportProtocol| match protocol {
+            TransportProtocol::Udp => "udp",
+            TransportProtocol::Tcp => "tcp",
+        };
+        match self {
+            RuleExpr::Iface(dir, iface) => write!(f, "{}if \"{}\"", direction(dir), iface),
+            RuleExpr::IfaceName(dir, iface) => {
+                write!(f, "{}ifname \"{}\"", direction(dir), iface)
+            }
+            RuleExpr::Net(end, net) => {
+                write!(f, "{} {} {}", ip_family(&net.ip()), addr_end(end), net)
+            }
+            RuleExpr::Ip(end, ip) => write!(f, "{} {} {}", ip_family(ip), addr_end(end), ip),
+            RuleExpr::L4Proto(proto) => write!(f, "meta l4proto {}", protocol(proto)),
+            RuleExpr::Port(proto, end, port) => {
+                write!(f, "{} {} {}", protocol(proto), port_end(end), port)
+            }
+            RuleExpr::PortRange(proto, end, ports) => {
+                write!(f, "{} {} {}", protocol(proto), port_end(end), ports)
+            }
+            RuleExpr::Icmp(IpAddr::V4(_)) => f.write_str("meta l4proto icmp"),
+            RuleExpr::Icmp(IpAddr::V6(_)) => f.write_str("meta l4proto ipv6-icmp"),
+            RuleExpr::Icmpv6 { icmp_type, code } => {
+                write!(f, "icmpv6 type {} icmpv6 code {}", icmp_type, code)
+            }
+            RuleExpr::Established => f.write_str("ct state established"),
+            RuleExpr::Related => f.write_str("ct state related"),
+            RuleExpr::Skuid(uid) => write!(f, "meta skuid {}", uid),
+            RuleExpr::Cgroup(classid) => write!(f, "meta cgroup {:#x}", classid),
+            RuleExpr::CtMark(mark) => write!(f, "ct mark {:#x}", mark),
+            RuleExpr::MetaMark(mark) => write!(f, "meta mark {:#x}", mark),
+            RuleExpr::SetMark(mark) => {
+                write!(f, "ct mark set {:#x} meta mark set {:#x}", mark, mark)
+            }
+            RuleExpr::SetCtMark(mark) => write!(f, "ct mark set {:#x}", mark),
+            RuleExpr::Masquerade => f.write_str("masquerade"),
         }
     }
 }
 
-fn allow_interface_rule<'a>(
-    chain: &'a Chain<'_>,
-    direction: Direction,
-    iface: &str,
-) -> Result<Rule<'a>> {
-    let mut rule = Rule::new(&chain);
-    check_iface(&mut rule, direction, iface)?;
-    add_verdict(&mut rule, &Verdict::Accept);
+impl fmt::Display for ChainHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let chain_type = match self.chain_type {
+            nftnl::ChainType::Filter => "filter",
+            nftnl::ChainType::Route => "route",
+            nftnl::ChainType::Nat => "nat",
+        };
+        let hook = match self.hook {
+            nftnl::Hook::PreRouting => "prerouting",
+            nftnl::Hook::In => "input",
+            nftnl::Hook::Forward => "forward",
+            nftnl::Hook::Out => "output",
+            nftnl::Hook::PostRouting => "postrouting",
+        };
+        let policy = match self.policy {
+            nftnl::Policy::Accept => "accept",
+            nftnl::Policy::Drop => "drop",
+        };
+        write!(
+            f,
+            "type {} hook {} priority {}; policy {};",
+            chain_type, hook, self.priority, policy
+        )
+    }
+}
 
-    Ok(rule)
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Action::Accept => "accept",
+            Action::Drop => "drop",
+            Action::RejectPortUnreach => "reject with icmpx type port-unreachable",
+            Action::RejectTcpReset => "reject with tcp reset",
+        })
+    }
+}
+
+fn add_expr(rule: &mut Rule<'_>, expr: &RuleExpr) -> Result<()> {
+    match expr {
+        RuleExpr::Iface(direction, iface) => check_iface(rule, *direction, iface)?,
+        RuleExpr::IfaceName(direction, iface) => {
+            let iface_name =
+                iface_name_bytes(iface).expect("Interface names are validated by PolicyBatch");
+            check_iface_name(rule, *direction, &iface_name);
+        }
+        RuleExpr::Net(end, net) => check_net(rule, *end, *net),
+        RuleExpr::Ip(end, ip) => check_ip(rule, *end, *ip),
+        RuleExpr::L4Proto(protocol) => check_l4proto(rule, *protocol),
+        RuleExpr::Port(protocol, end, port) => check_port(rule, *protocol, *end, *port),
+        RuleExpr::PortRange(protocol, end, ports) => {
+            check_port_range(rule, *protocol, *end, *ports)
+        }
+        RuleExpr::Icmp(ip) => {
+            let icmp_proto = match ip {
+                IpAddr::V4(_) => libc::IPPROTO_ICMP as u8,
+                IpAddr::V6(_) => libc::IPPROTO_ICMPV6 as u8,
+            };
+            rule.add_expr(&nft_expr!(meta l4proto));
+            rule.add_expr(&nft_expr!(cmp == icmp_proto));
+        }
+        RuleExpr::Icmpv6 { icmp_type, code } => {
+            let (icmp_type, code) = (*icmp_type, *code);
+            rule.add_expr(&nft_expr!(meta l4proto));
+            rule.add_expr(&nft_expr!(cmp == libc::IPPROTO_ICMPV6 as u8));
+
+            rule.add_expr(&Payload::Transport(
+                nftnl::expr::TransportHeaderField::Icmpv6(nftnl::expr::Icmpv6HeaderField::Type),
+            ));
+            rule.add_expr(&nft_expr!(cmp == icmp_type));
+            rule.add_expr(&nftnl::expr::Payload::Transport(
+                nftnl::expr::TransportHeaderField::Icmpv6(nftnl::expr::Icmpv6HeaderField::Code),
+            ));
+            rule.add_expr(&nft_expr!(cmp == code));
+        }
+        RuleExpr::Established => check_ct_state(rule, nftnl::expr::ct::States::ESTABLISHED),
+        RuleExpr::Related => check_ct_state(rule, nftnl::expr::ct::States::RELATED),
+        RuleExpr::Skuid(uid) => {
+            let uid = *uid;
+            rule.add_expr(&nft_expr!(meta skuid));
+            rule.add_expr(&nft_expr!(cmp == uid));
+        }
+        RuleExpr::Cgroup(classid) => {
+            let classid = *classid;
+            rule.add_expr(&nft_expr!(meta cgroup));
+            rule.add_expr(&nft_expr!(cmp == classid));
+        }
+        RuleExpr::CtMark(mark) => {
+            let mark = *mark;
+            rule.add_expr(&nft_expr!(ct mark));
+            rule.add_expr(&nft_expr!(cmp == mark));
+        }
+        RuleExpr::MetaMark(mark) => {
+            let mark = *mark;
+            rule.add_expr(&nft_expr!(meta mark));
+            rule.add_expr(&nft_expr!(cmp == mark));
+        }
+        RuleExpr::SetMark(mark) => {
+            let mark = *mark;
+            rule.add_expr(&nft_expr!(immediate data mark));
+            rule.add_expr(&nft_expr!(ct mark set));
+            rule.add_expr(&nft_expr!(meta mark set));
+        }
+        RuleExpr::SetCtMark(mark) => {
+            let mark = *mark;
+            rule.add_expr(&nft_expr!(immediate data mark));
+            rule.add_expr(&nft_expr!(ct mark set));
+        }
+        RuleExpr::Masquerade => rule.add_expr(&nft_expr!(masquerade)),
+    }
+    Ok(())
+}
+
+fn verdict(action: Action) -> Verdict {
+    match action {
+        Action::Accept => Verdict::Accept,
+        Action::Drop => Verdict::Drop,
+        Action::RejectPortUnreach => Verdict::Reject(RejectionType::Icmp(IcmpCode::PortUnreach)),
+        Action::RejectTcpReset => Verdict::Reject(RejectionType::TcpRst),
+    }
 }
 
 fn check_iface(rule: &mut Rule<'_>, direction: Direction, iface: &str) -> Result<()> {
@@ -687,6 +1083,27 @@ fn check_iface(rule: &mut Rule<'_>, direction: Direction, iface: &str) -> Result
     Ok(())
 }
 
+/// Matches the interface by name rather than by index, so that rules can refer to interfaces
+/// that don't exist yet.
+fn check_iface_name(rule: &mut Rule<'_>, direction: Direction, iface_name: &[u8]) {
+    rule.add_expr(&match direction {
+        Direction::In => nft_expr!(meta iifname),
+        Direction::Out => nft_expr!(meta oifname),
+    });
+    rule.add_expr(&nft_expr!(cmp == iface_name));
+}
+
+/// Returns the interface name padded with zeroes, as loaded by `meta iifname` and
+/// `meta oifname`, or `None` if it isn't a valid interface name.
+fn iface_name_bytes(iface: &str) -> Option<[u8; libc::IFNAMSIZ]> {
+    if iface.is_empty() || iface.len() >= libc::IFNAMSIZ || iface.contains('\0') {
+        return None;
+    }
+    let mut buffer = [0u8; libc::IFNAMSIZ];
+    buffer[..iface.len()].copy_from_slice(iface.as_bytes());
+    Some(buffer)
+}
+
 fn check_net(rule: &mut Rule<'_>, end: End, net: impl Into<IpNetwork>) {
     let net = net.into();
     // Must check network layer protocol before loading network layer payload
@@ -705,11 +1122,6 @@ fn check_net(rule: &mut Rule<'_>, end: End, net: impl Into<IpNetwork>) {
     rule.add_expr(&nft_expr!(cmp == net.ip()));
 }
 
-fn check_endpoint(rule: &mut Rule<'_>, end: End, endpoint: &Endpoint) {
-    check_ip(rule, end, endpoint.address.ip());
-    check_port(rule, endpoint.protocol, end, endpoint.address.port());
-}
-
 fn check_ip(rule: &mut Rule<'_>, end: End, ip: impl Into<IpAddr>) {
     let ip = ip.into();
     // Must check network layer protocol before loading network layer payload
@@ -728,16 +1140,60 @@ fn check_ip(rule: &mut Rule<'_>, end: End, ip: impl Into<IpAddr>) {
 }
 
 fn check_port(rule: &mut Rule<'_>, protocol: TransportProtocol, end: End, port: u16) {
+    check_port_range(rule, protocol, end, PortRange::from(port))
+}
+
+fn check_port_range(rule: &mut Rule<'_>, protocol: TransportProtocol, end: End, ports: PortRange) {
     // Must check transport layer protocol before loading transport layer payload
     check_l4proto(rule, protocol);
 
-    rule.add_expr(&match (protocol, end) {
+    for comparison in port_comparisons(ports) {
+        rule.add_expr(&port_payload(protocol, end));
+        rule.add_expr(&match &comparison {
+            PortComparison::Eq(port) => nft_expr!(cmp == &port[..]),
+            PortComparison::Gte(port) => nft_expr!(cmp >= &port[..]),
+            PortComparison::Lte(port) => nft_expr!(cmp <= &port[..]),
+        });
+    }
+}
+
+/// A comparison of the port loaded from a transport header, with the port in network byte order
+/// as it's loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PortComparison {
+    Eq([u8; 2]),
+    Gte([u8; 2]),
+    Lte([u8; 2]),
+}
+
+/// Returns the comparisons that match the ports in `ports`. Ranges are compared byte by byte,
+/// and big-endian ports sort the same way as the numbers do.
+fn port_comparisons(ports: PortRange) -> Vec<PortComparison> {
+    if ports.is_single_port() {
+        vec![PortComparison::Eq(ports.start.to_be_bytes())]
+    } else {
+        vec![
+            PortComparison::Gte(ports.start.to_be_bytes()),
+            PortComparison::Lte(ports.end.to_be_bytes()),
+        ]
+    }
+}
+
+fn port_payload(protocol: TransportProtocol, end: End) -> Payload {
+    match (protocol, end) {
         (TransportProtocol::Udp, End::Src) => nft_expr!(payload udp sport),
         (TransportProtocol::Udp, End::Dst) => nft_expr!(payload udp dport),
         (TransportProtocol::Tcp, End::Src) => nft_expr!(payload tcp sport),
         (TransportProtocol::Tcp, End::Dst) => nft_expr!(payload tcp dport),
-    });
-    rule.add_expr(&nft_expr!(cmp == port.to_be()));
+    }
+}
+
+/// Only matches packets of connections in any of the given states.
+fn check_ct_state(rule: &mut Rule<'_>, states: nftnl::expr::ct::States) {
+    rule.add_expr(&nft_expr!(ct state));
+    let allowed_states = states.bits();
+    rule.add_expr(&nft_expr!(bitwise mask allowed_states, xor 0u32));
+    rule.add_expr(&nft_expr!(cmp != 0u32));
 }
 
 fn check_l3proto(rule: &mut Rule<'_>, ip: IpAddr) {
@@ -770,3 +1226,281 @@ fn add_verdict(rule: &mut Rule<'_>, verdict: &expr::Verdict) {
     }
     rule.add_expr(verdict);
 }
+
+/// One of the nftables rules that accept the traffic of an `AllowRule`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct AllowRuleSpec {
+    chain: Direction,
+    interface: Option<String>,
+    /// The remote network.
+    network: IpNetwork,
+    protocol: Option<TransportProtocol>,
+    /// The ports to match, and whether they are the source or destination ports.
+    ports: Option<(End, PortRange)>,
+    /// Only accept replies in connections that have already been allowed.
+    established_only: bool,
+}
+
+/// Returns the rules accepting the connections allowed by `allow_rule`, and the replies in them.
+fn allow_rule_specs(allow_rule: &AllowRule) -> Vec<AllowRuleSpec> {
+    let (request_chain, reply_chain) = match allow_rule.direction {
+        firewall::Direction::In => (Direction::In, Direction::Out),
+        firewall::Direction::Out => (Direction::Out, Direction::In),
+    };
+    let protocols = match allow_rule.protocols() {
+        Some(protocols) => protocols.into_iter().map(Some).collect(),
+        None => vec![None],
+    };
+
+    let mut specs = Vec::new();
+    for protocol in protocols {
+        let ports = allow_rule.ports.filter(|_| protocol.is_some());
+        specs.push(AllowRuleSpec {
+            chain: request_chain,
+            interface: allow_rule.interface.clone(),
+            network: allow_rule.network,
+            protocol,
+            ports: ports.map(|ports| (End::Dst, ports)),
+            established_only: false,
+        });
+        specs.push(AllowRuleSpec {
+            chain: reply_chain,
+            interface: allow_rule.interface.clone(),
+            network: allow_rule.network,
+            protocol,
+            ports: ports.map(|ports| (End::Src, ports)),
+            established_only: true,
+        });
+    }
+    specs
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv6Addr;
+
+    fn tunnel() -> tunnel::TunnelMetadata {
+        tunnel::TunnelMetadata {
+            interface: "wg-mullvad".to_owned(),
+            ips: vec![
+                IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2)),
+                IpAddr::V6("fc00:bbbb:bbbb:bb01::2".parse().unwrap()),
+            ],
+            ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+            ipv6_gateway: Some(Ipv6Addr::new(0xfc00, 0xbbbb, 0xbbbb, 0xbb01, 0, 0, 0, 1)),
+            mtu: None,
+        }
+    }
+
+    fn peer_endpoint() -> Endpoint {
+        Endpoint::new(Ipv4Addr::new(192, 0, 2, 1), 51820, TransportProtocol::Udp)
+    }
+
+    fn allow_rule(direction: firewall::Direction) -> AllowRule {
+        AllowRule {
+            direction,
+            network: "192.0.2.0/24".parse().unwrap(),
+            protocol: None,
+            ports: None,
+            interface: None,
+        }
+    }
+
+    #[test]
+    fn allows_outgoing_connections_and_replies() {
+        let mut rule = allow_rule(firewall::Direction::Out);
+        rule.protocol = Some(TransportProtocol::Udp);
+        rule.ports = Some(PortRange::from(123));
+        rule.interface = Some("eth0".to_owned());
+
+        assert_eq!(
+            allow_rule_specs(&rule),
+            vec![
+                AllowRuleSpec {
+                    chain: Direction::Out,
+                    interface: Some("eth0".to_owned()),
+                    network: rule.network,
+                    protocol: Some(TransportProtocol::Udp),
+                    ports: Some((End::Dst, PortRange::from(123))),
+                    established_only: false,
+                },
+                AllowRuleSpec {
+                    chain: Direction::In,
+                    interface: Some("eth0".to_owned()),
+                    network: rule.network,
+                    protocol: Some(TransportProtocol::Udp),
+                    ports: Some((End::Src, PortRange::from(123))),
+                    established_only: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn allows_incoming_connections_to_local_ports() {
+        let mut rule = allow_rule(firewall::Direction::In);
+        let ports = PortRange {
+            start: 9100,
+            end: 9200,
+        };
+        rule.ports = Some(ports);
+
+        let specs = allow_rule_specs(&rule);
+        let summary: Vec<_> = specs
+            .iter()
+            .map(|spec| (spec.chain, spec.protocol, spec.ports, spec.established_only))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    Direction::In,
+                    Some(TransportProtocol::Tcp),
+                    Some((End::Dst, ports)),
+                    false
+                ),
+                (
+                    Direction::Out,
+                    Some(TransportProtocol::Tcp),
+                    Some((End::Src, ports)),
+                    true
+                ),
+                (
+                    Direction::In,
+                    Some(TransportProtocol::Udp),
+                    Some((End::Dst, ports)),
+                    false
+                ),
+                (
+                    Direction::Out,
+                    Some(TransportProtocol::Udp),
+                    Some((End::Src, ports)),
+                    true
+                ),
+            ]
+        );
+        assert!(specs
+            .iter()
+            .all(|spec| spec.network == rule.network && spec.interface.is_none()));
+    }
+
+    #[test]
+    fn compares_single_ports_for_equality() {
+        assert_eq!(
+            port_comparisons(PortRange::from(53)),
+            vec![PortComparison::Eq([0, 53])]
+        );
+        assert_eq!(
+            port_comparisons(PortRange::from(51820)),
+            vec![PortComparison::Eq([0xca, 0x6c])]
+        );
+    }
+
+    #[test]
+    fn compares_port_ranges_in_network_byte_order() {
+        let ports = PortRange {
+            start: 255,
+            end: 1024,
+        };
+        let comparisons = port_comparisons(ports);
+        assert_eq!(
+            comparisons,
+            vec![PortComparison::Gte([0, 255]), PortComparison::Lte([4, 0])]
+        );
+
+        // The kernel compares the loaded bytes with the operands lexicographically
+        let matches = |port: u16| {
+            comparisons.iter().all(|comparison| match comparison {
+                PortComparison::Eq(operand) => port.to_be_bytes() == *operand,
+                PortComparison::Gte(operand) => port.to_be_bytes() >= *operand,
+                PortComparison::Lte(operand) => port.to_be_bytes() <= *operand,
+            })
+        };
+        for port in &[255, 256, 511, 512, 1000, 1024] {
+            assert!(matches(*port), "port {} should match", port);
+        }
+        for port in &[0, 254, 1025, 1280, 65535] {
+            assert!(!matches(*port), "port {} should not match", port);
+        }
+    }
+
+    #[test]
+    fn allows_any_protocol_without_ports() {
+        let specs = allow_rule_specs(&allow_rule(firewall::Direction::Out));
+
+        assert_eq!(specs.len(), 2);
+        assert!(specs
+            .iter()
+            .all(|spec| spec.protocol.is_none() && spec.ports.is_none()));
+    }
+
+    #[test]
+    fn pads_interface_names() {
+        let name = iface_name_bytes("wlan0").unwrap();
+        assert_eq!(&name[..6], b"wlan0\0");
+        assert!(name[5..].iter().all(|byte| *byte == 0));
+
+        assert!(iface_name_bytes("").is_none());
+        assert!(iface_name_bytes("a-very-long-name").is_none());
+    }
+
+    // The snapshots are the rendered policies. When the rules change on purpose, the snapshots
+    // are updated with the new output of `render_policy`.
+
+    #[test]
+    fn renders_connecting_policy() {
+        let policy = FirewallPolicy::Connecting {
+            peer_endpoint: peer_endpoint(),
+            pingable_hosts: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1))],
+            allow_lan: false,
+            lan_networks: LanNetworks::default(),
+            allow_rules: vec![],
+        };
+        assert_eq!(
+            render_policy(&policy),
+            include_str!("snapshots/linux_connecting.nft")
+        );
+    }
+
+    #[test]
+    fn renders_connected_policy() {
+        let mut lan_networks = LanNetworks::default();
+        lan_networks.add("100.64.0.0/10".parse().unwrap());
+        lan_networks.remove("fc00::/7".parse().unwrap());
+        let mut ntp_rule = allow_rule(firewall::Direction::Out);
+        ntp_rule.protocol = Some(TransportProtocol::Udp);
+        ntp_rule.ports = Some(PortRange::from(123));
+        ntp_rule.interface = Some("eth0".to_owned());
+
+        let policy = FirewallPolicy::Connected {
+            peer_endpoint: peer_endpoint(),
+            tunnel: tunnel(),
+            allow_lan: true,
+            lan_networks,
+            dns_servers: vec![
+                IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1)),
+                IpAddr::V4(Ipv4Addr::new(100, 64, 0, 53)),
+            ],
+            split_dns_servers: vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 53))],
+            allow_rules: vec![ntp_rule],
+        };
+        assert_eq!(
+            render_policy(&policy),
+            include_str!("snapshots/linux_connected.nft")
+        );
+    }
+
+    #[test]
+    fn renders_blocked_policy() {
+        let policy = FirewallPolicy::Blocked {
+            allow_lan: false,
+            lan_networks: LanNetworks::default(),
+            allow_rules: vec![],
+        };
+        assert_eq!(
+            render_policy(&policy),
+            include_str!("snapshots/linux_blocked.nft")
+        );
+    }
+}
@@ -5,7 +5,10 @@ use std::{
     env,
     net::{IpAddr, Ipv4Addr},
 };
-use talpid_types::net;
+use talpid_types::net::{
+    self,
+    firewall::{self, AllowRule, LanNetworks},
+};
 
 pub use pfctl::Error;
 
@@ -94,11 +97,24 @@ impl Firewall {
         &mut self,
         policy: FirewallPolicy,
     ) -> Result<Vec<pfctl::FilterRule>> {
+        let mut allow_rules = self.get_allow_rules(policy.allow_rules())?;
+        let mut rules = self.get_policy_rules(policy)?;
+        if !allow_rules.is_empty() {
+            // Important to block DNS before the allow rules, so DNS can't leak through them
+            rules.append(&mut self.get_block_dns_rules()?);
+            rules.append(&mut allow_rules);
+        }
+        Ok(rules)
+    }
+
+    fn get_policy_rules(&mut self, policy: FirewallPolicy) -> Result<Vec<pfctl::FilterRule>> {
         match policy {
             FirewallPolicy::Connecting {
                 peer_endpoint,
                 allow_lan,
+                lan_networks,
                 pingable_hosts,
+                ..
             } => {
                 let mut rules = vec![self.get_allow_relay_rule(peer_endpoint)?];
                 rules.extend(self.get_allow_pingable_hosts(&pingable_hosts)?);
@@ -106,7 +122,7 @@ impl Firewall {
                     // Important to block DNS after allow relay rule (so the relay can operate
                     // over port 53) but before allow LAN (so DNS does not leak to the LAN)
                     rules.append(&mut self.get_block_dns_rules()?);
-                    rules.append(&mut self.get_allow_lan_rules()?);
+                    rules.append(&mut self.get_allow_lan_rules(&lan_networks)?);
                 }
                 Ok(rules)
             }
@@ -114,50 +130,21 @@ impl Firewall {
                 peer_endpoint,
                 tunnel,
                 allow_lan,
+                lan_networks,
+                dns_servers,
+                ..
             } => {
                 let mut rules = vec![];
-                let allow_tcp_dns_to_relay_rule = self
-                    .create_rule_builder(FilterRuleAction::Pass)
-                    .direction(pfctl::Direction::Out)
-                    .quick(true)
-                    .interface(&tunnel.interface)
-                    .proto(pfctl::Proto::Tcp)
-                    .keep_state(pfctl::StatePolicy::Keep)
-                    .tcp_flags(Self::get_tcp_flags())
-                    .to(pfctl::Endpoint::new(tunnel.ipv4_gateway, 53))
-                    .build()?;
-                rules.push(allow_tcp_dns_to_relay_rule);
-                let allow_udp_dns_to_relay_rule = self
-                    .create_rule_builder(FilterRuleAction::Pass)
-                    .direction(pfctl::Direction::Out)
-                    .quick(true)
-                    .interface(&tunnel.interface)
-                    .proto(pfctl::Proto::Udp)
-                    .to(pfctl::Endpoint::new(tunnel.ipv4_gateway, 53))
-                    .build()?;
-                rules.push(allow_udp_dns_to_relay_rule);
-
-                if let Some(ipv6_gateway) = tunnel.ipv6_gateway {
-                    let v6_dns_rule_tcp = self
-                        .create_rule_builder(FilterRuleAction::Pass)
-                        .direction(pfctl::Direction::Out)
-                        .quick(true)
-                        .interface(&tunnel.interface)
-                        .proto(pfctl::Proto::Tcp)
-                        .keep_state(pfctl::StatePolicy::Keep)
-                        .tcp_flags(Self::get_tcp_flags())
-                        .to(pfctl::Endpoint::new(ipv6_gateway, 53))
-                        .build()?;
-                    rules.push(v6_dns_rule_tcp);
-                    let v6_dns_rule_udp = self
-                        .create_rule_builder(FilterRuleAction::Pass)
-                        .direction(pfctl::Direction::Out)
-                        .quick(true)
-                        .interface(&tunnel.interface)
-                        .proto(pfctl::Proto::Udp)
-                        .to(pfctl::Endpoint::new(ipv6_gateway, 53))
-                        .build()?;
-                    rules.push(v6_dns_rule_udp);
+                for server in &dns_servers {
+                    // Local servers may be reached outside the tunnel if LAN access is allowed
+                    let outside_tunnel =
+                        super::allows_dns_outside_tunnel(server, &tunnel, allow_lan, &lan_networks);
+                    let interface = if outside_tunnel {
+                        None
+                    } else {
+                        Some(tunnel.interface.as_str())
+                    };
+                    rules.append(&mut self.get_allow_dns_rules(interface, *server)?);
                 }
 
                 rules.push(self.get_allow_relay_rule(peer_endpoint)?);
@@ -169,17 +156,21 @@ impl Firewall {
                 rules.push(self.get_allow_tunnel_rule(tunnel.interface.as_str())?);
 
                 if allow_lan {
-                    rules.append(&mut self.get_allow_lan_rules()?);
+                    rules.append(&mut self.get_allow_lan_rules(&lan_networks)?);
                 }
 
                 Ok(rules)
             }
-            FirewallPolicy::Blocked { allow_lan } => {
+            FirewallPolicy::Blocked {
+                allow_lan,
+                lan_networks,
+                ..
+            } => {
                 let mut rules = Vec::new();
                 if allow_lan {
                     // Important to block DNS before allow LAN (so DNS does not leak to the LAN)
                     rules.append(&mut self.get_block_dns_rules()?);
-                    rules.append(&mut self.get_allow_lan_rules()?);
+                    rules.append(&mut self.get_allow_lan_rules(&lan_networks)?);
                 }
                 Ok(rules)
             }
@@ -201,6 +192,33 @@ impl Firewall {
             .build()?)
     }
 
+    fn get_allow_dns_rules(
+        &self,
+        interface: Option<&str>,
+        server: IpAddr,
+    ) -> Result<Vec<pfctl::FilterRule>> {
+        let mut tcp_rule_builder = self.create_rule_builder(FilterRuleAction::Pass);
+        tcp_rule_builder
+            .direction(pfctl::Direction::Out)
+            .quick(true)
+            .proto(pfctl::Proto::Tcp)
+            .keep_state(pfctl::StatePolicy::Keep)
+            .tcp_flags(Self::get_tcp_flags())
+            .to(pfctl::Endpoint::new(server, 53));
+        let mut udp_rule_builder = self.create_rule_builder(FilterRuleAction::Pass);
+        udp_rule_builder
+            .direction(pfctl::Direction::Out)
+            .quick(true)
+            .proto(pfctl::Proto::Udp)
+            .to(pfctl::Endpoint::new(server, 53));
+        if let Some(interface) = interface {
+            tcp_rule_builder.interface(interface);
+            udp_rule_builder.interface(interface);
+        }
+
+        Ok(vec![tcp_rule_builder.build()?, udp_rule_builder.build()?])
+    }
+
     fn get_block_dns_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
         let block_tcp_dns_rule = self
             .create_rule_builder(FilterRuleAction::Drop(DropAction::Return))
@@ -254,6 +272,50 @@ impl Firewall {
         Ok(rules)
     }
 
+    fn get_allow_rules(&self, allow_rules: &[AllowRule]) -> Result<Vec<pfctl::FilterRule>> {
+        let mut rules = vec![];
+        for allow_rule in allow_rules {
+            let protocols = match allow_rule.protocols() {
+                Some(protocols) => protocols.into_iter().map(as_pfctl_proto).collect(),
+                None => vec![pfctl::Proto::Any],
+            };
+            for proto in protocols {
+                let remote = pfctl::Ip::from(allow_rule.network);
+                let port = match allow_rule.ports {
+                    Some(ports) if proto != pfctl::Proto::Any => pfctl::Port::Range(
+                        ports.start,
+                        ports.end,
+                        pfctl::PortRangeModifier::Inclusive,
+                    ),
+                    _ => pfctl::Port::Any,
+                };
+
+                let mut rule_builder = self.create_rule_builder(FilterRuleAction::Pass);
+                rule_builder
+                    .quick(true)
+                    .proto(proto)
+                    .keep_state(pfctl::StatePolicy::Keep);
+                if proto == pfctl::Proto::Tcp {
+                    rule_builder.tcp_flags(Self::get_tcp_flags());
+                }
+                if let Some(interface) = &allow_rule.interface {
+                    rule_builder.interface(interface.as_str());
+                }
+                match allow_rule.direction {
+                    firewall::Direction::In => rule_builder
+                        .direction(pfctl::Direction::In)
+                        .from(remote)
+                        .to(port),
+                    firewall::Direction::Out => rule_builder
+                        .direction(pfctl::Direction::Out)
+                        .to(pfctl::Endpoint::new(remote, port)),
+                };
+                rules.push(rule_builder.build()?);
+            }
+        }
+        Ok(rules)
+    }
+
     fn get_allow_tunnel_rule(&self, tunnel_interface: &str) -> Result<pfctl::FilterRule> {
         Ok(self
             .create_rule_builder(FilterRuleAction::Pass)
@@ -274,30 +336,30 @@ impl Firewall {
         Ok(vec![lo0_rule])
     }
 
-    fn get_allow_lan_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
+    fn get_allow_lan_rules(&self, lan_networks: &LanNetworks) -> Result<Vec<pfctl::FilterRule>> {
         let mut rules = vec![];
-        for net in &*super::ALLOWED_LAN_NETS {
+        for net in super::allowed_lan_nets(lan_networks) {
             let mut rule_builder = self.create_rule_builder(FilterRuleAction::Pass);
             rule_builder.quick(true);
             let allow_out = rule_builder
                 .direction(pfctl::Direction::Out)
                 .from(pfctl::Ip::Any)
-                .to(pfctl::Ip::from(*net))
+                .to(pfctl::Ip::from(net))
                 .build()?;
             let allow_in = rule_builder
                 .direction(pfctl::Direction::In)
-                .from(pfctl::Ip::from(*net))
+                .from(pfctl::Ip::from(net))
                 .to(pfctl::Ip::Any)
                 .build()?;
             rules.push(allow_out);
             rules.push(allow_in);
         }
-        for multicast_net in &*super::ALLOWED_LAN_MULTICAST_NETS {
+        for multicast_net in super::allowed_lan_multicast_nets(lan_networks) {
             let allow_multicast_out = self
                 .create_rule_builder(FilterRuleAction::Pass)
                 .quick(true)
                 .direction(pfctl::Direction::Out)
-                .to(pfctl::Ip::from(*multicast_net))
+                .to(pfctl::Ip::from(multicast_net))
                 .build()?;
             rules.push(allow_multicast_out);
         }
@@ -1,15 +1,16 @@
+use ipnetwork::IpNetwork;
 #[cfg(unix)]
-use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
-#[cfg(unix)]
+use ipnetwork::{Ipv4Network, Ipv6Network};
 use lazy_static::lazy_static;
-use std::fmt;
-#[cfg(windows)]
-use std::net::IpAddr;
 #[cfg(unix)]
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::net::{Ipv4Addr, Ipv6Addr};
 #[cfg(windows)]
 use std::path::PathBuf;
-use talpid_types::net::Endpoint;
+use std::{fmt, net::IpAddr};
+use talpid_types::net::{
+    firewall::{self, AllowRule, FirewallPolicyKind, LanNetworks},
+    Endpoint,
+};
 
 
 #[cfg(target_os = "macos")]
@@ -28,19 +29,17 @@ mod imp;
 #[path = "android.rs"]
 mod imp;
 
+#[cfg(target_os = "linux")]
+pub use self::imp::render_policy;
 pub use self::imp::Error;
 
-#[cfg(unix)]
 lazy_static! {
     /// When "allow local network" is enabled the app will allow traffic to and from these networks.
-    pub(crate) static ref ALLOWED_LAN_NETS: [IpNetwork; 6] = [
-        IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(10, 0, 0, 0), 8).unwrap()),
-        IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(172, 16, 0, 0), 12).unwrap()),
-        IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(192, 168, 0, 0), 16).unwrap()),
-        IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(169, 254, 0, 0), 16).unwrap()),
-        IpNetwork::V6(Ipv6Network::new(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), 10).unwrap()),
-        IpNetwork::V6(Ipv6Network::new(Ipv6Addr::new(0xfc00, 0, 0, 0, 0, 0, 0, 0), 7).unwrap()),
-    ];
+    pub(crate) static ref ALLOWED_LAN_NETS: [IpNetwork; 6] = firewall::default_lan_networks();
+}
+
+#[cfg(unix)]
+lazy_static! {
     /// When "allow local network" is enabled the app will allow traffic to these networks.
     pub(crate) static ref ALLOWED_LAN_MULTICAST_NETS: [IpNetwork; 8] = [
         // Local network broadcast. Not routable
@@ -77,6 +76,46 @@ const DHCPV6_SERVER_PORT: u16 = 547;
 #[cfg(all(unix, not(target_os = "android")))]
 const DHCPV6_CLIENT_PORT: u16 = 546;
 
+/// Returns the networks that communication is allowed with when communication with LAN networks
+/// is allowed, after applying the changes in `lan_networks` to [`ALLOWED_LAN_NETS`].
+pub fn allowed_lan_nets(lan_networks: &LanNetworks) -> Vec<IpNetwork> {
+    lan_networks.apply()
+}
+
+/// Returns the multicast networks that traffic is allowed to when communication with LAN networks
+/// is allowed. Networks can only be removed from [`ALLOWED_LAN_MULTICAST_NETS`].
+#[cfg(unix)]
+pub(crate) fn allowed_lan_multicast_nets(lan_networks: &LanNetworks) -> Vec<IpNetwork> {
+    ALLOWED_LAN_MULTICAST_NETS
+        .iter()
+        .filter(|net| !lan_networks.removed.contains(net))
+        .cloned()
+        .collect()
+}
+
+/// Returns whether `address` belongs to a local network, which can only be reached when
+/// communication with LAN networks is allowed.
+pub fn is_local_address(address: &IpAddr, lan_networks: &LanNetworks) -> bool {
+    allowed_lan_nets(lan_networks)
+        .iter()
+        .any(|net| net.contains(*address))
+}
+
+/// Returns whether DNS requests to `server` may be sent outside the tunnel. This is only the case
+/// for servers on the local network, when communication with LAN networks is allowed. The tunnel
+/// gateways are always reached inside the tunnel.
+#[cfg(not(target_os = "android"))]
+fn allows_dns_outside_tunnel(
+    server: &IpAddr,
+    tunnel: &crate::tunnel::TunnelMetadata,
+    allow_lan: bool,
+    lan_networks: &LanNetworks,
+) -> bool {
+    let is_gateway = *server == IpAddr::from(tunnel.ipv4_gateway)
+        || tunnel.ipv6_gateway.map(IpAddr::from) == Some(*server);
+    allow_lan && !is_gateway && is_local_address(server, lan_networks)
+}
+
 
 /// A enum that describes network security strategy
 ///
@@ -94,6 +133,10 @@ pub enum FirewallPolicy {
         pingable_hosts: Vec<IpAddr>,
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// Changes to the networks that are considered to be LAN networks.
+        lan_networks: LanNetworks,
+        /// Traffic that is allowed in addition to what the policy allows.
+        allow_rules: Vec<AllowRule>,
         /// A process that is allowed to send packets to the relay.
         #[cfg(windows)]
         relay_client: PathBuf,
@@ -107,6 +150,18 @@ pub enum FirewallPolicy {
         tunnel: crate::tunnel::TunnelMetadata,
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// Changes to the networks that are considered to be LAN networks.
+        lan_networks: LanNetworks,
+        /// Servers that DNS requests are allowed to inside the tunnel. Servers on the local
+        /// network are also allowed outside the tunnel, if communication with LAN networks
+        /// is allowed.
+        dns_servers: Vec<IpAddr>,
+        /// Servers that DNS requests for split DNS domains are allowed to. Like `dns_servers`,
+        /// they are only reached outside the tunnel if they are local and LAN access is allowed.
+        #[cfg(target_os = "linux")]
+        split_dns_servers: Vec<IpAddr>,
+        /// Traffic that is allowed in addition to what the policy allows.
+        allow_rules: Vec<AllowRule>,
         /// A process that is allowed to send packets to the relay.
         #[cfg(windows)]
         relay_client: PathBuf,
@@ -116,6 +171,10 @@ pub enum FirewallPolicy {
     Blocked {
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// Changes to the networks that are considered to be LAN networks.
+        lan_networks: LanNetworks,
+        /// Traffic that is allowed in addition to what the policy allows.
+        allow_rules: Vec<AllowRule>,
     },
 }
 
@@ -142,10 +201,11 @@ impl fmt::Display for FirewallPolicy {
                 peer_endpoint,
                 tunnel,
                 allow_lan,
+                dns_servers,
                 ..
             } => write!(
                 f,
-                "Connected to {} over \"{}\" (ip: {}, v4 gw: {}, v6 gw: {:?}), {} LAN",
+                "Connected to {} over \"{}\" (ip: {}, v4 gw: {}, v6 gw: {:?}, dns: {}), {} LAN",
                 peer_endpoint,
                 tunnel.interface,
                 tunnel
@@ -156,13 +216,76 @@ impl fmt::Display for FirewallPolicy {
                     .join(","),
                 tunnel.ipv4_gateway,
                 tunnel.ipv6_gateway,
+                dns_servers
+                    .iter()
+                    .map(|ip| ip.to_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
                 if *allow_lan { "Allowing" } else { "Blocking" }
             ),
-            FirewallPolicy::Blocked { allow_lan } => write!(
+            FirewallPolicy::Blocked { allow_lan, .. } => write!(
                 f,
                 "Blocked, {} LAN",
                 if *allow_lan { "Allowing" } else { "Blocking" }
             ),
+        }?;
+        let lan_networks = self.lan_networks();
+        if !lan_networks.added.is_empty() || !lan_networks.removed.is_empty() {
+            let join = |networks: &[IpNetwork]| {
+                networks
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            };
+            write!(
+                f,
+                " (LAN networks added: {}, removed: {})",
+                join(&lan_networks.added),
+                join(&lan_networks.removed)
+            )?;
+        }
+        let allow_rules = self.allow_rules();
+        if !allow_rules.is_empty() {
+            write!(
+                f,
+                ", allowing {}",
+                allow_rules
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl FirewallPolicy {
+    /// Returns the traffic that is allowed in addition to what the policy allows.
+    pub fn allow_rules(&self) -> &[AllowRule] {
+        match self {
+            FirewallPolicy::Connecting { allow_rules, .. }
+            | FirewallPolicy::Connected { allow_rules, .. }
+            | FirewallPolicy::Blocked { allow_rules, .. } => allow_rules,
+        }
+    }
+
+    /// Returns the changes to the networks that are considered to be LAN networks.
+    pub fn lan_networks(&self) -> &LanNetworks {
+        match self {
+            FirewallPolicy::Connecting { lan_networks, .. }
+            | FirewallPolicy::Connected { lan_networks, .. }
+            | FirewallPolicy::Blocked { lan_networks, .. } => lan_networks,
+        }
+    }
+
+    /// Returns the kind of tunnel state that the policy is used in.
+    pub fn kind(&self) -> FirewallPolicyKind {
+        match self {
+            FirewallPolicy::Connecting { .. } => FirewallPolicyKind::Connecting,
+            FirewallPolicy::Connected { .. } => FirewallPolicyKind::Connected,
+            FirewallPolicy::Blocked { .. } => FirewallPolicyKind::Blocked,
         }
     }
 }
@@ -171,6 +294,7 @@ impl fmt::Display for FirewallPolicy {
 /// by manipulating the OS firewall and DNS settings.
 pub struct Firewall {
     inner: imp::Firewall,
+    policy: Option<FirewallPolicy>,
 }
 
 /// Arguments required when first initializing the firewall.
@@ -186,6 +310,7 @@ impl Firewall {
     pub fn new(args: FirewallArguments) -> Result<Self, Error> {
         Ok(Firewall {
             inner: imp::Firewall::new(args)?,
+            policy: None,
         })
     }
 
@@ -193,13 +318,21 @@ impl Firewall {
     /// until this method is called again with another policy, or until `reset_policy` is called.
     pub fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<(), Error> {
         log::info!("Applying firewall policy: {}", policy);
-        self.inner.apply_policy(policy)
+        self.inner.apply_policy(policy.clone())?;
+        self.policy = Some(policy);
+        Ok(())
+    }
+
+    /// Returns the policy that was last applied with `apply_policy`, unless it has been reset.
+    pub fn policy(&self) -> Option<&FirewallPolicy> {
+        self.policy.as_ref()
     }
 
     /// Resets/removes any currently enforced `FirewallPolicy`. Returns the system to the same state
     /// it had before any policy was applied through this `Firewall` instance.
     pub fn reset_policy(&mut self) -> Result<(), Error> {
         log::info!("Resetting firewall policy");
+        self.policy = None;
         self.inner.reset_policy()
     }
 }
@@ -219,3 +352,57 @@ trait FirewallT: Sized {
     /// modifying the system.
     fn reset_policy(&mut self) -> Result<(), Self::Error>;
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn allows_added_lan_networks() {
+        let custom_net: IpNetwork = "100.64.0.0/10".parse().unwrap();
+        let lan_networks = LanNetworks {
+            added: vec![custom_net],
+            removed: vec![],
+        };
+
+        let nets = allowed_lan_nets(&lan_networks);
+        assert_eq!(nets.len(), ALLOWED_LAN_NETS.len() + 1);
+        assert!(nets.contains(&custom_net));
+
+        let address = IpAddr::V4(Ipv4Addr::new(100, 64, 1, 1));
+        assert!(!is_local_address(&address, &LanNetworks::default()));
+        assert!(is_local_address(&address, &lan_networks));
+    }
+
+    #[test]
+    fn blocks_removed_lan_networks() {
+        let removed_net = ALLOWED_LAN_NETS[0];
+        let lan_networks = LanNetworks {
+            added: vec![],
+            removed: vec![removed_net],
+        };
+
+        let nets = allowed_lan_nets(&lan_networks);
+        assert_eq!(nets.len(), ALLOWED_LAN_NETS.len() - 1);
+        assert!(!nets.contains(&removed_net));
+
+        let address = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        assert!(is_local_address(&address, &LanNetworks::default()));
+        assert!(!is_local_address(&address, &lan_networks));
+        assert!(is_local_address(
+            &IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)),
+            &lan_networks
+        ));
+    }
+
+    #[test]
+    fn ignores_added_default_lan_networks() {
+        let lan_networks = LanNetworks {
+            added: vec![ALLOWED_LAN_NETS[2]],
+            removed: vec![],
+        };
+
+        assert_eq!(allowed_lan_nets(&lan_networks), ALLOWED_LAN_NETS.to_vec());
+    }
+}
@@ -5,9 +5,9 @@ use std::{net::IpAddr, path::Path, ptr};
 use self::winfw::*;
 use super::{FirewallArguments, FirewallPolicy, FirewallT};
 use crate::winnet;
-use log::{debug, error, trace};
+use log::{debug, error, trace, warn};
 use std::os::windows::ffi::OsStrExt;
-use talpid_types::net::Endpoint;
+use talpid_types::net::{firewall::LanNetworks, Endpoint};
 use widestring::WideCString;
 
 
@@ -77,12 +77,19 @@ impl FirewallT for Firewall {
     }
 
     fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<(), Self::Error> {
+        if !policy.allow_rules().is_empty() {
+            warn!("Ignoring firewall allow rules, which are not supported on Windows");
+        }
+        if *policy.lan_networks() != LanNetworks::default() {
+            warn!("Ignoring changes to the LAN networks, which are not supported on Windows");
+        }
         match policy {
             FirewallPolicy::Connecting {
                 peer_endpoint,
                 pingable_hosts,
                 allow_lan,
                 relay_client,
+                ..
             } => {
                 let cfg = &WinFwSettings::new(allow_lan);
                 // TODO: Determine interface alias at runtime
@@ -98,12 +105,14 @@ impl FirewallT for Firewall {
                 peer_endpoint,
                 tunnel,
                 allow_lan,
+                dns_servers,
                 relay_client,
+                ..
             } => {
                 let cfg = &WinFwSettings::new(allow_lan);
-                self.set_connected_state(&peer_endpoint, &cfg, &tunnel, &relay_client)
+                self.set_connected_state(&peer_endpoint, &cfg, &tunnel, &dns_servers, &relay_client)
             }
-            FirewallPolicy::Blocked { allow_lan } => {
+            FirewallPolicy::Blocked { allow_lan, .. } => {
                 let cfg = &WinFwSettings::new(allow_lan);
                 self.set_blocked_state(&cfg)
             }
@@ -200,14 +209,38 @@ impl Firewall {
         endpoint: &Endpoint,
         winfw_settings: &WinFwSettings,
         tunnel_metadata: &crate::tunnel::TunnelMetadata,
+        dns_servers: &[IpAddr],
         relay_client: &Path,
     ) -> Result<(), Error> {
         trace!("Applying 'connected' firewall policy");
         let ip_str = Self::widestring_ip(endpoint.address.ip());
-        let v4_gateway = Self::widestring_ip(tunnel_metadata.ipv4_gateway.into());
-        let v6_gateway = tunnel_metadata
-            .ipv6_gateway
-            .map(|v6_ip| Self::widestring_ip(v6_ip.into()));
+
+        // Local servers may be reached outside the tunnel if LAN access is allowed
+        let (non_tunnel_dns_servers, tunnel_dns_servers): (Vec<&IpAddr>, Vec<&IpAddr>) =
+            dns_servers.iter().partition(|server| {
+                super::allows_dns_outside_tunnel(
+                    server,
+                    tunnel_metadata,
+                    winfw_settings.permits_lan(),
+                    &LanNetworks::default(),
+                )
+            });
+        let tunnel_dns_servers = tunnel_dns_servers
+            .into_iter()
+            .map(|ip| Self::widestring_ip(*ip))
+            .collect::<Vec<_>>();
+        let tunnel_dns_server_ptrs = tunnel_dns_servers
+            .iter()
+            .map(|ip| ip.as_ptr())
+            .collect::<Vec<_>>();
+        let non_tunnel_dns_servers = non_tunnel_dns_servers
+            .into_iter()
+            .map(|ip| Self::widestring_ip(*ip))
+            .collect::<Vec<_>>();
+        let non_tunnel_dns_server_ptrs = non_tunnel_dns_servers
+            .iter()
+            .map(|ip| ip.as_ptr())
+            .collect::<Vec<_>>();
 
         let tunnel_alias =
             WideCString::new(tunnel_metadata.interface.encode_utf16().collect::<Vec<_>>()).unwrap();
@@ -228,11 +261,6 @@ impl Firewall {
             debug!("Network interface metrics were not changed");
         }
 
-        let v6_gateway_ptr = match &v6_gateway {
-            Some(v6_ip) => v6_ip.as_ptr(),
-            None => ptr::null(),
-        };
-
         let mut relay_client: Vec<u16> = relay_client.as_os_str().encode_wide().collect();
         relay_client.push(0u16);
 
@@ -242,8 +270,10 @@ impl Firewall {
                 &winfw_relay,
                 relay_client.as_ptr(),
                 tunnel_alias.as_ptr(),
-                v4_gateway.as_ptr(),
-                v6_gateway_ptr,
+                tunnel_dns_server_ptrs.as_ptr(),
+                tunnel_dns_server_ptrs.len(),
+                non_tunnel_dns_server_ptrs.as_ptr(),
+                non_tunnel_dns_server_ptrs.len(),
             )
             .into_result()
         }
@@ -299,6 +329,10 @@ mod winfw {
                 permitLan: permit_lan,
             }
         }
+
+        pub fn permits_lan(&self) -> bool {
+            self.permitLan
+        }
     }
 
     #[repr(C)]
@@ -357,8 +391,10 @@ mod winfw {
             relay: &WinFwRelay,
             relayClient: *const libc::wchar_t,
             tunnelIfaceAlias: *const libc::wchar_t,
-            v4Gateway: *const libc::wchar_t,
-            v6Gateway: *const libc::wchar_t,
+            tunnelDnsServers: *const *const libc::wchar_t,
+            numTunnelDnsServers: usize,
+            nonTunnelDnsServers: *const *const libc::wchar_t,
+            numNonTunnelDnsServers: usize,
         ) -> ApplyConnectedResult;
 
         #[link_name = "WinFw_ApplyPolicyBlocked"]
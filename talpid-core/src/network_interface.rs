@@ -22,6 +22,10 @@ pub enum Error {
     #[error(display = "Unable to open a tunnel device")]
     CreateDeviceError(#[error(source)] tun::Error),
 
+    /// Failed to remove IP address
+    #[error(display = "Failed to remove IP address")]
+    RemoveIpError(#[error(source)] io::Error),
+
     /// Failed to apply async flags to tunnel device
     #[error(display = "Failed to apply async flags to tunnel device")]
     SetDeviceAsyncError(#[error(source)] nix::Error),
@@ -39,6 +43,9 @@ pub trait NetworkInterface: Sized {
     /// Set host IPs for interface
     fn set_ip(&mut self, ip: IpAddr) -> Result<(), Error>;
 
+    /// Remove a host IP from the interface
+    fn remove_ip(&mut self, ip: IpAddr) -> Result<(), Error>;
+
     /// Set MTU for interface
     fn set_mtu(&mut self, mtu: u16) -> Result<(), Error>;
 
@@ -126,6 +133,21 @@ impl NetworkInterface for TunnelDevice {
         }
     }
 
+    fn remove_ip(&mut self, ip: IpAddr) -> Result<(), Error> {
+        #[cfg(target_os = "linux")]
+        let command = duct::cmd!("ip", "addr", "del", ip.to_string(), "dev", self.dev.name());
+        #[cfg(target_os = "macos")]
+        let command = duct::cmd!(
+            "ifconfig",
+            self.dev.name(),
+            if ip.is_ipv4() { "inet" } else { "inet6" },
+            ip.to_string(),
+            "-alias"
+        );
+
+        command.run().map(|_| ()).map_err(Error::RemoveIpError)
+    }
+
     fn set_up(&mut self, up: bool) -> Result<(), Error> {
         self.dev.enabled(up).map_err(Error::ToggleDeviceError)
     }
@@ -7,18 +7,140 @@ mod imp;
 #[path = "win.rs"]
 mod imp;
 
+mod tcp;
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+use talpid_types::{net::wireguard::ConnectivityProbe, ErrorExt};
+
 pub use imp::Error;
 
 pub trait Pinger: Send {
-    /// Sends an ICMP packet
+    /// Sends an ICMP packet, or whichever probe the pinger uses
     fn send_icmp(&mut self) -> Result<(), Error>;
+    /// Returns the round-trip times of all replies received since the last call.
+    fn receive_replies(&mut self) -> Result<Vec<Duration>, Error> {
+        Ok(vec![])
+    }
     /// Clears all resources used by the pinger.
     fn reset(&mut self) {}
 }
 
-pub fn new_pinger(
-    addr: std::net::Ipv4Addr,
+pub fn new_pinger(addr: IpAddr, interface_name: String) -> Result<Box<dyn Pinger>, Error> {
+    Ok(Box::new(imp::Pinger::new(addr, interface_name)?))
+}
+
+/// Creates a pinger that sends all of the given probes. ICMP probes are sent to every address in
+/// `gateways`. TCP and HTTP probes are sent from the tunnel address of the same IP version. If no
+/// probes are given, only ICMP is used. Gateways that can't be pinged are skipped, and an error is
+/// only returned if no pinger could be created.
+pub fn new_probing_pinger(
+    gateways: &[IpAddr],
     interface_name: String,
+    tunnel_addresses: &[IpAddr],
+    probes: &[ConnectivityProbe],
 ) -> Result<Box<dyn Pinger>, Error> {
-    Ok(Box::new(imp::Pinger::new(addr, interface_name)?))
+    let icmp_probe = [ConnectivityProbe::Icmp];
+    let probes = if probes.is_empty() {
+        &icmp_probe[..]
+    } else {
+        probes
+    };
+
+    let tcp_pinger = |address: SocketAddr, send_http_request| -> Box<dyn Pinger> {
+        let source_address = tunnel_addresses
+            .iter()
+            .find(|tunnel_address| tunnel_address.is_ipv4() == address.is_ipv4())
+            .cloned();
+        Box::new(tcp::TcpPinger::new(
+            address,
+            send_http_request,
+            interface_name.clone(),
+            source_address,
+        ))
+    };
+
+    let mut pingers: Vec<Box<dyn Pinger>> = Vec::with_capacity(probes.len());
+    let mut icmp_error = None;
+    for probe in probes {
+        match probe {
+            ConnectivityProbe::Icmp => {
+                for gateway in gateways {
+                    match new_pinger(*gateway, interface_name.clone()) {
+                        Ok(pinger) => pingers.push(pinger),
+                        Err(error) => {
+                            log::warn!(
+                                "{}",
+                                error.display_chain_with_msg(&format!(
+                                    "Not pinging {} since no pinger could be created for it",
+                                    gateway
+                                ))
+                            );
+                            icmp_error = Some(error);
+                        }
+                    }
+                }
+            }
+            ConnectivityProbe::Tcp(address) => {
+                pingers.push(tcp_pinger(*address, false));
+            }
+            ConnectivityProbe::Http(address) => {
+                pingers.push(tcp_pinger(*address, true));
+            }
+        }
+    }
+    if pingers.is_empty() {
+        if let Some(error) = icmp_error {
+            return Err(error);
+        }
+    }
+    if pingers.len() == 1 {
+        return Ok(pingers.remove(0));
+    }
+    Ok(Box::new(CombinedPinger::new(pingers)))
+}
+
+/// Combines several pingers into one. Probes are sent with every pinger, and a reply received by
+/// any of them counts.
+pub struct CombinedPinger {
+    pingers: Vec<Box<dyn Pinger>>,
+}
+
+impl CombinedPinger {
+    pub fn new(pingers: Vec<Box<dyn Pinger>>) -> Self {
+        Self { pingers }
+    }
+}
+
+impl Pinger for CombinedPinger {
+    fn send_icmp(&mut self) -> Result<(), Error> {
+        let mut last_error = None;
+        let mut any_sent = false;
+        for pinger in &mut self.pingers {
+            match pinger.send_icmp() {
+                Ok(()) => any_sent = true,
+                Err(error) => last_error = Some(error),
+            }
+        }
+        match last_error {
+            Some(error) if !any_sent => Err(error),
+            _ => Ok(()),
+        }
+    }
+
+    fn receive_replies(&mut self) -> Result<Vec<Duration>, Error> {
+        let mut round_trip_times = vec![];
+        for pinger in &mut self.pingers {
+            round_trip_times.extend(pinger.receive_replies()?);
+        }
+        Ok(round_trip_times)
+    }
+
+    fn reset(&mut self) {
+        for pinger in &mut self.pingers {
+            pinger.reset();
+        }
+    }
 }
@@ -0,0 +1,308 @@
+use super::{Error, Pinger};
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+/// Time to wait for a probe to complete before it's considered lost.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The beginning of the status line of an HTTP response.
+const HTTP_VERSION_PREFIX: &[u8] = b"HTTP/";
+
+/// A pinger that verifies connectivity by connecting to a TCP service reachable through the
+/// tunnel. Optionally, an HTTP request is sent as well and a response is expected.
+///
+/// The probe sockets are bound to the tunnel interface, so probes can't succeed outside the
+/// tunnel. A refused connection counts as a failed probe. Probes don't block: they make progress
+/// every time replies are received, so the round-trip times are only as precise as the interval
+/// that `receive_replies` is called at.
+pub struct TcpPinger {
+    addr: SocketAddr,
+    send_http_request: bool,
+    interface_name: String,
+    source_address: Option<IpAddr>,
+    probes: Vec<Probe>,
+}
+
+/// A probe that has been sent but hasn't completed yet.
+struct Probe {
+    socket: Socket,
+    start: Instant,
+    state: ProbeState,
+}
+
+enum ProbeState {
+    Connecting,
+    /// Waiting for the beginning of the HTTP response.
+    Receiving(Vec<u8>),
+}
+
+impl TcpPinger {
+    /// Creates a pinger that connects to `addr` through the interface `interface_name`, from
+    /// `source_address` if one is given.
+    pub fn new(
+        addr: SocketAddr,
+        send_http_request: bool,
+        interface_name: String,
+        source_address: Option<IpAddr>,
+    ) -> Self {
+        Self {
+            addr,
+            send_http_request,
+            interface_name,
+            source_address,
+            probes: vec![],
+        }
+    }
+
+    fn open_socket(&self) -> Result<Socket, Error> {
+        let domain = match self.addr {
+            SocketAddr::V4(_) => Domain::ipv4(),
+            SocketAddr::V6(_) => Domain::ipv6(),
+        };
+        let socket = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))
+            .map_err(Error::ProbeError)?;
+        socket.set_nonblocking(true).map_err(Error::ProbeError)?;
+        if let Some(source_address) = self.source_address {
+            socket
+                .bind(&SockAddr::from(SocketAddr::new(source_address, 0)))
+                .map_err(Error::ProbeError)?;
+        }
+        // Windows sends packets from the interface that owns the source address
+        #[cfg(unix)]
+        super::imp::bind_to_interface(&socket, self.addr.ip(), &self.interface_name)?;
+        Ok(socket)
+    }
+
+    /// Advances the probe as far as possible without blocking. Returns the round-trip time once
+    /// the probe has succeeded, or `None` if it's still in progress.
+    fn advance(&self, probe: &mut Probe) -> io::Result<Option<Duration>> {
+        if let ProbeState::Connecting = probe.state {
+            if let Some(error) = probe.socket.take_error()? {
+                return Err(error);
+            }
+            if probe.socket.peer_addr().is_err() {
+                return Ok(None);
+            }
+            if !self.send_http_request {
+                return Ok(Some(probe.start.elapsed()));
+            }
+
+            let request = http_request(self.addr);
+            match probe.socket.send(request.as_bytes()) {
+                Ok(sent) if sent == request.len() => (),
+                Ok(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "Failed to send the whole probe request",
+                    ))
+                }
+                Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(error) => return Err(error),
+            }
+            probe.state = ProbeState::Receiving(vec![]);
+        }
+
+        if let ProbeState::Receiving(response) = &mut probe.state {
+            let mut buffer = [0u8; 16];
+            let remaining = HTTP_VERSION_PREFIX.len() - response.len();
+            match probe.socket.recv(&mut buffer[..remaining]) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Connection closed before a response was received",
+                    ))
+                }
+                Ok(received) => response.extend_from_slice(&buffer[..received]),
+                Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(error) => return Err(error),
+            }
+            if response.len() < HTTP_VERSION_PREFIX.len() {
+                return Ok(None);
+            }
+            if &response[..] != HTTP_VERSION_PREFIX {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Probe response is not HTTP",
+                ));
+            }
+        }
+        Ok(Some(probe.start.elapsed()))
+    }
+}
+
+/// Returns a minimal HTTP request for the root of the server at `addr`.
+fn http_request(addr: SocketAddr) -> String {
+    let host = match addr.ip() {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("[{}]", ip),
+    };
+    format!(
+        "HEAD / HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        host
+    )
+}
+
+/// Returns whether a non-blocking connect has been started and will complete later.
+fn is_connect_in_progress(error: &io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        if error.raw_os_error() == Some(libc::EINPROGRESS) {
+            return true;
+        }
+    }
+    error.kind() == io::ErrorKind::WouldBlock
+}
+
+impl Pinger for TcpPinger {
+    fn send_icmp(&mut self) -> Result<(), Error> {
+        let socket = self.open_socket()?;
+        match socket.connect(&SockAddr::from(self.addr)) {
+            Ok(()) => (),
+            Err(ref error) if is_connect_in_progress(error) => (),
+            Err(error) => {
+                log::trace!("Probe to {} failed: {}", self.addr, error);
+                return Ok(());
+            }
+        }
+        self.probes.push(Probe {
+            socket,
+            start: Instant::now(),
+            state: ProbeState::Connecting,
+        });
+        Ok(())
+    }
+
+    fn receive_replies(&mut self) -> Result<Vec<Duration>, Error> {
+        let mut round_trip_times = vec![];
+        for mut probe in std::mem::take(&mut self.probes) {
+            match self.advance(&mut probe) {
+                Ok(Some(round_trip_time)) => round_trip_times.push(round_trip_time),
+                Ok(None) if probe.start.elapsed() < PROBE_TIMEOUT => self.probes.push(probe),
+                Ok(None) => log::trace!("Probe to {} timed out", self.addr),
+                Err(error) => log::trace!("Probe to {} failed: {}", self.addr, error),
+            }
+        }
+        Ok(round_trip_times)
+    }
+
+    fn reset(&mut self) {
+        self.probes.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    #[cfg(target_os = "macos")]
+    const LOOPBACK_INTERFACE: &str = "lo0";
+    #[cfg(not(target_os = "macos"))]
+    const LOOPBACK_INTERFACE: &str = "lo";
+
+    fn new_pinger(addr: SocketAddr, send_http_request: bool) -> TcpPinger {
+        TcpPinger::new(
+            addr,
+            send_http_request,
+            LOOPBACK_INTERFACE.to_owned(),
+            Some(addr.ip()),
+        )
+    }
+
+    /// Polls the pinger until it has received a reply or all probes have failed.
+    fn wait_for_replies(pinger: &mut TcpPinger) -> Vec<Duration> {
+        let start = Instant::now();
+        while start.elapsed() < PROBE_TIMEOUT {
+            let replies = pinger.receive_replies().unwrap();
+            if !replies.is_empty() || pinger.probes.is_empty() {
+                return replies;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        vec![]
+    }
+
+    #[test]
+    fn test_tcp_probe_counts_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut pinger = new_pinger(listener.local_addr().unwrap(), false);
+
+        pinger.send_icmp().unwrap();
+        assert_eq!(wait_for_replies(&mut pinger).len(), 1);
+    }
+
+    #[test]
+    fn test_tcp_probe_fails_on_refused_connection() {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut pinger = new_pinger(addr, false);
+
+        pinger.send_icmp().unwrap();
+        assert!(wait_for_replies(&mut pinger).is_empty());
+        assert!(pinger.probes.is_empty());
+    }
+
+    #[test]
+    fn test_http_probe_requires_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut pinger = new_pinger(listener.local_addr().unwrap(), true);
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut byte = [0u8; 1];
+            while !request.ends_with(b"\r\n\r\n") {
+                stream.read_exact(&mut byte).unwrap();
+                request.push(byte[0]);
+            }
+            assert!(request.starts_with(b"HEAD / HTTP/1.0"));
+            stream
+                .write_all(b"HTTP/1.0 204 No Content\r\n\r\n")
+                .unwrap();
+        });
+
+        pinger.send_icmp().unwrap();
+        assert_eq!(wait_for_replies(&mut pinger).len(), 1);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_http_probe_fails_on_other_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut pinger = new_pinger(listener.local_addr().unwrap(), true);
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"SSH-2.0-OpenSSH\r\n").unwrap();
+        });
+
+        pinger.send_icmp().unwrap();
+        assert!(wait_for_replies(&mut pinger).is_empty());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_reset_discards_pending_replies() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut pinger = new_pinger(listener.local_addr().unwrap(), false);
+
+        pinger.send_icmp().unwrap();
+        pinger.reset();
+        thread::sleep(Duration::from_millis(200));
+        assert!(pinger.receive_replies().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_http_request_brackets_ipv6_host() {
+        assert!(http_request("[::1]:80".parse().unwrap()).contains("\r\nHost: [::1]\r\n"));
+        assert!(http_request("10.64.0.1:80".parse().unwrap()).contains("\r\nHost: 10.64.0.1\r\n"));
+    }
+}
@@ -1,109 +1,334 @@
-use std::{io, net::Ipv4Addr};
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::{ffi::CString, os::unix::io::AsRawFd};
+
+const ICMP_HEADER_LENGTH: usize = 8;
+const ICMP_PAYLOAD_LENGTH: usize = 56;
+
+const ICMPV4_ECHO_REQUEST: u8 = 8;
+const ICMPV4_ECHO_REPLY: u8 = 0;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+/// Requests that have not been answered within this time are forgotten.
+const MAX_REPLY_WAIT: Duration = Duration::from_secs(30);
+
+#[cfg(target_os = "macos")]
+const IP_BOUND_IF: libc::c_int = 25;
+#[cfg(target_os = "macos")]
+const IPV6_BOUND_IF: libc::c_int = 125;
 
 #[derive(err_derive::Error, Debug)]
+#[error(no_from)]
 pub enum Error {
-    #[error(display = "Failed to run ping command")]
-    PingError(#[error(source)] io::Error),
+    /// Failed to open an ICMP socket
+    #[error(display = "Failed to open ICMP socket")]
+    OpenError(#[error(source)] io::Error),
+
+    /// Failed to bind an ICMP socket or a TCP probe socket to the tunnel interface
+    #[error(display = "Failed to bind socket to interface {}", _0)]
+    BindError(String, #[error(source)] io::Error),
+
+    /// Failed to read from the ICMP socket
+    #[error(display = "Failed to read from ICMP socket")]
+    ReadError(#[error(source)] io::Error),
+
+    /// Failed to write to the ICMP socket
+    #[error(display = "Failed to write to ICMP socket")]
+    WriteError(#[error(source)] io::Error),
 
     #[error(display = "Ping timed out")]
     TimeoutError,
+
+    /// Failed to start a TCP connectivity probe
+    #[error(display = "Failed to start TCP probe")]
+    ProbeError(#[error(source)] io::Error),
 }
 
-/// A pinger that sends ICMP requests without waiting for responses
+type Result<T> = std::result::Result<T, Error>;
+
+/// A pinger that sends ICMP echo requests through a socket bound to the tunnel interface and
+/// matches the echo replies it receives to measure round-trip times.
 pub struct Pinger {
-    addr: Ipv4Addr,
-    interface_name: String,
-    processes: Vec<duct::Handle>,
+    sock: Socket,
+    kind: SocketKind,
+    addr: IpAddr,
+    id: u16,
+    seq: u16,
+    outstanding_requests: HashMap<u16, Instant>,
+}
+
+/// The kind of ICMP socket that could be opened.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SocketKind {
+    /// Unprivileged ICMP socket. On Linux the kernel replaces the identifier with its own and
+    /// only delivers replies that belong to the socket.
+    Datagram,
+    /// Raw socket, requires elevated privileges and receives all ICMP traffic on the interface.
+    Raw,
 }
 
 impl Pinger {
-    pub fn new(addr: Ipv4Addr, interface_name: String) -> Result<Self, Error> {
+    pub fn new(addr: IpAddr, interface_name: String) -> Result<Self> {
+        let (sock, kind) = Self::open_socket(addr)?;
+        sock.set_nonblocking(true).map_err(Error::OpenError)?;
+        bind_to_interface(&sock, addr, &interface_name)?;
+
         Ok(Self {
-            processes: vec![],
+            sock,
+            kind,
             addr,
-            interface_name,
+            id: rand::random(),
+            seq: 0,
+            outstanding_requests: HashMap::new(),
         })
     }
 
+    /// Prefers a datagram ICMP socket and falls back to a raw socket if the platform or the
+    /// system configuration does not allow unprivileged ICMP sockets.
+    fn open_socket(addr: IpAddr) -> Result<(Socket, SocketKind)> {
+        let (domain, protocol) = match addr {
+            IpAddr::V4(_) => (Domain::ipv4(), Protocol::icmpv4()),
+            IpAddr::V6(_) => (Domain::ipv6(), Protocol::icmpv6()),
+        };
+        match Socket::new(domain, Type::dgram(), Some(protocol)) {
+            Ok(sock) => Ok((sock, SocketKind::Datagram)),
+            Err(dgram_error) => {
+                log::trace!(
+                    "Failed to open datagram ICMP socket, falling back to a raw socket: {}",
+                    dgram_error
+                );
+                Socket::new(domain, Type::raw(), Some(protocol))
+                    .map(|sock| (sock, SocketKind::Raw))
+                    .map_err(Error::OpenError)
+            }
+        }
+    }
+
+    /// Returns the next echo request packet
+    fn next_ping_request(&mut self) -> Vec<u8> {
+        use rand::Rng;
+
+        let seq = self.next_seq();
+        let mut packet = vec![0u8; ICMP_HEADER_LENGTH + ICMP_PAYLOAD_LENGTH];
+        packet[0] = match self.addr {
+            IpAddr::V4(_) => ICMPV4_ECHO_REQUEST,
+            IpAddr::V6(_) => ICMPV6_ECHO_REQUEST,
+        };
+        packet[4..6].copy_from_slice(&self.id.to_be_bytes());
+        packet[6..8].copy_from_slice(&seq.to_be_bytes());
+        rand::thread_rng().fill(&mut packet[ICMP_HEADER_LENGTH..]);
+
+        // The kernel computes the checksum for ICMPv6 since it depends on the IPv6 pseudo-header
+        if self.addr.is_ipv4() {
+            let checksum = internet_checksum(&packet);
+            packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+        }
+        packet
+    }
 
-    fn try_deplete_process_list(&mut self) {
-        self.processes.retain(|child| {
-            match child.try_wait() {
-                // child has terminated, doesn't have to be retained
-                Ok(Some(_)) => false,
-                _ => true,
+    fn next_seq(&mut self) -> u16 {
+        let seq = self.seq;
+        self.seq = self.seq.wrapping_add(1);
+        seq
+    }
+
+    /// Reads a single packet from the socket. Returns `None` if there is nothing more to read.
+    fn receive_packet(&self, buffer: &mut [u8]) -> Result<Option<(usize, SockAddr)>> {
+        match self.sock.recv_from(buffer) {
+            Ok(result) => Ok(Some(result)),
+            Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(ref error) if error.kind() == io::ErrorKind::Interrupted => Ok(None),
+            Err(error) => Err(Error::ReadError(error)),
+        }
+    }
+
+    /// Returns the sequence number of the packet if it's an echo reply to one of our requests.
+    fn parse_echo_reply(&self, packet: &[u8], source: &SockAddr) -> Option<u16> {
+        let source_ip = source
+            .as_inet()
+            .map(|addr| IpAddr::V4(*addr.ip()))
+            .or_else(|| source.as_inet6().map(|addr| IpAddr::V6(*addr.ip())));
+        if source_ip != Some(self.addr) {
+            return None;
+        }
+
+        let icmp = match self.addr {
+            // IPv4 sockets may return the IP header along with the ICMP packet
+            IpAddr::V4(_) if packet.first().map(|byte| byte >> 4) == Some(4) => {
+                let header_length = usize::from(packet[0] & 0x0f) * 4;
+                packet.get(header_length..)?
             }
-        });
+            _ => packet,
+        };
+        if icmp.len() < ICMP_HEADER_LENGTH {
+            return None;
+        }
+
+        let expected_type = match self.addr {
+            IpAddr::V4(_) => ICMPV4_ECHO_REPLY,
+            IpAddr::V6(_) => ICMPV6_ECHO_REPLY,
+        };
+        if icmp[0] != expected_type || icmp[1] != 0 {
+            return None;
+        }
+
+        let id = u16::from_be_bytes([icmp[4], icmp[5]]);
+        if self.kind == SocketKind::Raw && id != self.id {
+            return None;
+        }
+        Some(u16::from_be_bytes([icmp[6], icmp[7]]))
+    }
+
+    fn forget_stale_requests(&mut self, now: Instant) {
+        self.outstanding_requests
+            .retain(|_, sent| now.saturating_duration_since(*sent) < MAX_REPLY_WAIT);
     }
 }
 
 impl super::Pinger for Pinger {
-    // Send an ICMP packet without waiting for a reply
-    fn send_icmp(&mut self) -> Result<(), Error> {
-        self.try_deplete_process_list();
+    fn send_icmp(&mut self) -> Result<()> {
+        let destination = SockAddr::from(SocketAddr::new(self.addr, 0));
+        let request = self.next_ping_request();
+        let seq = u16::from_be_bytes([request[6], request[7]]);
 
-        let cmd = ping_cmd(self.addr, 1, &self.interface_name);
-        let handle = cmd.start().map_err(Error::PingError)?;
-        self.processes.push(handle);
+        let now = Instant::now();
+        self.forget_stale_requests(now);
+        self.sock
+            .send_to(&request, &destination)
+            .map_err(Error::WriteError)?;
+        self.outstanding_requests.insert(seq, now);
         Ok(())
     }
 
-    fn reset(&mut self) {
-        let processes = std::mem::replace(&mut self.processes, vec![]);
-        for proc in processes {
-            if proc
-                .try_wait()
-                .map(|maybe_stopped| maybe_stopped.is_none())
-                .unwrap_or(false)
-            {
-                if let Err(err) = proc.kill() {
-                    log::error!("Failed to kill ping process - {}", err);
+    fn receive_replies(&mut self) -> Result<Vec<Duration>> {
+        let mut round_trip_times = vec![];
+        let mut buffer = [0u8; 1500];
+        while let Some((length, source)) = self.receive_packet(&mut buffer)? {
+            let received = Instant::now();
+            if let Some(seq) = self.parse_echo_reply(&buffer[..length], &source) {
+                if let Some(sent) = self.outstanding_requests.remove(&seq) {
+                    round_trip_times.push(received.saturating_duration_since(sent));
                 }
             }
         }
+        Ok(round_trip_times)
     }
-}
 
-impl Drop for Pinger {
-    fn drop(&mut self) {
-        for child in self.processes.iter_mut() {
-            if let Err(e) = child.kill() {
-                log::error!("Failed to kill ping process - {}", e);
-            }
-        }
+    fn reset(&mut self) {
+        self.outstanding_requests.clear();
     }
 }
 
-
-fn ping_cmd(ip: Ipv4Addr, timeout_secs: u16, interface: &str) -> duct::Expression {
-    let mut args = vec!["-n", "-i", "1"];
-
-    let timeout_flag = if cfg!(target_os = "linux") || cfg!(target_os = "android") {
-        "-w"
-    } else {
-        "-t"
+/// Binds the socket to the interface, so that its packets can't leave through another one.
+#[cfg(target_os = "linux")]
+pub(super) fn bind_to_interface(sock: &Socket, _addr: IpAddr, interface_name: &str) -> Result<()> {
+    let c_name = CString::new(interface_name).map_err(|error| {
+        Error::BindError(
+            interface_name.to_owned(),
+            io::Error::new(io::ErrorKind::InvalidInput, error),
+        )
+    })?;
+    let name_bytes = c_name.as_bytes_with_nul();
+    let result = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            name_bytes.as_ptr() as *const libc::c_void,
+            name_bytes.len() as libc::socklen_t,
+        )
     };
-    let timeout_secs = timeout_secs.to_string();
-
-    args.extend_from_slice(&[timeout_flag, &timeout_secs]);
+    if result != 0 {
+        return Err(Error::BindError(
+            interface_name.to_owned(),
+            io::Error::last_os_error(),
+        ));
+    }
+    Ok(())
+}
 
-    let interface_flag = if cfg!(target_os = "linux") {
-        Some("-I")
-    } else if cfg!(target_os = "macos") {
-        Some("-b")
-    } else {
-        None
+/// Binds the socket to the interface, so that its packets can't leave through another one.
+#[cfg(target_os = "macos")]
+pub(super) fn bind_to_interface(sock: &Socket, addr: IpAddr, interface_name: &str) -> Result<()> {
+    let c_name = CString::new(interface_name).map_err(|error| {
+        Error::BindError(
+            interface_name.to_owned(),
+            io::Error::new(io::ErrorKind::InvalidInput, error),
+        )
+    })?;
+    let index = unsafe { libc::if_nametoindex(c_name.as_ptr()) };
+    if index == 0 {
+        return Err(Error::BindError(
+            interface_name.to_owned(),
+            io::Error::last_os_error(),
+        ));
+    }
+    let (level, option) = match addr {
+        IpAddr::V4(_) => (libc::IPPROTO_IP, IP_BOUND_IF),
+        IpAddr::V6(_) => (libc::IPPROTO_IPV6, IPV6_BOUND_IF),
+    };
+    let result = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            level,
+            option,
+            &index as *const libc::c_uint as *const libc::c_void,
+            std::mem::size_of::<libc::c_uint>() as libc::socklen_t,
+        )
     };
+    if result != 0 {
+        return Err(Error::BindError(
+            interface_name.to_owned(),
+            io::Error::last_os_error(),
+        ));
+    }
+    Ok(())
+}
+
+/// On Android all traffic is routed through the tunnel, so binding the socket is not needed.
+#[cfg(target_os = "android")]
+pub(super) fn bind_to_interface(
+    _sock: &Socket,
+    _addr: IpAddr,
+    _interface_name: &str,
+) -> Result<()> {
+    Ok(())
+}
 
-    if let Some(interface_flag) = interface_flag {
-        args.extend_from_slice(&[interface_flag, interface]);
+/// Computes the one's complement checksum used by ICMPv4.
+fn internet_checksum(data: &[u8]) -> u16 {
+    let mut sum = data.chunks(2).fold(0u32, |sum, chunk| {
+        let word = match chunk {
+            [high, low] => u16::from_be_bytes([*high, *low]),
+            [high] => u16::from_be_bytes([*high, 0]),
+            _ => unreachable!("chunks are at most 2 bytes long"),
+        };
+        sum + u32::from(word)
+    });
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
     }
+    !(sum as u16)
+}
 
-    let ip = ip.to_string();
-    args.push(&ip);
+#[cfg(test)]
+mod test {
+    use super::internet_checksum;
 
-    duct::cmd("ping", args)
-        .stdin_null()
-        .stdout_null()
-        .unchecked()
+    #[test]
+    fn test_checksum_of_echo_request() {
+        let mut packet = [8u8, 0, 0, 0, 0x12, 0x34, 0, 1, 0xab, 0xcd, 0xef];
+        let checksum = internet_checksum(&packet);
+        packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+        // A packet with a valid checksum sums up to zero
+        assert_eq!(internet_checksum(&packet), 0);
+    }
 }
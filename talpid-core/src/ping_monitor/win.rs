@@ -33,6 +33,14 @@ pub enum Error {
 
     #[error(display = "Timed out")]
     TimeoutError,
+
+    /// Failed to start a TCP connectivity probe
+    #[error(display = "Failed to start TCP probe")]
+    ProbeError(#[error(source)] io::Error),
+
+    /// Pinging IPv6 addresses is not supported
+    #[error(display = "Pinging IPv6 addresses is not supported")]
+    UnsupportedAddressError,
 }
 
 type Result<T> = std::result::Result<T, Error>;
@@ -46,7 +54,11 @@ pub struct Pinger {
 
 
 impl Pinger {
-    pub fn new(addr: Ipv4Addr, _interface_name: String) -> Result<Self> {
+    pub fn new(addr: IpAddr, _interface_name: String) -> Result<Self> {
+        let addr = match addr {
+            IpAddr::V4(addr) => addr,
+            IpAddr::V6(_) => return Err(Error::UnsupportedAddressError),
+        };
         let sock = Socket::new(Domain::ipv4(), Type::raw(), Some(Protocol::icmpv4()))
             .map_err(Error::OpenError)?;
         sock.set_nonblocking(true).map_err(Error::OpenError)?;
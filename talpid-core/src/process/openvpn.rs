@@ -8,6 +8,7 @@ use shell_escape;
 use std::{
     ffi::{OsStr, OsString},
     fmt, io,
+    net::SocketAddr,
     path::{Path, PathBuf},
 };
 use talpid_types::net;
@@ -57,6 +58,7 @@ static ALLOWED_TLS1_3_CIPHERS: &[&str] =
 pub struct OpenVpnCommand {
     openvpn_bin: OsString,
     config: Option<PathBuf>,
+    profile: Option<PathBuf>,
     remote: Option<net::Endpoint>,
     user_pass_path: Option<PathBuf>,
     proxy_auth_path: Option<PathBuf>,
@@ -64,6 +66,7 @@ pub struct OpenVpnCommand {
     crl: Option<PathBuf>,
     iproute_bin: Option<OsString>,
     plugin: Option<(PathBuf, Vec<String>)>,
+    management: Option<(SocketAddr, PathBuf)>,
     log: Option<PathBuf>,
     tunnel_options: net::openvpn::TunnelOptions,
     proxy_settings: Option<net::openvpn::ProxySettings>,
@@ -79,6 +82,7 @@ impl OpenVpnCommand {
         OpenVpnCommand {
             openvpn_bin: OsString::from(openvpn_bin.as_ref()),
             config: None,
+            profile: None,
             remote: None,
             user_pass_path: None,
             proxy_auth_path: None,
@@ -86,6 +90,7 @@ impl OpenVpnCommand {
             crl: None,
             iproute_bin: None,
             plugin: None,
+            management: None,
             log: None,
             tunnel_options: net::openvpn::TunnelOptions::default(),
             proxy_settings: None,
@@ -101,6 +106,13 @@ impl OpenVpnCommand {
         self
     }
 
+    /// Sets a user-supplied profile that will be given to OpenVPN. The TLS cipher restrictions
+    /// are passed after the profile, so they apply to profiles too.
+    pub fn profile(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.profile = Some(path.as_ref().to_path_buf());
+        self
+    }
+
     /// Sets the address and protocol that OpenVPN will connect to.
     pub fn remote(&mut self, remote: net::Endpoint) -> &mut Self {
         self.remote = Some(remote);
@@ -145,6 +157,17 @@ impl OpenVpnCommand {
         self
     }
 
+    /// Makes OpenVPN connect to a management client listening on `address`. The client has to
+    /// authenticate with the password stored in the file at `password_file`.
+    pub fn management(
+        &mut self,
+        address: SocketAddr,
+        password_file: impl AsRef<Path>,
+    ) -> &mut Self {
+        self.management = Some((address, password_file.as_ref().to_path_buf()));
+        self
+    }
+
     /// Sets a log file path.
     pub fn log(&mut self, path: impl AsRef<Path>) -> &mut Self {
         self.log = Some(path.as_ref().to_path_buf());
@@ -197,6 +220,10 @@ impl OpenVpnCommand {
             args.push(OsString::from("--config"));
             args.push(OsString::from(config.as_os_str()));
         }
+        if let Some(ref profile) = self.profile {
+            args.push(OsString::from("--config"));
+            args.push(OsString::from(profile.as_os_str()));
+        }
 
         args.extend(self.remote_arguments().iter().map(OsString::from));
         args.extend(self.authentication_arguments());
@@ -221,6 +248,14 @@ impl OpenVpnCommand {
             args.extend(plugin_args.iter().map(OsString::from));
         }
 
+        if let Some((address, ref password_file)) = self.management {
+            args.push(OsString::from("--management"));
+            args.push(OsString::from(address.ip().to_string()));
+            args.push(OsString::from(address.port().to_string()));
+            args.push(OsString::from(password_file));
+            args.push(OsString::from("--management-client"));
+        }
+
         if let Some(ref path) = self.log {
             args.push(OsString::from("--log"));
             args.push(OsString::from(path))
@@ -230,6 +265,7 @@ impl OpenVpnCommand {
             args.push(OsString::from("--mssfix"));
             args.push(OsString::from(mssfix.to_string()));
         }
+        args.extend(self.advanced_arguments().iter().map(OsString::from));
 
         if !self.enable_ipv6 {
             args.push(OsString::from("--pull-filter"));
@@ -271,6 +307,48 @@ impl OpenVpnCommand {
         args
     }
 
+    /// Returns the arguments for the optional tunnel options. They come after the base arguments,
+    /// so they replace any defaults set there.
+    fn advanced_arguments(&self) -> Vec<String> {
+        let options = &self.tunnel_options;
+        let mut args = vec![];
+        if !options.data_ciphers.is_empty() {
+            args.push("--ncp-ciphers".to_owned());
+            args.push(
+                options
+                    .data_ciphers
+                    .iter()
+                    .map(|cipher| cipher.as_str())
+                    .collect::<Vec<_>>()
+                    .join(":"),
+            );
+        }
+        if let Some(tun_mtu) = options.tun_mtu {
+            args.push("--tun-mtu".to_owned());
+            args.push(tun_mtu.to_string());
+        }
+        if let Some(fragment) = options.fragment {
+            match self.remote {
+                Some(net::Endpoint {
+                    protocol: net::TransportProtocol::Tcp,
+                    ..
+                }) => log::warn!("Ignoring the fragment option since it only works with UDP"),
+                _ => {
+                    args.push("--fragment".to_owned());
+                    args.push(fragment.to_string());
+                }
+            }
+        }
+        if let Some(reneg_sec) = options.reneg_sec {
+            args.push("--reneg-sec".to_owned());
+            args.push(reneg_sec.to_string());
+        }
+        for directive in &options.extra_directives {
+            args.extend(directive.to_arguments());
+        }
+        args
+    }
+
     fn remote_arguments(&self) -> Vec<String> {
         let mut args: Vec<String> = vec![];
         if let Some(ref endpoint) = self.remote {
@@ -420,7 +498,10 @@ impl StoppableProcess for OpenVpnProcHandle {
 mod tests {
     use super::OpenVpnCommand;
     use std::{ffi::OsString, net::Ipv4Addr};
-    use talpid_types::net::{Endpoint, TransportProtocol};
+    use talpid_types::net::{
+        openvpn::{DataCipher, ExtraDirective, TunnelOptions},
+        Endpoint, TransportProtocol,
+    };
 
     #[test]
     fn passes_one_remote() {
@@ -433,6 +514,71 @@ mod tests {
         assert!(testee_args.contains(&OsString::from("3333")));
     }
 
+    #[test]
+    fn passes_profile_with_tls_ciphers() {
+        let testee_args = OpenVpnCommand::new("")
+            .profile("./profile.conf")
+            .get_arguments();
+        let profile_index = testee_args
+            .iter()
+            .position(|arg| arg == "./profile.conf")
+            .unwrap();
+        let cipher_index = testee_args
+            .iter()
+            .position(|arg| arg == "--tls-cipher")
+            .unwrap();
+        assert!(profile_index < cipher_index);
+    }
+
+    #[test]
+    fn passes_advanced_options() {
+        let tunnel_options = TunnelOptions {
+            data_ciphers: vec![DataCipher::Chacha20Poly1305, DataCipher::Aes256Gcm],
+            tun_mtu: Some(1400),
+            fragment: Some(1300),
+            reneg_sec: Some(600),
+            extra_directives: vec![ExtraDirective::TcpNodelay, ExtraDirective::Sndbuf(393216)],
+            ..TunnelOptions::default()
+        };
+        let testee_args = OpenVpnCommand::new("")
+            .tunnel_options(&tunnel_options)
+            .get_arguments();
+
+        let advanced_args: Vec<OsString> = [
+            "--ncp-ciphers",
+            "CHACHA20-POLY1305:AES-256-GCM",
+            "--tun-mtu",
+            "1400",
+            "--fragment",
+            "1300",
+            "--reneg-sec",
+            "600",
+            "--tcp-nodelay",
+            "--sndbuf",
+            "393216",
+        ]
+        .iter()
+        .map(OsString::from)
+        .collect();
+        assert!(testee_args
+            .windows(advanced_args.len())
+            .any(|window| window == &advanced_args[..]));
+    }
+
+    #[test]
+    fn skips_fragment_with_tcp() {
+        let remote = Endpoint::new(Ipv4Addr::new(127, 0, 0, 1), 443, TransportProtocol::Tcp);
+        let tunnel_options = TunnelOptions {
+            fragment: Some(1300),
+            ..TunnelOptions::default()
+        };
+        let testee_args = OpenVpnCommand::new("")
+            .remote(remote)
+            .tunnel_options(&tunnel_options)
+            .get_arguments();
+        assert!(!testee_args.contains(&OsString::from("--fragment")));
+    }
+
     #[test]
     fn passes_plugin_path() {
         let path = "./a/path";
@@ -447,4 +593,24 @@ mod tests {
         assert!(testee_args.contains(&OsString::from("123")));
         assert!(testee_args.contains(&OsString::from("cde")));
     }
+
+    #[test]
+    fn passes_management_interface() {
+        let testee_args = OpenVpnCommand::new("")
+            .management("127.0.0.1:7505".parse().unwrap(), "./password")
+            .get_arguments();
+        let expected: Vec<OsString> = [
+            "--management",
+            "127.0.0.1",
+            "7505",
+            "./password",
+            "--management-client",
+        ]
+        .iter()
+        .map(OsString::from)
+        .collect();
+        assert!(testee_args
+            .windows(expected.len())
+            .any(|window| window == &expected[..]));
+    }
 }
@@ -1,5 +1,8 @@
 use crate::{
-    routing::{imp::RouteManagerCommand, NetNode, Node, RequiredRoute, Route},
+    routing::{
+        imp::{DefaultRouteEvent, RouteManagerCommand},
+        NetNode, Node, RequiredRoute, Route,
+    },
     split_tunnel,
 };
 
@@ -15,7 +18,11 @@ use std::{
     process::Command,
 };
 
-use futures::{channel::mpsc::UnboundedReceiver, future::FutureExt, StreamExt, TryStreamExt};
+use futures::{
+    channel::mpsc::{UnboundedReceiver, UnboundedSender},
+    future::FutureExt,
+    StreamExt, TryStreamExt,
+};
 
 
 use netlink_packet_route::{
@@ -108,6 +115,7 @@ pub struct RouteManagerImpl {
     default_routes: HashSet<Route>,
     best_default_node_v4: Option<Node>,
     best_default_node_v6: Option<Node>,
+    default_route_listeners: Vec<UnboundedSender<DefaultRouteEvent>>,
 
     split_table_id: i32,
 }
@@ -140,6 +148,7 @@ impl RouteManagerImpl {
             default_routes: HashSet::new(),
             best_default_node_v4: None,
             best_default_node_v6: None,
+            default_route_listeners: vec![],
 
             split_table_id,
         };
@@ -367,6 +376,45 @@ impl RouteManagerImpl {
         Ok(())
     }
 
+    async fn remove_required_routes(
+        &mut self,
+        required_routes: HashSet<RequiredRoute>,
+    ) -> Result<()> {
+        for required_route in required_routes {
+            let route = match required_route.node {
+                NetNode::RealNode(node) => {
+                    Route::new(node, required_route.prefix).table(required_route.table_id)
+                }
+                NetNode::DefaultNode => {
+                    self.required_default_routes.remove(&RequiredDefaultRoute {
+                        table_id: required_route.table_id,
+                        destination: required_route.prefix,
+                    });
+                    let best_node = if required_route.prefix.is_ipv4() {
+                        self.best_default_node_v4.clone()
+                    } else {
+                        self.best_default_node_v6.clone()
+                    };
+                    match best_node {
+                        Some(node) => {
+                            Route::new(node, required_route.prefix).table(required_route.table_id)
+                        }
+                        None => continue,
+                    }
+                }
+            };
+
+            if self.added_routes.remove(&route) {
+                if let Err(e) = self.delete_route(&route).await {
+                    if !is_missing_route_error(&e) {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
     async fn get_default_routes(&self) -> Result<HashSet<Route>> {
         let mut routes = self.get_default_routes_inner(IpVersion::V4).await?;
         routes.extend(self.get_default_routes_inner(IpVersion::V6).await?);
@@ -463,6 +511,7 @@ impl RouteManagerImpl {
                 }
             }
             self.best_default_node_v4 = Some(new_node);
+            self.notify_default_route_listeners(DefaultRouteEvent::Ipv4Changed);
         }
 
         let new_best_v6 = Self::pick_best_default_node(&self.default_routes, IpVersion::V6);
@@ -493,11 +542,17 @@ impl RouteManagerImpl {
                 }
             }
             self.best_default_node_v6 = Some(new_node);
+            self.notify_default_route_listeners(DefaultRouteEvent::Ipv6Changed);
         }
 
         Ok(())
     }
 
+    fn notify_default_route_listeners(&mut self, event: DefaultRouteEvent) {
+        self.default_route_listeners
+            .retain(|listener| listener.unbounded_send(event).is_ok());
+    }
+
     fn pick_best_default_node(routes: &HashSet<Route>, version: IpVersion) -> Option<Node> {
         // Pick the route with the lowest metric - thus the most favourable route.
         routes
@@ -535,30 +590,18 @@ impl RouteManagerImpl {
             let route =
                 Route::new(best_node, required_route.destination).table(required_route.table_id);
             if let Err(e) = self.delete_route(&route).await {
-                if let Error::NetlinkError(err) = &e {
-                    if let rtnetlink::ErrorKind::NetlinkError(msg) = err.get_ref().kind() {
-                        // -3 means that the route doesn't exist anymore anyway
-                        if msg.code == -3 {
-                            continue;
-                        }
-                    }
+                if !is_missing_route_error(&e) {
+                    log::error!("Failed to remove route - {} - {}", route, e);
                 }
-                log::error!("Failed to remove route - {} - {}", route, e);
             }
         }
         self.required_default_routes.clear();
 
         for route in self.added_routes.drain().collect::<Vec<_>>().iter() {
             if let Err(e) = self.delete_route(&route).await {
-                if let Error::NetlinkError(err) = &e {
-                    if let rtnetlink::ErrorKind::NetlinkError(msg) = err.get_ref().kind() {
-                        // -3 means that the route doesn't exist anymore anyway
-                        if msg.code == -3 {
-                            continue;
-                        }
-                    }
+                if !is_missing_route_error(&e) {
+                    log::error!("Failed to remove route - {} - {}", route, e);
                 }
-                log::error!("Failed to remove route - {} - {}", route, e);
             }
         }
     }
@@ -593,6 +636,10 @@ impl RouteManagerImpl {
                 log::debug!("Adding routes: {:?}", routes);
                 let _ = result_rx.send(self.add_required_routes(routes.clone()).await);
             }
+            RouteManagerCommand::RemoveRoutes(routes, result_rx) => {
+                log::debug!("Removing routes: {:?}", routes);
+                let _ = result_rx.send(self.remove_required_routes(routes).await);
+            }
             RouteManagerCommand::EnableExclusionsRoutes(result_rx) => {
                 let _ = result_rx.send(self.enable_exclusions_routes().await);
             }
@@ -607,6 +654,12 @@ impl RouteManagerImpl {
                 log::debug!("Clearing routes");
                 self.cleanup_routes().await;
             }
+            RouteManagerCommand::AddDefaultRouteListener(listener) => {
+                self.default_route_listeners.push(listener);
+            }
+            RouteManagerCommand::ClearDefaultRouteListeners => {
+                self.default_route_listeners.clear();
+            }
         }
         Ok(())
     }
@@ -873,6 +926,16 @@ impl Drop for RouteManagerImpl {
     }
 }
 
+/// Returns whether a route could not be deleted because it doesn't exist anymore (-3).
+fn is_missing_route_error(error: &Error) -> bool {
+    if let Error::NetlinkError(err) = error {
+        if let rtnetlink::ErrorKind::NetlinkError(msg) = err.get_ref().kind() {
+            return msg.code == -3;
+        }
+    }
+    false
+}
+
 fn ip_to_bytes(addr: IpAddr) -> Vec<u8> {
     match addr {
         IpAddr::V4(addr) => addr.octets().to_vec(),
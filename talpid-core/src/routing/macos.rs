@@ -101,6 +101,10 @@ impl RouteManagerImpl {
                             let result = self.add_required_routes(routes).await;
                             let _ = result_tx.send(result);
                         },
+                        Some(RouteManagerCommand::RemoveRoutes(routes, result_tx)) => {
+                            let result = self.remove_required_routes(routes).await;
+                            let _ = result_tx.send(result);
+                        },
                         Some(RouteManagerCommand::ClearRoutes) => {
                             self.cleanup_routes().await;
                         },
@@ -162,8 +166,29 @@ impl RouteManagerImpl {
             };
         }
 
-        self.default_destinations = default_destinations;
+        self.default_destinations.extend(default_destinations);
+
+        Ok(())
+    }
 
+    async fn remove_required_routes(
+        &mut self,
+        required_routes: HashSet<RequiredRoute>,
+    ) -> Result<()> {
+        for route in required_routes {
+            let destination = route.prefix;
+            let was_applied = match route.node {
+                NetNode::DefaultNode => self.default_destinations.remove(&destination),
+                NetNode::RealNode(node) => {
+                    self.applied_routes.remove(&Route::new(node, destination))
+                }
+            };
+            if was_applied {
+                self.applied_routes
+                    .retain(|applied_route| applied_route.prefix != destination);
+                Self::delete_route(destination).await?;
+            }
+        }
         Ok(())
     }
 
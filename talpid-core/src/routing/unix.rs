@@ -3,6 +3,8 @@
 // TODO: remove the allow(dead_code) for android once it's up to scratch.
 use super::RequiredRoute;
 
+#[cfg(target_os = "linux")]
+use futures::channel::mpsc::UnboundedReceiver;
 use futures::channel::{
     mpsc::{self, UnboundedSender},
     oneshot,
@@ -44,12 +46,27 @@ pub enum Error {
     RouteManagerDown,
 }
 
+/// Event emitted when the best default route changes.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultRouteEvent {
+    /// The best default IPv4 route now goes through a different node.
+    Ipv4Changed,
+    /// The best default IPv6 route now goes through a different node.
+    Ipv6Changed,
+}
+
 #[derive(Debug)]
 pub enum RouteManagerCommand {
     AddRoutes(
         HashSet<RequiredRoute>,
         oneshot::Sender<Result<(), PlatformError>>,
     ),
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    RemoveRoutes(
+        HashSet<RequiredRoute>,
+        oneshot::Sender<Result<(), PlatformError>>,
+    ),
     ClearRoutes,
     Shutdown(oneshot::Sender<()>),
     #[cfg(target_os = "linux")]
@@ -62,6 +79,10 @@ pub enum RouteManagerCommand {
         Vec<IpAddr>,
         oneshot::Sender<Result<(), PlatformError>>,
     ),
+    #[cfg(target_os = "linux")]
+    AddDefaultRouteListener(UnboundedSender<DefaultRouteEvent>),
+    #[cfg(target_os = "linux")]
+    ClearDefaultRouteListeners,
 }
 
 /// RouteManager applies a set of routes to the route table.
@@ -133,6 +154,34 @@ impl RouteManager {
         }
     }
 
+    /// Removes the given routes, which were previously applied in [`RouteManager::new`] or
+    /// [`RouteManager::add_routes`]. Other routes are left as they are.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub fn remove_routes(&mut self, routes: HashSet<RequiredRoute>) -> Result<(), Error> {
+        if let Some(tx) = &self.manage_tx {
+            let (result_tx, result_rx) = oneshot::channel();
+            if tx
+                .unbounded_send(RouteManagerCommand::RemoveRoutes(routes, result_tx))
+                .is_err()
+            {
+                return Err(Error::RouteManagerDown);
+            }
+
+            match self.runtime.block_on(result_rx) {
+                Ok(result) => result.map_err(Error::PlatformError),
+                Err(error) => {
+                    log::trace!(
+                        "{}",
+                        error.display_chain_with_msg("oneshot channel is closed")
+                    );
+                    Ok(())
+                }
+            }
+        } else {
+            Err(Error::RouteManagerDown)
+        }
+    }
+
     /// Removes all routes previously applied in [`RouteManager::new`] or
     /// [`RouteManager::add_routes`].
     pub fn clear_routes(&mut self) -> Result<(), Error> {
@@ -217,6 +266,43 @@ impl RouteManager {
             Err(Error::RouteManagerDown)
         }
     }
+
+    /// Returns a stream of events that are emitted whenever the best default route changes. The
+    /// listener is removed when the stream is dropped or
+    /// [`RouteManager::clear_default_route_listeners`] is called.
+    #[cfg(target_os = "linux")]
+    pub fn add_default_route_listener(
+        &mut self,
+    ) -> Result<UnboundedReceiver<DefaultRouteEvent>, Error> {
+        if let Some(tx) = &self.manage_tx {
+            let (event_tx, event_rx) = mpsc::unbounded();
+            if tx
+                .unbounded_send(RouteManagerCommand::AddDefaultRouteListener(event_tx))
+                .is_err()
+            {
+                return Err(Error::RouteManagerDown);
+            }
+            Ok(event_rx)
+        } else {
+            Err(Error::RouteManagerDown)
+        }
+    }
+
+    /// Removes all listeners added by [`RouteManager::add_default_route_listener`].
+    #[cfg(target_os = "linux")]
+    pub fn clear_default_route_listeners(&mut self) -> Result<(), Error> {
+        if let Some(tx) = &self.manage_tx {
+            if tx
+                .unbounded_send(RouteManagerCommand::ClearDefaultRouteListeners)
+                .is_err()
+            {
+                return Err(Error::RouteManagerDown);
+            }
+            Ok(())
+        } else {
+            Err(Error::RouteManagerDown)
+        }
+    }
 }
 
 impl Drop for RouteManager {
@@ -1,14 +1,12 @@
-use crate::{
-    ping_monitor::{new_pinger, Pinger},
-    tunnel::wireguard::stats::Stats,
-};
+use crate::ping_monitor::{new_probing_pinger, Pinger};
 use std::{
-    net::Ipv4Addr,
-    sync::{mpsc, Mutex, Weak},
+    net::IpAddr,
+    sync::{mpsc, Arc, Mutex},
     time::{Duration, Instant},
 };
+use talpid_types::net::wireguard::ConnectivityProbe;
 
-use super::{Tunnel, TunnelError};
+use super::{stats::Stats, wireguard::TunnelError};
 
 /// Sleep time used when initially establishing connectivity
 const DELAY_ON_INITIAL_SETUP: Duration = Duration::from_millis(50);
@@ -41,6 +39,13 @@ pub enum Error {
     PingError(#[error(source)] crate::ping_monitor::Error),
 }
 
+/// Provides the traffic counters of a running tunnel to the connectivity monitor.
+pub(crate) trait StatsSource: Send {
+    /// If None is returned, then the underlying tunnel has already been closed and all subsequent
+    /// calls will also return None.
+    fn get_stats(&self) -> Option<Result<Stats, Error>>;
+}
+
 
 /// Verifies if a connection to a tunnel is working.
 /// The connectivity monitor is biased to receiving traffic - it is expected that all outgoing
@@ -65,32 +70,37 @@ pub enum Error {
 /// Once a connection established, a connection is only considered broken once the connectivity
 /// monitor has started pinging and no traffic has been received for a duration of `PING_TIMEOUT`.
 pub struct ConnectivityMonitor {
-    tunnel_handle: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
+    stats_source: Box<dyn StatsSource>,
     conn_state: ConnState,
     initial_ping_timestamp: Option<Instant>,
     num_pings_sent: u32,
     pinger: Box<dyn Pinger>,
+    last_round_trip_time: Arc<Mutex<Option<Duration>>>,
     close_receiver: mpsc::Receiver<()>,
 }
 
 
 impl ConnectivityMonitor {
     pub(super) fn new(
-        addr: Ipv4Addr,
+        gateways: &[IpAddr],
         interface: String,
-        tunnel_handle: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
+        tunnel_addresses: &[IpAddr],
+        probes: &[ConnectivityProbe],
+        stats_source: Box<dyn StatsSource>,
         close_receiver: mpsc::Receiver<()>,
     ) -> Result<Self, Error> {
-        let pinger = new_pinger(addr, interface).map_err(Error::PingError)?;
+        let pinger = new_probing_pinger(gateways, interface, tunnel_addresses, probes)
+            .map_err(Error::PingError)?;
 
         let now = Instant::now();
 
         Ok(Self {
-            tunnel_handle,
+            stats_source,
             conn_state: ConnState::new(now, Default::default()),
             initial_ping_timestamp: None,
             num_pings_sent: 0,
             pinger,
+            last_round_trip_time: Arc::new(Mutex::new(None)),
             close_receiver,
         })
     }
@@ -151,14 +161,38 @@ impl ConnectivityMonitor {
         Ok(())
     }
 
+    /// Returns the round-trip time of the most recently answered ping, if any.
+    pub(super) fn last_round_trip_time(&self) -> Option<Duration> {
+        *self
+            .last_round_trip_time
+            .lock()
+            .expect("Round-trip time lock poisoned")
+    }
+
+    /// Returns a handle that the most recent round-trip time can be read through while the
+    /// monitor is running on another thread.
+    pub(super) fn round_trip_time_handle(&self) -> Arc<Mutex<Option<Duration>>> {
+        self.last_round_trip_time.clone()
+    }
+
+    /// Makes the monitor record round-trip times through a handle that was shared before the
+    /// monitor was created.
+    pub(super) fn set_round_trip_time_handle(&mut self, handle: Arc<Mutex<Option<Duration>>>) {
+        self.last_round_trip_time = handle;
+    }
+
     /// Returns true if connection is established
     fn check_connectivity(&mut self, now: Instant) -> Result<bool, Error> {
-        match self.get_stats() {
+        match self.stats_source.get_stats() {
             None => Ok(false),
             Some(new_stats) => {
                 let new_stats = new_stats?;
+                let received_replies = self.record_ping_replies()?;
 
-                if self.conn_state.update(now, new_stats) {
+                // Any traffic or a reply to any probe counts as proof of connectivity
+                if self.conn_state.update(now, new_stats)
+                    || (received_replies && self.conn_state.connected())
+                {
                     self.reset_pinger();
                     return Ok(true);
                 }
@@ -169,17 +203,6 @@ impl ConnectivityMonitor {
         }
     }
 
-    /// If None is returned, then the underlying tunnel has already been closed and all subsequent
-    /// calls will also return None.
-    fn get_stats(&self) -> Option<Result<Stats, Error>> {
-        self.tunnel_handle
-            .upgrade()?
-            .lock()
-            .ok()?
-            .as_ref()
-            .map(|tunnel| tunnel.get_tunnel_stats().map_err(Error::ConfigReadError))
-    }
-
     fn maybe_send_ping(&mut self, now: Instant) -> Result<(), Error> {
         // Only send out a ping if we haven't received a byte in a while or no traffic has flowed
         // in the last 2 minutes, but if a ping already has been sent out, only send one out every
@@ -201,6 +224,22 @@ impl ConnectivityMonitor {
         Ok(())
     }
 
+    /// Returns true if any replies were received since the last call
+    fn record_ping_replies(&mut self) -> Result<bool, Error> {
+        let round_trip_times = self.pinger.receive_replies().map_err(Error::PingError)?;
+        match round_trip_times.into_iter().last() {
+            Some(round_trip_time) => {
+                log::trace!("Ping round-trip time: {:?}", round_trip_time);
+                *self
+                    .last_round_trip_time
+                    .lock()
+                    .expect("Round-trip time lock poisoned") = Some(round_trip_time);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
     fn ping_timed_out(&self) -> bool {
         self.initial_ping_timestamp
             .map(|initial_ping_timestamp| initial_ping_timestamp.elapsed() > PING_TIMEOUT)
@@ -343,11 +382,14 @@ impl ConnState {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::tunnel::wireguard::{stats, TunnelError};
+    use crate::tunnel::{
+        stats,
+        wireguard::{Tunnel, TunnelError},
+    };
     use std::{
         sync::{
             atomic::{AtomicBool, Ordering},
-            Arc, Mutex,
+            Arc, Mutex, Weak,
         },
         time::{Duration, Instant},
     };
@@ -451,6 +493,7 @@ mod test {
     #[derive(Default)]
     struct MockPinger {
         on_send_ping: Option<Box<dyn FnMut() + Send>>,
+        replies: Vec<Duration>,
     }
 
     impl Pinger for MockPinger {
@@ -460,6 +503,10 @@ mod test {
             }
             Ok(())
         }
+
+        fn receive_replies(&mut self) -> Result<Vec<Duration>, crate::ping_monitor::Error> {
+            Ok(std::mem::replace(&mut self.replies, vec![]))
+        }
     }
 
     struct MockTunnel {
@@ -525,6 +572,18 @@ mod test {
         fn get_tunnel_stats(&self) -> Result<stats::Stats, TunnelError> {
             (self.on_get_stats)()
         }
+
+        fn set_key(
+            &mut self,
+            _private_key: &talpid_types::net::wireguard::PrivateKey,
+            _addresses: &[IpAddr],
+        ) -> Result<(), TunnelError> {
+            Ok(())
+        }
+
+        fn rebind(&mut self) -> Result<(), TunnelError> {
+            Ok(())
+        }
     }
 
     fn mock_monitor(
@@ -538,8 +597,9 @@ mod test {
             initial_ping_timestamp: None,
             num_pings_sent: 0,
             pinger,
+            last_round_trip_time: Arc::new(Mutex::new(None)),
             close_receiver,
-            tunnel_handle,
+            stats_source: Box::new(tunnel_handle),
         }
     }
 
@@ -604,6 +664,47 @@ mod test {
         assert!(monitor.check_connectivity(now).unwrap())
     }
 
+    #[test]
+    /// Verify that round-trip times reported by the pinger are recorded by the monitor.
+    fn test_records_round_trip_time() {
+        let (_tunnel_anchor, tunnel) = MockTunnel::always_incrementing().into_locked();
+        let (_tx, rx) = mpsc::channel();
+        let pinger = MockPinger {
+            replies: vec![Duration::from_millis(40), Duration::from_millis(25)],
+            ..MockPinger::default()
+        };
+        let now = Instant::now();
+        let start = now - Duration::from_secs(1);
+        let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, rx);
+
+        assert_eq!(monitor.last_round_trip_time(), None);
+        monitor.check_connectivity(now).unwrap();
+        assert_eq!(
+            monitor.last_round_trip_time(),
+            Some(Duration::from_millis(25))
+        );
+    }
+
+    #[test]
+    /// Verify that a reply to a probe keeps the connection alive even if the tunnel's traffic
+    /// counters haven't changed.
+    fn test_probe_reply_counts_as_connectivity() {
+        let (_tunnel_anchor, tunnel) = MockTunnel::never_incrementing().into_locked();
+        let (_tx, rx) = mpsc::channel();
+        let pinger = MockPinger {
+            replies: vec![Duration::from_millis(30)],
+            ..MockPinger::default()
+        };
+        let now = Instant::now();
+        let start = now - (BYTES_RX_TIMEOUT + PING_TIMEOUT + Duration::from_secs(10));
+        let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, rx);
+
+        monitor.conn_state = connected_state(start);
+        monitor.maybe_send_ping(start).unwrap();
+        assert!(monitor.check_connectivity(now).unwrap());
+        assert!(monitor.initial_ping_timestamp.is_none());
+    }
+
     #[test]
     /// Verify that the connectivity monitor doesn't fail if the tunnel constantly sends traffic,
     /// and it shuts down properly.
@@ -9,7 +9,11 @@ use std::{
 };
 #[cfg(not(target_os = "android"))]
 use talpid_types::net::openvpn as openvpn_types;
-use talpid_types::net::{wireguard as wireguard_types, TunnelParameters};
+use talpid_types::{
+    net::{wireguard as wireguard_types, TunnelParameters},
+    tunnel::TunnelStats,
+    ErrorExt,
+};
 
 #[cfg(target_os = "android")]
 pub use self::tun_provider::TunConfig;
@@ -21,6 +25,12 @@ pub mod openvpn;
 /// A module for all WireGuard related tunnel management.
 pub mod wireguard;
 
+/// Verifies that a tunnel is passing traffic.
+mod connectivity_check;
+
+/// Traffic counters of a tunnel.
+pub mod stats;
+
 /// A module for low level platform specific tunnel device management.
 pub(crate) mod tun_provider;
 
@@ -87,6 +97,8 @@ pub struct TunnelMetadata {
     pub ipv4_gateway: Ipv4Addr,
     /// The IP to the IPv6 default gateway on the tunnel interface.
     pub ipv6_gateway: Option<Ipv6Addr>,
+    /// The MTU of the tunnel interface, if known.
+    pub mtu: Option<u16>,
 }
 
 #[cfg(not(target_os = "android"))]
@@ -122,11 +134,13 @@ impl TunnelEvent {
                         .parse()
                         .expect("V6 Tunnel gateway IP not in valid format")
                 });
+                let mtu = env.get("tun_mtu").and_then(|mtu| mtu.parse().ok());
                 Some(TunnelEvent::Up(TunnelMetadata {
                     interface,
                     ips,
                     ipv4_gateway,
                     ipv6_gateway,
+                    mtu,
                 }))
             }
             openvpn_plugin::EventType::RoutePredown => Some(TunnelEvent::Down),
@@ -206,7 +220,16 @@ impl TunnelMonitor {
     where
         L: Fn(TunnelEvent) + Send + Sync + Clone + 'static,
     {
-        let config = wireguard::config::Config::from_parameters(&params)?;
+        let mut config = wireguard::config::Config::from_parameters(&params)?;
+        if params.options.mtu.is_none() && params.options.automatic_mtu {
+            match wireguard::mtu::discover_tunnel_mtu(params.connection.peer.endpoint) {
+                Ok(mtu) => config.mtu = mtu,
+                Err(error) => log::warn!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to discover the path MTU, using default")
+                ),
+            }
+        }
         let monitor = wireguard::WireguardMonitor::start(
             &config,
             log.as_ref().map(|p| p.as_path()),
@@ -332,6 +355,24 @@ impl CloseHandle {
             }
         }
     }
+
+    /// Returns the traffic counters and latency of the tunnel, if the tunnel reports them.
+    pub fn stats(&self) -> Option<TunnelStats> {
+        match self {
+            #[cfg(not(target_os = "android"))]
+            CloseHandle::OpenVpn(handle) => Some(handle.stats()),
+            CloseHandle::Wireguard(handle) => match handle.stats() {
+                Ok(stats) => Some(stats),
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to read tunnel stats")
+                    );
+                    None
+                }
+            },
+        }
+    }
 }
 
 enum InternalTunnelMonitor {
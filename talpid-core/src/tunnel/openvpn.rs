@@ -1,4 +1,8 @@
-use super::TunnelEvent;
+use super::{
+    connectivity_check::{self, ConnectivityMonitor, StatsSource},
+    stats::Stats,
+    TunnelEvent,
+};
 use crate::{
     mktemp,
     process::{
@@ -10,17 +14,18 @@ use crate::{
 use std::{
     collections::HashMap,
     fs,
-    io::{self, Write},
+    io::{self, BufRead, BufReader, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener},
     path::{Path, PathBuf},
     process::ExitStatus,
     sync::{
         atomic::{AtomicBool, Ordering},
-        mpsc, Arc,
+        mpsc, Arc, Mutex,
     },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
-use talpid_types::net::openvpn;
+use talpid_types::{net::openvpn, tunnel::TunnelStats, ErrorExt};
 use tokio02::task;
 #[cfg(target_os = "linux")]
 use which;
@@ -80,6 +85,14 @@ pub enum Error {
     #[error(display = "Error while writing credentials to temporary file")]
     CredentialsWriteError(#[error(source)] io::Error),
 
+    /// Failed to set up the management interface that traffic counters are read from.
+    #[error(display = "Unable to set up the OpenVPN management interface")]
+    ManagementInterfaceError(#[error(source)] io::Error),
+
+    /// The tunnel was closed by the connectivity monitor.
+    #[error(display = "The tunnel stopped passing traffic")]
+    ConnectivityLost,
+
     /// Failures related to the proxy service.
     #[error(display = "Unable to start the proxy service")]
     StartProxyError(#[error(source)] io::Error),
@@ -120,6 +133,18 @@ const OPENVPN_BIN_FILENAME: &str = "openvpn";
 #[cfg(windows)]
 const OPENVPN_BIN_FILENAME: &str = "openvpn.exe";
 
+/// How often to check whether OpenVPN has started reporting traffic counters.
+const TRAFFIC_STATS_WAIT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to wait for OpenVPN to connect to the management interface. OpenVPN connects to it
+/// before it starts connecting the tunnel.
+const MANAGEMENT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often OpenVPN reports the traffic counters, in seconds.
+const BYTECOUNT_INTERVAL: u32 = 1;
+
+const BYTECOUNT_PREFIX: &str = ">BYTECOUNT:";
+
 /// Struct for monitoring an OpenVPN process.
 #[derive(Debug)]
 pub struct OpenVpnMonitor<C: OpenVpnBuilder = OpenVpnCommand> {
@@ -131,6 +156,13 @@ pub struct OpenVpnMonitor<C: OpenVpnBuilder = OpenVpnCommand> {
     _user_pass_file: mktemp::TempFile,
     /// Keep the 'TempFile' for the proxy user-pass file in the struct, so it's removed on drop.
     _proxy_auth_file: Option<mktemp::TempFile>,
+    /// Keep the management interface password file in the struct, so it's removed on drop.
+    _management_password_file: Option<mktemp::TempFile>,
+    traffic_stats: Arc<Mutex<Stats>>,
+    round_trip_time: Arc<Mutex<Option<Duration>>>,
+    connectivity_lost: Arc<AtomicBool>,
+    /// Stops the connectivity monitor when dropped.
+    _connectivity_monitor_stop_tx: mpsc::Sender<()>,
 
     runtime: tokio02::runtime::Runtime,
     event_server_abort_tx: triggered::Trigger,
@@ -192,7 +224,10 @@ impl OpenVpnMonitor<OpenVpnCommand> {
 
         let proxy_monitor = Self::start_proxy(&params.proxy, &proxy_resources)?;
 
-        let cmd = Self::create_openvpn_cmd(
+        let management =
+            Self::create_management_interface().map_err(Error::ManagementInterfaceError)?;
+
+        let mut cmd = Self::create_openvpn_cmd(
             params,
             user_pass_file.as_ref(),
             match proxy_auth_file {
@@ -202,6 +237,11 @@ impl OpenVpnMonitor<OpenVpnCommand> {
             resource_dir,
             &proxy_monitor,
         )?;
+        cmd.management(management.address, &management.password_file);
+        log::debug!(
+            "Listening for the OpenVPN management interface on {}",
+            management.address
+        );
 
         let plugin_path = Self::get_plugin_path(resource_dir)?;
 
@@ -213,6 +253,7 @@ impl OpenVpnMonitor<OpenVpnCommand> {
             user_pass_file,
             proxy_auth_file,
             proxy_monitor,
+            Some(management),
         )
     }
 }
@@ -226,6 +267,7 @@ impl<C: OpenVpnBuilder + 'static> OpenVpnMonitor<C> {
         user_pass_file: mktemp::TempFile,
         proxy_auth_file: Option<mktemp::TempFile>,
         proxy_monitor: Option<Box<dyn ProxyMonitor>>,
+        management: Option<ManagementInterface>,
     ) -> Result<OpenVpnMonitor<C>>
     where
         L: Fn(openvpn_plugin::EventType, HashMap<String, String>) + Send + Sync + 'static,
@@ -246,6 +288,23 @@ impl<C: OpenVpnBuilder + 'static> OpenVpnMonitor<C> {
             .build()
             .map_err(Error::RuntimeError)?;
 
+        let (tunnel_up_tx, tunnel_up_rx) = mpsc::channel();
+        let tunnel_up_tx = Mutex::new(tunnel_up_tx);
+        let on_event = move |event: openvpn_plugin::EventType, env: HashMap<String, String>| {
+            if event == openvpn_plugin::EventType::RouteUp {
+                let gateway = env
+                    .get("route_vpn_gateway")
+                    .and_then(|gateway| gateway.parse::<IpAddr>().ok());
+                if let (Some(gateway), Some(interface)) = (gateway, env.get("dev")) {
+                    let _ = tunnel_up_tx
+                        .lock()
+                        .expect("Tunnel up sender lock poisoned")
+                        .send((gateway, interface.clone()));
+                }
+            }
+            on_event(event, env)
+        };
+
         let (start_tx, start_rx) = mpsc::channel();
         let server_join_handle = runtime.spawn(event_server::start(
             ipc_path.clone(),
@@ -267,13 +326,43 @@ impl<C: OpenVpnBuilder + 'static> OpenVpnMonitor<C> {
             .start()
             .map_err(|e| Error::ChildProcessError("Failed to start", e))?;
 
-        Ok(OpenVpnMonitor {
+        let traffic_stats = Arc::new(Mutex::new(Stats::default()));
+        let management_password_file = management.map(|management| {
+            spawn_traffic_stats_reader(
+                management.listener,
+                management.password,
+                traffic_stats.clone(),
+            );
+            management.password_file
+        });
+
+        let close_handle = OpenVpnCloseHandle {
             child: Arc::new(child),
+            closed: Arc::new(AtomicBool::new(false)),
+            traffic_stats,
+            round_trip_time: Arc::new(Mutex::new(None)),
+        };
+        let connectivity_lost = Arc::new(AtomicBool::new(false));
+        let (connectivity_monitor_stop_tx, connectivity_monitor_stop_rx) = mpsc::channel();
+        Self::spawn_connectivity_monitor(
+            tunnel_up_rx,
+            connectivity_monitor_stop_rx,
+            close_handle.clone(),
+            connectivity_lost.clone(),
+        );
+
+        Ok(OpenVpnMonitor {
+            child: close_handle.child,
             proxy_monitor,
             log_path,
-            closed: Arc::new(AtomicBool::new(false)),
+            closed: close_handle.closed,
             _user_pass_file: user_pass_file,
             _proxy_auth_file: proxy_auth_file,
+            _management_password_file: management_password_file,
+            traffic_stats: close_handle.traffic_stats,
+            round_trip_time: close_handle.round_trip_time,
+            connectivity_lost,
+            _connectivity_monitor_stop_tx: connectivity_monitor_stop_tx,
 
             runtime,
             event_server_abort_tx,
@@ -281,12 +370,94 @@ impl<C: OpenVpnBuilder + 'static> OpenVpnMonitor<C> {
         })
     }
 
+    /// Verifies that the tunnel passes traffic once it is up, the same way as is done for
+    /// WireGuard tunnels, and closes the tunnel if it stops doing so.
+    fn spawn_connectivity_monitor(
+        tunnel_up_rx: mpsc::Receiver<(IpAddr, String)>,
+        stop_rx: mpsc::Receiver<()>,
+        close_handle: OpenVpnCloseHandle<C::ProcessHandle>,
+        connectivity_lost: Arc<AtomicBool>,
+    ) {
+        thread::spawn(move || {
+            let (gateway, interface) = match tunnel_up_rx.recv() {
+                Ok(tunnel) => tunnel,
+                Err(_) => return,
+            };
+
+            // The traffic counters are only available once OpenVPN has connected to the management
+            // interface. Connectivity can't be verified without them.
+            let traffic_stats = close_handle.traffic_stats.clone();
+            while *traffic_stats.lock().expect("Traffic stats lock poisoned") == Stats::default() {
+                match stop_rx.recv_timeout(TRAFFIC_STATS_WAIT_INTERVAL) {
+                    Err(mpsc::RecvTimeoutError::Timeout) => (),
+                    Ok(()) | Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                }
+            }
+
+            let mut connectivity_monitor = match ConnectivityMonitor::new(
+                &[gateway],
+                interface,
+                &[],
+                &[],
+                Box::new(traffic_stats),
+                stop_rx,
+            ) {
+                Ok(connectivity_monitor) => connectivity_monitor,
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to start connectivity monitor")
+                    );
+                    return;
+                }
+            };
+            connectivity_monitor.set_round_trip_time_handle(close_handle.round_trip_time.clone());
+
+            match connectivity_monitor.establish_connectivity() {
+                Ok(true) => {
+                    if let Err(error) = connectivity_monitor.run() {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg("Connectivity monitor failed")
+                        );
+                    }
+                }
+                Ok(false) => log::warn!("Timeout while checking tunnel connection"),
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to check tunnel connection")
+                    );
+                }
+            }
+
+            connectivity_lost.store(true, Ordering::SeqCst);
+            if let Err(error) = close_handle.close() {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to close the OpenVPN tunnel")
+                );
+            }
+        });
+    }
+
+    /// Returns the number of bytes sent and received through the tunnel, as last reported by
+    /// OpenVPN.
+    pub fn traffic_stats(&self) -> Stats {
+        *self
+            .traffic_stats
+            .lock()
+            .expect("Traffic stats lock poisoned")
+    }
+
     /// Creates a handle to this monitor, allowing the tunnel to be closed while some other
     /// thread is blocked in `wait`.
     pub fn close_handle(&self) -> OpenVpnCloseHandle<C::ProcessHandle> {
         OpenVpnCloseHandle {
             child: self.child.clone(),
             closed: self.closed.clone(),
+            traffic_stats: self.traffic_stats.clone(),
+            round_trip_time: self.round_trip_time.clone(),
         }
     }
 
@@ -340,7 +511,17 @@ impl<C: OpenVpnBuilder + 'static> OpenVpnMonitor<C> {
     /// Supplement `inner_wait_tunnel()` with logging and error handling.
     fn wait_tunnel(&mut self) -> Result<()> {
         let result = self.inner_wait_tunnel();
+        let traffic_stats = self.traffic_stats();
+        log::debug!(
+            "OpenVPN tunnel sent {} bytes and received {} bytes",
+            traffic_stats.tx_bytes,
+            traffic_stats.rx_bytes
+        );
         match result {
+            WaitResult::Child(Ok(_), _) if self.connectivity_lost.load(Ordering::SeqCst) => {
+                log::error!("OpenVPN was stopped since the tunnel stopped passing traffic");
+                Err(Error::ConnectivityLost)
+            }
             WaitResult::Child(Ok(exit_status), closed) => {
                 if exit_status.success() || closed {
                     log::debug!(
@@ -444,6 +625,23 @@ impl<C: OpenVpnBuilder + 'static> OpenVpnMonitor<C> {
         Ok(None)
     }
 
+    fn create_management_interface() -> io::Result<ManagementInterface> {
+        // The listener is kept until OpenVPN has connected, so no other process can take the port
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let address = listener.local_addr()?;
+        let password = uuid::Uuid::new_v4().to_string();
+        let password_file = mktemp::TempFile::new();
+        let mut file = fs::File::create(&password_file)?;
+        Self::set_user_pass_file_permissions(&file)?;
+        write!(file, "{}\n", password)?;
+        Ok(ManagementInterface {
+            listener,
+            address,
+            password,
+            password_file,
+        })
+    }
+
     fn create_credentials_file(username: &str, password: &str) -> io::Result<mktemp::TempFile> {
         let temp_file = mktemp::TempFile::new();
         log::debug!("Writing credentials to {}", temp_file.as_ref().display());
@@ -484,16 +682,26 @@ impl<C: OpenVpnBuilder + 'static> OpenVpnMonitor<C> {
         proxy_monitor: &Option<Box<dyn ProxyMonitor>>,
     ) -> Result<OpenVpnCommand> {
         let mut cmd = OpenVpnCommand::new(Self::get_openvpn_bin(resource_dir)?);
-        if let Some(config) = Self::get_config_path(resource_dir) {
-            cmd.config(config);
+        match params.config.profile {
+            Some(ref profile) => {
+                cmd.profile(profile);
+            }
+            None => {
+                if let Some(config) = Self::get_config_path(resource_dir) {
+                    cmd.config(config);
+                }
+                cmd.ca(resource_dir.join("ca.crt"));
+            }
         }
         #[cfg(target_os = "linux")]
         cmd.iproute_bin(which::which("ip").map_err(Error::IpRouteNotFound)?);
+        // Profiles without `auth-user-pass` authenticate with certificates only.
+        if params.config.profile.is_none() || !params.config.username.is_empty() {
+            cmd.user_pass(user_pass_file);
+        }
         cmd.remote(params.config.endpoint)
-            .user_pass(user_pass_file)
             .tunnel_options(&params.options)
-            .enable_ipv6(params.generic_options.enable_ipv6)
-            .ca(resource_dir.join("ca.crt"));
+            .enable_ipv6(params.generic_options.enable_ipv6);
         #[cfg(windows)]
         cmd.tunnel_alias(Some(
             crate::winnet::get_tap_interface_alias().map_err(Error::WinnetError)?,
@@ -531,11 +739,93 @@ impl<C: OpenVpnBuilder + 'static> OpenVpnMonitor<C> {
     }
 }
 
+/// The management interface that the traffic counters of the tunnel are read from. OpenVPN
+/// connects to the listener, which is bound before OpenVPN is started.
+#[derive(Debug)]
+struct ManagementInterface {
+    listener: TcpListener,
+    address: SocketAddr,
+    password: String,
+    password_file: mktemp::TempFile,
+}
+
+/// Reads the traffic counters from the management interface until OpenVPN closes it. OpenVPN
+/// connects to `listener` on its own, since it is started with `--management-client`.
+fn spawn_traffic_stats_reader(
+    listener: TcpListener,
+    password: String,
+    traffic_stats: Arc<Mutex<Stats>>,
+) {
+    thread::spawn(move || {
+        if let Err(error) = read_traffic_stats(listener, &password, &traffic_stats) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Stopped reading traffic counters from OpenVPN")
+            );
+        }
+    });
+}
+
+fn read_traffic_stats(
+    listener: TcpListener,
+    password: &str,
+    traffic_stats: &Mutex<Stats>,
+) -> io::Result<()> {
+    // Don't wait forever if OpenVPN exits before connecting
+    listener.set_nonblocking(true)?;
+    let deadline = Instant::now() + MANAGEMENT_CONNECT_TIMEOUT;
+    let stream = loop {
+        match listener.accept() {
+            Ok((stream, _)) => break stream,
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                if Instant::now() >= deadline {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "OpenVPN did not connect to the management interface",
+                    ));
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+            Err(error) => return Err(error),
+        }
+    };
+    drop(listener);
+    stream.set_nonblocking(false)?;
+
+    write!(&stream, "{}\nbytecount {}\n", password, BYTECOUNT_INTERVAL)?;
+    for line in BufReader::new(stream).lines() {
+        if let Some(stats) = parse_bytecount(&line?) {
+            *traffic_stats.lock().expect("Traffic stats lock poisoned") = stats;
+        }
+    }
+    log::debug!("The OpenVPN management interface was closed");
+    Ok(())
+}
+
+/// Parses a `>BYTECOUNT:{bytes in},{bytes out}` notification.
+fn parse_bytecount(line: &str) -> Option<Stats> {
+    if !line.starts_with(BYTECOUNT_PREFIX) {
+        return None;
+    }
+    let mut counters = line[BYTECOUNT_PREFIX.len()..].trim_end().split(',');
+    let rx_bytes = counters.next()?.parse().ok()?;
+    let tx_bytes = counters.next()?.parse().ok()?;
+    Some(Stats { tx_bytes, rx_bytes })
+}
+
+impl StatsSource for Arc<Mutex<Stats>> {
+    fn get_stats(&self) -> Option<std::result::Result<Stats, connectivity_check::Error>> {
+        self.lock().ok().map(|stats| Ok(*stats))
+    }
+}
+
 /// A handle to an `OpenVpnMonitor` for closing it.
 #[derive(Debug, Clone)]
 pub struct OpenVpnCloseHandle<H: ProcessHandle = OpenVpnProcHandle> {
     child: Arc<H>,
     closed: Arc<AtomicBool>,
+    traffic_stats: Arc<Mutex<Stats>>,
+    round_trip_time: Arc<Mutex<Option<Duration>>>,
 }
 
 impl<H: ProcessHandle> OpenVpnCloseHandle<H> {
@@ -547,6 +837,23 @@ impl<H: ProcessHandle> OpenVpnCloseHandle<H> {
             Ok(())
         }
     }
+
+    /// Returns the traffic counters last reported by OpenVPN, and the round-trip time of the
+    /// most recently answered connectivity probe.
+    pub fn stats(&self) -> TunnelStats {
+        let traffic_stats = *self
+            .traffic_stats
+            .lock()
+            .expect("Traffic stats lock poisoned");
+        TunnelStats {
+            tx_bytes: traffic_stats.tx_bytes,
+            rx_bytes: traffic_stats.rx_bytes,
+            round_trip_time: *self
+                .round_trip_time
+                .lock()
+                .expect("Round-trip time lock poisoned"),
+        }
+    }
 }
 
 /// Internal enum to differentiate between if the child process or the event dispatcher died first.
@@ -799,6 +1106,7 @@ mod tests {
             TempFile::new(),
             None,
             None,
+            None,
         );
         assert_eq!(
             Some(PathBuf::from("./my_test_plugin")),
@@ -817,6 +1125,7 @@ mod tests {
             TempFile::new(),
             None,
             None,
+            None,
         );
         assert_eq!(
             Some(PathBuf::from("./my_test_log_file")),
@@ -828,9 +1137,17 @@ mod tests {
     fn exit_successfully() {
         let mut builder = TestOpenVpnBuilder::default();
         builder.process_handle = Some(TestProcessHandle(0));
-        let testee =
-            OpenVpnMonitor::new_internal(builder, |_, _| {}, "", None, TempFile::new(), None, None)
-                .unwrap();
+        let testee = OpenVpnMonitor::new_internal(
+            builder,
+            |_, _| {},
+            "",
+            None,
+            TempFile::new(),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
         assert!(testee.wait().is_ok());
     }
 
@@ -838,9 +1155,17 @@ mod tests {
     fn exit_error() {
         let mut builder = TestOpenVpnBuilder::default();
         builder.process_handle = Some(TestProcessHandle(1));
-        let testee =
-            OpenVpnMonitor::new_internal(builder, |_, _| {}, "", None, TempFile::new(), None, None)
-                .unwrap();
+        let testee = OpenVpnMonitor::new_internal(
+            builder,
+            |_, _| {},
+            "",
+            None,
+            TempFile::new(),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
         assert!(testee.wait().is_err());
     }
 
@@ -848,9 +1173,17 @@ mod tests {
     fn wait_closed() {
         let mut builder = TestOpenVpnBuilder::default();
         builder.process_handle = Some(TestProcessHandle(1));
-        let testee =
-            OpenVpnMonitor::new_internal(builder, |_, _| {}, "", None, TempFile::new(), None, None)
-                .unwrap();
+        let testee = OpenVpnMonitor::new_internal(
+            builder,
+            |_, _| {},
+            "",
+            None,
+            TempFile::new(),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
         testee.close_handle().close().unwrap();
         assert!(testee.wait().is_ok());
     }
@@ -858,12 +1191,59 @@ mod tests {
     #[test]
     fn failed_process_start() {
         let builder = TestOpenVpnBuilder::default();
-        let error =
-            OpenVpnMonitor::new_internal(builder, |_, _| {}, "", None, TempFile::new(), None, None)
-                .unwrap_err();
+        let error = OpenVpnMonitor::new_internal(
+            builder,
+            |_, _| {},
+            "",
+            None,
+            TempFile::new(),
+            None,
+            None,
+            None,
+        )
+        .unwrap_err();
         match error {
             Error::ChildProcessError(..) => (),
             _ => panic!("Wrong error"),
         }
     }
+
+    #[test]
+    fn parses_bytecount() {
+        let stats = parse_bytecount(">BYTECOUNT:5432,1234\r").unwrap();
+        assert_eq!(stats.rx_bytes, 5432);
+        assert_eq!(stats.tx_bytes, 1234);
+
+        assert!(parse_bytecount("SUCCESS: bytecount interval changed").is_none());
+        assert!(parse_bytecount(">BYTECOUNT:5432").is_none());
+    }
+
+    #[test]
+    fn reads_traffic_stats_from_management_client() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let traffic_stats = Arc::new(std::sync::Mutex::new(Stats::default()));
+        let reader_stats = traffic_stats.clone();
+        let reader = thread::spawn(move || {
+            read_traffic_stats(listener, "secret", &reader_stats).unwrap();
+        });
+
+        // Act as OpenVPN, which connects to the listener
+        let mut openvpn = std::net::TcpStream::connect(address).unwrap();
+        let mut commands = BufReader::new(openvpn.try_clone().unwrap()).lines();
+        assert_eq!(commands.next().unwrap().unwrap(), "secret");
+        assert_eq!(commands.next().unwrap().unwrap(), "bytecount 1");
+        write!(openvpn, ">BYTECOUNT:200,100\r\n").unwrap();
+        drop(commands);
+        drop(openvpn);
+
+        reader.join().unwrap();
+        assert_eq!(
+            *traffic_stats.lock().unwrap(),
+            Stats {
+                tx_bytes: 100,
+                rx_bytes: 200,
+            }
+        );
+    }
 }
@@ -14,6 +14,10 @@ pub enum Error {
     #[error(display = "Failed to set tunnel IP address: {}", _0)]
     SetIpAddr(IpAddr, #[cause] network_interface::Error),
 
+    /// Failure to remove a tunnel device IP address.
+    #[error(display = "Failed to remove tunnel IP address: {}", _0)]
+    RemoveIpAddr(IpAddr, #[cause] network_interface::Error),
+
     /// Failure to set the tunnel device as up.
     #[error(display = "Failed to set the tunnel device as up")]
     SetUp(#[cause] network_interface::Error),
@@ -52,6 +56,39 @@ impl UnixTun {
     pub fn interface_name(&self) -> &str {
         self.get_name()
     }
+
+    /// Replaces the IP addresses of the tunnel device. New addresses are added before stale ones
+    /// are removed. The device only has a single IPv4 address, so setting a new one replaces the
+    /// old one.
+    pub fn replace_addresses(
+        &mut self,
+        old_addresses: &[IpAddr],
+        new_addresses: &[IpAddr],
+    ) -> Result<(), Error> {
+        let added_addresses = new_addresses
+            .iter()
+            .filter(|ip| !old_addresses.contains(ip))
+            .collect::<Vec<_>>();
+        for ip in &added_addresses {
+            self.0
+                .set_ip(**ip)
+                .map_err(|cause| Error::SetIpAddr(**ip, cause))?;
+        }
+
+        let replaced_ipv4 = added_addresses.iter().any(|ip| ip.is_ipv4());
+        for ip in old_addresses
+            .iter()
+            .filter(|ip| !new_addresses.contains(ip))
+        {
+            if ip.is_ipv4() && replaced_ipv4 {
+                continue;
+            }
+            self.0
+                .remove_ip(*ip)
+                .map_err(|cause| Error::RemoveIpAddr(*ip, cause))?;
+        }
+        Ok(())
+    }
 }
 
 impl Deref for UnixTun {
@@ -17,6 +17,8 @@ pub struct Config {
     pub ipv6_gateway: Option<Ipv6Addr>,
     /// Maximum transmission unit for the tunnel
     pub mtu: u16,
+    /// Probes used to verify that the tunnel works
+    pub connectivity_probes: Vec<wireguard::ConnectivityProbe>,
 }
 
 const DEFAULT_MTU: u16 = 1380;
@@ -96,6 +98,7 @@ impl Config {
             ipv4_gateway: connection_config.ipv4_gateway,
             ipv6_gateway,
             mtu,
+            connectivity_probes: wg_options.connectivity_probes.clone(),
         })
     }
 
@@ -115,6 +118,15 @@ impl Config {
                 .add("public_key", peer.public_key.as_bytes().as_ref())
                 .add("endpoint", peer.endpoint.to_string().as_str())
                 .add("replace_allowed_ips", "true");
+            if let Some(psk) = &peer.psk {
+                wg_conf.add("preshared_key", psk.as_bytes().as_ref());
+            }
+            if let Some(interval) = peer.persistent_keepalive {
+                wg_conf.add(
+                    "persistent_keepalive_interval",
+                    interval.to_string().as_str(),
+                );
+            }
             for addr in &peer.allowed_ips {
                 wg_conf.add("allowed_ip", addr.to_string().as_str());
             }
@@ -125,6 +137,14 @@ impl Config {
     }
 }
 
+/// Returns a CString that only replaces the private key of a running WireGuard-go tunnel. Peers
+/// are kept as they are and will perform a new handshake using the new key.
+pub fn private_key_userspace_format(private_key: &wireguard::PrivateKey) -> CString {
+    let mut wg_conf = WgConfigBuffer::new();
+    wg_conf.add("private_key", private_key.to_bytes().as_ref());
+    CString::new(wg_conf.into_config()).expect("null bytes inside config")
+}
+
 enum ConfValue<'a> {
     String(&'a str),
     Bytes(&'a [u8]),
@@ -174,3 +194,18 @@ impl WgConfigBuffer {
         self.buf
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_private_key_update_only_sets_key() {
+        let private_key = wireguard::PrivateKey::from([1u8; 32]);
+        let config = private_key_userspace_format(&private_key);
+        assert_eq!(
+            config.to_str().unwrap(),
+            format!("private_key={}\n\n", hex::encode(private_key.to_bytes()))
+        );
+    }
+}
@@ -1,20 +1,22 @@
 use self::config::Config;
 #[cfg(not(windows))]
 use super::tun_provider;
-use super::{tun_provider::TunProvider, TunnelEvent, TunnelMetadata};
+use super::{connectivity_check, stats, tun_provider::TunProvider, TunnelEvent, TunnelMetadata};
 use crate::routing::{self, RequiredRoute};
 use std::{
     collections::HashSet,
+    net::IpAddr,
     path::Path,
-    sync::{mpsc, Arc, Mutex},
+    sync::{mpsc, Arc, Mutex, Weak},
+    time::Duration,
 };
-use talpid_types::ErrorExt;
+use talpid_types::{net::wireguard::PrivateKey, tunnel::TunnelStats, ErrorExt};
 
 /// WireGuard config data-types
 pub mod config;
-mod connectivity_check;
 mod logging;
-mod stats;
+/// Path MTU discovery
+pub mod mtu;
 mod wireguard_go;
 
 use self::wireguard_go::WgGoTunnel;
@@ -39,6 +41,10 @@ pub enum Error {
     /// Failed to setup connectivity monitor
     #[error(display = "Connectivity monitor failed")]
     ConnectivityMonitorError(#[error(source)] connectivity_check::Error),
+
+    /// The tunnel has already been stopped
+    #[error(display = "Tunnel is not running")]
+    TunnelNotRunningError,
 }
 
 
@@ -51,6 +57,8 @@ pub struct WireguardMonitor {
     close_msg_sender: mpsc::Sender<CloseMsg>,
     close_msg_receiver: mpsc::Receiver<CloseMsg>,
     pinger_stop_sender: mpsc::Sender<()>,
+    /// Round-trip time of the most recently answered connectivity probe
+    round_trip_time: Arc<Mutex<Option<Duration>>>,
 }
 
 impl WireguardMonitor {
@@ -80,27 +88,41 @@ impl WireguardMonitor {
         let event_callback = Box::new(on_event.clone());
         let (close_msg_sender, close_msg_receiver) = mpsc::channel();
         let (pinger_tx, pinger_rx) = mpsc::channel();
-        let monitor = WireguardMonitor {
-            tunnel: Arc::new(Mutex::new(Some(tunnel))),
-            event_callback,
-            close_msg_sender,
-            close_msg_receiver,
-            pinger_stop_sender: pinger_tx,
-        };
+        let tunnel: Arc<Mutex<Option<Box<dyn Tunnel>>>> = Arc::new(Mutex::new(Some(tunnel)));
 
         let metadata = Self::tunnel_metadata(&iface_name, &config);
-        let gateway = config.ipv4_gateway;
-        let close_sender = monitor.close_msg_sender.clone();
+        #[cfg_attr(windows, allow(unused_mut))]
+        let mut gateways = vec![IpAddr::from(config.ipv4_gateway)];
+        // The Windows pinger can't send ICMPv6 packets
+        #[cfg(not(windows))]
+        gateways.extend(config.ipv6_gateway.map(IpAddr::from));
+        let close_sender = close_msg_sender.clone();
+        let peer_addresses: Vec<IpAddr> =
+            config.peers.iter().map(|peer| peer.endpoint.ip()).collect();
         let mut connectivity_monitor = connectivity_check::ConnectivityMonitor::new(
-            gateway,
+            &gateways,
             iface_name,
-            Arc::downgrade(&monitor.tunnel),
+            &config.tunnel.addresses,
+            &config.connectivity_probes,
+            Box::new(Arc::downgrade(&tunnel)),
             pinger_rx,
         )?;
 
+        let monitor = WireguardMonitor {
+            tunnel,
+            event_callback,
+            close_msg_sender,
+            close_msg_receiver,
+            pinger_stop_sender: pinger_tx,
+            round_trip_time: connectivity_monitor.round_trip_time_handle(),
+        };
+
         std::thread::spawn(move || {
             match connectivity_monitor.establish_connectivity() {
                 Ok(true) => {
+                    if let Some(round_trip_time) = connectivity_monitor.last_round_trip_time() {
+                        log::debug!("Tunnel round-trip time: {:?}", round_trip_time);
+                    }
                     (on_event)(TunnelEvent::Up(metadata));
 
                     if let Err(error) = connectivity_monitor.run() {
@@ -109,13 +131,24 @@ impl WireguardMonitor {
                             error.display_chain_with_msg("Connectivity monitor failed")
                         );
                     }
+                    if let Some(round_trip_time) = connectivity_monitor.last_round_trip_time() {
+                        log::debug!("Last tunnel round-trip time: {:?}", round_trip_time);
+                    }
+                }
+                Ok(false) => {
+                    log::warn!("Timeout while checking tunnel connection");
+                    for peer in &peer_addresses {
+                        mtu::forget_discovered_mtus(*peer);
+                    }
                 }
-                Ok(false) => log::warn!("Timeout while checking tunnel connection"),
                 Err(error) => {
                     log::error!(
                         "{}",
                         error.display_chain_with_msg("Failed to check tunnel connection")
                     );
+                    for peer in &peer_addresses {
+                        mtu::forget_discovered_mtus(*peer);
+                    }
                 }
             }
 
@@ -129,6 +162,8 @@ impl WireguardMonitor {
     pub fn close_handle(&self) -> CloseHandle {
         CloseHandle {
             chan: self.close_msg_sender.clone(),
+            tunnel: Arc::downgrade(&self.tunnel),
+            round_trip_time: self.round_trip_time.clone(),
         }
     }
 
@@ -203,6 +238,7 @@ impl WireguardMonitor {
             ips: config.tunnel.addresses.clone(),
             ipv4_gateway: config.ipv4_gateway,
             ipv6_gateway: config.ipv6_gateway,
+            mtu: Some(config.mtu),
         }
     }
 }
@@ -213,9 +249,11 @@ enum CloseMsg {
 }
 
 /// Close handle for a WireGuard tunnel.
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct CloseHandle {
     chan: mpsc::Sender<CloseMsg>,
+    tunnel: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
+    round_trip_time: Arc<Mutex<Option<Duration>>>,
 }
 
 impl CloseHandle {
@@ -225,12 +263,79 @@ impl CloseHandle {
             log::trace!("Failed to send close message to wireguard tunnel - {}", e);
         }
     }
+
+    /// Replaces the private key and the interface addresses of the running tunnel, without
+    /// tearing it down.
+    pub fn set_key(&self, private_key: &PrivateKey, addresses: &[IpAddr]) -> Result<()> {
+        let tunnel = self.tunnel.upgrade().ok_or(Error::TunnelNotRunningError)?;
+        let mut tunnel = tunnel.lock().expect("Tunnel lock poisoned");
+        match tunnel.as_mut() {
+            Some(tunnel) => tunnel
+                .set_key(private_key, addresses)
+                .map_err(Error::TunnelError),
+            None => Err(Error::TunnelNotRunningError),
+        }
+    }
+
+    /// Rebinds the sockets of the running tunnel, so that traffic to the peer follows the
+    /// current default route.
+    pub fn rebind(&self) -> Result<()> {
+        let tunnel = self.tunnel.upgrade().ok_or(Error::TunnelNotRunningError)?;
+        let mut tunnel = tunnel.lock().expect("Tunnel lock poisoned");
+        match tunnel.as_mut() {
+            Some(tunnel) => tunnel.rebind().map_err(Error::TunnelError),
+            None => Err(Error::TunnelNotRunningError),
+        }
+    }
+
+    /// Returns the traffic counters of the running tunnel, and the round-trip time of the most
+    /// recently answered connectivity probe.
+    pub fn stats(&self) -> Result<TunnelStats> {
+        let tunnel = self.tunnel.upgrade().ok_or(Error::TunnelNotRunningError)?;
+        let tunnel = tunnel.lock().expect("Tunnel lock poisoned");
+        let stats = match tunnel.as_ref() {
+            Some(tunnel) => tunnel.get_tunnel_stats().map_err(Error::TunnelError)?,
+            None => return Err(Error::TunnelNotRunningError),
+        };
+        Ok(TunnelStats {
+            tx_bytes: stats.tx_bytes,
+            rx_bytes: stats.rx_bytes,
+            round_trip_time: *self
+                .round_trip_time
+                .lock()
+                .expect("Round-trip time lock poisoned"),
+        })
+    }
+}
+
+impl std::fmt::Debug for CloseHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CloseHandle")
+            .field("chan", &self.chan)
+            .finish()
+    }
 }
 
 pub(crate) trait Tunnel: Send {
     fn get_interface_name(&self) -> &str;
     fn stop(self: Box<Self>) -> std::result::Result<(), TunnelError>;
     fn get_tunnel_stats(&self) -> std::result::Result<stats::Stats, TunnelError>;
+    fn set_key(
+        &mut self,
+        private_key: &PrivateKey,
+        addresses: &[IpAddr],
+    ) -> std::result::Result<(), TunnelError>;
+    fn rebind(&mut self) -> std::result::Result<(), TunnelError>;
+}
+
+impl connectivity_check::StatsSource for Weak<Mutex<Option<Box<dyn Tunnel>>>> {
+    fn get_stats(&self) -> Option<std::result::Result<stats::Stats, connectivity_check::Error>> {
+        self.upgrade()?.lock().ok()?.as_ref().map(|tunnel| {
+            tunnel
+                .get_tunnel_stats()
+                .map_err(connectivity_check::Error::ConfigReadError)
+        })
+    }
 }
 
 /// Errors to be returned from WireGuard implementations, namely implementers of the Tunnel trait
@@ -267,6 +372,24 @@ pub enum TunnelError {
     #[error(display = "Failed to get config of WireGuard tunnel")]
     GetConfigError,
 
+    /// Error whilst trying to update the config of a running WireGuard tunnel
+    #[error(display = "Failed to update config of WireGuard tunnel")]
+    SetConfigError,
+
+    /// Error whilst trying to rebind the sockets of a running WireGuard tunnel
+    #[error(display = "Failed to rebind sockets of WireGuard tunnel")]
+    RebindError,
+
+    /// Failed to replace the IP addresses of the tunnel device.
+    #[cfg(all(unix, not(target_os = "android")))]
+    #[error(display = "Failed to replace IP addresses of the tunnel device")]
+    UpdateAddressesError(#[error(source)] tun_provider::Error),
+
+    /// The IP addresses of a running tunnel can't be changed on this platform.
+    #[cfg(any(target_os = "android", target_os = "windows"))]
+    #[error(display = "Changing the IP addresses of a running tunnel is not supported")]
+    AddressChangeNotSupported,
+
     /// Failed to duplicate tunnel file descriptor for wireguard-go
     #[cfg(any(target_os = "linux", target_os = "macos", target_os = "android"))]
     #[error(display = "Failed to duplicate tunnel file descriptor for wireguard-go")]
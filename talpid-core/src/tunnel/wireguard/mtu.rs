@@ -0,0 +1,279 @@
+//! Discovery of the largest MTU that a WireGuard tunnel can use without its packets being
+//! fragmented on the way to the relay.
+//!
+//! Discovery is only implemented on Linux. Other platforms use the default MTU. Discovered MTUs
+//! are only kept in memory, so they are discovered again after a restart.
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// The smallest MTU that a tunnel will be given. Links that carry IPv6 must support at least this
+/// MTU.
+pub const MIN_MTU: u16 = 1280;
+/// The largest MTU that a tunnel will be given. This is the default MTU of wireguard-go.
+pub const MAX_MTU: u16 = 1420;
+
+const IPV4_HEADER_SIZE: u16 = 20;
+const IPV6_HEADER_SIZE: u16 = 40;
+const UDP_HEADER_SIZE: u16 = 8;
+/// Size of the header and authentication tag that WireGuard adds to every data packet.
+const WIREGUARD_HEADER_SIZE: u16 = 32;
+/// How long a discovered MTU is used before the path is probed again. Routes can change without
+/// the local address changing, so the result can't be kept forever.
+const DISCOVERED_MTU_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+type MtuCache = HashMap<(IpAddr, IpAddr), (u16, Instant)>;
+
+lazy_static! {
+    /// Discovered tunnel MTUs and when they were discovered, keyed by the local address used to
+    /// reach the relay and the address of the relay. Networks are only told apart by the local
+    /// address, so two networks that hand out the same address share an entry until it expires.
+    static ref DISCOVERED_MTUS: Mutex<MtuCache> = Mutex::new(HashMap::new());
+}
+
+/// Errors that can happen while discovering the path MTU.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Failed to open a socket towards the relay.
+    #[error(display = "Failed to open socket towards the relay")]
+    OpenSocketError(#[error(source)] io::Error),
+
+    /// Failed to enable path MTU discovery on the socket.
+    #[error(display = "Failed to enable path MTU discovery")]
+    EnableDiscoveryError(#[error(source)] io::Error),
+
+    /// Failed to read the path MTU from the socket.
+    #[error(display = "Failed to read the path MTU")]
+    ReadMtuError(#[error(source)] io::Error),
+
+    /// Failed to send a probe to the relay.
+    #[error(display = "Failed to send path MTU probe")]
+    SendProbeError(#[error(source)] io::Error),
+
+    /// Path MTU discovery is not implemented for this platform.
+    #[cfg(not(target_os = "linux"))]
+    #[error(display = "Path MTU discovery is not supported on this platform")]
+    NotSupported,
+}
+
+/// Returns the largest tunnel MTU for which the encapsulated packets fit the path MTU to `peer`.
+/// The result is remembered per network for [`DISCOVERED_MTU_LIFETIME`], so the path is usually
+/// only probed the first time a relay is used from a network.
+pub fn discover_tunnel_mtu(peer: SocketAddr) -> Result<u16, Error> {
+    let socket = connect(peer).map_err(Error::OpenSocketError)?;
+    let local_ip = socket.local_addr().map_err(Error::OpenSocketError)?.ip();
+    let key = (local_ip, peer.ip());
+
+    let cached_mtu = get_cached_mtu(
+        &mut DISCOVERED_MTUS.lock().expect("MTU cache lock poisoned"),
+        &key,
+        Instant::now(),
+    );
+    if let Some(mtu) = cached_mtu {
+        return Ok(mtu);
+    }
+
+    let path_mtu = imp::discover_path_mtu(&socket, peer.ip())?;
+    let mtu = tunnel_mtu_for_path(path_mtu, peer.ip());
+    log::debug!(
+        "Path MTU to {} is {}, using tunnel MTU {}",
+        peer.ip(),
+        path_mtu,
+        mtu
+    );
+
+    DISCOVERED_MTUS
+        .lock()
+        .expect("MTU cache lock poisoned")
+        .insert(key, (mtu, Instant::now()));
+    Ok(mtu)
+}
+
+/// Forgets the MTUs discovered for `peer`, so that the path is probed again the next time the
+/// relay is used. Called when a tunnel fails to connect, since a stale MTU can be the cause.
+pub fn forget_discovered_mtus(peer: IpAddr) {
+    DISCOVERED_MTUS
+        .lock()
+        .expect("MTU cache lock poisoned")
+        .retain(|(_, cached_peer), _| *cached_peer != peer);
+}
+
+/// Returns the cached MTU for `key`, removing it if it has expired.
+fn get_cached_mtu(cache: &mut MtuCache, key: &(IpAddr, IpAddr), now: Instant) -> Option<u16> {
+    let (mtu, discovered_at) = *cache.get(key)?;
+    if now.saturating_duration_since(discovered_at) < DISCOVERED_MTU_LIFETIME {
+        Some(mtu)
+    } else {
+        cache.remove(key);
+        None
+    }
+}
+
+fn connect(peer: SocketAddr) -> io::Result<UdpSocket> {
+    let bind_addr: IpAddr = match peer {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(bind_addr, 0))?;
+    socket.connect(peer)?;
+    Ok(socket)
+}
+
+fn ip_header_size(peer: IpAddr) -> u16 {
+    match peer {
+        IpAddr::V4(_) => IPV4_HEADER_SIZE,
+        IpAddr::V6(_) => IPV6_HEADER_SIZE,
+    }
+}
+
+fn tunnel_mtu_for_path(path_mtu: u16, peer: IpAddr) -> u16 {
+    let overhead = ip_header_size(peer) + UDP_HEADER_SIZE + WIREGUARD_HEADER_SIZE;
+    path_mtu.saturating_sub(overhead).max(MIN_MTU).min(MAX_MTU)
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::{ip_header_size, Error, UDP_HEADER_SIZE};
+    use std::{
+        io,
+        net::{IpAddr, UdpSocket},
+        os::unix::io::AsRawFd,
+        thread,
+        time::Duration,
+    };
+
+    /// Number of probes to send before settling on a path MTU.
+    const MAX_PROBES: usize = 4;
+    /// Time to wait for an ICMP error after sending a probe.
+    const PROBE_TIMEOUT: Duration = Duration::from_millis(300);
+
+    /// Sends probes with the don't-fragment bit set, sized to the path MTU known by the kernel.
+    /// Routers that can't forward a probe reply with an ICMP error, which lowers the path MTU of
+    /// the socket. This is repeated until the path MTU stops changing. The probes are sent to the
+    /// WireGuard port of the relay, which silently drops them.
+    pub fn discover_path_mtu(socket: &UdpSocket, peer: IpAddr) -> Result<u16, Error> {
+        let (level, discover_option, mtu_option, discover_value) = match peer {
+            IpAddr::V4(_) => (
+                libc::IPPROTO_IP,
+                libc::IP_MTU_DISCOVER,
+                libc::IP_MTU,
+                libc::IP_PMTUDISC_DO,
+            ),
+            IpAddr::V6(_) => (
+                libc::IPPROTO_IPV6,
+                libc::IPV6_MTU_DISCOVER,
+                libc::IPV6_MTU,
+                libc::IPV6_PMTUDISC_DO,
+            ),
+        };
+        set_option(socket, level, discover_option, discover_value)
+            .map_err(Error::EnableDiscoveryError)?;
+
+        let header_size = ip_header_size(peer) + UDP_HEADER_SIZE;
+        let mut path_mtu = get_mtu(socket, level, mtu_option)?;
+        for _ in 0..MAX_PROBES {
+            let probe = vec![0u8; usize::from(path_mtu.saturating_sub(header_size))];
+            match socket.send(&probe) {
+                Ok(_) => thread::sleep(PROBE_TIMEOUT),
+                // The kernel already knows that the probe doesn't fit the path.
+                Err(error) if error.raw_os_error() == Some(libc::EMSGSIZE) => (),
+                Err(error) => return Err(Error::SendProbeError(error)),
+            }
+
+            let new_path_mtu = get_mtu(socket, level, mtu_option)?;
+            if new_path_mtu == path_mtu {
+                break;
+            }
+            path_mtu = new_path_mtu;
+        }
+        Ok(path_mtu)
+    }
+
+    fn get_mtu(socket: &UdpSocket, level: libc::c_int, option: libc::c_int) -> Result<u16, Error> {
+        let mut mtu: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                level,
+                option,
+                &mut mtu as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if result != 0 {
+            return Err(Error::ReadMtuError(io::Error::last_os_error()));
+        }
+        Ok(mtu.max(0).min(libc::c_int::from(u16::max_value())) as u16)
+    }
+
+    fn set_option(
+        socket: &UdpSocket,
+        level: libc::c_int,
+        option: libc::c_int,
+        value: libc::c_int,
+    ) -> io::Result<()> {
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                level,
+                option,
+                &value as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use super::Error;
+    use std::net::{IpAddr, UdpSocket};
+
+    pub fn discover_path_mtu(_socket: &UdpSocket, _peer: IpAddr) -> Result<u16, Error> {
+        Err(Error::NotSupported)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tunnel_mtu_for_path() {
+        let ipv4_peer: IpAddr = Ipv4Addr::new(10, 0, 0, 1).into();
+        let ipv6_peer: IpAddr = Ipv6Addr::LOCALHOST.into();
+
+        assert_eq!(tunnel_mtu_for_path(1500, ipv4_peer), MAX_MTU);
+        assert_eq!(tunnel_mtu_for_path(1428, ipv4_peer), 1368);
+        assert_eq!(tunnel_mtu_for_path(1420, ipv4_peer), 1360);
+        assert_eq!(tunnel_mtu_for_path(1420, ipv6_peer), 1340);
+        assert_eq!(tunnel_mtu_for_path(576, ipv4_peer), MIN_MTU);
+    }
+
+    #[test]
+    fn test_cached_mtu_expires() {
+        let key: (IpAddr, IpAddr) = (
+            Ipv4Addr::new(192, 168, 1, 2).into(),
+            Ipv4Addr::new(10, 0, 0, 1).into(),
+        );
+        let discovered_at = Instant::now();
+        let mut cache = MtuCache::new();
+        cache.insert(key, (1360, discovered_at));
+
+        assert_eq!(get_cached_mtu(&mut cache, &key, discovered_at), Some(1360));
+        let expired = discovered_at + DISCOVERED_MTU_LIFETIME;
+        assert_eq!(get_cached_mtu(&mut cache, &key, expired), None);
+        assert!(cache.is_empty());
+    }
+}
@@ -1,14 +1,20 @@
-use super::{stats::Stats, Config, Tunnel, TunnelError};
+use super::{
+    config::{self, Config},
+    Tunnel, TunnelError,
+};
 use crate::tunnel::{
+    stats::Stats,
     tun_provider::TunProvider,
     wireguard::logging::{clean_up_logging, initialize_logging, logging_callback, WgLogLevel},
 };
 use ipnetwork::IpNetwork;
 use std::{
     ffi::{c_void, CStr},
+    net::IpAddr,
     os::raw::c_char,
     path::Path,
 };
+use talpid_types::net::wireguard::PrivateKey;
 use zeroize::Zeroize;
 
 #[cfg(target_os = "windows")]
@@ -20,10 +26,7 @@ use crate::tunnel::tun_provider;
 #[cfg(not(target_os = "windows"))]
 use {
     crate::tunnel::tun_provider::{Tun, TunConfig},
-    std::{
-        net::IpAddr,
-        os::unix::io::{AsRawFd, RawFd},
-    },
+    std::os::unix::io::{AsRawFd, RawFd},
 };
 
 type Result<T> = std::result::Result<T, TunnelError>;
@@ -45,10 +48,12 @@ impl Drop for LoggingContext {
 pub struct WgGoTunnel {
     interface_name: String,
     handle: Option<i32>,
+    addresses: Vec<IpAddr>,
     // holding on to the tunnel device and the log file ensures that the associated file handles
     // live long enough and get closed when the tunnel is stopped
     #[cfg(not(target_os = "windows"))]
-    _tunnel_device: Tun,
+    #[cfg_attr(target_os = "android", allow(dead_code))]
+    tunnel_device: Tun,
     // context that maps to fs::File instance, used with logging callback
     _logging_context: LoggingContext,
 }
@@ -106,7 +111,8 @@ impl WgGoTunnel {
         Ok(WgGoTunnel {
             interface_name,
             handle: Some(handle),
-            _tunnel_device: tunnel_device,
+            addresses: config.tunnel.addresses.clone(),
+            tunnel_device,
             _logging_context: logging_context,
         })
     }
@@ -156,6 +162,7 @@ impl WgGoTunnel {
         Ok(WgGoTunnel {
             interface_name: iface_name.clone(),
             handle: Some(handle),
+            addresses: config.tunnel.addresses.clone(),
             _logging_context: logging_context,
         })
     }
@@ -235,6 +242,20 @@ impl WgGoTunnel {
         Ok(())
     }
 
+    #[cfg(all(unix, not(target_os = "android")))]
+    fn replace_addresses(&mut self, addresses: &[IpAddr]) -> Result<()> {
+        self.tunnel_device
+            .replace_addresses(&self.addresses, addresses)
+            .map_err(TunnelError::UpdateAddressesError)
+    }
+
+    // The addresses are part of the VPN service configuration on Android, and Windows lacks a
+    // way to remove them, so changing them requires a new tunnel.
+    #[cfg(any(target_os = "android", target_os = "windows"))]
+    fn replace_addresses(&mut self, _addresses: &[IpAddr]) -> Result<()> {
+        Err(TunnelError::AddressChangeNotSupported)
+    }
+
     fn stop_tunnel(&mut self) -> Result<()> {
         if let Some(handle) = self.handle.take() {
             let status = unsafe { wgTurnOff(handle) };
@@ -315,6 +336,40 @@ impl Tunnel for WgGoTunnel {
         result
     }
 
+    fn set_key(&mut self, private_key: &PrivateKey, addresses: &[IpAddr]) -> Result<()> {
+        let handle = self.handle.ok_or(TunnelError::SetConfigError)?;
+        if self.addresses != addresses {
+            self.replace_addresses(addresses)?;
+            self.addresses = addresses.to_vec();
+        }
+
+        let wg_config_str = config::private_key_userspace_format(private_key);
+        let status = unsafe { wgSetConfig(handle, wg_config_str.as_ptr() as *const i8) };
+        // Zeroing out config string to not leave private key in memory.
+        wg_config_str.into_bytes().zeroize();
+
+        if status < 0 {
+            return Err(TunnelError::SetConfigError);
+        }
+        Ok(())
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "windows")))]
+    fn rebind(&mut self) -> Result<()> {
+        let handle = self.handle.ok_or(TunnelError::RebindError)?;
+        if unsafe { wgRebindTunnelSocket(handle) } < 0 {
+            return Err(TunnelError::RebindError);
+        }
+        Ok(())
+    }
+
+    // Windows rebinds the sockets from the default route callback, and on Android the sockets
+    // follow the network the VPN service is bound to.
+    #[cfg(any(target_os = "android", target_os = "windows"))]
+    fn rebind(&mut self) -> Result<()> {
+        Ok(())
+    }
+
     fn stop(mut self: Box<Self>) -> Result<()> {
         self.stop_tunnel()
     }
@@ -367,6 +422,9 @@ extern "C" {
     // Pass a handle that was created by wgTurnOn to stop a wireguard tunnel.
     fn wgTurnOff(handle: i32) -> i32;
 
+    // Applies a partial configuration to a running tunnel. Negative return values signify errors.
+    fn wgSetConfig(handle: i32, settings: *const i8) -> i32;
+
     // Returns the file descriptor of the tunnel IPv4 socket.
     fn wgGetConfig(handle: i32) -> *mut std::os::raw::c_char;
 
@@ -384,4 +442,9 @@ extern "C" {
     // Rebind tunnel socket when network interfaces change
     #[cfg(target_os = "windows")]
     fn wgRebindTunnelSocket(family: u16, interfaceIndex: u32);
+
+    // Rebind the tunnel sockets after the default route has changed. Negative return values
+    // signify errors.
+    #[cfg(not(any(target_os = "android", target_os = "windows")))]
+    fn wgRebindTunnelSocket(handle: i32) -> i32;
 }
@@ -3,21 +3,39 @@ use super::{
     SharedTunnelStateValues, TunnelCommand, TunnelState, TunnelStateTransition, TunnelStateWrapper,
 };
 use crate::{
-    firewall::FirewallPolicy,
+    firewall::{self, FirewallPolicy},
     tunnel::{CloseHandle, TunnelEvent, TunnelMetadata},
 };
 use futures01::{
     sync::{mpsc, oneshot},
     Async, Future, Stream,
 };
+use std::net::IpAddr;
 use talpid_types::{
-    net::{Endpoint, TunnelParameters},
+    net::{
+        firewall::LanNetworks, wireguard, Endpoint, GenericTunnelOptions, TunnelEndpoint,
+        TunnelParameters,
+    },
     tunnel::ErrorStateCause,
     BoxedError, ErrorExt,
 };
 
+#[cfg(target_os = "linux")]
+use crate::dns::{
+    diagnostics::ConnectedDiagnostics,
+    stub_resolver::{StubResolver, Upstream, STUB_RESOLVER_ADDRESS},
+};
+#[cfg(target_os = "linux")]
+use crate::routing::DefaultRouteEvent;
 #[cfg(windows)]
 use crate::tunnel::TunnelMonitor;
+#[cfg(target_os = "linux")]
+use futures::{StreamExt, TryStreamExt};
+#[cfg(target_os = "linux")]
+use talpid_types::dns::DnsDiagnostics;
+
+#[cfg(target_os = "linux")]
+type DefaultRouteEvents = Box<dyn Stream<Item = DefaultRouteEvent, Error = ()> + Send>;
 
 
 pub struct ConnectedStateBootstrap {
@@ -35,6 +53,8 @@ pub struct ConnectedState {
     tunnel_parameters: TunnelParameters,
     tunnel_close_event: Option<oneshot::Receiver<Option<ErrorStateCause>>>,
     close_handle: Option<CloseHandle>,
+    #[cfg(target_os = "linux")]
+    default_route_events: Option<DefaultRouteEvents>,
 }
 
 impl ConnectedState {
@@ -45,6 +65,8 @@ impl ConnectedState {
             tunnel_parameters: bootstrap.tunnel_parameters,
             tunnel_close_event: bootstrap.tunnel_close_event,
             close_handle: bootstrap.close_handle,
+            #[cfg(target_os = "linux")]
+            default_route_events: None,
         }
     }
 
@@ -59,6 +81,11 @@ impl ConnectedState {
             peer_endpoint,
             tunnel: self.metadata.clone(),
             allow_lan: shared_values.allow_lan,
+            lan_networks: shared_values.lan_networks.clone(),
+            dns_servers: self.get_plain_dns_servers(shared_values),
+            #[cfg(target_os = "linux")]
+            split_dns_servers: split_dns_servers(self.tunnel_parameters.get_generic_options()),
+            allow_rules: shared_values.allow_rules.clone(),
             #[cfg(windows)]
             relay_client: TunnelMonitor::get_relay_client(
                 &shared_values.resource_dir,
@@ -78,17 +105,88 @@ impl ConnectedState {
         }
     }
 
+    /// Returns the endpoint of the tunnel, along with the MTU that the tunnel ended up using.
+    fn get_tunnel_endpoint(&self) -> TunnelEndpoint {
+        let mut tunnel_endpoint = self.tunnel_parameters.get_tunnel_endpoint();
+        if let Some(mtu) = self.metadata.mtu {
+            tunnel_endpoint.mtu = Some(mtu);
+        }
+        tunnel_endpoint
+    }
+
+    fn get_dns_servers(&self, shared_values: &SharedTunnelStateValues) -> Vec<IpAddr> {
+        dns_servers(
+            self.tunnel_parameters.get_generic_options(),
+            &self.metadata,
+            shared_values.allow_lan,
+            &shared_values.lan_networks,
+        )
+    }
+
+    fn is_blocked_lan_server(
+        &self,
+        server: &IpAddr,
+        shared_values: &SharedTunnelStateValues,
+    ) -> bool {
+        is_blocked_lan_server(
+            server,
+            &self.metadata,
+            shared_values.allow_lan,
+            &shared_values.lan_networks,
+        )
+    }
+
+    fn get_plain_dns_servers(&self, shared_values: &SharedTunnelStateValues) -> Vec<IpAddr> {
+        plain_dns_servers(
+            self.tunnel_parameters.get_generic_options(),
+            &self.metadata,
+            shared_values.allow_lan,
+            &shared_values.lan_networks,
+        )
+    }
+
     fn set_dns(&self, shared_values: &mut SharedTunnelStateValues) -> Result<(), BoxedError> {
-        let mut dns_ips = vec![self.metadata.ipv4_gateway.into()];
-        if let Some(ipv6_gateway) = self.metadata.ipv6_gateway {
-            dns_ips.push(ipv6_gateway.into());
-        };
+        let dns_ips = self.get_dns_servers(shared_values);
+        let custom_dns_servers = &self
+            .tunnel_parameters
+            .get_generic_options()
+            .custom_dns_servers;
+        for server in custom_dns_servers
+            .iter()
+            .filter(|server| self.is_blocked_lan_server(server, shared_values))
+        {
+            log::warn!(
+                "Not using DNS server {} since it is on the local network, and LAN access is \
+                 blocked",
+                server
+            );
+        }
+        if !custom_dns_servers.is_empty()
+            && !custom_dns_servers
+                .iter()
+                .any(|server| dns_ips.contains(server))
+        {
+            log::warn!("None of the custom DNS servers can be used, using the tunnel DNS instead");
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        let system_dns_ips = &dns_ips;
+        #[cfg(target_os = "linux")]
+        let system_dns_ips = &self.start_stub_resolver(shared_values, &dns_ips, false)?;
 
+        #[cfg(target_os = "linux")]
         shared_values
             .dns_monitor
-            .set(&self.metadata.interface, &dns_ips)
+            .force_backend(self.tunnel_parameters.get_generic_options().dns_backend);
+
+        shared_values
+            .dns_monitor
+            .set(&self.metadata.interface, system_dns_ips)
             .map_err(BoxedError::new)?;
 
+        #[cfg(target_os = "linux")]
+        self.set_split_dns(shared_values, &dns_ips)?;
+
         #[cfg(target_os = "linux")]
         shared_values
             .route_manager
@@ -98,20 +196,255 @@ impl ConnectedState {
         Ok(())
     }
 
+    /// Starts the local resolver if content blocking or encrypted DNS is enabled, or if `force`
+    /// is set. Queries are forwarded to the encrypted DNS servers, or to `dns_ips` if no
+    /// encrypted DNS servers are used or falling back to plain DNS is allowed. Queries matching a
+    /// split DNS rule are forwarded to the servers of the rule. Returns the DNS servers that the
+    /// system should use.
+    #[cfg(target_os = "linux")]
+    fn start_stub_resolver(
+        &self,
+        shared_values: &mut SharedTunnelStateValues,
+        dns_ips: &[IpAddr],
+        force: bool,
+    ) -> Result<Vec<IpAddr>, BoxedError> {
+        // Stop any running resolver first, since the new one binds the same address
+        shared_values.stub_resolver = None;
+
+        let generic_options = self.tunnel_parameters.get_generic_options();
+        let categories = generic_options.dns_blocking;
+        let encrypted_dns = &generic_options.encrypted_dns;
+        if !categories.any() && !encrypted_dns.is_enabled() && !force {
+            return Ok(dns_ips.to_vec());
+        }
+
+        if categories.any() {
+            log::info!("Blocking {} through the local DNS resolver", categories);
+        }
+        let mut upstream = Vec::new();
+        for server in &encrypted_dns.servers {
+            log::info!("Forwarding DNS queries to {}", server);
+            upstream.push(Upstream::Encrypted(server.clone()));
+        }
+        if !encrypted_dns.is_enabled() || encrypted_dns.allow_plaintext_fallback {
+            upstream.extend(dns_ips.iter().cloned().map(Upstream::Plain));
+        }
+
+        let stub_resolver = StubResolver::start(
+            shared_values.blocklists.clone(),
+            categories,
+            upstream,
+            generic_options.split_dns.clone(),
+            &self.metadata.ips,
+        )
+        .map_err(BoxedError::new)?;
+        shared_values.stub_resolver = Some(stub_resolver);
+        Ok(vec![IpAddr::from(STUB_RESOLVER_ADDRESS)])
+    }
+
+    /// Applies the split DNS rules. If the local resolver is running, it already handles them.
+    /// Otherwise they are applied through the system resolver, or by starting the local resolver
+    /// if the system resolver can't route queries per domain.
+    #[cfg(target_os = "linux")]
+    fn set_split_dns(
+        &self,
+        shared_values: &mut SharedTunnelStateValues,
+        dns_ips: &[IpAddr],
+    ) -> Result<(), BoxedError> {
+        let rules = &self.tunnel_parameters.get_generic_options().split_dns;
+        if rules.is_empty() || shared_values.stub_resolver.is_some() {
+            return Ok(());
+        }
+
+        match shared_values.dns_monitor.set_split_dns(rules) {
+            Ok(true) => return Ok(()),
+            Ok(false) => log::debug!("The system resolver can't route queries per domain"),
+            Err(error) => log::warn!(
+                "{}",
+                error.display_chain_with_msg(
+                    "Failed to apply split DNS rules through the system resolver"
+                )
+            ),
+        }
+        log::info!("Applying split DNS rules through the local DNS resolver");
+        let system_dns_ips = self.start_stub_resolver(shared_values, dns_ips, true)?;
+        shared_values
+            .dns_monitor
+            .set(&self.metadata.interface, &system_dns_ips)
+            .map_err(BoxedError::new)
+    }
+
+    /// Reads the DNS settings and sends test queries on a separate thread, so that the state
+    /// machine isn't blocked while waiting for answers.
+    #[cfg(target_os = "linux")]
+    fn diagnose_dns(
+        &self,
+        shared_values: &SharedTunnelStateValues,
+        result_tx: oneshot::Sender<DnsDiagnostics>,
+    ) {
+        let (expected_servers, upstream_servers) = if shared_values.stub_resolver.is_some() {
+            (
+                vec![IpAddr::from(STUB_RESOLVER_ADDRESS)],
+                self.get_plain_dns_servers(shared_values),
+            )
+        } else {
+            (self.get_dns_servers(shared_values), vec![])
+        };
+        let diagnostics = ConnectedDiagnostics::new(
+            &shared_values.dns_monitor,
+            expected_servers,
+            self.metadata.ips.clone(),
+            upstream_servers,
+        );
+        std::thread::spawn(move || {
+            let _ = result_tx.send(diagnostics.run());
+        });
+    }
+
     fn reset_dns(shared_values: &mut SharedTunnelStateValues) {
         if let Err(error) = shared_values.dns_monitor.reset() {
             log::error!("{}", error.display_chain_with_msg("Unable to reset DNS"));
         }
+        #[cfg(target_os = "linux")]
+        {
+            shared_values.stub_resolver = None;
+        }
     }
 
     fn reset_routes(shared_values: &mut SharedTunnelStateValues) {
         #[cfg(windows)]
         shared_values.route_manager.clear_default_route_callbacks();
+        #[cfg(target_os = "linux")]
+        {
+            if let Err(error) = shared_values.route_manager.clear_default_route_listeners() {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to clear default route listeners")
+                );
+            }
+        }
         if let Err(error) = shared_values.route_manager.clear_routes() {
             log::error!("{}", error.display_chain_with_msg("Failed to clear routes"));
         }
     }
 
+    /// Pushes a new key into the running WireGuard tunnel. The firewall policy covers both the
+    /// old and the new addresses while the interface is being updated, so that the addresses and
+    /// the policy never disagree.
+    fn set_wireguard_key(
+        mut self,
+        tunnel_config: wireguard::TunnelConfig,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> EventConsequence<Self> {
+        let enable_ipv6 = match &self.tunnel_parameters {
+            TunnelParameters::Wireguard(params) => params.generic_options.enable_ipv6,
+            _ => return EventConsequence::SameState(self),
+        };
+        let close_handle = match &self.close_handle {
+            Some(CloseHandle::Wireguard(close_handle)) => close_handle.clone(),
+            _ => return EventConsequence::SameState(self),
+        };
+        let addresses: Vec<IpAddr> = tunnel_config
+            .addresses
+            .iter()
+            .cloned()
+            .filter(|ip| ip.is_ipv4() || enable_ipv6)
+            .collect();
+
+        let old_addresses = self.metadata.ips.clone();
+        self.metadata.ips.extend(
+            addresses
+                .iter()
+                .cloned()
+                .filter(|ip| !old_addresses.contains(ip)),
+        );
+        if let Err(error) = self.set_firewall_policy(shared_values) {
+            return self.firewall_policy_failed(error, shared_values);
+        }
+
+        if let Err(error) = close_handle.set_key(&tunnel_config.private_key, &addresses) {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Failed to update WireGuard key, reconnecting")
+            );
+            return self.disconnect(shared_values, AfterDisconnect::Reconnect(0));
+        }
+        log::info!("Updated the WireGuard key of the tunnel");
+
+        self.metadata.ips = addresses;
+        if let TunnelParameters::Wireguard(params) = &mut self.tunnel_parameters {
+            params.connection.tunnel = tunnel_config;
+        }
+        match self.set_firewall_policy(shared_values) {
+            Ok(()) => EventConsequence::SameState(self),
+            Err(error) => self.firewall_policy_failed(error, shared_values),
+        }
+    }
+
+    /// A WireGuard tunnel can follow the default route by rebinding its sockets, so it doesn't
+    /// have to be reconnected when the default route changes.
+    #[cfg(target_os = "linux")]
+    fn listen_for_default_route_changes(
+        mut self,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> Self {
+        if let Some(CloseHandle::Wireguard(_)) = self.close_handle {
+            match shared_values.route_manager.add_default_route_listener() {
+                Ok(events) => {
+                    self.default_route_events = Some(Box::new(events.map(Ok::<_, ()>).compat()));
+                }
+                Err(error) => log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to listen for default route changes")
+                ),
+            }
+        }
+        self
+    }
+
+    /// Moves the WireGuard tunnel over to the new default route. The tunnel interface, routes and
+    /// DNS settings are kept, so open connections survive the switch.
+    #[cfg(target_os = "linux")]
+    fn roam(self, shared_values: &mut SharedTunnelStateValues) -> EventConsequence<Self> {
+        let close_handle = match &self.close_handle {
+            Some(CloseHandle::Wireguard(close_handle)) => close_handle.clone(),
+            _ => return EventConsequence::SameState(self),
+        };
+        if let Err(error) = close_handle.rebind() {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Failed to rebind WireGuard tunnel, reconnecting")
+            );
+            return self.disconnect(shared_values, AfterDisconnect::Reconnect(0));
+        }
+        // Refresh the peer rule so that it applies to the traffic on the new route.
+        if let Err(error) = self.set_firewall_policy(shared_values) {
+            return self.firewall_policy_failed(error, shared_values);
+        }
+        log::info!("Rebound the WireGuard tunnel to the new default route");
+
+        let tunnel_endpoint = self.get_tunnel_endpoint();
+        EventConsequence::NewState((
+            TunnelStateWrapper::from(self),
+            TunnelStateTransition::Roamed(tunnel_endpoint),
+        ))
+    }
+
+    fn firewall_policy_failed(
+        self,
+        error: crate::firewall::Error,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> EventConsequence<Self> {
+        log::error!(
+            "{}",
+            error.display_chain_with_msg("Failed to apply firewall policy for connected state")
+        );
+        self.disconnect(
+            shared_values,
+            AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError),
+        )
+    }
+
     fn disconnect(
         self,
         shared_values: &mut SharedTunnelStateValues,
@@ -134,27 +467,40 @@ impl ConnectedState {
         use self::EventConsequence::*;
 
         match try_handle_event!(self, commands.poll()) {
-            Ok(TunnelCommand::AllowLan(allow_lan)) => {
-                if let Err(error_cause) = shared_values.set_allow_lan(allow_lan) {
-                    self.disconnect(shared_values, AfterDisconnect::Block(error_cause))
+            Ok(TunnelCommand::AllowLan(allow_lan, lan_networks)) => {
+                let lan_routes = shared_values.lan_routes();
+                let dns_servers = self.get_dns_servers(shared_values);
+                if let Err(error_cause) = shared_values.set_allow_lan(allow_lan, lan_networks) {
+                    return self.disconnect(shared_values, AfterDisconnect::Block(error_cause));
+                }
+                if shared_values.lan_routes() != lan_routes {
+                    shared_values.update_lan_routes(lan_routes);
+                }
+                if let Err(error) = self.set_firewall_policy(shared_values) {
+                    self.firewall_policy_failed(error, shared_values)
+                } else if self.get_dns_servers(shared_values) == dns_servers {
+                    SameState(self)
                 } else {
-                    match self.set_firewall_policy(shared_values) {
+                    // Custom DNS servers on the local network were added or removed
+                    match self.set_dns(shared_values) {
                         Ok(()) => SameState(self),
                         Err(error) => {
-                            log::error!(
-                                "{}",
-                                error.display_chain_with_msg(
-                                    "Failed to apply firewall policy for connected state"
-                                )
-                            );
+                            log::error!("{}", error.display_chain_with_msg("Failed to set DNS"));
                             self.disconnect(
                                 shared_values,
-                                AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError),
+                                AfterDisconnect::Block(ErrorStateCause::SetDnsError),
                             )
                         }
                     }
                 }
             }
+            Ok(TunnelCommand::AllowRules(allow_rules)) => {
+                shared_values.allow_rules = allow_rules;
+                match self.set_firewall_policy(shared_values) {
+                    Ok(()) => SameState(self),
+                    Err(error) => self.firewall_policy_failed(error, shared_values),
+                }
+            }
             Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self)
@@ -179,6 +525,23 @@ impl ConnectedState {
             Ok(TunnelCommand::Block(reason)) => {
                 self.disconnect(shared_values, AfterDisconnect::Block(reason))
             }
+            Ok(TunnelCommand::SetWireguardKey(tunnel_config)) => {
+                self.set_wireguard_key(tunnel_config, shared_values)
+            }
+            #[cfg(target_os = "linux")]
+            Ok(TunnelCommand::DiagnoseDns(result_tx)) => {
+                self.diagnose_dns(shared_values, result_tx);
+                SameState(self)
+            }
+            Ok(TunnelCommand::GetTunnelStats(result_tx)) => {
+                let _ = result_tx.send(self.close_handle.as_ref().and_then(CloseHandle::stats));
+                SameState(self)
+            }
+            #[cfg(target_os = "linux")]
+            Ok(TunnelCommand::GetFirewallPolicy(result_tx)) => {
+                let _ = result_tx.send(shared_values.firewall.policy().cloned());
+                SameState(self)
+            }
         }
     }
 
@@ -223,6 +586,41 @@ impl ConnectedState {
         Self::reset_routes(shared_values);
         NewState(ConnectingState::enter(shared_values, 0))
     }
+
+    #[cfg(target_os = "linux")]
+    fn handle_default_route_events(
+        mut self,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> EventConsequence<Self> {
+        use self::EventConsequence::*;
+
+        let events = match &mut self.default_route_events {
+            Some(events) => events,
+            None => return NoEvents(self),
+        };
+        match events.poll() {
+            Ok(Async::Ready(Some(_))) => {
+                // A single rebind covers any changes that have queued up since.
+                while let Ok(Async::Ready(Some(_))) = events.poll() {}
+                self.roam(shared_values)
+            }
+            Ok(Async::NotReady) => NoEvents(self),
+            Ok(Async::Ready(None)) | Err(()) => {
+                log::warn!("Stopped listening for default route changes");
+                self.default_route_events = None;
+                SameState(self)
+            }
+        }
+    }
+
+    // Default route changes are only tracked on Linux.
+    #[cfg(not(target_os = "linux"))]
+    fn handle_default_route_events(
+        self,
+        _shared_values: &mut SharedTunnelStateValues,
+    ) -> EventConsequence<Self> {
+        EventConsequence::NoEvents(self)
+    }
 }
 
 impl TunnelState for ConnectedState {
@@ -234,7 +632,7 @@ impl TunnelState for ConnectedState {
         bootstrap: Self::Bootstrap,
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
         let connected_state = ConnectedState::from(bootstrap);
-        let tunnel_endpoint = connected_state.tunnel_parameters.get_tunnel_endpoint();
+        let tunnel_endpoint = connected_state.get_tunnel_endpoint();
 
         if let Err(error) = connected_state.set_firewall_policy(shared_values) {
             log::error!(
@@ -260,6 +658,8 @@ impl TunnelState for ConnectedState {
                 ),
             )
         } else {
+            #[cfg(target_os = "linux")]
+            let connected_state = connected_state.listen_for_default_route_changes(shared_values);
             (
                 TunnelStateWrapper::from(connected_state),
                 TunnelStateTransition::Connected(tunnel_endpoint),
@@ -275,5 +675,73 @@ impl TunnelState for ConnectedState {
         self.handle_commands(commands, shared_values)
             .or_else(Self::handle_tunnel_events, shared_values)
             .or_else(Self::handle_tunnel_close_event, shared_values)
+            .or_else(Self::handle_default_route_events, shared_values)
     }
 }
+
+/// Returns the custom DNS servers if any can be used, or otherwise the tunnel gateway(s).
+/// Custom servers on the local network can't be reached while LAN access is blocked, so they
+/// are left out.
+pub(super) fn dns_servers(
+    options: &GenericTunnelOptions,
+    tunnel: &TunnelMetadata,
+    allow_lan: bool,
+    lan_networks: &LanNetworks,
+) -> Vec<IpAddr> {
+    let custom_dns_servers: Vec<IpAddr> = options
+        .custom_dns_servers
+        .iter()
+        .filter(|server| !is_blocked_lan_server(server, tunnel, allow_lan, lan_networks))
+        .cloned()
+        .collect();
+    if !custom_dns_servers.is_empty() {
+        return custom_dns_servers;
+    }
+
+    let mut dns_ips = vec![tunnel.ipv4_gateway.into()];
+    if let Some(ipv6_gateway) = tunnel.ipv6_gateway {
+        dns_ips.push(ipv6_gateway.into());
+    };
+    dns_ips
+}
+
+/// Returns the DNS servers that may be sent unencrypted queries. These are all DNS servers,
+/// unless encrypted DNS is used without falling back to plain DNS.
+pub(super) fn plain_dns_servers(
+    options: &GenericTunnelOptions,
+    tunnel: &TunnelMetadata,
+    allow_lan: bool,
+    lan_networks: &LanNetworks,
+) -> Vec<IpAddr> {
+    #[cfg(target_os = "linux")]
+    {
+        let encrypted_dns = &options.encrypted_dns;
+        if encrypted_dns.is_enabled() && !encrypted_dns.allow_plaintext_fallback {
+            return vec![];
+        }
+    }
+    dns_servers(options, tunnel, allow_lan, lan_networks)
+}
+
+/// Returns the servers of all split DNS rules.
+#[cfg(target_os = "linux")]
+pub(super) fn split_dns_servers(options: &GenericTunnelOptions) -> Vec<IpAddr> {
+    options
+        .split_dns
+        .iter()
+        .flat_map(|rule| rule.servers.iter().cloned())
+        .collect()
+}
+
+/// Returns whether `server` is on the local network while LAN access is blocked. The tunnel
+/// gateways are always reached inside the tunnel.
+fn is_blocked_lan_server(
+    server: &IpAddr,
+    tunnel: &TunnelMetadata,
+    allow_lan: bool,
+    lan_networks: &LanNetworks,
+) -> bool {
+    let is_gateway = *server == IpAddr::from(tunnel.ipv4_gateway)
+        || tunnel.ipv6_gateway.map(IpAddr::from) == Some(*server);
+    !allow_lan && !is_gateway && firewall::is_local_address(server, lan_networks)
+}
@@ -3,6 +3,8 @@ use super::{
     EventConsequence, SharedTunnelStateValues, TunnelCommand, TunnelState, TunnelStateTransition,
     TunnelStateWrapper,
 };
+#[cfg(target_os = "linux")]
+use crate::dns::diagnostics;
 use crate::{
     firewall::FirewallPolicy,
     routing::RouteManager,
@@ -60,6 +62,8 @@ impl ConnectingState {
             peer_endpoint,
             pingable_hosts: gateway_list_from_params(params),
             allow_lan: shared_values.allow_lan,
+            lan_networks: shared_values.lan_networks.clone(),
+            allow_rules: shared_values.allow_rules.clone(),
             #[cfg(windows)]
             relay_client: TunnelMonitor::get_relay_client(&shared_values.resource_dir, &params),
         };
@@ -200,10 +204,14 @@ impl ConnectingState {
         use self::EventConsequence::*;
 
         match try_handle_event!(self, commands.poll()) {
-            Ok(TunnelCommand::AllowLan(allow_lan)) => {
-                if let Err(error_cause) = shared_values.set_allow_lan(allow_lan) {
+            Ok(TunnelCommand::AllowLan(allow_lan, lan_networks)) => {
+                let lan_routes = shared_values.lan_routes();
+                if let Err(error_cause) = shared_values.set_allow_lan(allow_lan, lan_networks) {
                     self.disconnect(shared_values, AfterDisconnect::Block(error_cause))
                 } else {
+                    if shared_values.lan_routes() != lan_routes {
+                        shared_values.update_lan_routes(lan_routes);
+                    }
                     match Self::set_firewall_policy(shared_values, &self.tunnel_parameters) {
                         Ok(()) => SameState(self),
                         Err(error) => {
@@ -222,6 +230,25 @@ impl ConnectingState {
                     }
                 }
             }
+            Ok(TunnelCommand::AllowRules(allow_rules)) => {
+                shared_values.allow_rules = allow_rules;
+                match Self::set_firewall_policy(shared_values, &self.tunnel_parameters) {
+                    Ok(()) => SameState(self),
+                    Err(error) => {
+                        error!(
+                            "{}",
+                            error.display_chain_with_msg(
+                                "Failed to apply firewall policy for connecting state"
+                            )
+                        );
+
+                        self.disconnect(
+                            shared_values,
+                            AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError),
+                        )
+                    }
+                }
+            }
             Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self)
@@ -246,6 +273,28 @@ impl ConnectingState {
             Ok(TunnelCommand::Block(reason)) => {
                 self.disconnect(shared_values, AfterDisconnect::Block(reason))
             }
+            Ok(TunnelCommand::SetWireguardKey(_)) => match self.tunnel_parameters {
+                // The tunnel isn't up yet, so start over with parameters that use the new key
+                TunnelParameters::Wireguard(_) => {
+                    let retry_attempt = self.retry_attempt;
+                    self.disconnect(shared_values, AfterDisconnect::Reconnect(retry_attempt))
+                }
+                _ => SameState(self),
+            },
+            #[cfg(target_os = "linux")]
+            Ok(TunnelCommand::DiagnoseDns(result_tx)) => {
+                let _ = result_tx.send(diagnostics::not_connected());
+                SameState(self)
+            }
+            Ok(TunnelCommand::GetTunnelStats(result_tx)) => {
+                let _ = result_tx.send(None);
+                SameState(self)
+            }
+            #[cfg(target_os = "linux")]
+            Ok(TunnelCommand::GetFirewallPolicy(result_tx)) => {
+                let _ = result_tx.send(shared_values.firewall.policy().cloned());
+                SameState(self)
+            }
         }
     }
 
@@ -389,6 +438,9 @@ impl TunnelState for ConnectingState {
                         retry_attempt,
                     ) {
                         Ok(connecting_state) => {
+                            #[cfg(any(target_os = "linux", target_os = "macos"))]
+                            shared_values.add_lan_routes();
+
                             let params = connecting_state.tunnel_parameters.clone();
                             (
                                 TunnelStateWrapper::from(connecting_state),
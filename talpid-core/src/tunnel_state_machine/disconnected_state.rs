@@ -2,6 +2,8 @@ use super::{
     ConnectingState, ErrorState, EventConsequence, SharedTunnelStateValues, TunnelCommand,
     TunnelState, TunnelStateTransition, TunnelStateWrapper,
 };
+#[cfg(target_os = "linux")]
+use crate::dns::diagnostics;
 use crate::firewall::FirewallPolicy;
 use futures01::{sync::mpsc, Stream};
 use talpid_types::ErrorExt;
@@ -14,6 +16,8 @@ impl DisconnectedState {
         let result = if shared_values.block_when_disconnected {
             let policy = FirewallPolicy::Blocked {
                 allow_lan: shared_values.allow_lan,
+                lan_networks: shared_values.lan_networks.clone(),
+                allow_rules: shared_values.allow_rules.clone(),
             };
             shared_values.firewall.apply_policy(policy).map_err(|e| {
                 e.display_chain_with_msg(
@@ -64,18 +68,27 @@ impl TunnelState for DisconnectedState {
         use self::EventConsequence::*;
 
         match try_handle_event!(self, commands.poll()) {
-            Ok(TunnelCommand::AllowLan(allow_lan)) => {
-                if shared_values.allow_lan != allow_lan {
+            Ok(TunnelCommand::AllowLan(allow_lan, lan_networks)) => {
+                if shared_values.allow_lan != allow_lan
+                    || shared_values.lan_networks != lan_networks
+                {
                     // The only platform that can fail is Android, but Android doesn't support the
                     // "block when disconnected" option, so the following call never fails.
                     shared_values
-                        .set_allow_lan(allow_lan)
+                        .set_allow_lan(allow_lan, lan_networks)
                         .expect("Failed to set allow LAN parameter");
 
                     Self::set_firewall_policy(shared_values);
                 }
                 SameState(self)
             }
+            Ok(TunnelCommand::AllowRules(allow_rules)) => {
+                if shared_values.allow_rules != allow_rules {
+                    shared_values.allow_rules = allow_rules;
+                    Self::set_firewall_policy(shared_values);
+                }
+                SameState(self)
+            }
             Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 if shared_values.block_when_disconnected != block_when_disconnected {
                     shared_values.block_when_disconnected = block_when_disconnected;
@@ -89,6 +102,20 @@ impl TunnelState for DisconnectedState {
             }
             Ok(TunnelCommand::Connect) => NewState(ConnectingState::enter(shared_values, 0)),
             Ok(TunnelCommand::Block(reason)) => NewState(ErrorState::enter(shared_values, reason)),
+            #[cfg(target_os = "linux")]
+            Ok(TunnelCommand::DiagnoseDns(result_tx)) => {
+                let _ = result_tx.send(diagnostics::not_connected());
+                SameState(self)
+            }
+            Ok(TunnelCommand::GetTunnelStats(result_tx)) => {
+                let _ = result_tx.send(None);
+                SameState(self)
+            }
+            #[cfg(target_os = "linux")]
+            Ok(TunnelCommand::GetFirewallPolicy(result_tx)) => {
+                let _ = result_tx.send(shared_values.firewall.policy().cloned());
+                SameState(self)
+            }
             Ok(_) => SameState(self),
             Err(_) => Finished,
         }
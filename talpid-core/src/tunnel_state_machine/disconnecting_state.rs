@@ -2,6 +2,8 @@ use super::{
     ConnectingState, DisconnectedState, ErrorState, EventConsequence, SharedTunnelStateValues,
     TunnelCommand, TunnelState, TunnelStateTransition, TunnelStateWrapper,
 };
+#[cfg(target_os = "linux")]
+use crate::dns::diagnostics;
 use crate::tunnel::CloseHandle;
 use futures01::{
     sync::{mpsc, oneshot},
@@ -31,8 +33,12 @@ impl DisconnectingState {
 
         self.after_disconnect = match after_disconnect {
             AfterDisconnect::Nothing => match event {
-                Ok(TunnelCommand::AllowLan(allow_lan)) => {
-                    let _ = shared_values.set_allow_lan(allow_lan);
+                Ok(TunnelCommand::AllowLan(allow_lan, lan_networks)) => {
+                    let _ = shared_values.set_allow_lan(allow_lan, lan_networks);
+                    AfterDisconnect::Nothing
+                }
+                Ok(TunnelCommand::AllowRules(allow_rules)) => {
+                    shared_values.allow_rules = allow_rules;
                     AfterDisconnect::Nothing
                 }
                 Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
@@ -45,11 +51,29 @@ impl DisconnectingState {
                 }
                 Ok(TunnelCommand::Connect) => AfterDisconnect::Reconnect(0),
                 Ok(TunnelCommand::Block(reason)) => AfterDisconnect::Block(reason),
+                #[cfg(target_os = "linux")]
+                Ok(TunnelCommand::DiagnoseDns(result_tx)) => {
+                    let _ = result_tx.send(diagnostics::not_connected());
+                    AfterDisconnect::Nothing
+                }
+                Ok(TunnelCommand::GetTunnelStats(result_tx)) => {
+                    let _ = result_tx.send(None);
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "linux")]
+                Ok(TunnelCommand::GetFirewallPolicy(result_tx)) => {
+                    let _ = result_tx.send(shared_values.firewall.policy().cloned());
+                    AfterDisconnect::Nothing
+                }
                 _ => AfterDisconnect::Nothing,
             },
             AfterDisconnect::Block(reason) => match event {
-                Ok(TunnelCommand::AllowLan(allow_lan)) => {
-                    let _ = shared_values.set_allow_lan(allow_lan);
+                Ok(TunnelCommand::AllowLan(allow_lan, lan_networks)) => {
+                    let _ = shared_values.set_allow_lan(allow_lan, lan_networks);
+                    AfterDisconnect::Block(reason)
+                }
+                Ok(TunnelCommand::AllowRules(allow_rules)) => {
+                    shared_values.allow_rules = allow_rules;
                     AfterDisconnect::Block(reason)
                 }
                 Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
@@ -67,11 +91,29 @@ impl DisconnectingState {
                 Ok(TunnelCommand::Connect) => AfterDisconnect::Reconnect(0),
                 Ok(TunnelCommand::Disconnect) => AfterDisconnect::Nothing,
                 Ok(TunnelCommand::Block(new_reason)) => AfterDisconnect::Block(new_reason),
-                Err(_) => AfterDisconnect::Block(reason),
+                Ok(TunnelCommand::SetWireguardKey(_)) | Err(_) => AfterDisconnect::Block(reason),
+                #[cfg(target_os = "linux")]
+                Ok(TunnelCommand::DiagnoseDns(result_tx)) => {
+                    let _ = result_tx.send(diagnostics::not_connected());
+                    AfterDisconnect::Block(reason)
+                }
+                Ok(TunnelCommand::GetTunnelStats(result_tx)) => {
+                    let _ = result_tx.send(None);
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "linux")]
+                Ok(TunnelCommand::GetFirewallPolicy(result_tx)) => {
+                    let _ = result_tx.send(shared_values.firewall.policy().cloned());
+                    AfterDisconnect::Block(reason)
+                }
             },
             AfterDisconnect::Reconnect(retry_attempt) => match event {
-                Ok(TunnelCommand::AllowLan(allow_lan)) => {
-                    let _ = shared_values.set_allow_lan(allow_lan);
+                Ok(TunnelCommand::AllowLan(allow_lan, lan_networks)) => {
+                    let _ = shared_values.set_allow_lan(allow_lan, lan_networks);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Ok(TunnelCommand::AllowRules(allow_rules)) => {
+                    shared_values.allow_rules = allow_rules;
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
                 Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
@@ -89,6 +131,21 @@ impl DisconnectingState {
                 Ok(TunnelCommand::Connect) => AfterDisconnect::Reconnect(retry_attempt),
                 Ok(TunnelCommand::Disconnect) | Err(_) => AfterDisconnect::Nothing,
                 Ok(TunnelCommand::Block(reason)) => AfterDisconnect::Block(reason),
+                Ok(TunnelCommand::SetWireguardKey(_)) => AfterDisconnect::Reconnect(retry_attempt),
+                #[cfg(target_os = "linux")]
+                Ok(TunnelCommand::DiagnoseDns(result_tx)) => {
+                    let _ = result_tx.send(diagnostics::not_connected());
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Ok(TunnelCommand::GetTunnelStats(result_tx)) => {
+                    let _ = result_tx.send(None);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "linux")]
+                Ok(TunnelCommand::GetFirewallPolicy(result_tx)) => {
+                    let _ = result_tx.send(shared_values.firewall.policy().cloned());
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
             },
         };
 
@@ -2,6 +2,8 @@ use super::{
     ConnectingState, DisconnectedState, EventConsequence, SharedTunnelStateValues, TunnelCommand,
     TunnelState, TunnelStateTransition, TunnelStateWrapper,
 };
+#[cfg(target_os = "linux")]
+use crate::dns::diagnostics;
 use crate::firewall::FirewallPolicy;
 use futures01::{sync::mpsc, Stream};
 use talpid_types::{
@@ -19,6 +21,8 @@ impl ErrorState {
     fn set_firewall_policy(shared_values: &mut SharedTunnelStateValues) -> bool {
         let policy = FirewallPolicy::Blocked {
             allow_lan: shared_values.allow_lan,
+            lan_networks: shared_values.lan_networks.clone(),
+            allow_rules: shared_values.allow_rules.clone(),
         };
 
         match shared_values.firewall.apply_policy(policy) {
@@ -80,14 +84,20 @@ impl TunnelState for ErrorState {
         use self::EventConsequence::*;
 
         match try_handle_event!(self, commands.poll()) {
-            Ok(TunnelCommand::AllowLan(allow_lan)) => {
-                if let Err(error_state_cause) = shared_values.set_allow_lan(allow_lan) {
+            Ok(TunnelCommand::AllowLan(allow_lan, lan_networks)) => {
+                if let Err(error_state_cause) = shared_values.set_allow_lan(allow_lan, lan_networks)
+                {
                     NewState(Self::enter(shared_values, error_state_cause))
                 } else {
                     Self::set_firewall_policy(shared_values);
                     SameState(self)
                 }
             }
+            Ok(TunnelCommand::AllowRules(allow_rules)) => {
+                shared_values.allow_rules = allow_rules;
+                Self::set_firewall_policy(shared_values);
+                SameState(self)
+            }
             Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self)
@@ -105,6 +115,21 @@ impl TunnelState for ErrorState {
                 NewState(DisconnectedState::enter(shared_values, ()))
             }
             Ok(TunnelCommand::Block(reason)) => NewState(ErrorState::enter(shared_values, reason)),
+            Ok(TunnelCommand::SetWireguardKey(_)) => SameState(self),
+            #[cfg(target_os = "linux")]
+            Ok(TunnelCommand::DiagnoseDns(result_tx)) => {
+                let _ = result_tx.send(diagnostics::not_connected());
+                SameState(self)
+            }
+            Ok(TunnelCommand::GetTunnelStats(result_tx)) => {
+                let _ = result_tx.send(None);
+                SameState(self)
+            }
+            #[cfg(target_os = "linux")]
+            Ok(TunnelCommand::GetFirewallPolicy(result_tx)) => {
+                let _ = result_tx.send(shared_values.firewall.policy().cloned());
+                SameState(self)
+            }
         }
     }
 }
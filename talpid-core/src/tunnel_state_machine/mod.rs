@@ -14,6 +14,10 @@ use self::{
     disconnecting_state::{AfterDisconnect, DisconnectingState},
     error_state::ErrorState,
 };
+#[cfg(target_os = "linux")]
+use crate::dns::stub_resolver::{Blocklists, StubResolver};
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use crate::routing::{NetNode, RequiredRoute};
 use crate::{
     dns::DnsMonitor,
     firewall::{Firewall, FirewallArguments},
@@ -22,11 +26,16 @@ use crate::{
     routing::RouteManager,
     tunnel::tun_provider::TunProvider,
 };
+#[cfg(target_os = "linux")]
+use crate::{firewall::FirewallPolicy, tunnel::TunnelMetadata};
 
 use futures01::{
     sync::{mpsc, oneshot},
     Async, Future, Poll, Stream,
 };
+use ipnetwork::IpNetwork;
+#[cfg(target_os = "linux")]
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::{
     collections::HashSet,
     io,
@@ -36,9 +45,16 @@ use std::{
 };
 #[cfg(target_os = "android")]
 use talpid_types::android::AndroidContext;
+#[cfg(target_os = "linux")]
+use talpid_types::net::{
+    firewall::FirewallPolicyKind, Endpoint, GenericTunnelOptions, TransportProtocol,
+};
 use talpid_types::{
-    net::TunnelParameters,
-    tunnel::{ErrorStateCause, ParameterGenerationError, TunnelStateTransition},
+    net::{
+        firewall::{AllowRule, LanNetworks},
+        wireguard, TunnelParameters,
+    },
+    tunnel::{ErrorStateCause, ParameterGenerationError, TunnelStateTransition, TunnelStats},
     ErrorExt,
 };
 use tokio_core::reactor::Core;
@@ -79,6 +95,8 @@ pub enum Error {
 /// Spawn the tunnel state machine thread, returning a channel for sending tunnel commands.
 pub fn spawn(
     allow_lan: bool,
+    lan_networks: LanNetworks,
+    allow_rules: Vec<AllowRule>,
     block_when_disconnected: bool,
     tunnel_parameters_generator: impl TunnelParametersGenerator,
     log_dir: Option<PathBuf>,
@@ -86,6 +104,7 @@ pub fn spawn(
     cache_dir: impl AsRef<Path> + Send + 'static,
     state_change_listener: impl Sender<TunnelStateTransition> + Send + 'static,
     shutdown_tx: oneshot::Sender<()>,
+    #[cfg(target_os = "linux")] blocklists: Blocklists,
     #[cfg(target_os = "android")] android_context: AndroidContext,
 ) -> Result<Arc<mpsc::UnboundedSender<TunnelCommand>>, Error> {
     let (command_tx, command_rx) = mpsc::unbounded();
@@ -109,6 +128,8 @@ pub fn spawn(
     thread::spawn(move || {
         match create_event_loop(
             allow_lan,
+            lan_networks,
+            allow_rules,
             block_when_disconnected,
             is_offline,
             tunnel_parameters_generator,
@@ -119,6 +140,8 @@ pub fn spawn(
             command_rx,
             state_change_listener,
             shutdown_tx,
+            #[cfg(target_os = "linux")]
+            blocklists,
         ) {
             Ok((mut reactor, event_loop)) => {
                 startup_result_tx.send(Ok(())).expect(
@@ -149,6 +172,8 @@ pub fn spawn(
 
 fn create_event_loop(
     allow_lan: bool,
+    lan_networks: LanNetworks,
+    allow_rules: Vec<AllowRule>,
     block_when_disconnected: bool,
     is_offline: bool,
     tunnel_parameters_generator: impl TunnelParametersGenerator,
@@ -159,10 +184,13 @@ fn create_event_loop(
     commands: mpsc::UnboundedReceiver<TunnelCommand>,
     state_change_listener: impl Sender<TunnelStateTransition>,
     shutdown_tx: oneshot::Sender<()>,
+    #[cfg(target_os = "linux")] blocklists: Blocklists,
 ) -> Result<(Core, impl Future<Item = (), Error = Error>), Error> {
     let reactor = Core::new().map_err(Error::ReactorError)?;
     let state_machine = TunnelStateMachine::new(
         allow_lan,
+        lan_networks,
+        allow_rules,
         block_when_disconnected,
         is_offline,
         tunnel_parameters_generator,
@@ -171,6 +199,8 @@ fn create_event_loop(
         resource_dir,
         cache_dir,
         commands,
+        #[cfg(target_os = "linux")]
+        blocklists,
     )?;
 
     let future = state_machine
@@ -191,8 +221,11 @@ fn create_event_loop(
 
 /// Representation of external commands for the tunnel state machine.
 pub enum TunnelCommand {
-    /// Enable or disable LAN access in the firewall.
-    AllowLan(bool),
+    /// Enable or disable LAN access in the firewall, and set the changes to the networks that
+    /// are considered to be LAN networks.
+    AllowLan(bool, LanNetworks),
+    /// Set the traffic that the firewall always allows.
+    AllowRules(Vec<AllowRule>),
     /// Enable or disable the block_when_disconnected feature.
     BlockWhenDisconnected(bool),
     /// Notify the state machine of the connectivity of the device.
@@ -203,6 +236,17 @@ pub enum TunnelCommand {
     Disconnect,
     /// Disconnect any open tunnel and block all network access
     Block(ErrorStateCause),
+    /// Replace the private key and addresses of a connected WireGuard tunnel without
+    /// reconnecting.
+    SetWireguardKey(wireguard::TunnelConfig),
+    /// Check the DNS settings and where DNS queries go. Only meaningful while connected.
+    #[cfg(target_os = "linux")]
+    DiagnoseDns(oneshot::Sender<talpid_types::dns::DnsDiagnostics>),
+    /// Get the traffic counters and latency of the tunnel. Only available while connected.
+    GetTunnelStats(oneshot::Sender<Option<TunnelStats>>),
+    /// Get the firewall policy that is currently applied, if any.
+    #[cfg(target_os = "linux")]
+    GetFirewallPolicy(oneshot::Sender<Option<FirewallPolicy>>),
 }
 
 /// Asynchronous handling of the tunnel state machine.
@@ -220,6 +264,8 @@ struct TunnelStateMachine {
 impl TunnelStateMachine {
     fn new(
         allow_lan: bool,
+        lan_networks: LanNetworks,
+        allow_rules: Vec<AllowRule>,
         block_when_disconnected: bool,
         is_offline: bool,
         tunnel_parameters_generator: impl TunnelParametersGenerator,
@@ -228,6 +274,7 @@ impl TunnelStateMachine {
         resource_dir: PathBuf,
         cache_dir: impl AsRef<Path>,
         commands: mpsc::UnboundedReceiver<TunnelCommand>,
+        #[cfg(target_os = "linux")] blocklists: Blocklists,
     ) -> Result<Self, Error> {
         let args = if block_when_disconnected {
             FirewallArguments {
@@ -250,12 +297,18 @@ impl TunnelStateMachine {
             dns_monitor,
             route_manager,
             allow_lan,
+            lan_networks,
+            allow_rules,
             block_when_disconnected,
             is_offline,
             tunnel_parameters_generator: Box::new(tunnel_parameters_generator),
             tun_provider,
             log_dir,
             resource_dir,
+            #[cfg(target_os = "linux")]
+            blocklists,
+            #[cfg(target_os = "linux")]
+            stub_resolver: None,
         };
 
         let (initial_state, _) = DisconnectedState::enter(&mut shared_values, ());
@@ -335,6 +388,10 @@ struct SharedTunnelStateValues {
     route_manager: RouteManager,
     /// Should LAN access be allowed outside the tunnel.
     allow_lan: bool,
+    /// Changes to the networks that LAN access applies to.
+    lan_networks: LanNetworks,
+    /// Traffic that the firewall allows in every state.
+    allow_rules: Vec<AllowRule>,
     /// Should network access be allowed when in the disconnected state.
     block_when_disconnected: bool,
     /// True when the computer is known to be offline.
@@ -347,10 +404,21 @@ struct SharedTunnelStateValues {
     log_dir: Option<PathBuf>,
     /// Resource directory path.
     resource_dir: PathBuf,
+    /// Domains blocked by the local resolver.
+    #[cfg(target_os = "linux")]
+    blocklists: Blocklists,
+    /// The local resolver, which runs while connected if content blocking is enabled.
+    #[cfg(target_os = "linux")]
+    stub_resolver: Option<StubResolver>,
 }
 
 impl SharedTunnelStateValues {
-    pub fn set_allow_lan(&mut self, allow_lan: bool) -> Result<(), ErrorStateCause> {
+    pub fn set_allow_lan(
+        &mut self,
+        allow_lan: bool,
+        lan_networks: LanNetworks,
+    ) -> Result<(), ErrorStateCause> {
+        self.lan_networks = lan_networks;
         if self.allow_lan != allow_lan {
             self.allow_lan = allow_lan;
 
@@ -371,6 +439,142 @@ impl SharedTunnelStateValues {
 
         Ok(())
     }
+
+    /// Returns the networks that are routed outside the tunnel because they have been added to
+    /// the LAN networks. The default LAN networks don't need routes, since they are either
+    /// directly connected or not reachable at all.
+    pub fn lan_routes(&self) -> Vec<IpNetwork> {
+        if self.allow_lan && cfg!(any(target_os = "linux", target_os = "macos")) {
+            self.lan_networks.added_networks()
+        } else {
+            vec![]
+        }
+    }
+
+    /// Routes the networks returned by `lan_routes` outside the tunnel. The routes are removed
+    /// together with the routes of the tunnel.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub fn add_lan_routes(&mut self) {
+        let routes = Self::lan_required_routes(self.lan_routes());
+        if routes.is_empty() {
+            return;
+        }
+        if let Err(error) = self.route_manager.add_routes(routes) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to route LAN networks outside the tunnel")
+            );
+        }
+    }
+
+    /// Replaces the LAN routes that were added for `previous_lan_routes` with the routes
+    /// currently returned by `lan_routes`, without touching the routes of the tunnel.
+    pub fn update_lan_routes(&mut self, previous_lan_routes: Vec<IpNetwork>) {
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        {
+            let lan_routes = self.lan_routes();
+            let stale_routes = Self::lan_required_routes(
+                previous_lan_routes
+                    .iter()
+                    .filter(|network| !lan_routes.contains(network))
+                    .cloned()
+                    .collect(),
+            );
+            let new_routes = Self::lan_required_routes(
+                lan_routes
+                    .into_iter()
+                    .filter(|network| !previous_lan_routes.contains(network))
+                    .collect(),
+            );
+
+            if !stale_routes.is_empty() {
+                if let Err(error) = self.route_manager.remove_routes(stale_routes) {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to remove routes for LAN networks")
+                    );
+                }
+            }
+            if !new_routes.is_empty() {
+                if let Err(error) = self.route_manager.add_routes(new_routes) {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg(
+                            "Failed to route LAN networks outside the tunnel"
+                        )
+                    );
+                }
+            }
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+        let _ = previous_lan_routes;
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn lan_required_routes(networks: Vec<IpNetwork>) -> HashSet<RequiredRoute> {
+        networks
+            .into_iter()
+            .map(|network| RequiredRoute::new(network, NetNode::DefaultNode))
+            .collect()
+    }
+}
+
+/// Returns the firewall policy of the given kind that the tunnel states would apply with these
+/// settings, for a made up relay and WireGuard tunnel. This shows what a policy looks like while
+/// no state that applies it is active.
+#[cfg(target_os = "linux")]
+pub fn sample_firewall_policy(
+    kind: FirewallPolicyKind,
+    allow_lan: bool,
+    lan_networks: LanNetworks,
+    allow_rules: Vec<AllowRule>,
+    options: &GenericTunnelOptions,
+) -> FirewallPolicy {
+    let peer_endpoint = Endpoint::new(Ipv4Addr::new(192, 0, 2, 1), 51820, TransportProtocol::Udp);
+    let mut tunnel = TunnelMetadata {
+        interface: "wg-mullvad".to_owned(),
+        ips: vec![Ipv4Addr::new(10, 64, 0, 2).into()],
+        ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+        ipv6_gateway: None,
+        mtu: None,
+    };
+    if options.enable_ipv6 {
+        tunnel
+            .ips
+            .push(Ipv6Addr::new(0xfc00, 0xbbbb, 0xbbbb, 0xbb01, 0, 0, 0, 2).into());
+        tunnel.ipv6_gateway = Some(Ipv6Addr::new(0xfc00, 0xbbbb, 0xbbbb, 0xbb01, 0, 0, 0, 1));
+    }
+
+    match kind {
+        FirewallPolicyKind::Connecting => FirewallPolicy::Connecting {
+            peer_endpoint,
+            pingable_hosts: std::iter::once(IpAddr::from(tunnel.ipv4_gateway))
+                .chain(tunnel.ipv6_gateway.map(IpAddr::from))
+                .collect(),
+            allow_lan,
+            lan_networks,
+            allow_rules,
+        },
+        FirewallPolicyKind::Connected => FirewallPolicy::Connected {
+            peer_endpoint,
+            dns_servers: connected_state::plain_dns_servers(
+                options,
+                &tunnel,
+                allow_lan,
+                &lan_networks,
+            ),
+            split_dns_servers: connected_state::split_dns_servers(options),
+            tunnel,
+            allow_lan,
+            lan_networks,
+            allow_rules,
+        },
+        FirewallPolicyKind::Blocked => FirewallPolicy::Blocked {
+            allow_lan,
+            lan_networks,
+            allow_rules,
+        },
+    }
 }
 
 /// Asynchronous result of an attempt to progress a state.
@@ -0,0 +1,371 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+};
+
+/// A category of domains that can be blocked by the local DNS resolver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockingCategory {
+    Ads,
+    Trackers,
+    Malware,
+}
+
+impl BlockingCategory {
+    pub const ALL: [BlockingCategory; 3] = [
+        BlockingCategory::Ads,
+        BlockingCategory::Trackers,
+        BlockingCategory::Malware,
+    ];
+
+    /// Returns the name of the category, as used in settings and blocklist file names.
+    pub fn name(self) -> &'static str {
+        match self {
+            BlockingCategory::Ads => "ads",
+            BlockingCategory::Trackers => "trackers",
+            BlockingCategory::Malware => "malware",
+        }
+    }
+}
+
+impl fmt::Display for BlockingCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The categories of domains that are blocked while connected. Blocking is done by a local DNS
+/// resolver, which is only used if at least one category is enabled.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BlockingCategories {
+    pub ads: bool,
+    pub trackers: bool,
+    pub malware: bool,
+}
+
+impl BlockingCategories {
+    /// Returns whether `category` is blocked.
+    pub fn contains(&self, category: BlockingCategory) -> bool {
+        match category {
+            BlockingCategory::Ads => self.ads,
+            BlockingCategory::Trackers => self.trackers,
+            BlockingCategory::Malware => self.malware,
+        }
+    }
+
+    /// Enables or disables blocking of `category`.
+    pub fn set(&mut self, category: BlockingCategory, enabled: bool) {
+        match category {
+            BlockingCategory::Ads => self.ads = enabled,
+            BlockingCategory::Trackers => self.trackers = enabled,
+            BlockingCategory::Malware => self.malware = enabled,
+        }
+    }
+
+    /// Returns whether any category is blocked.
+    pub fn any(&self) -> bool {
+        BlockingCategory::ALL
+            .iter()
+            .any(|category| self.contains(*category))
+    }
+}
+
+impl fmt::Display for BlockingCategories {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let categories = BlockingCategory::ALL
+            .iter()
+            .filter(|category| self.contains(**category))
+            .map(|category| category.name())
+            .collect::<Vec<_>>();
+        if categories.is_empty() {
+            f.write_str("nothing")
+        } else {
+            f.write_str(&categories.join(", "))
+        }
+    }
+}
+
+/// Counters of the queries handled by the local DNS resolver.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolverStats {
+    /// The number of queries checked against the blocklists.
+    pub queries: u64,
+    /// The number of queries that were answered as blocked.
+    pub blocked: u64,
+}
+
+/// The protocol used to reach an encrypted DNS server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EncryptedDnsProtocol {
+    /// DNS over HTTPS, as described in RFC 8484.
+    Https,
+    /// DNS over TLS, as described in RFC 7858.
+    Tls,
+}
+
+impl EncryptedDnsProtocol {
+    /// Returns the port that servers using the protocol usually listen on.
+    pub fn default_port(self) -> u16 {
+        match self {
+            EncryptedDnsProtocol::Https => 443,
+            EncryptedDnsProtocol::Tls => 853,
+        }
+    }
+}
+
+impl fmt::Display for EncryptedDnsProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncryptedDnsProtocol::Https => f.write_str("DNS over HTTPS"),
+            EncryptedDnsProtocol::Tls => f.write_str("DNS over TLS"),
+        }
+    }
+}
+
+/// A DNS server that is reached over an encrypted connection.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct EncryptedDnsServer {
+    pub protocol: EncryptedDnsProtocol,
+    /// The address of the server. It is used as is, so that the name of the server never has
+    /// to be resolved.
+    pub address: IpAddr,
+    /// The port of the server. The default port of the protocol is used if this is not set.
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// The name that the certificate of the server must be valid for.
+    pub hostname: String,
+    /// The path that queries are sent to when using DNS over HTTPS. Defaults to `/dns-query`.
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Hex encoded SHA-256 fingerprints of server certificates to trust. If any are set, the
+    /// certificate of the server must match one of them, and is then trusted without being
+    /// signed by a certificate authority.
+    #[serde(default)]
+    pub pinned_certificates: Vec<String>,
+}
+
+impl EncryptedDnsServer {
+    /// Returns the address and port that the server listens on.
+    pub fn socket_addr(&self) -> SocketAddr {
+        SocketAddr::new(
+            self.address,
+            self.port.unwrap_or_else(|| self.protocol.default_port()),
+        )
+    }
+}
+
+impl fmt::Display for EncryptedDnsServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}) using {}",
+            self.hostname,
+            self.socket_addr(),
+            self.protocol
+        )
+    }
+}
+
+/// Encrypted DNS servers to forward queries to through the local DNS resolver.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EncryptedDnsSettings {
+    /// The servers to use, in order. Encrypted DNS is only used if this is not empty.
+    pub servers: Vec<EncryptedDnsServer>,
+    /// Whether queries may be sent unencrypted to the regular DNS servers if no encrypted DNS
+    /// server responds.
+    pub allow_plaintext_fallback: bool,
+}
+
+impl EncryptedDnsSettings {
+    /// Returns whether encrypted DNS should be used.
+    pub fn is_enabled(&self) -> bool {
+        !self.servers.is_empty()
+    }
+}
+
+/// Sends queries for a domain and its subdomains to specific DNS servers, such as resolvers on
+/// the local or a corporate network, instead of to the DNS servers used in the tunnel.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SplitDnsRule {
+    /// The domain, without a trailing dot.
+    pub domain: String,
+    /// The servers to send queries for the domain to.
+    pub servers: Vec<IpAddr>,
+}
+
+impl SplitDnsRule {
+    /// Returns whether `name` is the domain of the rule or a subdomain of it.
+    pub fn matches(&self, name: &str) -> bool {
+        let name = name.trim_end_matches('.').as_bytes();
+        let domain = self.domain.trim_end_matches('.').as_bytes();
+        if name.len() == domain.len() {
+            return name.eq_ignore_ascii_case(domain);
+        }
+        name.len() > domain.len()
+            && name[name.len() - domain.len()..].eq_ignore_ascii_case(domain)
+            && name[name.len() - domain.len() - 1] == b'.'
+    }
+}
+
+impl fmt::Display for SplitDnsRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} via {}",
+            self.domain,
+            self.servers
+                .iter()
+                .map(|server| server.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+}
+
+/// A way of managing the system DNS settings on Linux.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsBackend {
+    SystemdResolved,
+    NetworkManager,
+    Resolvconf,
+    StaticFile,
+}
+
+impl DnsBackend {
+    /// All backends, in the order they are tried when detecting which one to use.
+    pub const ALL: [DnsBackend; 4] = [
+        DnsBackend::SystemdResolved,
+        DnsBackend::NetworkManager,
+        DnsBackend::Resolvconf,
+        DnsBackend::StaticFile,
+    ];
+
+    /// Returns the name of the backend, as used by the CLI.
+    pub fn name(self) -> &'static str {
+        match self {
+            DnsBackend::SystemdResolved => "systemd-resolved",
+            DnsBackend::NetworkManager => "network-manager",
+            DnsBackend::Resolvconf => "resolvconf",
+            DnsBackend::StaticFile => "static-file",
+        }
+    }
+}
+
+impl fmt::Display for DnsBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Whether a DNS backend can be used on this system.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsBackendCandidate {
+    pub backend: DnsBackend,
+    /// Why the backend can't be used, or `None` if it can.
+    pub rejection_reason: Option<String>,
+}
+
+/// Which DNS backends can be used, and which one is used when DNS is set.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsBackends {
+    /// The backend that the settings force to be used, if any.
+    pub forced: Option<DnsBackend>,
+    /// The backend that is used the next time DNS is set, if any can be used.
+    pub selected: Option<DnsBackend>,
+    pub candidates: Vec<DnsBackendCandidate>,
+}
+
+impl fmt::Display for DnsBackends {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.selected {
+            Some(backend) => write!(f, "selected {}", backend)?,
+            None => f.write_str("no usable backend")?,
+        }
+        if let Some(forced) = self.forced {
+            write!(f, " (forced {})", forced)?;
+        }
+        for candidate in &self.candidates {
+            match candidate.rejection_reason {
+                Some(ref reason) => write!(f, "; {} rejected: {}", candidate.backend, reason)?,
+                None => write!(f, "; {} usable", candidate.backend)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of a single DNS diagnostics check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsCheckResult {
+    Pass,
+    Fail,
+    /// The check could not be performed, for example because there is no tunnel.
+    Skipped,
+}
+
+impl fmt::Display for DnsCheckResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsCheckResult::Pass => f.write_str("pass"),
+            DnsCheckResult::Fail => f.write_str("FAIL"),
+            DnsCheckResult::Skipped => f.write_str("skipped"),
+        }
+    }
+}
+
+/// A single check made when diagnosing DNS.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsCheck {
+    /// Short description of what was checked.
+    pub name: String,
+    pub result: DnsCheckResult,
+    /// What was found, in a human readable form.
+    pub details: String,
+}
+
+/// Results of checking that DNS is configured as intended and that queries go through the
+/// tunnel.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsDiagnostics {
+    /// The name of the backend that manages the system DNS settings, if DNS has been set.
+    pub backend: Option<String>,
+    /// The DNS servers that the system was configured to use.
+    pub expected_servers: Vec<IpAddr>,
+    /// The DNS servers that the system is actually using, as read back from the backend.
+    pub effective_servers: Vec<IpAddr>,
+    pub checks: Vec<DnsCheck>,
+}
+
+impl DnsDiagnostics {
+    /// Returns whether no check failed.
+    pub fn passed(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| check.result != DnsCheckResult::Fail)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn split_dns_rule_matches_subdomains() {
+        let rule = SplitDnsRule {
+            domain: "corp.example".to_owned(),
+            servers: vec![],
+        };
+        assert!(rule.matches("corp.example"));
+        assert!(rule.matches("Intranet.CORP.example."));
+        assert!(!rule.matches("notcorp.example"));
+        assert!(!rule.matches("example"));
+        assert!(!rule.matches("corp.example.com"));
+    }
+}
@@ -4,6 +4,7 @@ use std::{error::Error, fmt};
 
 #[cfg(target_os = "android")]
 pub mod android;
+pub mod dns;
 pub mod net;
 pub mod tunnel;
 
@@ -0,0 +1,321 @@
+use super::TransportProtocol;
+use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+/// The direction in which the connections allowed by an [`AllowRule`] are established.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Connections to the device from the network of the rule.
+    In,
+    /// Connections from the device to the network of the rule.
+    Out,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::In => f.write_str("in"),
+            Direction::Out => f.write_str("out"),
+        }
+    }
+}
+
+/// An inclusive range of ports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl PortRange {
+    /// Returns whether the range only contains a single port.
+    pub fn is_single_port(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+impl From<u16> for PortRange {
+    fn from(port: u16) -> Self {
+        PortRange {
+            start: port,
+            end: port,
+        }
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = PortRangeParseError;
+
+    /// Parses either a single port, such as `123`, or a range, such as `8000-8100`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_port = |port: &str| port.trim().parse::<u16>().map_err(|_| PortRangeParseError);
+        let range = match s.find('-') {
+            Some(index) => PortRange {
+                start: parse_port(&s[..index])?,
+                end: parse_port(&s[index + 1..])?,
+            },
+            None => PortRange::from(parse_port(s)?),
+        };
+        if range.start > range.end {
+            return Err(PortRangeParseError);
+        }
+        Ok(range)
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_single_port() {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+/// Returned when `PortRange::from_str` fails to convert a string into a [`PortRange`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortRangeParseError;
+
+impl fmt::Display for PortRangeParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Not a valid port or port range")
+    }
+}
+
+impl std::error::Error for PortRangeParseError {}
+
+/// Traffic that the firewall always allows, in addition to what the current policy allows.
+/// Replies to the allowed connections are allowed too.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct AllowRule {
+    pub direction: Direction,
+    /// The remote network.
+    pub network: IpNetwork,
+    /// The transport protocol. Any protocol is allowed if this is `None`, unless `ports` is
+    /// set, in which case both TCP and UDP are allowed.
+    #[serde(default)]
+    pub protocol: Option<TransportProtocol>,
+    /// The ports that connections are established to. These are remote ports for outgoing
+    /// connections and local ports for incoming connections.
+    #[serde(default)]
+    pub ports: Option<PortRange>,
+    /// The interface that the traffic has to go through. Any interface is allowed if this is
+    /// `None`.
+    #[serde(default)]
+    pub interface: Option<String>,
+}
+
+impl AllowRule {
+    /// Returns the transport protocols that the rule matches, or `None` if it matches any
+    /// protocol.
+    pub fn protocols(&self) -> Option<Vec<TransportProtocol>> {
+        match (self.protocol, self.ports) {
+            (Some(protocol), _) => Some(vec![protocol]),
+            (None, Some(_)) => Some(vec![TransportProtocol::Tcp, TransportProtocol::Udp]),
+            (None, None) => None,
+        }
+    }
+}
+
+impl fmt::Display for AllowRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.direction {
+            Direction::In => write!(f, "in from {}", self.network)?,
+            Direction::Out => write!(f, "out to {}", self.network)?,
+        }
+        if let Some(protocol) = self.protocol {
+            write!(f, " {}", protocol)?;
+        }
+        if let Some(ports) = self.ports {
+            let noun = if ports.is_single_port() {
+                "port"
+            } else {
+                "ports"
+            };
+            write!(f, " {} {}", noun, ports)?;
+        }
+        if let Some(interface) = &self.interface {
+            write!(f, " on {}", interface)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the networks that are treated as local when LAN access is allowed, unless they have
+/// been removed through [`LanNetworks`].
+pub fn default_lan_networks() -> [IpNetwork; 6] {
+    [
+        IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(10, 0, 0, 0), 8).unwrap()),
+        IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(172, 16, 0, 0), 12).unwrap()),
+        IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(192, 168, 0, 0), 16).unwrap()),
+        IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(169, 254, 0, 0), 16).unwrap()),
+        IpNetwork::V6(Ipv6Network::new(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), 10).unwrap()),
+        IpNetwork::V6(Ipv6Network::new(Ipv6Addr::new(0xfc00, 0, 0, 0, 0, 0, 0, 0), 7).unwrap()),
+    ]
+}
+
+/// Changes to the networks that can be reached outside the tunnel when LAN access is allowed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct LanNetworks {
+    /// Networks that are treated as local in addition to the default ones.
+    #[serde(default)]
+    pub added: Vec<IpNetwork>,
+    /// Default networks that are not treated as local.
+    #[serde(default)]
+    pub removed: Vec<IpNetwork>,
+}
+
+impl LanNetworks {
+    /// Returns the default LAN networks with the changes applied.
+    pub fn apply(&self) -> Vec<IpNetwork> {
+        let default_networks = default_lan_networks();
+        default_networks
+            .iter()
+            .filter(|network| !self.removed.contains(network))
+            .chain(self.added_networks().iter())
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the added networks that aren't default networks.
+    pub fn added_networks(&self) -> Vec<IpNetwork> {
+        let default_networks = default_lan_networks();
+        self.added
+            .iter()
+            .filter(|network| !default_networks.contains(network))
+            .cloned()
+            .collect()
+    }
+
+    /// Treats `network` as local. A removed default network is restored, and other default
+    /// networks are already local.
+    pub fn add(&mut self, network: IpNetwork) {
+        if self.removed.contains(&network) {
+            self.removed.retain(|removed| *removed != network);
+        } else if !default_lan_networks().contains(&network) && !self.added.contains(&network) {
+            self.added.push(network);
+        }
+    }
+
+    /// Stops treating `network` as local. An added network is forgotten, and networks that are
+    /// neither added nor default networks are already not local.
+    pub fn remove(&mut self, network: IpNetwork) {
+        if self.added.contains(&network) {
+            self.added.retain(|added| *added != network);
+        } else if default_lan_networks().contains(&network) && !self.removed.contains(&network) {
+            self.removed.push(network);
+        }
+    }
+}
+
+/// The kinds of firewall policies that the tunnel states apply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FirewallPolicyKind {
+    /// The policy used while a tunnel is being established.
+    Connecting,
+    /// The policy used while connected through a tunnel.
+    Connected,
+    /// The policy used when all traffic is blocked.
+    Blocked,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_port_ranges() {
+        assert_eq!("123".parse(), Ok(PortRange::from(123)));
+        assert_eq!(
+            "8000-8100".parse(),
+            Ok(PortRange {
+                start: 8000,
+                end: 8100
+            })
+        );
+        assert_eq!("8100-8000".parse::<PortRange>(), Err(PortRangeParseError));
+        assert_eq!("80-".parse::<PortRange>(), Err(PortRangeParseError));
+        assert_eq!("65536".parse::<PortRange>(), Err(PortRangeParseError));
+    }
+
+    #[test]
+    fn ports_without_protocol_match_tcp_and_udp() {
+        let mut rule = AllowRule {
+            direction: Direction::Out,
+            network: "192.0.2.10/32".parse().unwrap(),
+            protocol: None,
+            ports: None,
+            interface: None,
+        };
+        assert_eq!(rule.protocols(), None);
+
+        rule.ports = Some(PortRange::from(123));
+        assert_eq!(
+            rule.protocols(),
+            Some(vec![TransportProtocol::Tcp, TransportProtocol::Udp])
+        );
+
+        rule.protocol = Some(TransportProtocol::Udp);
+        assert_eq!(rule.protocols(), Some(vec![TransportProtocol::Udp]));
+        assert_eq!(rule.to_string(), "out to 192.0.2.10/32 UDP port 123");
+    }
+
+    #[test]
+    fn applies_lan_network_changes() {
+        let defaults = default_lan_networks();
+        let cgnat: IpNetwork = "100.64.0.0/10".parse().unwrap();
+
+        let mut lan_networks = LanNetworks::default();
+        lan_networks.add(cgnat);
+        lan_networks.remove(defaults[0]);
+        let mut expected = defaults[1..].to_vec();
+        expected.push(cgnat);
+        assert_eq!(lan_networks.apply(), expected);
+
+        lan_networks.add(defaults[0]);
+        lan_networks.remove(cgnat);
+        assert_eq!(lan_networks, LanNetworks::default());
+        assert_eq!(lan_networks.apply(), defaults.to_vec());
+    }
+
+    #[test]
+    fn adding_and_removing_lan_networks_undo_each_other() {
+        let defaults = default_lan_networks();
+        let cgnat: IpNetwork = "100.64.0.0/10".parse().unwrap();
+
+        // Removing a network that isn't local does nothing, so adding it makes it local
+        let mut lan_networks = LanNetworks::default();
+        lan_networks.remove(cgnat);
+        assert_eq!(lan_networks, LanNetworks::default());
+        lan_networks.add(cgnat);
+        assert_eq!(lan_networks.added, vec![cgnat]);
+        assert!(lan_networks.apply().contains(&cgnat));
+
+        // Adding a default network does nothing, so removing it makes it non-local
+        let mut lan_networks = LanNetworks::default();
+        lan_networks.add(defaults[0]);
+        assert_eq!(lan_networks, LanNetworks::default());
+        lan_networks.remove(defaults[0]);
+        assert_eq!(lan_networks.removed, vec![defaults[0]]);
+        assert!(!lan_networks.apply().contains(&defaults[0]));
+    }
+
+    #[test]
+    fn skips_added_default_lan_networks() {
+        let cgnat: IpNetwork = "100.64.0.0/10".parse().unwrap();
+        let lan_networks = LanNetworks {
+            added: vec![default_lan_networks()[2], cgnat],
+            removed: vec![],
+        };
+
+        assert_eq!(lan_networks.added_networks(), vec![cgnat]);
+    }
+}
@@ -7,6 +7,7 @@ use std::{
     str::FromStr,
 };
 
+pub mod firewall;
 pub mod openvpn;
 pub mod proxy;
 pub mod wireguard;
@@ -28,11 +29,13 @@ impl TunnelParameters {
                 tunnel_type: TunnelType::OpenVpn,
                 endpoint: params.config.endpoint,
                 proxy: params.proxy.as_ref().map(|proxy| proxy.get_endpoint()),
+                mtu: None,
             },
             TunnelParameters::Wireguard(params) => TunnelEndpoint {
                 tunnel_type: TunnelType::Wireguard,
                 endpoint: params.connection.get_endpoint(),
                 proxy: None,
+                mtu: params.options.mtu,
             },
         }
     }
@@ -89,6 +92,10 @@ pub struct TunnelEndpoint {
     pub tunnel_type: TunnelType,
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub proxy: Option<proxy::ProxyEndpoint>,
+    /// MTU of the tunnel interface, if known.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[serde(default)]
+    pub mtu: Option<u16>,
 }
 
 impl fmt::Display for TunnelEndpoint {
@@ -184,6 +191,25 @@ pub struct GenericTunnelOptions {
     /// Enable configuration of IPv6 on the tunnel interface, allowing IPv6 communication to be
     /// forwarded through the tunnel.
     pub enable_ipv6: bool,
+    /// DNS servers to use instead of the tunnel gateway(s) while connected. Servers on the local
+    /// network are only used if LAN access is allowed.
+    #[serde(default)]
+    pub custom_dns_servers: Vec<IpAddr>,
+    /// Categories of domains to block using a local DNS resolver. Only supported on Linux.
+    #[serde(default)]
+    pub dns_blocking: crate::dns::BlockingCategories,
+    /// Encrypted DNS servers to use instead of plain DNS while connected. Only supported on
+    /// Linux.
+    #[serde(default)]
+    pub encrypted_dns: crate::dns::EncryptedDnsSettings,
+    /// Domains whose queries are sent to other DNS servers than the ones used in the tunnel.
+    /// Only supported on Linux.
+    #[serde(default)]
+    pub split_dns: Vec<crate::dns::SplitDnsRule>,
+    /// The backend to manage the system DNS settings through, instead of detecting one. Only
+    /// used on Linux.
+    #[serde(default)]
+    pub dns_backend: Option<crate::dns::DnsBackend>,
 }
 
 /// Returns a vector of IP networks representing all of the internet, 0.0.0.0/0.
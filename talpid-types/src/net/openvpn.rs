@@ -3,7 +3,7 @@ use crate::net::{
     Endpoint, GenericTunnelOptions, TransportProtocol,
 };
 use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
+use std::{fmt, net::SocketAddr, path::PathBuf, str::FromStr};
 
 /// Information needed by `OpenVpnMonitor` to establish a tunnel connection.
 /// See [`crate::net::TunnelParameters`].
@@ -21,6 +21,10 @@ pub struct ConnectionConfig {
     pub endpoint: Endpoint,
     pub username: String,
     pub password: String,
+    /// User-supplied OpenVPN profile to use instead of the bundled configuration. The profile
+    /// must not contain a remote, since `endpoint` is used for that.
+    #[serde(default)]
+    pub profile: Option<PathBuf>,
 }
 
 impl ConnectionConfig {
@@ -29,6 +33,7 @@ impl ConnectionConfig {
             endpoint,
             username,
             password,
+            profile: None,
         }
     }
 }
@@ -42,8 +47,142 @@ pub struct TunnelOptions {
     /// Optional argument for openvpn to try and limit TCP packet size,
     /// as discussed [here](https://openvpn.net/archive/openvpn-users/2003-11/msg00154.html)
     pub mssfix: Option<u16>,
+    /// Data channel ciphers that may be negotiated with the server, in order of preference.
+    /// The default ciphers are used if this is empty.
+    #[serde(default)]
+    pub data_ciphers: Vec<DataCipher>,
+    /// MTU of the tunnel device.
+    #[serde(default)]
+    pub tun_mtu: Option<u16>,
+    /// Maximum size of UDP datagrams. Larger packets are fragmented by OpenVPN. Ignored for TCP.
+    #[serde(default)]
+    pub fragment: Option<u16>,
+    /// Number of seconds after which the data channel key is renegotiated.
+    #[serde(default)]
+    pub reneg_sec: Option<u32>,
+    /// Extra directives that are passed to OpenVPN as is.
+    #[serde(default)]
+    pub extra_directives: Vec<ExtraDirective>,
 }
 
+/// A cipher for the OpenVPN data channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DataCipher {
+    Aes256Gcm,
+    Aes128Gcm,
+    Chacha20Poly1305,
+}
+
+impl DataCipher {
+    /// Returns the name that OpenVPN uses for the cipher.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DataCipher::Aes256Gcm => "AES-256-GCM",
+            DataCipher::Aes128Gcm => "AES-128-GCM",
+            DataCipher::Chacha20Poly1305 => "CHACHA20-POLY1305",
+        }
+    }
+}
+
+impl fmt::Display for DataCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for DataCipher {
+    type Err = UnsupportedOption;
+
+    fn from_str(cipher: &str) -> Result<Self, Self::Err> {
+        [
+            DataCipher::Aes256Gcm,
+            DataCipher::Aes128Gcm,
+            DataCipher::Chacha20Poly1305,
+        ]
+        .iter()
+        .find(|known_cipher| known_cipher.as_str().eq_ignore_ascii_case(cipher))
+        .copied()
+        .ok_or_else(|| UnsupportedOption(cipher.to_owned()))
+    }
+}
+
+/// An OpenVPN directive that is passed on to OpenVPN as is. Only directives that can't interfere
+/// with how the tunnel is set up and monitored are available.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExtraDirective {
+    TcpNodelay,
+    MuteReplayWarnings,
+    Sndbuf(u32),
+    Rcvbuf(u32),
+    Txqueuelen(u32),
+    ReplayWindow(u32),
+    HandWindow(u32),
+}
+
+impl ExtraDirective {
+    /// Returns the OpenVPN arguments for the directive.
+    pub fn to_arguments(&self) -> Vec<String> {
+        let (name, value) = match self {
+            ExtraDirective::TcpNodelay => ("--tcp-nodelay", None),
+            ExtraDirective::MuteReplayWarnings => ("--mute-replay-warnings", None),
+            ExtraDirective::Sndbuf(size) => ("--sndbuf", Some(size)),
+            ExtraDirective::Rcvbuf(size) => ("--rcvbuf", Some(size)),
+            ExtraDirective::Txqueuelen(length) => ("--txqueuelen", Some(length)),
+            ExtraDirective::ReplayWindow(size) => ("--replay-window", Some(size)),
+            ExtraDirective::HandWindow(seconds) => ("--hand-window", Some(seconds)),
+        };
+        let mut arguments = vec![name.to_owned()];
+        arguments.extend(value.map(u32::to_string));
+        arguments
+    }
+}
+
+impl fmt::Display for ExtraDirective {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.to_arguments().join(" ").trim_start_matches("--")
+        )
+    }
+}
+
+impl FromStr for ExtraDirective {
+    type Err = UnsupportedOption;
+
+    /// Parses a directive as written in an OpenVPN config, e.g. `sndbuf 393216`.
+    fn from_str(directive: &str) -> Result<Self, Self::Err> {
+        let unsupported = || UnsupportedOption(directive.to_owned());
+        let mut words = directive.trim().trim_start_matches("--").split_whitespace();
+        let name = words.next().ok_or_else(unsupported)?;
+        let value = words
+            .next()
+            .map(|value| value.parse::<u32>().map_err(|_| unsupported()))
+            .transpose()?;
+        if words.next().is_some() {
+            return Err(unsupported());
+        }
+
+        match (name, value) {
+            ("tcp-nodelay", None) => Ok(ExtraDirective::TcpNodelay),
+            ("mute-replay-warnings", None) => Ok(ExtraDirective::MuteReplayWarnings),
+            ("sndbuf", Some(size)) => Ok(ExtraDirective::Sndbuf(size)),
+            ("rcvbuf", Some(size)) => Ok(ExtraDirective::Rcvbuf(size)),
+            ("txqueuelen", Some(length)) => Ok(ExtraDirective::Txqueuelen(length)),
+            ("replay-window", Some(size)) => Ok(ExtraDirective::ReplayWindow(size)),
+            ("hand-window", Some(seconds)) => Ok(ExtraDirective::HandWindow(seconds)),
+            _ => Err(unsupported()),
+        }
+    }
+}
+
+/// Returned when parsing a cipher or directive that isn't supported.
+#[derive(err_derive::Error, Debug, Clone, PartialEq, Eq)]
+#[error(display = "Unsupported option: {}", _0)]
+pub struct UnsupportedOption(pub String);
+
 /// Proxy server options to be used by `OpenVpnMonitor` when starting a tunnel.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
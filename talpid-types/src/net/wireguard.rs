@@ -8,6 +8,7 @@ use std::{
     cmp, fmt,
     hash::{Hash, Hasher},
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
 };
 
 
@@ -47,6 +48,12 @@ pub struct PeerConfig {
     pub allowed_ips: Vec<IpNetwork>,
     /// IP address of the WireGuard server.
     pub endpoint: SocketAddr,
+    /// Optional symmetric key mixed into the handshake.
+    #[serde(default)]
+    pub psk: Option<PresharedKey>,
+    /// Interval in seconds at which keepalive packets are sent to the peer.
+    #[serde(default)]
+    pub persistent_keepalive: Option<u16>,
 }
 
 #[derive(Clone, Eq, PartialEq, Deserialize, Serialize, Debug)]
@@ -70,9 +77,73 @@ pub struct TunnelOptions {
         jnix(map = "|maybe_mtu| maybe_mtu.map(|mtu| mtu as i32)")
     )]
     pub mtu: Option<u16>,
+    /// Discover the MTU of the path to the relay instead of using a fixed default. Has no effect
+    /// if `mtu` is set, and is only supported on Linux.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[serde(default)]
+    pub automatic_mtu: bool,
     /// Interval used for automatic key rotation, in hours
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub automatic_rotation: Option<u32>,
+    /// Probes used to check that the tunnel works. A reply to any of the probes counts. If the
+    /// list is empty, ICMP echo requests are sent to the tunnel gateway.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[serde(default)]
+    pub connectivity_probes: Vec<ConnectivityProbe>,
+}
+
+/// A method used by the connectivity monitor to verify that traffic can pass through the tunnel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectivityProbe {
+    /// Send ICMP echo requests to the tunnel gateway.
+    Icmp,
+    /// Open TCP connections to an address reachable through the tunnel.
+    Tcp(SocketAddr),
+    /// Send HTTP requests to an address reachable through the tunnel.
+    Http(SocketAddr),
+}
+
+impl fmt::Display for ConnectivityProbe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectivityProbe::Icmp => write!(f, "icmp"),
+            ConnectivityProbe::Tcp(address) => write!(f, "tcp:{}", address),
+            ConnectivityProbe::Http(address) => write!(f, "http:{}", address),
+        }
+    }
+}
+
+impl FromStr for ConnectivityProbe {
+    type Err = ConnectivityProbeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "icmp" {
+            return Ok(ConnectivityProbe::Icmp);
+        }
+        let mut parts = s.splitn(2, ':');
+        let kind = parts.next().unwrap_or("");
+        let address = parts
+            .next()
+            .and_then(|address| address.parse().ok())
+            .ok_or(ConnectivityProbeParseError)?;
+        match kind {
+            "tcp" => Ok(ConnectivityProbe::Tcp(address)),
+            "http" => Ok(ConnectivityProbe::Http(address)),
+            _ => Err(ConnectivityProbeParseError),
+        }
+    }
+}
+
+/// Returned when `ConnectivityProbe::from_str` fails to convert a string into a
+/// [`ConnectivityProbe`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectivityProbeParseError;
+
+impl fmt::Display for ConnectivityProbeParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Expected 'icmp', 'tcp:<address>:<port>' or 'http:<address>:<port>'")
+    }
 }
 
 /// Wireguard x25519 private key
@@ -101,6 +172,14 @@ impl From<[u8; 32]> for PrivateKey {
     }
 }
 
+impl FromStr for PrivateKey {
+    type Err = KeyParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_key(s).map(Self::from)
+    }
+}
+
 impl cmp::PartialEq for PrivateKey {
     fn eq(&self, other: &PrivateKey) -> bool {
         self.0.to_bytes() == other.0.to_bytes()
@@ -167,6 +246,14 @@ impl From<[u8; 32]> for PublicKey {
     }
 }
 
+impl FromStr for PublicKey {
+    type Err = KeyParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_key(s).map(Self::from)
+    }
+}
+
 impl Serialize for PublicKey {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -211,6 +298,80 @@ impl fmt::Display for PublicKey {
     }
 }
 
+/// Wireguard pre-shared symmetric key
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct PresharedKey([u8; 32]);
+
+impl PresharedKey {
+    /// Get the pre-shared key as bytes
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    pub fn to_base64(&self) -> String {
+        base64::encode(&self.0)
+    }
+}
+
+impl From<[u8; 32]> for PresharedKey {
+    fn from(key: [u8; 32]) -> PresharedKey {
+        PresharedKey(key)
+    }
+}
+
+impl FromStr for PresharedKey {
+    type Err = KeyParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_key(s).map(Self::from)
+    }
+}
+
+impl Serialize for PresharedKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_key(&self.0, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PresharedKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_key(deserializer)
+    }
+}
+
+impl fmt::Debug for PresharedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Keep the key out of logs.
+        f.write_str("PresharedKey(..)")
+    }
+}
+
+/// Returned when a string is not a base64 encoded 32 byte key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyParseError;
+
+impl fmt::Display for KeyParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Expected a base64 encoded 32 byte key")
+    }
+}
+
+fn parse_key(key_str: &str) -> Result<[u8; 32], KeyParseError> {
+    let buffer = base64::decode(key_str.trim()).map_err(|_| KeyParseError)?;
+    if buffer.len() != 32 {
+        return Err(KeyParseError);
+    }
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&buffer);
+    Ok(key)
+}
+
 fn serialize_key<S>(key: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
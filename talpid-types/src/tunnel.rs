@@ -2,7 +2,7 @@ use crate::net::TunnelEndpoint;
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
-use std::fmt;
+use std::{fmt, time::Duration};
 
 /// Event emitted from the states in `talpid_core::tunnel_state_machine` when the tunnel state
 /// machine enters a new state.
@@ -16,12 +16,25 @@ pub enum TunnelStateTransition {
     Connecting(TunnelEndpoint),
     /// Tunnel is connected.
     Connected(TunnelEndpoint),
+    /// Tunnel is still connected, but its traffic now leaves through a new default route.
+    Roamed(TunnelEndpoint),
     /// Disconnecting tunnel.
     Disconnecting(ActionAfterDisconnect),
     /// Tunnel is disconnected but usually secured by blocking all connections.
     Error(ErrorState),
 }
 
+/// Traffic counters and latency of a running tunnel.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TunnelStats {
+    /// Number of bytes sent through the tunnel.
+    pub tx_bytes: u64,
+    /// Number of bytes received through the tunnel.
+    pub rx_bytes: u64,
+    /// Round-trip time of the most recently answered connectivity probe, if any.
+    pub round_trip_time: Option<Duration>,
+}
+
 /// Action that will be taken after disconnection is complete.
 #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -42,6 +42,18 @@ std::optional<FwContext::PingableHosts> ConvertPingableHosts(const PingableHosts
 	return converted;
 }
 
+std::vector<wfp::IpAddress> ConvertDnsServers(const wchar_t * const *servers, size_t numServers)
+{
+	std::vector<wfp::IpAddress> converted;
+
+	for (size_t i = 0; i < numServers; ++i)
+	{
+		converted.emplace_back(wfp::IpAddress(servers[i]));
+	}
+
+	return converted;
+}
+
 } // anonymous namespace
 
 WINFW_LINKAGE
@@ -237,8 +249,10 @@ WinFw_ApplyPolicyConnected(
 	const WinFwRelay *relay,
 	const wchar_t *relayClient,
 	const wchar_t *tunnelInterfaceAlias,
-	const wchar_t *v4DnsHost,
-	const wchar_t *v6DnsHost
+	const wchar_t * const *tunnelDnsServers,
+	size_t numTunnelDnsServers,
+	const wchar_t * const *nonTunnelDnsServers,
+	size_t numNonTunnelDnsServers
 )
 {
 	if (nullptr == g_fwContext)
@@ -268,16 +282,14 @@ WinFw_ApplyPolicyConnected(
 			THROW_ERROR("Invalid argument: tunnelInterfaceAlias");
 		}
 
-		if (nullptr == v4DnsHost)
+		if (nullptr == tunnelDnsServers && 0 != numTunnelDnsServers)
 		{
-			THROW_ERROR("Invalid argument: v4DnsHost");
+			THROW_ERROR("Invalid argument: tunnelDnsServers");
 		}
 
-		std::vector<wfp::IpAddress> tunnelDnsServers = { wfp::IpAddress(v4DnsHost) };
-
-		if (nullptr != v6DnsHost)
+		if (nullptr == nonTunnelDnsServers && 0 != numNonTunnelDnsServers)
 		{
-			tunnelDnsServers.emplace_back(wfp::IpAddress(v6DnsHost));
+			THROW_ERROR("Invalid argument: nonTunnelDnsServers");
 		}
 
 		return g_fwContext->applyPolicyConnected(
@@ -285,7 +297,8 @@ WinFw_ApplyPolicyConnected(
 			*relay,
 			relayClient,
 			tunnelInterfaceAlias,
-			tunnelDnsServers
+			ConvertDnsServers(tunnelDnsServers, numTunnelDnsServers),
+			ConvertDnsServers(nonTunnelDnsServers, numNonTunnelDnsServers)
 		);
 	}
 	catch (std::exception &err)
@@ -158,14 +158,18 @@ WinFw_ApplyPolicyConnecting(
 // - What is specified by settings
 // - Communication with the relay server
 // - Non-DNS traffic inside the VPN tunnel
-// - DNS requests inside the VPN tunnel, to the specified DNS server
+// - DNS requests inside the VPN tunnel, to the specified DNS servers
+// - DNS requests outside the VPN tunnel, to the specified non-tunnel DNS servers
 //
 // Parameters:
 //
 // tunnelInterfaceAlias:
 //   Friendly name of VPN tunnel interface
-// v4DnsHost/v6DnsHost:
-//   String encoded IP address of DNS to use inside tunnel
+// tunnelDnsServers/numTunnelDnsServers:
+//   String encoded IP addresses of DNS servers to use inside tunnel
+// nonTunnelDnsServers/numNonTunnelDnsServers:
+//   String encoded IP addresses of DNS servers to use outside tunnel, such as ones on the LAN.
+//   The array may be null if there are no such servers
 //
 extern "C"
 WINFW_LINKAGE
@@ -176,8 +180,10 @@ WinFw_ApplyPolicyConnected(
 	const WinFwRelay *relay,
 	const wchar_t *relayClient,
 	const wchar_t *tunnelInterfaceAlias,
-	const wchar_t *v4DnsHost,
-	const wchar_t *v6DnsHost
+	const wchar_t * const *tunnelDnsServers,
+	size_t numTunnelDnsServers,
+	const wchar_t * const *nonTunnelDnsServers,
+	size_t numNonTunnelDnsServers
 );
 
 //
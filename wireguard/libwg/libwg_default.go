@@ -75,3 +75,19 @@ func wgTurnOn(mtu int, cSettings *C.char, fd int, logSink LogSink, logContext Lo
 
 	return handle
 }
+
+//export wgRebindTunnelSocket
+func wgRebindTunnelSocket(tunnelHandle int32) int32 {
+	tunnel, err := tunnels.Get(tunnelHandle)
+	if err != nil {
+		return ERROR_GENERAL_FAILURE
+	}
+
+	tunnel.Logger.Info.Println("Rebinding tunnel sockets")
+	err = tunnel.Device.BindUpdate()
+	if err != nil {
+		tunnel.Logger.Error.Println("Failed to rebind tunnel sockets: ", err)
+		return ERROR_GENERAL_FAILURE
+	}
+	return 0
+}